@@ -0,0 +1,69 @@
+use crate::{
+    beatmap::{ApproachRate, CircleSize},
+    hit_score::HitScore,
+};
+
+/// Highest and lowest offset applied to the beatmap difficulty
+const MAX_OFFSET: f64 = 3.0;
+/// Harder offsets can't make the settings go above this (AR 11 and CS 10 are barely playable)
+const MAX_AR: f64 = 11.0;
+const MAX_CS: f64 = 10.0;
+
+/// Difficulty of the "adaptive" fun mode: AR and CS tighten while the players are hitting 300s and relax after misses.
+/// The offset is applied to each hit object when it's spawned, so the beatmap's own settings are only the starting point.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AdaptiveDifficulty {
+    offset: f64,
+}
+
+impl AdaptiveDifficulty {
+    pub fn record(&mut self, hit: HitScore) {
+        let change = match hit {
+            HitScore::Hit300 => 0.05,
+            HitScore::Hit100 => 0.0,
+            HitScore::Hit50 => -0.1,
+            HitScore::Miss => -0.5,
+        };
+
+        self.offset = (self.offset + change).clamp(-MAX_OFFSET, MAX_OFFSET);
+    }
+
+    /// Difficulty added to (or removed from) the beatmap settings
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    pub fn ar(&self, base: ApproachRate) -> ApproachRate {
+        ApproachRate((base.0 + self.offset).clamp(0.0, MAX_AR.max(base.0)))
+    }
+
+    /// CS changes at half the rate of AR, since small circles get hard quickly
+    pub fn cs(&self, base: CircleSize) -> CircleSize {
+        CircleSize((base.0 + self.offset / 2.0).clamp(0.0, MAX_CS.max(base.0)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tighten_and_relax() {
+        let mut adaptive = AdaptiveDifficulty::default();
+        assert_eq!(adaptive.ar(ApproachRate(9.0)).0, 9.0);
+
+        for _ in 0..20 {
+            adaptive.record(HitScore::Hit300);
+        }
+        assert!((adaptive.offset() - 1.0).abs() < 1e-9);
+        assert!((adaptive.ar(ApproachRate(9.0)).0 - 10.0).abs() < 1e-9);
+        assert!((adaptive.cs(CircleSize(4.0)).0 - 4.5).abs() < 1e-9);
+        assert_eq!(adaptive.ar(ApproachRate(10.5)).0, MAX_AR);
+
+        for _ in 0..10 {
+            adaptive.record(HitScore::Miss);
+        }
+        assert_eq!(adaptive.offset(), -MAX_OFFSET);
+        assert_eq!(adaptive.ar(ApproachRate(2.0)).0, 0.0);
+    }
+}
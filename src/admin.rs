@@ -0,0 +1,379 @@
+use anyhow::{anyhow, Result};
+use tracing::{error, info};
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Local, Query, Res, ResMut},
+};
+use valence::{
+    client::event::ClickContainer,
+    nbt::{compound, List},
+    prelude::{Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{ItemKind, ItemStack, Text, TextFormat},
+};
+
+use crate::{
+    command_registry::{CommandSpec, OsuCommand},
+    configs::Configs,
+    error::{error_message, OsuError},
+    inventory::{open_new_inventory, InventoriesToOpen},
+    lobby::Lobby,
+    osu::{Osu, OsuState, OsuStateChange},
+    player_name::PlayerName,
+};
+
+/// Step of the volume buttons of the admin inventory
+const VOLUME_STEP: u8 = 10;
+/// Ticks between two refreshes of the maintenance notice in the action bar (it fades after a few seconds)
+const MAINTENANCE_NOTICE_TICKS: usize = 20;
+
+/// Inventory shared by the operators to lock the song selection, toggle the maintenance and run the common event commands (`/admin`)
+#[derive(Component)]
+pub struct AdminInventory;
+
+#[derive(Clone, Copy)]
+enum AdminAction {
+    LockSongSelection,
+    ForceSkip,
+    VolumeDown,
+    VolumeUp,
+    KickToLobby,
+    Maintenance,
+}
+
+const ADMIN_ACTIONS: [AdminAction; 6] = [
+    AdminAction::LockSongSelection,
+    AdminAction::ForceSkip,
+    AdminAction::VolumeDown,
+    AdminAction::VolumeUp,
+    AdminAction::KickToLobby,
+    AdminAction::Maintenance,
+];
+
+impl AdminAction {
+    fn name(&self) -> &'static str {
+        match self {
+            AdminAction::LockSongSelection => "Lock song selection",
+            AdminAction::ForceSkip => "Skip map",
+            AdminAction::VolumeDown => "Volume down",
+            AdminAction::VolumeUp => "Volume up",
+            AdminAction::KickToLobby => "Everyone to the lobby",
+            AdminAction::Maintenance => "Maintenance",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            AdminAction::LockSongSelection => "Only operators can pick songs",
+            AdminAction::ForceSkip => "Stops the map and goes back to the selection",
+            AdminAction::VolumeDown | AdminAction::VolumeUp => "Master volume of the server audio",
+            AdminAction::KickToLobby => {
+                "Stops the map, closes the inventories and teleports everyone to the spawn"
+            }
+            AdminAction::Maintenance => "Blocks new maps, the current play can finish",
+        }
+    }
+
+    /// Item and status line shown in the inventory
+    fn display(&self, osu: &Osu, configs: &Configs) -> (ItemKind, String) {
+        match self {
+            AdminAction::LockSongSelection | AdminAction::Maintenance => {
+                let enabled = match self {
+                    AdminAction::LockSongSelection => osu.is_song_selection_locked(),
+                    _ => osu.is_maintenance(),
+                };
+                if enabled {
+                    (
+                        ItemKind::LimeDye,
+                        r#"{"text": "Enabled", "color": "green"}"#.to_string(),
+                    )
+                } else {
+                    (
+                        ItemKind::GrayDye,
+                        r#"{"text": "Disabled", "color": "red"}"#.to_string(),
+                    )
+                }
+            }
+            AdminAction::ForceSkip => (ItemKind::Barrier, String::new()),
+            AdminAction::VolumeDown | AdminAction::VolumeUp => (
+                if matches!(self, AdminAction::VolumeDown) {
+                    ItemKind::RedStainedGlassPane
+                } else {
+                    ItemKind::LimeStainedGlassPane
+                },
+                format!(
+                    r#"{{"text": "Master volume: {}", "color": "yellow"}}"#,
+                    configs.volume().master
+                ),
+            ),
+            AdminAction::KickToLobby => (ItemKind::EnderPearl, String::new()),
+        }
+    }
+}
+
+impl AdminInventory {
+    fn draw(osu: &Osu, configs: &Configs, inventory: &mut Inventory) {
+        for (slot, action) in ADMIN_ACTIONS.iter().enumerate() {
+            let (item_kind, status) = action.display(osu, configs);
+            let mut lore = vec![format!(
+                r#"{{"text": "{}", "color": "gray"}}"#,
+                action.description()
+            )];
+            if !status.is_empty() {
+                lore.push(status);
+            }
+
+            let item = ItemStack::new(
+                item_kind,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => format!(r#"{{"text": "{}", "color": "gold"}}"#, action.name()),
+                        "Lore" => List::String(lore)
+                    }
+                }),
+            );
+
+            inventory.replace_slot(slot as u16, Some(item));
+        }
+    }
+}
+
+/// Broadcast when the maintenance is toggled (with `/maintenance` or the admin inventory)
+pub fn maintenance_announcement(maintenance: bool) -> Text {
+    if maintenance {
+        "Maintenance started: ".color(Color::RED)
+            + "no new maps can be started, the current play can finish".color(Color::GRAY)
+    } else {
+        "Maintenance ended: ".color(Color::YELLOW) + "maps can be started again".color(Color::GREEN)
+    }
+}
+
+/// Whether a map was picked and is being played (or its score or fail screen is shown)
+fn is_map_started(osu: &Osu) -> bool {
+    matches!(
+        osu.state(),
+        Some(
+            OsuState::PrePlaying { .. }
+                | OsuState::Playing(_)
+                | OsuState::ScoreDisplay(_)
+                | OsuState::Failed(_)
+        )
+    )
+}
+
+pub fn admin_command() -> CommandSpec {
+    CommandSpec::new("admin")
+}
+
+/// Handles `/admin` (operators only), opening the admin inventory
+pub fn execute_admin_commands(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    mut admin_inventories: Query<(Entity, &mut Inventory), With<AdminInventory>>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "admin" {
+            continue;
+        }
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if !is_operator {
+            client.send_message(
+                OsuError::OperatorOnly {
+                    action: "open the admin controls",
+                }
+                .message(),
+            );
+            continue;
+        }
+
+        let inventory_entity = match admin_inventories.get_single_mut() {
+            Ok((entity, mut inventory)) => {
+                AdminInventory::draw(&osu, &configs, &mut inventory);
+                entity
+            }
+            Err(_) => {
+                let mut inventory = Inventory::with_title(
+                    InventoryKind::Generic9x1,
+                    "Admin".color(Color::DARK_RED),
+                );
+                AdminInventory::draw(&osu, &configs, &mut inventory);
+                commands.spawn((AdminInventory, inventory)).id()
+            }
+        };
+
+        open_new_inventory(
+            &mut commands,
+            command_event.client,
+            &mut inventories_to_open,
+            inventory_entity,
+        );
+    }
+}
+
+pub fn handle_admin_clicks(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    mut configs: ResMut<Configs>,
+    mut osu: ResMut<Osu>,
+    open_inventories: Query<(Entity, &OpenInventory), With<Client>>,
+    mut admin_inventories: Query<&mut Inventory, With<AdminInventory>>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut clicks: EventReader<ClickContainer>,
+) {
+    for click in clicks.iter() {
+        let Ok((_, open_inventory)) = open_inventories.get(click.client) else {
+            continue;
+        };
+        let inventory_entity = open_inventory.entity();
+        let Ok(mut inventory) = admin_inventories.get_mut(inventory_entity) else {
+            continue;
+        };
+        let Some(&action) = ADMIN_ACTIONS.get(click.slot_id.unsigned_abs() as usize) else {
+            continue;
+        };
+        let Some(username) = clients
+            .get(click.client)
+            .ok()
+            .map(|client| client.username().to_string())
+        else {
+            continue;
+        };
+        // Operators can be removed from the configs while the inventory is open
+        let is_operator = player_names
+            .get(click.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if !is_operator {
+            continue;
+        }
+
+        let result: Result<Option<Text>> = match action {
+            AdminAction::LockSongSelection => {
+                let locked = !osu.is_song_selection_locked();
+                osu.set_song_selection_locked(locked);
+                Ok(Some(if locked {
+                    "The song selection was locked by ".color(Color::YELLOW)
+                } else {
+                    "The song selection was unlocked by ".color(Color::YELLOW)
+                }))
+            }
+            AdminAction::ForceSkip => {
+                if is_map_started(&osu) {
+                    osu.change_state(OsuStateChange::BackToSelection, &mut clients)
+                        .map(|_| Some("The map was skipped by ".color(Color::YELLOW)))
+                } else {
+                    Err(anyhow!("no map is being played"))
+                }
+            }
+            AdminAction::VolumeDown | AdminAction::VolumeUp => {
+                let mut volume = configs.volume();
+                volume.master = match action {
+                    AdminAction::VolumeDown => volume.master.saturating_sub(VOLUME_STEP),
+                    _ => volume.master.saturating_add(VOLUME_STEP).min(100),
+                };
+                osu.set_volume(volume);
+                configs.set_volume(volume).map(|_| None)
+            }
+            AdminAction::KickToLobby => {
+                if is_map_started(&osu) {
+                    if let Err(error) =
+                        osu.change_state(OsuStateChange::SongSelection, &mut clients)
+                    {
+                        error!(
+                            "Error while changing to Song Selection state while kicking everyone to the lobby: '{}'",
+                            error
+                        );
+                    }
+                }
+
+                let spawn_pos = osu.playfield().player_spawn_pos();
+                for (client_entity, _) in &open_inventories {
+                    if client_entity != click.client {
+                        commands.entity(client_entity).remove::<OpenInventory>();
+                    }
+                }
+                for mut client in &mut clients {
+                    client.set_position(spawn_pos);
+                }
+                Ok(Some(
+                    "Everyone was sent to the lobby by ".color(Color::YELLOW),
+                ))
+            }
+            AdminAction::Maintenance => {
+                let maintenance = !osu.is_maintenance();
+                osu.set_maintenance(maintenance);
+
+                let announcement = maintenance_announcement(maintenance);
+                for mut client in &mut clients {
+                    client.send_message(announcement.clone());
+                }
+                Ok(Some(if maintenance {
+                    "The maintenance was started by ".color(Color::YELLOW)
+                } else {
+                    "The maintenance was ended by ".color(Color::YELLOW)
+                }))
+            }
+        };
+
+        match result {
+            Ok(Some(message)) => {
+                info!("Admin action '{}' by '{}'", action.name(), username);
+                let message = message + username.clone().color(Color::AQUA);
+                for mut client in &mut clients {
+                    client.send_message(message.clone());
+                }
+            }
+            Ok(None) => {}
+            Err(error) => {
+                if let Ok(mut client) = clients.get_mut(click.client) {
+                    client.send_message(error_message(&error));
+                }
+            }
+        }
+
+        AdminInventory::draw(&osu, &configs, &mut inventory);
+        open_new_inventory(
+            &mut commands,
+            click.client,
+            &mut inventories_to_open,
+            inventory_entity,
+        );
+    }
+}
+
+/// Reminds the players of the maintenance in the action bar, except while playing (it shows the hit judgements)
+/// and in lobbies (it shows their status)
+pub fn show_maintenance_notice(
+    osu: Res<Osu>,
+    lobby: Res<Lobby>,
+    mut clients: Query<&mut Client>,
+    mut ticks: Local<usize>,
+) {
+    *ticks += 1;
+    if !osu.is_maintenance()
+        || osu.playing_beatmap().is_some()
+        || lobby.is_active()
+        || *ticks < MAINTENANCE_NOTICE_TICKS
+    {
+        return;
+    }
+    *ticks = 0;
+
+    let notice = "Maintenance: ".color(Color::RED)
+        + "new maps can't be started right now".color(Color::GRAY);
+    for mut client in &mut clients {
+        client.set_action_bar(notice.clone());
+    }
+}
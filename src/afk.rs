@@ -0,0 +1,77 @@
+use bevy_ecs::{
+    prelude::{Component, Entity},
+    system::{Commands, Query, Res},
+};
+use valence::{
+    prelude::{Client, Color, DVec3, GameMode, Server},
+    protocol::TextFormat,
+};
+
+use crate::{configs::Configs, osu::Osu};
+
+/// Marks players who were moved to spectator mode for being idle. They are not credited in the scores of the beatmaps played while they are away.
+#[derive(Component)]
+pub struct Afk;
+
+/// Last position and look direction of a player, used to detect idle players
+#[derive(Component, Default)]
+pub struct AfkTracker {
+    idle_ticks: usize,
+    last_pose: Option<(DVec3, f32, f32)>,
+}
+
+impl AfkTracker {
+    /// Returns whether the player did something since the last call
+    fn track(&mut self, client: &Client) -> bool {
+        let pose = (client.position(), client.yaw(), client.pitch());
+        let is_active = self.last_pose.is_some_and(|last_pose| last_pose != pose);
+
+        self.last_pose = Some(pose);
+        if is_active {
+            self.idle_ticks = 0;
+        } else {
+            self.idle_ticks += 1;
+        }
+
+        is_active
+    }
+}
+
+/// Moves players who are idle for longer than `Configs::afk_timeout` to spectator mode and brings them back once they move
+/// (also those moved by the lobby ready timeout, even if the AFK timeout is disabled)
+pub fn update_afk_players(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    server: Res<Server>,
+    mut clients: Query<(Entity, &mut Client, Option<&mut AfkTracker>, Option<&Afk>)>,
+) {
+    let timeout_ticks = configs
+        .afk_timeout()
+        .map(|timeout| timeout.as_secs() as usize * server.shared().tps() as usize);
+
+    for (entity, mut client, tracker, afk) in &mut clients {
+        let Some(mut tracker) = tracker else {
+            commands.entity(entity).insert(AfkTracker::default());
+            continue;
+        };
+
+        let is_active = tracker.track(&client);
+
+        if afk.is_some() && is_active {
+            client.set_game_mode(GameMode::Creative);
+            client.set_position(osu.playfield().player_spawn_pos());
+            // Teleporting is not an activity
+            tracker.last_pose = None;
+            client.send_message("Welcome back!".color(Color::GREEN));
+            commands.entity(entity).remove::<Afk>();
+        } else if afk.is_none() && timeout_ticks.is_some_and(|ticks| tracker.idle_ticks >= ticks) {
+            client.set_game_mode(GameMode::Spectator);
+            client.send_message(
+                "You were moved to spectator mode for being idle. Move to play again."
+                    .color(Color::GRAY),
+            );
+            commands.entity(entity).insert(Afk);
+        }
+    }
+}
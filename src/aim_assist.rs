@@ -0,0 +1,116 @@
+use anyhow::{anyhow, bail, Result};
+
+use bevy_ecs::{
+    prelude::EventReader,
+    system::{Query, Res},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    configs::Configs,
+    error::error_message,
+    hud::{save_hud_settings, HudSettings},
+    osu::Osu,
+    player_name::PlayerName,
+};
+
+/// Aim assist of the players who didn't choose one (the hitcircles keep their size)
+pub const NO_AIM_ASSIST: f64 = 1.0;
+
+/// Parses the multiplier of `/assist <multiplier>`, which can't exceed the `max_aim_assist` of the configs
+pub fn parse_aim_assist(args: &str, max_aim_assist: f64) -> Result<f64> {
+    let aim_assist: f64 = args
+        .trim()
+        .parse()
+        .ok()
+        .filter(|aim_assist: &f64| aim_assist.is_finite())
+        .ok_or_else(|| anyhow!("the aim assist must be a number (e.g. /assist 1.2)"))?;
+
+    if aim_assist < NO_AIM_ASSIST {
+        bail!("the aim assist can't be lower than {}", NO_AIM_ASSIST);
+    }
+    if aim_assist > max_aim_assist {
+        bail!(
+            "the aim assist can't be higher than {} in this server",
+            max_aim_assist
+        );
+    }
+
+    Ok(aim_assist)
+}
+
+/// Aim assist applied to the hits of a player, capped by the configs in case `max_aim_assist` was lowered after it was chosen
+pub fn effective_aim_assist(aim_assist: f64, max_aim_assist: f64) -> f64 {
+    aim_assist.min(max_aim_assist).max(NO_AIM_ASSIST)
+}
+
+pub fn assist_command() -> CommandSpec {
+    CommandSpec::new("assist").arg(ArgSpec::word("multiplier"))
+}
+
+/// Handles `/assist [multiplier]`, which multiplies the radius of the hitcircles when checking if the player is aiming at them,
+/// e.g. for players with high latency or using controllers. Without arguments, it shows the current aim assist.
+pub fn execute_assist_commands(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "assist" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(command_event.client)
+        else {
+            continue;
+        };
+
+        let max_aim_assist = configs.max_aim_assist();
+        let result: Result<Text> = if args.trim().is_empty() {
+            Ok("Aim assist: ".color(Color::YELLOW)
+                + format!(
+                    "x{}",
+                    effective_aim_assist(settings.aim_assist, max_aim_assist)
+                )
+                .color(Color::GREEN)
+                + format!(" (up to x{} in this server)", max_aim_assist).color(Color::GRAY))
+        } else {
+            parse_aim_assist(args, max_aim_assist).map(|aim_assist| {
+                settings.aim_assist = aim_assist;
+                save_hud_settings(&osu, player_name, &settings);
+
+                "Aim assist set to ".color(Color::YELLOW)
+                    + format!("x{}", aim_assist).color(Color::GREEN)
+                    + " (it's recorded with your scores)".color(Color::GRAY)
+            })
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aim_assist_is_capped() {
+        assert_eq!(parse_aim_assist("1.2", 1.5).unwrap(), 1.2);
+        assert_eq!(parse_aim_assist(" 1 ", 1.5).unwrap(), NO_AIM_ASSIST);
+        assert!(parse_aim_assist("2", 1.5).is_err());
+        assert!(parse_aim_assist("0.5", 1.5).is_err());
+        assert!(parse_aim_assist("big", 1.5).is_err());
+        assert!(parse_aim_assist("NaN", 1.5).is_err());
+
+        assert_eq!(effective_aim_assist(1.4, 1.2), 1.2);
+        assert_eq!(effective_aim_assist(1.1, 1.2), 1.1);
+    }
+}
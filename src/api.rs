@@ -0,0 +1,487 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+use tungstenite::Message;
+
+use bevy_ecs::system::{Local, Query, Res, ResMut, Resource};
+use valence::prelude::Client;
+
+use crate::{
+    beatmap::Beatmap,
+    mods::Mods,
+    osu::{Osu, OsuState},
+    song_selection::SongSelectionInventory,
+};
+
+/// Finished and failed beatmaps kept in `recent_results`
+const MAX_RECENT_RESULTS: usize = 10;
+/// Ticks between two snapshots (4 ticks = 5 updates per second)
+const SNAPSHOT_TICKS: usize = 4;
+/// How often WebSocket connections check for a new snapshot and for messages of the client
+const WEBSOCKET_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// WebSocket clients are pinged after this time without any message, and disconnected after twice this time
+const WEBSOCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Read and write timeout of the HTTP requests and the WebSocket handshakes
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections handled at the same time, the next ones are refused
+const MAX_CONNECTIONS: usize = 32;
+/// Size limit of the request line and headers of an HTTP request, larger requests are rejected
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+
+/// Game state served as JSON by the API
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct GameSnapshot {
+    players: Vec<String>,
+    state: &'static str,
+    beatmap: Option<BeatmapSnapshot>,
+    /// Score of the beatmap being played (or of the last one, in the score screen and the fail screen)
+    live: Option<LiveScore>,
+    recent_results: VecDeque<ResultSnapshot>,
+}
+
+/// What is being played, served by `/api/now-playing`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NowPlaying<'a> {
+    state: &'static str,
+    beatmap: Option<&'a BeatmapSnapshot>,
+    mods: String,
+    live: Option<&'a LiveScore>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BeatmapSnapshot {
+    artist: String,
+    title: String,
+    difficulty: String,
+    creator: String,
+    length_secs: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LiveScore {
+    score: usize,
+    combo: usize,
+    max_combo: usize,
+    accuracy: f32,
+    health: f64,
+    hits300: usize,
+    hits100: usize,
+    hits50: usize,
+    misses: usize,
+    play_time_ms: u128,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ResultSnapshot {
+    beatmap: BeatmapSnapshot,
+    score: usize,
+    accuracy: f32,
+    max_combo: usize,
+    grade: &'static str,
+    passed: bool,
+}
+
+impl From<&Beatmap> for BeatmapSnapshot {
+    fn from(beatmap: &Beatmap) -> Self {
+        Self {
+            artist: beatmap.data.artist.clone(),
+            title: beatmap.data.title.clone(),
+            difficulty: beatmap.data.difficulty_name.clone(),
+            creator: beatmap.data.creator.clone(),
+            length_secs: beatmap.data.length().as_secs(),
+        }
+    }
+}
+
+impl From<&Beatmap> for LiveScore {
+    fn from(beatmap: &Beatmap) -> Self {
+        let state = &beatmap.state;
+        Self {
+            score: state.score,
+            combo: state.combo,
+            max_combo: state.max_combo,
+            accuracy: state.accuracy(),
+            health: state.health,
+            hits300: state.hits300,
+            hits100: state.hits100,
+            hits50: state.hits50,
+            misses: state.misses,
+            play_time_ms: state.play_time.as_millis(),
+        }
+    }
+}
+
+impl GameSnapshot {
+    fn push_result(&mut self, beatmap: &Beatmap, passed: bool) {
+        if self.recent_results.len() == MAX_RECENT_RESULTS {
+            self.recent_results.pop_back();
+        }
+
+        self.recent_results.push_front(ResultSnapshot {
+            beatmap: beatmap.into(),
+            score: beatmap.state.score,
+            accuracy: beatmap.state.accuracy(),
+            max_combo: beatmap.state.max_combo,
+            grade: beatmap.state.grade().name(),
+            passed,
+        });
+    }
+}
+
+/// Embedded HTTP server exposing the game state for stream overlays and external scoreboards (enabled with `api_port` in `configs.json`,
+/// only reachable from the host unless `api_bind_address` is changed).
+/// `GET /state` returns the last snapshot and WebSocket connections receive every new snapshot. The read-only endpoints
+/// `GET /api/maps` (songs listed in the song selection), `GET /api/scores` (local leaderboards by beatmap) and `GET /api/now-playing`
+/// (beatmap, mods and live score) serve bots and overlays which only need a part of it.
+#[derive(Resource, Default)]
+pub struct Api {
+    snapshot: GameSnapshot,
+    maps: Vec<String>,
+    /// Documents served as JSON, shared with the connection threads (`None` if the API is disabled)
+    published: Option<Arc<RwLock<Published>>>,
+}
+
+/// JSON documents served by the API
+struct Published {
+    state: Arc<str>,
+    maps: Arc<str>,
+    scores: Arc<str>,
+    now_playing: Arc<str>,
+}
+
+impl Default for Published {
+    fn default() -> Self {
+        Self {
+            state: "{}".into(),
+            maps: "[]".into(),
+            scores: "{}".into(),
+            now_playing: "{}".into(),
+        }
+    }
+}
+
+impl Api {
+    pub fn start(bind_address: &str, port: Option<u16>) -> Self {
+        let Some(port) = port else {
+            return Self::default();
+        };
+
+        let published = Arc::new(RwLock::new(Published::default()));
+        let listener = match TcpListener::bind((bind_address, port)) {
+            Ok(listener) => listener,
+            Err(error) => {
+                error!(
+                    "Error while starting the API on {}:{}: {}",
+                    bind_address, port, error
+                );
+                return Self::default();
+            }
+        };
+        info!("API listening on {}:{}", bind_address, port);
+
+        let thread_published = published.clone();
+        let connections = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if connections.load(Ordering::Acquire) >= MAX_CONNECTIONS {
+                    warn!("API connection refused: too many connections");
+                    continue;
+                }
+                connections.fetch_add(1, Ordering::AcqRel);
+
+                let published = thread_published.clone();
+                let connections = connections.clone();
+                thread::spawn(move || {
+                    if let Err(error) = handle_connection(stream, &published) {
+                        warn!("Error while handling API connection: {}", error);
+                    }
+                    connections.fetch_sub(1, Ordering::AcqRel);
+                });
+            }
+        });
+
+        Self {
+            snapshot: GameSnapshot::default(),
+            maps: Vec::new(),
+            published: Some(published),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.published.is_some()
+    }
+
+    fn publish(&self, mods: &Mods) -> Result<()> {
+        if let Some(published) = &self.published {
+            let state = serde_json::to_string(&self.snapshot)?;
+            let now_playing = serde_json::to_string(&NowPlaying {
+                state: self.snapshot.state,
+                beatmap: self.snapshot.beatmap.as_ref(),
+                mods: mods.to_string(),
+                live: self.snapshot.live.as_ref(),
+            })?;
+            if let Ok(mut published) = published.write() {
+                published.state = state.into();
+                published.now_playing = now_playing.into();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the song list if it changed
+    fn publish_maps(&mut self, maps: Vec<String>) -> Result<()> {
+        if maps == self.maps {
+            return Ok(());
+        }
+        self.maps = maps;
+
+        if let Some(published) = &self.published {
+            let json = serde_json::to_string(&self.maps)?;
+            if let Ok(mut published) = published.write() {
+                published.maps = json.into();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn publish_scores(&self, osu: &Osu) -> Result<()> {
+        if let Some(published) = &self.published {
+            let json = serde_json::to_string(osu.local_scores())?;
+            if let Ok(mut published) = published.write() {
+                published.scores = json.into();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, published: &RwLock<Published>) -> Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    // Peek the request so the WebSocket handshake can still read it
+    let mut head = [0; 1024];
+    let len = stream.peek(&mut head)?;
+    let head = String::from_utf8_lossy(&head[..len]).to_ascii_lowercase();
+
+    if head.contains("upgrade: websocket") {
+        return stream_snapshots(stream, published);
+    }
+
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut line = String::new();
+    let mut headers_ended = false;
+    while reader.read_line(&mut line)? > 0 {
+        if line.trim_end().is_empty() {
+            headers_ended = true;
+            break;
+        }
+        line.clear();
+    }
+
+    let method = request_line.split_whitespace().next().unwrap_or("GET");
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    // Ignore the query string
+    let path = path.split('?').next().unwrap_or(path);
+    let document = |select: fn(&Published) -> &Arc<str>| {
+        published
+            .read()
+            .map(|published| select(&published).to_string())
+            .unwrap_or_default()
+    };
+    let (status, body) = match (method, path) {
+        _ if !headers_ended && reader.get_ref().limit() == 0 => (
+            "431 Request Header Fields Too Large",
+            r#"{"error": "the request is too large"}"#.to_string(),
+        ),
+        _ if !headers_ended => (
+            "400 Bad Request",
+            r#"{"error": "incomplete request"}"#.to_string(),
+        ),
+        ("GET", "/" | "/state") => ("200 OK", document(|published| &published.state)),
+        ("GET", "/api/maps") => ("200 OK", document(|published| &published.maps)),
+        ("GET", "/api/scores") => ("200 OK", document(|published| &published.scores)),
+        ("GET", "/api/now-playing") => ("200 OK", document(|published| &published.now_playing)),
+        ("GET", _) => ("404 Not Found", r#"{"error": "not found"}"#.to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            r#"{"error": "the API is read-only"}"#.to_string(),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+/// Sends every new snapshot to a WebSocket client until it closes the connection or stops answering
+fn stream_snapshots(stream: TcpStream, published: &RwLock<Published>) -> Result<()> {
+    let mut websocket = tungstenite::accept(stream)
+        .map_err(|error| anyhow!("WebSocket handshake failed: {}", error))?;
+    // Reading only waits for the next poll, so the new snapshots are still sent on time
+    websocket
+        .get_ref()
+        .set_read_timeout(Some(WEBSOCKET_POLL_INTERVAL))?;
+
+    let mut last_sent: Option<Arc<str>> = None;
+    let mut last_received = Instant::now();
+    let mut pinged = false;
+
+    loop {
+        match websocket.read_message() {
+            Ok(Message::Close(_)) => {
+                // Sends the close reply
+                let _ = websocket.write_pending();
+                return Ok(());
+            }
+            // Pings are answered by tungstenite on the next write
+            Ok(_) => {
+                last_received = Instant::now();
+                pinged = false;
+            }
+            Err(tungstenite::Error::Io(error))
+                if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            // The client disconnected
+            Err(_) => return Ok(()),
+        }
+
+        let idle_time = last_received.elapsed();
+        if idle_time >= 2 * WEBSOCKET_IDLE_TIMEOUT {
+            return Ok(());
+        }
+        if idle_time >= WEBSOCKET_IDLE_TIMEOUT && !pinged {
+            if websocket.write_message(Message::Ping(Vec::new())).is_err() {
+                return Ok(());
+            }
+            pinged = true;
+        }
+
+        let snapshot = published
+            .read()
+            .map(|published| published.state.clone())
+            .ok();
+        if snapshot.is_some() && snapshot != last_sent {
+            if let Some(json) = &snapshot {
+                if websocket
+                    .write_message(Message::Text(json.to_string()))
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+            last_sent = snapshot;
+        } else if websocket.write_pending().is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Tracks whether the result of the finished or failed beatmap was already added to the recent results
+#[derive(Default)]
+pub struct ApiTracker {
+    ticks: usize,
+    result_recorded: bool,
+    scores_published: bool,
+}
+
+/// Updates the snapshot served by the API
+pub fn update_api(
+    mut api: ResMut<Api>,
+    osu: Res<Osu>,
+    mods: Res<Mods>,
+    clients: Query<&Client>,
+    song_selections: Query<&SongSelectionInventory>,
+    mut tracker: Local<ApiTracker>,
+) {
+    if !api.is_enabled() {
+        return;
+    }
+
+    // The scores only change when a beatmap is finished
+    if !tracker.scores_published {
+        if let Err(error) = api.publish_scores(&osu) {
+            error!("Error while publishing the API scores: {}", error);
+        }
+        tracker.scores_published = true;
+    }
+
+    let result = osu
+        .finished_beatmap()
+        .map(|beatmap| (beatmap, true))
+        .or_else(|| osu.failed_beatmap().map(|beatmap| (beatmap, false)));
+    match result {
+        Some((beatmap, passed)) if !tracker.result_recorded => {
+            api.snapshot.push_result(beatmap, passed);
+            tracker.result_recorded = true;
+            tracker.scores_published = false;
+        }
+        Some(_) => (),
+        None => tracker.result_recorded = false,
+    }
+
+    tracker.ticks += 1;
+    if tracker.ticks < SNAPSHOT_TICKS {
+        return;
+    }
+    tracker.ticks = 0;
+
+    // The score is only live once the beatmap starts
+    let (state, beatmap, has_score) = match osu.state() {
+        None => ("starting", None, false),
+        Some(OsuState::SongSelection) => ("song_selection", None, false),
+        Some(OsuState::BeatmapSelection) => ("beatmap_selection", None, false),
+        Some(OsuState::PrePlaying { beatmap, .. }) => ("pre_playing", Some(beatmap), false),
+        Some(OsuState::Playing(beatmap)) => ("playing", Some(beatmap), true),
+        Some(OsuState::ScoreDisplay(beatmap)) => ("score_display", Some(beatmap), true),
+        Some(OsuState::Failed(beatmap)) => ("failed", Some(beatmap), true),
+    };
+
+    let snapshot = &mut api.snapshot;
+    snapshot.players = clients
+        .iter()
+        .map(|client| client.username().to_string())
+        .collect();
+    snapshot.state = state;
+    snapshot.beatmap = beatmap.map(Into::into);
+    snapshot.live = beatmap.filter(|_| has_score).map(Into::into);
+
+    if let Err(error) = api.publish(&mods) {
+        error!("Error while publishing the API snapshot: {}", error);
+    }
+
+    if let Ok(song_selection) = song_selections.get_single() {
+        let maps = song_selection
+            .songs()
+            .iter()
+            .filter_map(|song| song.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        if let Err(error) = api.publish_maps(maps) {
+            error!("Error while publishing the API maps: {}", error);
+        }
+    }
+}
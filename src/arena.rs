@@ -0,0 +1,221 @@
+use tracing::{error, info};
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::{Or, With},
+    system::{Commands, Query, Res, ResMut},
+};
+use valence::{
+    prelude::{Client, Color, Instance, OpenInventory},
+    protocol::TextFormat,
+    Despawned,
+};
+
+use crate::{
+    beatmap_browser::BeatmapBrowserInventory,
+    collections::CollectionBrowserInventory,
+    combo::ComboMilestoneNumber,
+    command_registry::{CommandSpec, OsuCommand},
+    configs::Configs,
+    crosshair::Crosshair,
+    error::OsuError,
+    fail_screen::FailScreenInventory,
+    hit_score::HitScoreNumber,
+    hitcircle::Hitcircle,
+    lobby::Lobby,
+    map_vote::MapVoteInventory,
+    marathon::Marathon,
+    osu::{Osu, OsuInstance, OsuStateChange},
+    player_name::PlayerName,
+    playfield::PlayfieldSurface,
+    ring::{ParticleRing, Ring, RingPart},
+    score_screen::ScoreScreenInventory,
+};
+
+pub fn reset_arena_command() -> CommandSpec {
+    CommandSpec::new("reset-arena")
+}
+
+/// Handles `/reset-arena` (operators only): despawns the hit objects, screens and votes, redraws the playfield from scratch
+/// and sends everyone back to the song selection. It recovers from visual corruption without restarting the server.
+pub fn execute_reset_arena(
+    mut commands: Commands,
+    mut command_events: EventReader<OsuCommand>,
+    configs: Res<Configs>,
+    mut osu: ResMut<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut marathon: ResMut<Marathon>,
+    mut lobby: ResMut<Lobby>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    client_inventories: Query<Entity, (With<Client>, With<OpenInventory>)>,
+    arena_entities: Query<
+        Entity,
+        Or<(
+            With<Hitcircle>,
+            With<Ring>,
+            With<RingPart>,
+            With<HitScoreNumber>,
+            With<ComboMilestoneNumber>,
+            With<FailScreenInventory>,
+            With<ScoreScreenInventory>,
+            With<MapVoteInventory>,
+            With<CollectionBrowserInventory>,
+            With<BeatmapBrowserInventory>,
+        )>,
+    >,
+    mut instances: Query<&mut Instance, With<OsuInstance>>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "reset-arena" {
+            continue;
+        }
+
+        let username = clients
+            .get(command_event.client)
+            .map(|client| client.username().to_string())
+            .unwrap_or_default();
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if !is_operator {
+            if let Ok(mut client) = clients.get_mut(command_event.client) {
+                client.send_message(
+                    OsuError::OperatorOnly {
+                        action: "reset the arena",
+                    }
+                    .message(),
+                );
+            }
+            continue;
+        }
+
+        for client in &client_inventories {
+            commands.entity(client).remove::<OpenInventory>();
+        }
+        let mut despawned = 0;
+        for entity in &arena_entities {
+            commands.entity(entity).insert(Despawned);
+            despawned += 1;
+        }
+
+        redraw_playfield(&osu, &mut surface, &mut instances);
+
+        if marathon.is_running() {
+            marathon.stop();
+        }
+        lobby.reset_round();
+
+        if let Err(error) = osu.change_state(OsuStateChange::SongSelection, &mut clients) {
+            error!(
+                "Error while changing to Song Selection state while resetting the arena: '{}'",
+                error
+            );
+        }
+
+        let spawn_pos = osu.playfield().player_spawn_pos();
+        let message = "The arena was reset by ".color(Color::YELLOW)
+            + username.clone().color(Color::AQUA)
+            + format!(" ({} entities despawned)", despawned).color(Color::GRAY);
+        for mut client in &mut clients {
+            client.set_position(spawn_pos);
+            client.send_message(message.clone());
+        }
+
+        info!("Arena reset by '{}'", username);
+    }
+}
+
+pub fn reset_playfield_command() -> CommandSpec {
+    CommandSpec::new("reset-playfield")
+}
+
+/// Handles `/reset-playfield` (operators only): a lighter `/reset-arena` which only cleans the screen, for leftovers of crashes
+/// or bugs like stuck rings and hit score numbers. The hit objects are despawned, the screen is redrawn black, the song is
+/// stopped and the state goes back to the song selection, but the lobby, marathon, open menus and the players are left alone.
+pub fn execute_reset_playfield(
+    mut commands: Commands,
+    mut command_events: EventReader<OsuCommand>,
+    configs: Res<Configs>,
+    mut osu: ResMut<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    playfield_entities: Query<
+        Entity,
+        Or<(
+            With<Hitcircle>,
+            With<Ring>,
+            With<RingPart>,
+            With<ParticleRing>,
+            With<HitScoreNumber>,
+            With<ComboMilestoneNumber>,
+            With<Crosshair>,
+        )>,
+    >,
+    mut instances: Query<&mut Instance, With<OsuInstance>>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "reset-playfield" {
+            continue;
+        }
+
+        let username = clients
+            .get(command_event.client)
+            .map(|client| client.username().to_string())
+            .unwrap_or_default();
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if !is_operator {
+            if let Ok(mut client) = clients.get_mut(command_event.client) {
+                client.send_message(
+                    OsuError::OperatorOnly {
+                        action: "reset the playfield",
+                    }
+                    .message(),
+                );
+            }
+            continue;
+        }
+
+        let mut despawned = 0;
+        for entity in &playfield_entities {
+            commands.entity(entity).insert(Despawned);
+            despawned += 1;
+        }
+
+        redraw_playfield(&osu, &mut surface, &mut instances);
+
+        // Stops the song of the interrupted play
+        if let Err(error) = osu.change_state(OsuStateChange::SongSelection, &mut clients) {
+            error!(
+                "Error while changing to Song Selection state while resetting the playfield: '{}'",
+                error
+            );
+        }
+
+        let message = "The playfield was reset by ".color(Color::YELLOW)
+            + username.clone().color(Color::AQUA)
+            + format!(" ({} entities despawned)", despawned).color(Color::GRAY);
+        for mut client in &mut clients {
+            client.send_message(message.clone());
+        }
+
+        info!("Playfield reset by '{}'", username);
+    }
+}
+
+/// Clears every block in front of the screen and draws it black again
+fn redraw_playfield(
+    osu: &Osu,
+    surface: &mut PlayfieldSurface,
+    instances: &mut Query<&mut Instance, With<OsuInstance>>,
+) {
+    // Blocks are drawn again from scratch, so the surface can't skip any of them
+    *surface = PlayfieldSurface::default();
+    match instances.get_single_mut() {
+        Ok(mut instance) => osu.playfield().reset(&mut instance),
+        Err(_) => error!("Could not find the OsuInstance to reset the playfield"),
+    }
+}
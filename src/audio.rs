@@ -1,20 +1,94 @@
 use anyhow::Result;
-use rodio::{Decoder, OutputStreamHandle, Sink, Source};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
 use std::{
     cmp::max,
-    fs::File,
-    io::{BufReader, Read, Seek},
-    path::Path,
+    fs::{self, File},
+    io::{BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
+    thread,
     time::Duration,
 };
+use tracing::warn;
 
-pub struct AudioPlayer {
-    sink: Sink,
+use crate::{
+    audio_clock::{AudioClock, MonotonicClock},
+    volume::Volume,
+};
+
+/// Effects which can play at the same time (the oldest one is cut when they are all busy)
+const EFFECT_SINKS: usize = 8;
+/// Extensions of the sound files, by order of preference
+const SAMPLE_EXTENSIONS: [&str; 3] = ["wav", "ogg", "mp3"];
+
+/// Length of the music, measured in the background
+type MusicLength = Arc<Mutex<Option<Duration>>>;
+
+/// Plays the music of the beatmaps and the sound effects
+pub trait AudioPlayer: Send + Sync {
+    /// Clock of the music, which drives the gameplay timing
+    fn clock(&self) -> &dyn AudioClock;
+
+    fn set_volume(&self, volume: Volume);
+
+    /// Plays `sample` on top of the music and the other effects
+    fn play_effect(&self, sample: &EffectSample) -> Result<()>;
+
+    fn set_music(&mut self, path: &Path) -> Result<()>;
+
+    fn play(&self);
+
+    fn pause(&self);
+
+    /// Stops the music (the effects keep playing)
+    fn stop(&self);
+
+    fn is_paused(&self) -> bool;
+
+    fn has_finished(&self) -> bool;
+}
+
+/// Plays the music and mixes the sound effects (hitsounds, fail sound, applause...) on top of it, without interrupting it
+pub struct RodioAudioBackend {
+    music: Sink,
     execution: Option<DecoderExecution>,
+    effects: Vec<Sink>,
+    /// Effect sink reused when none of them is idle
+    next_effect: AtomicUsize,
+}
+
+/// Plays nothing, for servers without an audio output device (e.g. headless servers).
+/// The play time of the music is driven by the wall clock instead of the decoded samples.
+#[derive(Default)]
+pub struct NullAudioBackend {
+    clock: MonotonicClock,
+    /// `None` if no music is set. The length is unknown for a moment, until it's measured.
+    music_length: Mutex<Option<MusicLength>>,
+}
+
+/// Short sound (e.g. a hitsound) kept in memory, so it can be decoded every time it's played without reading the file again
+#[derive(Clone)]
+pub struct EffectSample(Arc<[u8]>);
+
+impl EffectSample {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        // Fail early if the format is not supported
+        Decoder::new(Cursor::new(bytes.clone()))?;
+
+        Ok(Self(bytes.into()))
+    }
+}
+
+/// Path of the sound `name` in `dir` (e.g. `normal-hitnormal` finds `normal-hitnormal.wav`)
+pub fn find_sample(dir: &Path, name: &str) -> Option<PathBuf> {
+    SAMPLE_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.exists())
 }
 
 struct CustomDecoder<R: Read + Seek> {
@@ -22,67 +96,205 @@ struct CustomDecoder<R: Read + Seek> {
     samples_played: u32,
     shared_update_rate: u32,
     shared_samples_played: Arc<AtomicU32>,
+    shared_skip_to_sample: Arc<AtomicU32>,
 }
 
 struct DecoderExecution {
     samples_played: Arc<AtomicU32>,
+    skip_to_sample: Arc<AtomicU32>,
     sample_rate: u32,
     channels: u16,
 }
 
-impl AudioPlayer {
+impl RodioAudioBackend {
     pub fn new(stream_handle: &OutputStreamHandle) -> Result<Self> {
-        let sink = Sink::try_new(stream_handle)?;
-        sink.set_volume(0.25);
+        let music = Sink::try_new(stream_handle)?;
+        let effects = (0..EFFECT_SINKS)
+            .map(|_| Sink::try_new(stream_handle))
+            .collect::<Result<_, _>>()?;
 
-        Ok(Self {
-            sink,
+        let audio_player = Self {
+            music,
             execution: None,
-        })
+            effects,
+            next_effect: AtomicUsize::new(0),
+        };
+        audio_player.set_volume(Volume::default());
+
+        Ok(audio_player)
+    }
+}
+
+impl AudioClock for RodioAudioBackend {
+    fn play_time(&self) -> Duration {
+        if let Some(execution) = self.execution.as_ref() {
+            execution.play_time()
+        } else {
+            Duration::default()
+        }
+    }
+
+    fn seek(&self, time: Duration) {
+        if let Some(execution) = self.execution.as_ref() {
+            execution.skip_to(time);
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.music.speed() as f64
+    }
+}
+
+impl AudioPlayer for RodioAudioBackend {
+    fn clock(&self) -> &dyn AudioClock {
+        self
+    }
+
+    fn set_volume(&self, volume: Volume) {
+        self.music.set_volume(volume.music_gain());
+        for effect in &self.effects {
+            effect.set_volume(volume.effects_gain());
+        }
+    }
+
+    fn play_effect(&self, sample: &EffectSample) -> Result<()> {
+        let decoder = Decoder::new(Cursor::new(sample.0.clone()))?;
+        let sink = match self.effects.iter().find(|sink| sink.empty()) {
+            Some(sink) => sink,
+            None => {
+                let idx = self.next_effect.fetch_add(1, Ordering::Relaxed) % self.effects.len();
+                self.effects[idx].stop();
+                &self.effects[idx]
+            }
+        };
+
+        sink.append(decoder);
+        sink.play();
+
+        Ok(())
     }
 
-    pub fn set_music(&mut self, path: impl AsRef<Path>) -> Result<()> {
+    fn set_music(&mut self, path: &Path) -> Result<()> {
         let file = BufReader::new(File::open(path)?);
         let decoder = Decoder::new(file)?;
         let (decoder, execution) = CustomDecoder::new(decoder)?;
 
-        self.sink.stop();
-        self.sink.append(decoder);
+        self.music.stop();
+        self.music.append(decoder);
         self.execution = Some(execution);
 
         Ok(())
     }
 
-    pub fn play_time(&self) -> Duration {
-        if let Some(execution) = self.execution.as_ref() {
-            execution.play_time()
-        } else {
-            Duration::default()
-        }
+    fn play(&self) {
+        self.music.play()
+    }
+
+    fn pause(&self) {
+        self.music.pause()
+    }
+
+    fn stop(&self) {
+        self.music.stop()
+    }
+
+    fn is_paused(&self) -> bool {
+        self.music.is_paused()
+    }
+
+    fn has_finished(&self) -> bool {
+        self.music.empty()
+    }
+}
+
+impl AudioPlayer for NullAudioBackend {
+    fn clock(&self) -> &dyn AudioClock {
+        &self.clock
+    }
+
+    fn set_volume(&self, _volume: Volume) {}
+
+    fn play_effect(&self, _sample: &EffectSample) -> Result<()> {
+        Ok(())
     }
 
-    pub fn play(&self) {
-        self.sink.play()
+    fn set_music(&mut self, path: &Path) -> Result<()> {
+        // Fail early if the format is not supported, like the rodio backend
+        let decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+
+        let length = Arc::new(Mutex::new(None));
+        let measured_length = length.clone();
+        thread::spawn(move || {
+            let samples_per_sec = decoder.sample_rate() as f64 * decoder.channels() as f64;
+            let music_length = match decoder.total_duration() {
+                Some(music_length) => music_length,
+                None => Duration::from_secs_f64(decoder.count() as f64 / samples_per_sec),
+            };
+            *measured_length.lock().unwrap() = Some(music_length);
+        });
+
+        *self.music_length.get_mut().unwrap() = Some(length);
+        self.clock.reset();
+
+        Ok(())
+    }
+
+    fn play(&self) {
+        self.clock.resume();
     }
 
-    pub fn pause(&self) {
-        self.sink.pause()
+    fn pause(&self) {
+        self.clock.pause();
     }
 
-    pub fn stop(&self) {
-        self.sink.stop()
+    fn stop(&self) {
+        self.clock.reset();
+        *self.music_length.lock().unwrap() = None;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.music_length.lock().unwrap().is_some() && !self.clock.is_running()
+    }
+
+    fn has_finished(&self) -> bool {
+        match &*self.music_length.lock().unwrap() {
+            Some(length) => match *length.lock().unwrap() {
+                Some(length) => self.clock.play_time() >= length,
+                None => false,
+            },
+            None => true,
+        }
     }
+}
 
-    pub fn is_paused(&self) -> bool {
-        self.sink.is_paused()
+/// Plays the audio on the default output device, or nothing if it's disabled or there is no device
+pub fn open_audio_player(disabled: bool) -> (Option<OutputStream>, Box<dyn AudioPlayer>) {
+    if disabled {
+        return (None, Box::new(NullAudioBackend::default()));
     }
 
-    pub fn has_finished(&self) -> bool {
-        self.sink.empty()
+    let opened = OutputStream::try_default()
+        .map_err(anyhow::Error::from)
+        .and_then(|(stream, stream_handle)| Ok((stream, RodioAudioBackend::new(&stream_handle)?)));
+    match opened {
+        Ok((stream, audio_player)) => (Some(stream), Box::new(audio_player)),
+        Err(error) => {
+            warn!(
+                "No audio output device ({}), the server will run without audio",
+                error
+            );
+            (None, Box::new(NullAudioBackend::default()))
+        }
     }
 }
 
 impl DecoderExecution {
+    fn skip_to(&self, time: Duration) {
+        let sample =
+            time.as_micros() as u64 * self.sample_rate as u64 * self.channels as u64 / 1_000_000;
+        self.skip_to_sample.store(sample as u32, Ordering::Relaxed);
+    }
+
     fn play_time(&self) -> Duration {
         Duration::from_micros(
             (self.samples_played.load(Ordering::Relaxed) as u64 * 1_000_000)
@@ -94,10 +306,12 @@ impl DecoderExecution {
 impl<R: Read + Seek> CustomDecoder<R> {
     fn new(decoder: Decoder<R>) -> Result<(Self, DecoderExecution)> {
         let shared_samples_played = Arc::new(AtomicU32::new(0));
+        let shared_skip_to_sample = Arc::new(AtomicU32::new(0));
 
         let execution = DecoderExecution {
             sample_rate: decoder.sample_rate(),
             samples_played: shared_samples_played.clone(),
+            skip_to_sample: shared_skip_to_sample.clone(),
             channels: decoder.channels(),
         };
 
@@ -108,6 +322,7 @@ impl<R: Read + Seek> CustomDecoder<R> {
                 decoder,
                 samples_played: 0,
                 shared_samples_played,
+                shared_skip_to_sample,
                 shared_update_rate: shared_threshold,
             },
             execution,
@@ -119,6 +334,19 @@ impl<R: Read + Seek> Iterator for CustomDecoder<R> {
     type Item = i16;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // Discard samples until reaching the requested skip position
+        let skip_to_sample = self.shared_skip_to_sample.swap(0, Ordering::Relaxed);
+        if skip_to_sample > self.samples_played {
+            // Keep channels aligned
+            let skip_to_sample = skip_to_sample - skip_to_sample % self.decoder.channels() as u32;
+            while self.samples_played < skip_to_sample {
+                self.decoder.next()?;
+                self.samples_played += 1;
+            }
+            self.shared_samples_played
+                .store(self.samples_played, Ordering::Relaxed);
+        }
+
         self.samples_played += 1;
         if self.samples_played % self.shared_update_rate == 0 {
             self.shared_samples_played
@@ -146,3 +374,19 @@ impl<R: Read + Seek> Source for CustomDecoder<R> {
         self.decoder.total_duration()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn null_audio_without_music() {
+        let audio = NullAudioBackend::default();
+        assert!(audio.has_finished());
+        assert!(!audio.is_paused());
+
+        audio.play();
+        audio.stop();
+        assert_eq!(audio.clock().play_time(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Time of the music being played, which drives the gameplay timing (hit objects, hit judgement and the end of the map)
+pub trait AudioClock: Send + Sync {
+    fn play_time(&self) -> Duration;
+
+    /// Moves the music to `time`. Clocks following decoded audio can only skip forward, earlier times are ignored.
+    fn seek(&self, time: Duration);
+
+    /// Speed of the music
+    fn rate(&self) -> f64;
+}
+
+/// Clock following the wall clock, for servers which don't decode the audio (see `NullAudioBackend`) and for tests
+#[derive(Default)]
+pub struct MonotonicClock {
+    state: Mutex<ClockState>,
+}
+
+struct ClockState {
+    /// Music time played before the clock was last resumed
+    played: Duration,
+    /// When the clock was last resumed, `None` while paused
+    resumed_at: Option<Instant>,
+    rate: f64,
+}
+
+impl Default for ClockState {
+    fn default() -> Self {
+        Self {
+            played: Duration::ZERO,
+            resumed_at: None,
+            rate: 1.0,
+        }
+    }
+}
+
+impl ClockState {
+    fn play_time(&self) -> Duration {
+        self.played
+            + self
+                .resumed_at
+                .map(|resumed_at| resumed_at.elapsed().mul_f64(self.rate))
+                .unwrap_or_default()
+    }
+}
+
+impl MonotonicClock {
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.resumed_at.is_none() {
+            state.resumed_at = Some(Instant::now());
+        }
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.played = state.play_time();
+        state.resumed_at = None;
+    }
+
+    /// Goes back to the start and pauses, keeping the rate
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.played = Duration::ZERO;
+        state.resumed_at = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.lock().unwrap().resumed_at.is_some()
+    }
+
+    pub fn set_rate(&self, rate: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.played = state.play_time();
+        if state.resumed_at.is_some() {
+            state.resumed_at = Some(Instant::now());
+        }
+        state.rate = rate;
+    }
+}
+
+impl AudioClock for MonotonicClock {
+    fn play_time(&self) -> Duration {
+        self.state.lock().unwrap().play_time()
+    }
+
+    fn seek(&self, time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.played = time;
+        if state.resumed_at.is_some() {
+            state.resumed_at = Some(Instant::now());
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        self.state.lock().unwrap().rate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn monotonic_clock() {
+        let clock = MonotonicClock::default();
+        clock.seek(Duration::from_secs(5));
+        assert_eq!(clock.play_time(), Duration::from_secs(5));
+
+        clock.resume();
+        thread::sleep(Duration::from_millis(10));
+        clock.pause();
+        let play_time = clock.play_time();
+        assert!(play_time >= Duration::from_millis(5010));
+
+        // Paused clocks don't advance
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.play_time(), play_time);
+
+        clock.set_rate(2.0);
+        clock.seek(Duration::ZERO);
+        clock.resume();
+        thread::sleep(Duration::from_millis(10));
+        assert!(clock.play_time() >= Duration::from_millis(20));
+
+        clock.reset();
+        assert!(!clock.is_running());
+        assert_eq!(clock.play_time(), Duration::ZERO);
+        assert_eq!(clock.rate(), 2.0);
+    }
+}
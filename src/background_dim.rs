@@ -0,0 +1,185 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::With,
+    system::{Local, Query, Res},
+};
+use valence::{
+    prelude::{BlockPos, BlockState, Client, Color, Instance},
+    protocol::{packets::s2c::play::BlockUpdate, Text, TextFormat, VarInt},
+};
+
+use crate::{
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    configs::Configs,
+    error::error_message,
+    hud::{save_hud_settings, HudSettings},
+    osu::{Osu, OsuInstance},
+    player_name::PlayerName,
+    playfield::PlayfieldSurface,
+};
+
+/// Blocks of the screen background from the lowest dim to the highest one
+const DIM_BLOCKS: [BlockState; 4] = [
+    BlockState::LIGHT_GRAY_CONCRETE,
+    BlockState::GRAY_CONCRETE,
+    BlockState::BLACK_WOOL,
+    BlockState::BLACK_CONCRETE,
+];
+
+/// Block of the screen background seen with `dim` (from 0 to 100)
+pub fn dimmed_background(dim: u8) -> BlockState {
+    let level = dim.min(100) as usize * DIM_BLOCKS.len() / 101;
+    DIM_BLOCKS[level]
+}
+
+/// Parses the dim of `/dim <0-100>` (`off` shows the background of the skin)
+pub fn parse_background_dim(args: &str) -> Result<Option<u8>> {
+    let args = args.trim();
+    if args == "off" {
+        return Ok(None);
+    }
+
+    args.trim_end_matches('%')
+        .parse()
+        .ok()
+        .filter(|&dim| dim <= 100)
+        .map(Some)
+        .ok_or_else(|| anyhow!("the dim must be between 0 and 100 (e.g. /dim 80), or off"))
+}
+
+pub fn dim_command() -> CommandSpec {
+    CommandSpec::new("dim").arg(ArgSpec::word("dim"))
+}
+
+/// Handles `/dim [0-100|off]`, which changes the screen background seen by the player, e.g. to make the circles stand out more
+/// from it. Without arguments, it shows the current dim.
+pub fn execute_dim_commands(
+    osu: Res<Osu>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "dim" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(command_event.client)
+        else {
+            continue;
+        };
+
+        let result: Result<Text> = if args.trim().is_empty() {
+            Ok("Background dim: ".color(Color::YELLOW) + dim_text(settings.background_dim))
+        } else {
+            parse_background_dim(args).map(|dim| {
+                settings.background_dim = dim;
+                save_hud_settings(&osu, player_name, &settings);
+
+                "Background dim set to ".color(Color::YELLOW) + dim_text(dim)
+            })
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+fn dim_text(dim: Option<u8>) -> Text {
+    match dim {
+        Some(dim) => format!("{}%", dim).color(Color::GREEN),
+        None => "off".color(Color::GREEN) + " (background of the skin)".color(Color::GRAY),
+    }
+}
+
+/// Screen background sent to a player: the block drawn instead of the background (`None` for the blocks of the instance) and
+/// the positions sent so far
+struct DimmedScreen {
+    block: Option<BlockState>,
+    sent: usize,
+}
+
+/// Redraws the screen background of the players with a background dim (see `HudSettings::background_dim`). It's only sent to
+/// them, the blocks of the instance are not changed. Redraws are spread over several ticks like the ones of the
+/// `PlayfieldSurface`, and they wait for the redraws of the server (e.g. the fail screen, which shows its own background).
+pub fn update_background_dim(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    surface: Res<PlayfieldSurface>,
+    instances: Query<&Instance, With<OsuInstance>>,
+    mut clients: Query<(Entity, &mut Client, &HudSettings)>,
+    mut screens: Local<HashMap<Entity, DimmedScreen>>,
+) {
+    screens.retain(|&player, _| clients.contains(player));
+    if !surface.is_flushed() {
+        return;
+    }
+    let Ok(instance) = instances.get_single() else {
+        return;
+    };
+
+    let playfield = osu.playfield();
+    let positions: Vec<BlockPos> = playfield.background_positions().collect();
+    let Some(&sample) = positions.first() else {
+        return;
+    };
+    // The server only shows its own background while the screen isn't repainted
+    let showing_background =
+        surface.block(sample).unwrap_or(playfield.background()) == playfield.background();
+    let max_updates = configs.max_screen_block_updates().unwrap_or(usize::MAX);
+
+    for (player, mut client, settings) in &mut clients {
+        let block = settings
+            .background_dim
+            .map(dimmed_background)
+            .filter(|&block| showing_background && block != playfield.background());
+        // Players are seen with the blocks of the instance when they join, and dimmed from the next tick on
+        let Some(screen) = screens.get_mut(&player) else {
+            screens.insert(
+                player,
+                DimmedScreen {
+                    block: None,
+                    sent: positions.len(),
+                },
+            );
+            continue;
+        };
+        if screen.block != block {
+            *screen = DimmedScreen { block, sent: 0 };
+        }
+
+        for &position in positions.iter().skip(screen.sent).take(max_updates) {
+            let block = screen.block.unwrap_or_else(|| {
+                instance
+                    .block(position)
+                    .map_or(BlockState::AIR, |block| block.state())
+            });
+            client.write_packet(&BlockUpdate {
+                position,
+                block_id: VarInt(block.to_raw() as i32),
+            });
+        }
+        screen.sent = positions.len().min(screen.sent.saturating_add(max_updates));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn background_dim() {
+        assert_eq!(dimmed_background(0), BlockState::LIGHT_GRAY_CONCRETE);
+        assert_eq!(dimmed_background(40), BlockState::GRAY_CONCRETE);
+        assert_eq!(dimmed_background(100), BlockState::BLACK_CONCRETE);
+
+        assert_eq!(parse_background_dim(" 80% ").unwrap(), Some(80));
+        assert_eq!(parse_background_dim("off").unwrap(), None);
+        assert!(parse_background_dim("101").is_err());
+        assert!(parse_background_dim("dark").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use bevy_ecs::{
+    query::With,
+    system::{Local, Query, Res},
+};
+use valence::prelude::McEntity;
+
+use crate::{
+    configs::Configs,
+    hitcircle::Hitcircle,
+    osu::Osu,
+    ring::{Ring, RingPart},
+    timing::beat_at,
+};
+
+/// Blocks the circle rings are pushed outwards on each beat
+const PULSE_OFFSET: f64 = 0.5;
+const PULSE_TICKS: usize = 2;
+
+/// Pulses the circle ring of the hitcircles on each beat of the song (see `Skin::beat_pulse`)
+pub fn update_beat_pulse(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    hitcircles: Query<&Hitcircle>,
+    mut rings: Query<&mut Ring>,
+    mut ring_entities: Query<&mut McEntity, With<RingPart>>,
+    mut last_beat: Local<Option<(usize, u32)>>,
+    mut pulse_ticks_left: Local<usize>,
+) {
+    let beat = osu
+        .playing_beatmap()
+        .filter(|_| configs.skin().beat_pulse)
+        .and_then(|beatmap| {
+            beat_at(
+                &beatmap.data.beat_timings,
+                beatmap.state.play_time.as_millis() as i32,
+            )
+        });
+
+    let is_new_beat = beat.is_some() && beat != *last_beat;
+    *last_beat = beat;
+
+    let offset = if is_new_beat {
+        *pulse_ticks_left = PULSE_TICKS;
+        PULSE_OFFSET
+    } else if *pulse_ticks_left > 0 {
+        *pulse_ticks_left -= 1;
+        if *pulse_ticks_left > 0 {
+            return;
+        }
+        0.0
+    } else {
+        return;
+    };
+
+    for hitcircle in &hitcircles {
+        if let Ok(mut ring) = rings.get_mut(hitcircle.circle_ring()) {
+            ring.pulse(offset, &mut ring_entities);
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use anyhow::{anyhow, Context, Result};
-use osu_file_parser::{Decimal, OsuFile};
-use std::{collections::VecDeque, num::ParseFloatError, path::PathBuf, time::Duration};
+use anyhow::{Context, Result};
+use osu_file_parser::{general::Mode, Decimal, OsuFile};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::VecDeque,
+    fs,
+    num::ParseFloatError,
+    path::{Path, PathBuf},
+    str,
+    time::Duration,
+};
 use valence::{
     prelude::Color,
     protocol::{Text, TextFormat},
@@ -8,7 +16,26 @@ use valence::{
 
 use bevy_ecs::prelude::Entity;
 
-use crate::{hit_object::HitObject, hit_score::HitScore, minecraft::to_ticks};
+use crate::{
+    adaptive::AdaptiveDifficulty,
+    aim_assist::NO_AIM_ASSIST,
+    error::OsuError,
+    game_mode::GameModeHit,
+    hit_object::{HitObject, HitObjectParams, DEFAULT_STACK_LENIENCY},
+    hit_score::HitScore,
+    minecraft::to_ticks,
+    mods::Mods,
+    osu_file::parse_osu_file,
+    preprocess::Preprocessor,
+    star_rating::{performance_points, star_rating},
+    timing::{total_break_duration, BeatTiming, BreakPeriod},
+};
+
+const HIT_ERROR_HISTORY_SIZE: usize = 10;
+/// Time left before the first hit object appears after skipping the intro
+const INTRO_SKIP_LEAD: Duration = Duration::from_secs(2);
+/// Minimum time which has to be saved to allow skipping the intro
+const MIN_INTRO_SKIP: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub struct Beatmap {
@@ -23,10 +50,26 @@ pub struct BeatmapData {
     pub cs: CircleSize,
     pub hp: HpDrainRate,
     pub hit_objects: Vec<HitObject>,
+    pub breaks: Vec<BreakPeriod>,
+    pub beat_timings: Vec<BeatTiming>,
     pub audio_path: PathBuf,
     pub artist: String,
     pub title: String,
     pub difficulty_name: String,
+    pub creator: String,
+    /// Empty if the beatmap has no source
+    pub source: String,
+    /// Song directory of the beatmap
+    pub dir: PathBuf,
+    /// IDs of the beatmap and its set in the osu! website, `None` for unsubmitted beatmaps
+    pub beatmap_id: Option<i32>,
+    pub beatmap_set_id: Option<i32>,
+    /// SHA-256 of the .osu file, which identifies the beatmap on every server (see `ScoreSubmission`). Empty unless the
+    /// beatmap was loaded with `Beatmap::read`.
+    pub file_hash: String,
+    /// Mods the beatmap was loaded with (see `Preprocessor::with_mods`). The difficulty settings above are the ones of the
+    /// .osu file, use `Beatmap::ar`, `Beatmap::cs` and `Beatmap::od` for the ones the beatmap is played with.
+    pub mods: Mods,
 }
 
 #[derive(Clone, Debug)]
@@ -42,6 +85,13 @@ pub struct BeatmapState {
     pub combo: usize,
     pub max_combo: usize,
     pub health: f64,
+    pub last_hit: Option<HitScore>,
+    /// Timing error in milliseconds of the last hits (negative if early, positive if late)
+    pub hit_errors: VecDeque<i32>,
+    /// Set when playing in the adaptive mode (see `AdaptiveDifficulty`)
+    pub adaptive: Option<AdaptiveDifficulty>,
+    /// Highest aim assist used in the hits of the play (see `HudSettings::aim_assist`), recorded with the score
+    pub aim_assist: f64,
 }
 
 pub enum Grade {
@@ -79,6 +129,10 @@ impl Default for BeatmapState {
             score: 0,
             combo: 0,
             max_combo: 0,
+            last_hit: None,
+            hit_errors: Default::default(),
+            adaptive: None,
+            aim_assist: NO_AIM_ASSIST,
         }
     }
 }
@@ -96,6 +150,17 @@ impl BeatmapState {
             / divisor as f32
     }
 
+    pub fn push_hit_error(&mut self, hit_error: i32) {
+        if self.hit_errors.len() == HIT_ERROR_HISTORY_SIZE {
+            self.hit_errors.pop_front();
+        }
+        self.hit_errors.push_back(hit_error);
+    }
+
+    pub fn is_full_combo(&self) -> bool {
+        self.misses == 0 && self.hits300 + self.hits100 + self.hits50 > 0
+    }
+
     /// https://osu.ppy.sh/wiki/en/Gameplay/Grade
     pub fn grade(&self) -> Grade {
         let accuracy = self.accuracy();
@@ -120,6 +185,19 @@ impl BeatmapState {
     }
 }
 
+impl Grade {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grade::SS => "SS",
+            Grade::S => "S",
+            Grade::A => "A",
+            Grade::B => "B",
+            Grade::C => "C",
+            Grade::D => "D",
+        }
+    }
+}
+
 impl BeatmapData {
     /// https://osu.ppy.sh/wiki/en/Gameplay/Score/ScoreV1/osu%21#difficulty-multiplier
     pub fn difficulty_multiplier(&self) -> f64 {
@@ -132,28 +210,167 @@ impl BeatmapData {
             .round()
     }
 
+    /// Time of the end of the last hit object
+    pub fn length(&self) -> Duration {
+        let end_time = self
+            .hit_objects
+            .last()
+            .map(|hit_object| hit_object.end_time())
+            .unwrap_or(0);
+
+        Duration::from_millis(end_time as u64)
+    }
+
     /// Drain time without breaks
     pub fn drain_time(&self) -> Duration {
-        if self.hit_objects.is_empty() {
-            Duration::ZERO
-        } else {
-            Duration::from_millis(
-                (self.hit_objects.last().unwrap().time() - self.hit_objects.first().unwrap().time())
-                    as u64,
-            )
+        drain_time(&self.hit_objects, &self.breaks)
+    }
+
+    /// Page of the beatmap in the osu! website, if it was submitted
+    pub fn website_url(&self) -> Option<String> {
+        match (self.beatmap_set_id, self.beatmap_id) {
+            (Some(set_id), Some(id)) => Some(format!(
+                "https://osu.ppy.sh/beatmapsets/{}#osu/{}",
+                set_id, id
+            )),
+            (Some(set_id), None) => Some(format!("https://osu.ppy.sh/beatmapsets/{}", set_id)),
+            (None, Some(id)) => Some(format!("https://osu.ppy.sh/b/{}", id)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Time between the first and the last hit object without breaks
+fn drain_time(hit_objects: &[HitObject], breaks: &[BreakPeriod]) -> Duration {
+    let (Some(first), Some(last_end_time)) = (
+        hit_objects.first(),
+        hit_objects
+            .iter()
+            .map(|hit_object| hit_object.end_time())
+            .max(),
+    ) else {
+        return Duration::ZERO;
+    };
+
+    let start_time = first.time();
+    let end_time = last_end_time.max(start_time);
+
+    let play_time = Duration::from_millis((end_time - start_time) as u64);
+    let break_time = total_break_duration(breaks, start_time, end_time);
+
+    play_time.saturating_sub(break_time)
+}
+
+/// Beatmap info shown in the beatmap selection
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeatmapStats {
+    pub drain_time: Duration,
+    /// Min and max BPM
+    pub bpm: Option<(f64, f64)>,
+    pub circles: usize,
+    pub sliders: usize,
+    pub spinners: usize,
+    /// See `star_rating::star_rating`
+    pub star_rating: f64,
+}
+
+impl BeatmapStats {
+    pub fn from(osu_file: &OsuFile) -> Result<Self> {
+        let hit_objects = HitObject::from(osu_file)?;
+        let breaks = BreakPeriod::from(osu_file)?;
+        let beat_timings = BeatTiming::from(osu_file)?;
+
+        Ok(Self::new(
+            &hit_objects,
+            &breaks,
+            &beat_timings,
+            circle_size(osu_file)?,
+        ))
+    }
+
+    /// Star rating of the beatmap played with `mods` (the hit objects are parsed again)
+    pub fn modded_star_rating(osu_file: &OsuFile, mods: Mods) -> Result<f64> {
+        let hit_objects = HitObject::from(osu_file)?;
+
+        Ok(star_rating(&hit_objects, mods.cs(circle_size(osu_file)?)))
+    }
+
+    fn new(
+        hit_objects: &[HitObject],
+        breaks: &[BreakPeriod],
+        beat_timings: &[BeatTiming],
+        cs: CircleSize,
+    ) -> Self {
+        let count = |matches: fn(&HitObjectParams) -> bool| {
+            hit_objects
+                .iter()
+                .filter(|hit_object| matches(hit_object.params()))
+                .count()
+        };
+        let bpms = beat_timings
+            .iter()
+            .map(|beat_timing| 60_000.0 / beat_timing.beat_length);
+
+        Self {
+            drain_time: drain_time(hit_objects, breaks),
+            bpm: bpms.fold(None, |range, bpm| match range {
+                Some((min, max)) => Some((bpm.min(min), bpm.max(max))),
+                None => Some((bpm, bpm)),
+            }),
+            circles: count(|params| matches!(params, HitObjectParams::Hitcircle)),
+            sliders: count(|params| matches!(params, HitObjectParams::Slider)),
+            spinners: count(|params| matches!(params, HitObjectParams::Spinner)),
+            star_rating: star_rating(hit_objects, cs),
         }
     }
 }
 
 impl Beatmap {
+    /// Loads the .osu file at `path` (see `Beatmap::try_from_with`), along with the hash of the file
+    pub fn read(path: &Path, preprocessor: &Preprocessor) -> Result<Self> {
+        let file_data = fs::read(path)?;
+        let osu_file = parse_osu_file(str::from_utf8(&file_data)?)
+            .map_err(|error| OsuError::bad_beatmap(error.to_string()))?;
+        let beatmap_dir = path
+            .parent()
+            .with_context(|| "beatmap path does not contain parent directory")?;
+
+        let mut beatmap = Self::try_from_with(osu_file, beatmap_dir.to_path_buf(), preprocessor)?;
+        beatmap.data.file_hash = file_hash(&file_data);
+
+        Ok(beatmap)
+    }
+
     pub fn try_from(osu_file: OsuFile, beatmap_dir: PathBuf) -> Result<Self> {
+        Self::try_from_with(osu_file, beatmap_dir, &Preprocessor::default())
+    }
+
+    /// Loads a beatmap, running `preprocessor` over its hit objects
+    pub fn try_from_with(
+        osu_file: OsuFile,
+        beatmap_dir: PathBuf,
+        preprocessor: &Preprocessor,
+    ) -> Result<Self> {
         let difficulty = osu_file.difficulty.clone().unwrap_or_default();
         let metadata = osu_file.metadata.clone().unwrap_or_default();
 
         let to_f64 =
             |decimal: Decimal| -> Result<f64, ParseFloatError> { decimal.to_string().parse() };
-        let audio_path = audio_path_from(&osu_file, beatmap_dir)
-            .with_context(|| "beatmap audio file not found")?;
+        let mode = osu_file
+            .general
+            .as_ref()
+            .and_then(|general| general.mode.as_ref());
+        if let Some(Mode::Taiko) = mode {
+            return Err(OsuError::UnsupportedMode {
+                mode: "osu!taiko".to_string(),
+            }
+            .into());
+        }
+        let audio_path = audio_path_from(&osu_file, beatmap_dir.clone()).ok_or_else(|| {
+            OsuError::MissingAudio {
+                path: audio_file_path(&osu_file, beatmap_dir.clone()),
+            }
+        })?;
 
         let title = metadata
             .title
@@ -167,41 +384,231 @@ impl Beatmap {
             .artist
             .map(|artist| artist.into())
             .unwrap_or("Not named".to_string());
-
-        Ok(Self {
+        let creator: String = metadata
+            .creator
+            .map(|creator| creator.into())
+            .unwrap_or("Unknown".to_string());
+        let source: String = metadata
+            .source
+            .map(|source| source.into())
+            .unwrap_or_default();
+        // Unsubmitted beatmaps have no IDs, or IDs of 0 or -1
+        let beatmap_id = metadata
+            .beatmap_id
+            .map(|beatmap_id| beatmap_id.into())
+            .filter(|&beatmap_id: &i32| beatmap_id > 0);
+        let beatmap_set_id = metadata
+            .beatmap_set_id
+            .map(|beatmap_set_id| beatmap_set_id.into())
+            .filter(|&beatmap_set_id: &i32| beatmap_set_id > 0);
+
+        let stack_leniency = osu_file
+            .general
+            .as_ref()
+            .and_then(|general| general.stack_leniency.clone())
+            .map(|stack_leniency| to_f64(stack_leniency.into()))
+            .transpose()?
+            .unwrap_or(DEFAULT_STACK_LENIENCY);
+
+        let mut beatmap = Self {
             data: BeatmapData {
                 od: OverallDifficulty(to_f64(
                     difficulty
                         .overall_difficulty
-                        .ok_or(anyhow!("beatmap does not contain overall difficulty"))?
+                        .ok_or_else(|| OsuError::bad_beatmap("it has no overall difficulty"))?
                         .into(),
                 )?),
                 cs: CircleSize(to_f64(
                     difficulty
                         .circle_size
-                        .ok_or(anyhow!("beatmap does not contain circle size"))?
+                        .ok_or_else(|| OsuError::bad_beatmap("it has no circle size"))?
                         .into(),
                 )?),
                 ar: ApproachRate(to_f64(
                     difficulty
                         .approach_rate
-                        .ok_or(anyhow!("beatmap does not contain approach rate"))?
+                        .ok_or_else(|| OsuError::bad_beatmap("it has no approach rate"))?
                         .into(),
                 )?),
                 hp: HpDrainRate(to_f64(
                     difficulty
                         .hp_drain_rate
-                        .ok_or(anyhow!("beatmap does no contain hp drain rate"))?
+                        .ok_or_else(|| OsuError::bad_beatmap("it has no hp drain rate"))?
                         .into(),
                 )?),
                 hit_objects: HitObject::from(&osu_file)?,
+                breaks: BreakPeriod::from(&osu_file)?,
+                beat_timings: BeatTiming::from(&osu_file)?,
                 audio_path,
                 artist,
                 difficulty_name,
                 title,
+                creator,
+                source,
+                dir: beatmap_dir,
+                beatmap_id,
+                beatmap_set_id,
+                file_hash: String::new(),
+                mods: preprocessor.mods(),
             },
             state: Default::default(),
-        })
+        };
+
+        let stack_threshold = beatmap.ar().to_preempt_duration().mul_f64(stack_leniency);
+        preprocessor.apply(
+            &mut beatmap.data.hit_objects,
+            stack_threshold.as_millis() as u32,
+        );
+
+        Ok(beatmap)
+    }
+
+    /// AR of the next spawned hit objects, with the mods (it changes during the play in the adaptive mode)
+    pub fn ar(&self) -> ApproachRate {
+        let ar = self.data.mods.ar(self.data.ar);
+        match self.state.adaptive {
+            Some(adaptive) => adaptive.ar(ar),
+            None => ar,
+        }
+    }
+
+    /// CS of the next spawned hit objects, with the mods (it changes during the play in the adaptive mode)
+    pub fn cs(&self) -> CircleSize {
+        let cs = self.data.mods.cs(self.data.cs);
+        match self.state.adaptive {
+            Some(adaptive) => adaptive.cs(cs),
+            None => cs,
+        }
+    }
+
+    /// OD the hits are judged with
+    pub fn od(&self) -> OverallDifficulty {
+        self.data.mods.od(self.data.od)
+    }
+
+    /// Performance points of the play so far (see `star_rating::performance_points`), with the star rating of the hit objects
+    /// as they are played
+    pub fn performance_points(&self) -> f64 {
+        let star_rating = star_rating(&self.data.hit_objects, self.data.mods.cs(self.data.cs));
+
+        performance_points(
+            star_rating,
+            self.state.accuracy(),
+            self.state.max_combo,
+            self.data.hit_objects.len(),
+            self.state.misses,
+        )
+    }
+
+    /// Updates the score, hit counts, combo, health and HUD data once a hit object is judged.
+    /// `hit_error_ms` is the timing error of the click (see `BeatmapState::hit_errors`), `None` if the hit object expired.
+    pub fn judge(&mut self, hit: HitScore, hit_error_ms: Option<i32>) {
+        // Update score (https://osu.ppy.sh/wiki/en/Gameplay/Score/ScoreV1/osu%21#hit-circles)
+        let combo = self.state.combo;
+        let combo_multiplier = if combo == 0 { 0 } else { combo - 1 };
+        let difficulty_multiplier = self.data.difficulty_multiplier();
+        let mod_multiplier = self.data.mods.score_multiplier();
+
+        self.state.score += (hit.value() as f64
+            * (1.0 + (combo_multiplier as f64 * difficulty_multiplier * mod_multiplier) / 25.0))
+            as usize;
+
+        // Update hit scores
+        match hit {
+            HitScore::Hit300 => self.state.hits300 += 1,
+            HitScore::Hit100 => self.state.hits100 += 1,
+            HitScore::Hit50 => self.state.hits50 += 1,
+            HitScore::Miss => self.state.misses += 1,
+        }
+
+        // Update combo
+        match hit {
+            HitScore::Hit300 | HitScore::Hit100 | HitScore::Hit50 => {
+                self.state.combo += 1;
+                self.state.max_combo = self.state.max_combo.max(self.state.combo);
+            }
+            HitScore::Miss => self.state.combo = 0,
+        }
+
+        // Update HUD data
+        self.state.last_hit = Some(hit);
+        if let Some(adaptive) = &mut self.state.adaptive {
+            adaptive.record(hit);
+        }
+        if let Some(hit_error_ms) = hit_error_ms.filter(|_| !matches!(hit, HitScore::Miss)) {
+            self.state.push_hit_error(hit_error_ms);
+        }
+
+        // Update health
+        self.state.health = self.data.hp.drain(self.state.health, hit);
+    }
+
+    /// Time to skip to if the first hit object is far enough into the song
+    pub fn intro_skip_time(&self) -> Option<Duration> {
+        let first_hit_object = self.data.hit_objects.first()?;
+        let skip_time = Duration::from_millis(first_hit_object.time() as u64)
+            .checked_sub(self.data.ar.to_mc_duration() + INTRO_SKIP_LEAD)?;
+
+        (skip_time >= self.state.play_time + MIN_INTRO_SKIP).then_some(skip_time)
+    }
+
+    /// Next hit object to spawn, if it should be hit before `threshold` (the play time plus the look ahead of the game mode)
+    pub fn next_due_hit_object(&self, threshold: Duration) -> Option<&HitObject> {
+        self.data
+            .hit_objects
+            .get(self.state.next_hit_object_idx)
+            .filter(|hit_object| threshold.as_millis() as u32 >= hit_object.time())
+    }
+
+    // The three steps of a playing tick below are shared by `update_osu` and the synthetic plays of `test_support`
+
+    /// Stops tracking the active hit objects which are gone without being hit, judging them as misses. Returns how many expired.
+    pub fn expire_hit_objects(&mut self, mut is_expired: impl FnMut(Entity) -> bool) -> usize {
+        let expired = self
+            .state
+            .active_hit_objects
+            .iter()
+            .copied()
+            .filter(|&entity| is_expired(entity))
+            .collect::<Vec<_>>();
+        self.state
+            .active_hit_objects
+            .retain(|entity| !expired.contains(entity));
+        for _ in &expired {
+            self.judge(HitScore::Miss, None);
+        }
+
+        expired.len()
+    }
+
+    /// Spawns the next hit object with `spawn` if it should be hit within `look_ahead` of the play time, and tracks it
+    pub fn spawn_due_hit_object(
+        &mut self,
+        look_ahead: Duration,
+        spawn: impl FnOnce(&HitObject, &Beatmap) -> Result<Entity>,
+    ) -> Result<()> {
+        let Some(hit_object) = self.next_due_hit_object(self.state.play_time + look_ahead) else {
+            return Ok(());
+        };
+
+        let entity = spawn(hit_object, self)?;
+        self.state.active_hit_objects.push_back(entity);
+        self.state.next_hit_object_idx += 1;
+        Ok(())
+    }
+
+    /// Judges an active hit object and stops tracking it
+    pub fn apply_hit(&mut self, hit: &GameModeHit) {
+        self.judge(hit.hit, hit.hit_error_ms);
+        self.state
+            .active_hit_objects
+            .retain(|&entity| entity != hit.entity);
+    }
+
+    /// Time left until the first hit object should be hit
+    pub fn time_to_first_hit_object(&self) -> Option<Duration> {
+        let first_hit_object = self.data.hit_objects.first()?;
+        Duration::from_millis(first_hit_object.time() as u64).checked_sub(self.state.play_time)
     }
 
     pub fn score_text(&self) -> Vec<Text> {
@@ -284,7 +691,14 @@ impl ApproachRate {
         Duration::from_millis(ms as u64)
     }
 
-    /// Since I don't know how to fade-in blocks, I will consider that the preempt duration starts at halfway through the fade-in phase
+    /// Time before the hit when the hitcircles are fully opaque, at the end of the fade-in
+    pub fn to_opaque_duration(self) -> Duration {
+        self.to_preempt_duration()
+            .saturating_sub(self.to_fade_in_duration())
+    }
+
+    /// Blocks can't be transparent, so the preempt duration is considered to start halfway through the fade-in phase, and the
+    /// hitcircles are drawn with a darker color until the end of the fade-in (see `Hitcircle::draw_circle`)
     pub fn to_mc_duration(self) -> Duration {
         (self.to_preempt_duration() + self.to_fade_in_duration()) / 2
     }
@@ -307,22 +721,58 @@ impl HpDrainRate {
     }
 }
 
+fn circle_size(osu_file: &OsuFile) -> Result<CircleSize> {
+    let circle_size: Decimal = osu_file
+        .difficulty
+        .clone()
+        .and_then(|difficulty| difficulty.circle_size)
+        .ok_or_else(|| OsuError::bad_beatmap("it has no circle size"))?
+        .into();
+
+    Ok(CircleSize(circle_size.to_string().parse()?))
+}
+
+/// Time of the end of the last hit object of the beatmap
+pub fn beatmap_length(osu_file: &OsuFile) -> Result<Duration> {
+    let length = HitObject::from(osu_file)?
+        .iter()
+        .map(|hit_object| hit_object.end_time())
+        .max()
+        .unwrap_or(0);
+
+    Ok(Duration::from_millis(length as u64))
+}
+
+/// SHA-256 of the bytes of a .osu file in hexadecimal (see `BeatmapData::file_hash`). The raw bytes are hashed, so the
+/// hash is the same as the one of other clients for the same file.
+pub fn file_hash(osu_file_data: &[u8]) -> String {
+    Sha256::digest(osu_file_data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 pub fn audio_path_from(osu_file: &OsuFile, beatmap_dir: PathBuf) -> Option<PathBuf> {
+    let audio_path = audio_file_path(osu_file, beatmap_dir)?;
+
+    audio_path.exists().then_some(audio_path)
+}
+
+/// Path of the audio file named in the beatmap, which may not exist
+fn audio_file_path(osu_file: &OsuFile, beatmap_dir: PathBuf) -> Option<PathBuf> {
     let audio_file: PathBuf = osu_file
         .general
         .clone()
         .and_then(|g| g.audio_filename.map(|f| f.into()))?;
 
-    let audio_path = beatmap_dir.join(audio_file);
-
-    audio_path.exists().then_some(audio_path)
+    Some(beatmap_dir.join(audio_file))
 }
 
 #[cfg(test)]
 mod test {
     use std::time::Duration;
 
-    use crate::osu::Hitwindow;
+    use crate::{osu::Hitwindow, test_support::synthetic_beatmap};
 
     use super::*;
 
@@ -366,6 +816,7 @@ mod test {
         let fade_in = ar.to_fade_in_duration();
         assert_eq!(preempt, Duration::from_millis(450));
         assert_eq!(fade_in, Duration::from_millis(300));
+        assert_eq!(ar.to_opaque_duration(), Duration::from_millis(150));
 
         let ar = ApproachRate(5.0);
         let preempt = ar.to_preempt_duration();
@@ -395,4 +846,36 @@ mod test {
         let expected_acc = 98.47;
         assert!((state.accuracy() - expected_acc).abs() < 0.01);
     }
+
+    #[test]
+    fn beatmap_website_url() {
+        let mut data = synthetic_beatmap(5.0, 5.0, &[]).data;
+        assert_eq!(data.website_url(), None);
+
+        data.beatmap_set_id = Some(39804);
+        assert_eq!(
+            data.website_url().as_deref(),
+            Some("https://osu.ppy.sh/beatmapsets/39804")
+        );
+
+        data.beatmap_id = Some(129891);
+        assert_eq!(
+            data.website_url().as_deref(),
+            Some("https://osu.ppy.sh/beatmapsets/39804#osu/129891")
+        );
+    }
+
+    #[test]
+    fn hash_of_raw_file() {
+        let file = b"osu file format v14\r\n\r\n[General]\r\nAudioFilename: audio.mp3\r\n";
+        let hash = super::file_hash(file);
+        assert_eq!(hash.len(), 64);
+        // Line endings are part of the file, so they change the hash
+        let unix_file = b"osu file format v14\n\n[General]\nAudioFilename: audio.mp3\n";
+        assert_ne!(hash, super::file_hash(unix_file));
+        assert_eq!(
+            super::file_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
 }
@@ -0,0 +1,607 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::{
+    fs, io,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Query, Res, ResMut},
+};
+use valence::{
+    client::event::ClickContainer,
+    nbt::{compound, List},
+    prelude::{Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{ItemKind, ItemStack, TextFormat},
+};
+use zip::ZipArchive;
+
+use crate::{
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    configs::Configs,
+    error::{error_message, OsuError},
+    filter_query::{Comparison, FilterQuery},
+    inventory::{open_new_inventory, ClientInventory, InventoriesToOpen},
+    player_name::PlayerName,
+    progress::{cancelled_error, LongOperation, LongOperations, Progress},
+    song_selection::SongSelectionInventory,
+};
+
+const PAGE_SIZE: usize = 45;
+const PREVIOUS_PAGE_SLOT: u16 = 45;
+const NEXT_PAGE_SLOT: u16 = 53;
+const ARROW_ITEM_KIND: ItemKind = ItemKind::SpectralArrow;
+/// Keys of the conditions supported by `/browse` (e.g. `status=loved mode=mania`)
+const BROWSE_FILTER_KEYS: [&str; 2] = ["status", "mode"];
+const STATUSES: [&str; 6] = [
+    "ranked",
+    "approved",
+    "qualified",
+    "loved",
+    "pending",
+    "graveyard",
+];
+const MODES: [&str; 4] = ["osu", "taiko", "fruits", "mania"];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const MB: u64 = 1024 * 1024;
+/// Largest `.osz` which is downloaded (beatmapsets with videos are the largest ones)
+const MAX_DOWNLOAD_BYTES: u64 = 200 * MB;
+/// Largest total size of the files extracted from an `.osz`, so a malicious archive can't fill the disk
+const MAX_EXTRACTED_BYTES: u64 = 500 * MB;
+
+/// Search of `/browse [keywords] [status=<status|any>] [mode=<mode|any>]`. Only ranked osu!standard maps are searched by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeatmapSearch {
+    keywords: String,
+    status: Option<String>,
+    mode: Option<String>,
+}
+
+impl BeatmapSearch {
+    pub fn parse(args: &str) -> Result<Self> {
+        let query = FilterQuery::parse(args, &BROWSE_FILTER_KEYS);
+        let mut search = Self {
+            keywords: query.keywords,
+            status: Some("ranked".to_string()),
+            mode: Some("osu".to_string()),
+        };
+
+        for condition in query.conditions {
+            if condition.comparison != Comparison::Equal {
+                bail!("only '=' can be used with '{}'", condition.key);
+            }
+
+            let value = condition.value.to_lowercase();
+            let (field, allowed) = match condition.key.as_str() {
+                "status" => (&mut search.status, &STATUSES[..]),
+                _ => (&mut search.mode, &MODES[..]),
+            };
+            *field = match value.as_str() {
+                "any" => None,
+                value if allowed.contains(&value) => Some(value.to_string()),
+                _ => bail!(
+                    "unknown {} '{}' (expected {} or any)",
+                    condition.key,
+                    value,
+                    allowed.join(", ")
+                ),
+            };
+        }
+
+        Ok(search)
+    }
+
+    /// Query string parameters of the mirror search endpoint for the page `page`
+    fn query_pairs(&self, page: usize) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![
+            ("q", self.keywords.clone()),
+            ("limit", PAGE_SIZE.to_string()),
+            ("offset", (page * PAGE_SIZE).to_string()),
+        ];
+        if let Some(status) = &self.status {
+            pairs.push(("status", status.clone()));
+        }
+        if let Some(mode) = &self.mode {
+            pairs.push(("mode", mode.clone()));
+        }
+
+        pairs
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "'{}' ({}, {})",
+            self.keywords,
+            self.status.as_deref().unwrap_or("any status"),
+            self.mode.as_deref().unwrap_or("any mode")
+        )
+    }
+}
+
+/// Beatmapset returned by the mirror (same format as the osu! API v2)
+#[derive(Deserialize, Clone, Debug)]
+struct Beatmapset {
+    id: u64,
+    artist: String,
+    title: String,
+    creator: String,
+    status: String,
+    #[serde(default)]
+    beatmaps: Vec<BeatmapInfo>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct BeatmapInfo {
+    version: String,
+    difficulty_rating: f64,
+    mode: String,
+}
+
+impl Beatmapset {
+    fn display_name(&self) -> String {
+        format!("{} - {}", self.artist, self.title)
+    }
+
+    /// Name of the song folder the beatmapset is extracted to, like the ones created by osu!
+    fn dir_name(&self) -> String {
+        format!("{} {}", self.id, self.display_name())
+            .chars()
+            .filter(|c| !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
+            .collect::<String>()
+            .trim_end_matches(['.', ' '])
+            .to_string()
+    }
+}
+
+fn search(mirror_url: &str, search: &BeatmapSearch, page: usize) -> Result<Vec<Beatmapset>> {
+    let mut request = ureq::get(&format!("{}/api/v2/search", mirror_url)).timeout(REQUEST_TIMEOUT);
+    for (key, value) in search.query_pairs(page) {
+        request = request.query(key, &value);
+    }
+
+    Ok(request.call()?.into_json()?)
+}
+
+/// Downloads the `.osz` of the beatmapset and extracts it into `songs_dir`, returning the song folder
+fn download(
+    mirror_url: &str,
+    beatmapset: &Beatmapset,
+    songs_dir: &Path,
+    progress: &Progress,
+) -> Result<PathBuf> {
+    let song_dir = songs_dir.join(beatmapset.dir_name());
+    if song_dir.exists() {
+        bail!("'{}' was already downloaded", beatmapset.display_name());
+    }
+    progress.set_total(2);
+
+    let mut osz = Vec::new();
+    ureq::get(&format!("{}/d/{}", mirror_url, beatmapset.id))
+        .timeout(REQUEST_TIMEOUT)
+        .call()?
+        .into_reader()
+        .take(MAX_DOWNLOAD_BYTES + 1)
+        .read_to_end(&mut osz)?;
+    if osz.len() as u64 > MAX_DOWNLOAD_BYTES {
+        bail!(
+            "'{}' is larger than {} MB",
+            beatmapset.display_name(),
+            MAX_DOWNLOAD_BYTES / MB
+        );
+    }
+    progress.advance();
+    if progress.is_cancelled() {
+        return Err(cancelled_error());
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(osz))?;
+    if let Err(error) = extract(&mut archive, &song_dir) {
+        // Partially extracted songs would show up in the song selection without some of their files
+        let _ = fs::remove_dir_all(&song_dir);
+        return Err(error);
+    }
+    progress.advance();
+
+    Ok(song_dir)
+}
+
+/// Extracts `archive` into `dir`, stopping once more than `MAX_EXTRACTED_BYTES` were written (the sizes declared in the archive
+/// aren't trusted)
+fn extract(archive: &mut ZipArchive<Cursor<Vec<u8>>>, dir: &Path) -> Result<()> {
+    let mut extracted = 0;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let Some(path) = entry.enclosed_name().map(|name| dir.join(name)) else {
+            bail!("the archive has an invalid file name: '{}'", entry.name());
+        };
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = fs::File::create(&path)?;
+        extracted += io::copy(
+            &mut (&mut entry).take(MAX_EXTRACTED_BYTES - extracted + 1),
+            &mut file,
+        )?;
+        if extracted > MAX_EXTRACTED_BYTES {
+            bail!(
+                "the extracted files are larger than {} MB",
+                MAX_EXTRACTED_BYTES / MB
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Paginated results of `/browse`, searched on the beatmap mirror of `configs.json`. Clicking a beatmapset downloads it into the songs directory.
+/// Each player has their own browser.
+#[derive(Component)]
+pub struct BeatmapBrowserInventory {
+    client: Entity,
+    search: BeatmapSearch,
+    page: usize,
+    results: Vec<Beatmapset>,
+    searching: Option<LongOperation<Vec<Beatmapset>>>,
+    downloading: Option<LongOperation<PathBuf>>,
+}
+
+impl ClientInventory for BeatmapBrowserInventory {
+    fn client(&self) -> Entity {
+        self.client
+    }
+
+    /// The downloaded beatmap is still added to the song selection
+    fn is_busy(&self) -> bool {
+        self.downloading.is_some()
+    }
+}
+
+impl BeatmapBrowserInventory {
+    fn new(client: Entity, search: BeatmapSearch) -> (Self, Inventory) {
+        (
+            Self {
+                client,
+                search,
+                page: 0,
+                results: Vec::new(),
+                searching: None,
+                downloading: None,
+            },
+            Inventory::new(InventoryKind::Generic9x6),
+        )
+    }
+
+    fn start_search(&mut self, mirror_url: String, operations: &mut LongOperations) {
+        if let Some(searching) = self.searching.take() {
+            searching.cancel();
+        }
+
+        let beatmap_search = self.search.clone();
+        let page = self.page;
+        self.searching = Some(LongOperation::start(
+            format!("Searching beatmaps {}", self.search.describe()),
+            Some(self.client),
+            operations,
+            move |_| search(&mirror_url, &beatmap_search, page),
+        ));
+    }
+
+    fn has_next_page(&self) -> bool {
+        self.results.len() == PAGE_SIZE
+    }
+
+    fn draw(&self, inventory: &mut Inventory) {
+        for slot in 0..=NEXT_PAGE_SLOT {
+            inventory.replace_slot(slot, None);
+        }
+
+        let title = "Beatmap mirror".color(Color::DARK_BLUE)
+            + format!(" (page {}: ", self.page + 1).color(Color::DARK_GRAY)
+            + self.search.keywords.clone().color(Color::DARK_PURPLE)
+            + ")".color(Color::DARK_GRAY);
+        inventory.replace_title(title);
+
+        for (slot, beatmapset) in self.results.iter().enumerate() {
+            let mut lore = vec![
+                format!(
+                    r#"{{"text": "Mapper: {}", "color": "gray"}}"#,
+                    beatmapset.creator
+                ),
+                format!(
+                    r#"{{"text": "Status: {}", "color": "gray"}}"#,
+                    beatmapset.status
+                ),
+            ];
+            lore.extend(beatmapset.beatmaps.iter().map(|beatmap| {
+                format!(
+                    r#"{{"text": "{:.2}* {} ({})", "color": "dark_gray"}}"#,
+                    beatmap.difficulty_rating, beatmap.version, beatmap.mode
+                )
+            }));
+            lore.push(r#"{"text": "Click to download", "color": "green"}"#.to_string());
+
+            let item = ItemStack::new(
+                ItemKind::MusicDiscCat,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => format!(r#"{{"text": "{}", "color": "gold"}}"#, beatmapset.display_name()),
+                        "Lore" => List::String(lore),
+                    }
+                }),
+            );
+            inventory.replace_slot(slot as u16, Some(item));
+        }
+
+        if self.has_next_page() {
+            let item = ItemStack::new(
+                ARROW_ITEM_KIND,
+                1,
+                Some(compound! {"display" => compound! {
+                    "Name" => r#"{"text": "Next page", "color": "green"}"#.to_string(),
+                    "Lore" => List::String(vec![format!(r#"{{"text": "Go to page {}", "color": "gray"}}"#, self.page + 2)]),
+                }}),
+            );
+            inventory.replace_slot(NEXT_PAGE_SLOT, Some(item));
+        }
+
+        if self.page > 0 {
+            let item = ItemStack::new(
+                ARROW_ITEM_KIND,
+                1,
+                Some(compound! {"display" => compound! {
+                    "Name" => r#"{"text": "Previous page", "color": "red"}"#.to_string(),
+                    "Lore" => List::String(vec![format!(r#"{{"text": "Go to page {}", "color": "gray"}}"#, self.page)]),
+                }}),
+            );
+            inventory.replace_slot(PREVIOUS_PAGE_SLOT, Some(item));
+        }
+    }
+}
+
+pub fn browse_command() -> CommandSpec {
+    CommandSpec::new("browse").arg(ArgSpec::phrase("search"))
+}
+
+/// Handles `/browse [keywords] [status=<status|any>] [mode=<mode|any>]`: searches the beatmap mirror and opens the results once they arrive
+pub fn execute_browse_commands(
+    mut commands: Commands,
+    mut command_events: EventReader<OsuCommand>,
+    configs: Res<Configs>,
+    mut operations: ResMut<LongOperations>,
+    mut browsers: Query<&mut BeatmapBrowserInventory>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "browse" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+        // Downloaded beatmapsets are written to the songs directory of the server
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if !is_operator {
+            client.send_message(
+                OsuError::OperatorOnly {
+                    action: "browse and download beatmaps",
+                }
+                .message(),
+            );
+            continue;
+        }
+
+        let search = match BeatmapSearch::parse(args) {
+            Ok(search) => search,
+            Err(error) => {
+                client.send_message(error_message(&error));
+                continue;
+            }
+        };
+        client.send_message(
+            "Searching beatmaps ".color(Color::YELLOW) + search.describe().color(Color::GRAY),
+        );
+
+        let mirror_url = configs.mirror_url().to_string();
+        match browsers
+            .iter_mut()
+            .find(|browser| browser.client == command_event.client)
+        {
+            Some(mut browser) => {
+                browser.search = search;
+                browser.page = 0;
+                browser.start_search(mirror_url, &mut operations);
+            }
+            None => {
+                let (mut browser, inventory) =
+                    BeatmapBrowserInventory::new(command_event.client, search);
+                browser.start_search(mirror_url, &mut operations);
+                commands.spawn((browser, inventory));
+            }
+        }
+    }
+}
+
+/// Shows the search results and adds the downloaded songs to the song selection
+pub fn update_beatmap_browsers(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    mut browsers: Query<(Entity, &mut BeatmapBrowserInventory, &mut Inventory)>,
+    mut song_selections: Query<&mut SongSelectionInventory>,
+    mut clients: Query<&mut Client>,
+) {
+    for (browser_entity, mut browser, mut inventory) in &mut browsers {
+        let client_entity = browser.client;
+
+        if let Some(result) = browser
+            .searching
+            .as_mut()
+            .and_then(|searching| searching.try_finish())
+        {
+            browser.searching = None;
+            match result {
+                Ok(results) if results.is_empty() => {
+                    // The last page was full, so the next one was shown but it had no results
+                    browser.page = browser.page.saturating_sub(1);
+                    if let Ok(mut client) = clients.get_mut(client_entity) {
+                        client.send_message("No beatmaps found".color(Color::RED));
+                    }
+                }
+                Ok(results) => {
+                    browser.results = results;
+                    browser.draw(&mut inventory);
+                    open_new_inventory(
+                        &mut commands,
+                        client_entity,
+                        &mut inventories_to_open,
+                        browser_entity,
+                    );
+                }
+                Err(error) => {
+                    warn!("Error while searching beatmaps: {}", error);
+                    if let Ok(mut client) = clients.get_mut(client_entity) {
+                        client.send_message(
+                            format!("Error while searching beatmaps: '{}'", error)
+                                .color(Color::RED),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(result) = browser
+            .downloading
+            .as_mut()
+            .and_then(|downloading| downloading.try_finish())
+        {
+            browser.downloading = None;
+            let message = match result {
+                Ok(song_dir) => {
+                    for mut song_selection in &mut song_selections {
+                        if let Err(error) = song_selection.refresh_songs() {
+                            warn!("Error while refreshing the song selection: {}", error);
+                        }
+                    }
+                    info!("Downloaded '{}'", song_dir.display());
+
+                    let song_name = song_dir
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    "Downloaded ".color(Color::YELLOW) + song_name.color(Color::GREEN)
+                }
+                Err(error) => {
+                    warn!("Error while downloading a beatmap: {}", error);
+                    format!("Error while downloading the beatmap: '{}'", error).color(Color::RED)
+                }
+            };
+            if let Ok(mut client) = clients.get_mut(client_entity) {
+                client.send_message(message);
+            }
+        }
+    }
+}
+
+pub fn handle_beatmap_browser_clicks(
+    configs: Res<Configs>,
+    mut operations: ResMut<LongOperations>,
+    open_inventories: Query<(Entity, &OpenInventory), With<Client>>,
+    mut browsers: Query<&mut BeatmapBrowserInventory>,
+    mut clients: Query<&mut Client>,
+    mut clicks: EventReader<ClickContainer>,
+) {
+    for click in clicks.iter() {
+        let Some(mut browser) = open_inventories
+            .iter()
+            .find(|(client, _)| *client == click.client)
+            .and_then(|(_, inventory)| browsers.get_mut(inventory.entity()).ok())
+        else {
+            continue;
+        };
+        let mirror_url = configs.mirror_url().to_string();
+        let slot = click.slot_id as u16;
+
+        if slot == NEXT_PAGE_SLOT && browser.has_next_page() {
+            browser.page += 1;
+            browser.start_search(mirror_url, &mut operations);
+        } else if slot == PREVIOUS_PAGE_SLOT && browser.page > 0 {
+            browser.page -= 1;
+            browser.start_search(mirror_url, &mut operations);
+        } else if let Some(beatmapset) = browser.results.get(slot as usize).cloned() {
+            let Ok(mut client) = clients.get_mut(click.client) else {
+                continue;
+            };
+            if browser.downloading.is_some() {
+                client.send_message("Wait for the current download to finish".color(Color::RED));
+                continue;
+            }
+
+            client.send_message(
+                "Downloading ".color(Color::YELLOW) + beatmapset.display_name().color(Color::GREEN),
+            );
+            let songs_dir = PathBuf::from(configs.songs_directory());
+            browser.downloading = Some(LongOperation::start(
+                format!("Downloading '{}'", beatmapset.display_name()),
+                Some(click.client),
+                &mut operations,
+                move |progress| download(&mirror_url, &beatmapset, &songs_dir, progress),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_search() {
+        let search = BeatmapSearch::parse("camellia status=loved mode=any").unwrap();
+        assert_eq!(search.keywords, "camellia");
+        assert_eq!(search.status.as_deref(), Some("loved"));
+        assert_eq!(search.mode, None);
+
+        let search = BeatmapSearch::parse("freedom dive").unwrap();
+        assert_eq!(
+            search.query_pairs(2),
+            vec![
+                ("q", "freedom dive".to_string()),
+                ("limit", "45".to_string()),
+                ("offset", "90".to_string()),
+                ("status", "ranked".to_string()),
+                ("mode", "osu".to_string()),
+            ]
+        );
+
+        assert!(BeatmapSearch::parse("status=unranked").is_err());
+        assert!(BeatmapSearch::parse("mode>osu").is_err());
+    }
+
+    #[test]
+    fn song_dir_name() {
+        let beatmapset = Beatmapset {
+            id: 39804,
+            artist: "xi".to_string(),
+            title: "FREEDOM DiVE?".to_string(),
+            creator: "Nakagawa-Kanon".to_string(),
+            status: "ranked".to_string(),
+            beatmaps: Vec::new(),
+        };
+        assert_eq!(beatmapset.dir_name(), "39804 xi - FREEDOM DiVE");
+    }
+}
@@ -1,7 +1,12 @@
 use anyhow::{anyhow, Result};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use rand::seq::SliceRandom;
 use std::{
+    cmp::Reverse,
     fs::{read_dir, read_to_string},
     path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::Duration,
 };
 use valence::{
     client::event::ClickContainer,
@@ -13,28 +18,54 @@ use valence::{
 use bevy_ecs::{
     prelude::{Component, Entity, EventReader},
     query::{Changed, With},
-    system::{Commands, Query, ResMut},
+    system::{Commands, Query, Res, ResMut},
 };
 use osu_file_parser::{Decimal, OsuFile};
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::{
+    beatmap::{
+        beatmap_length, ApproachRate, Beatmap, BeatmapStats, CircleSize, HpDrainRate,
+        OverallDifficulty,
+    },
+    configs::Configs,
+    error::{error_message, OsuError},
+    filter_query::{Condition, FilterQuery},
     inventory::{open_new_inventory, InventoriesToOpen},
+    lobby::Lobby,
+    mods::Mods,
     osu::{Osu, OsuStateChange},
+    osu_file::parse_osu_file,
+    player_name::PlayerName,
     song_selection::{self, SongSelectionInventory},
+    star_rating::{star_rating_color, DifficultyTier},
 };
 
 const SONG_SELECTION_SLOT: u16 = 45;
 const LAST_SLOT: u16 = 53;
+/// Keys of the conditions supported by the difficulty filter (e.g. `ar>9`)
+const FILTER_KEYS: [&str; 6] = ["ar", "od", "cs", "hp", "stars", "mapper"];
 
 #[derive(Component, Default)]
 pub struct BeatmapSelectionInventory {
     beatmaps: Vec<BeatmapFile>,
+    filter: Option<String>,
+    /// Indices of the beatmaps matching the filter, in the order they are displayed
+    visible: Vec<usize>,
+    /// `.osu` files of the song directory which couldn't be read (see `read_beatmap_dir_with_warnings`)
+    warnings: Vec<String>,
 }
 
 pub struct BeatmapFile {
     osu_file: OsuFile,
     path: PathBuf,
+    length: Duration,
+    /// Computed the first time the beatmap is displayed (`None` if the beatmap couldn't be parsed)
+    stats: OnceLock<Option<BeatmapStats>>,
+    /// Star rating with the last mods it was displayed with
+    modded_star_rating: Mutex<Option<(Mods, Option<f64>)>>,
+    /// Why the beatmap can't be played, checked the first time it's displayed (see `BeatmapFile::problem`)
+    problem: OnceLock<Option<String>>,
 }
 
 impl BeatmapSelectionInventory {
@@ -49,39 +80,148 @@ impl BeatmapSelectionInventory {
     }
 
     pub fn load_beatmap_dir(&mut self, dir: &PathBuf) -> Result<&Vec<BeatmapFile>> {
-        let beatmaps: Vec<_> = read_dir(dir)?
-            .flatten()
-            .filter_map(|entry| {
-                let path = entry.path();
-
-                if let Some(extension) = path.extension() {
-                    if extension == "osu" {
-                        return Some(path);
-                    }
+        (self.beatmaps, self.warnings) = read_beatmap_dir_with_warnings(dir)?;
+        // Easiest difficulties first (beatmaps which couldn't be rated go last)
+        self.beatmaps.sort_by(|a, b| {
+            let star_rating = |beatmap: &BeatmapFile| {
+                beatmap
+                    .stats()
+                    .map_or(f64::INFINITY, |stats| stats.star_rating)
+            };
+            star_rating(a).total_cmp(&star_rating(b))
+        });
+        self.filter = None;
+        self.visible = (0..self.beatmaps.len()).collect();
+
+        Ok(&self.beatmaps)
+    }
+
+    /// Files of the last loaded song directory which were skipped
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Only shows the difficulties matching the query: difficulty or mapper names (fuzzy match) and conditions like `ar>9`, `od<=8`, `stars>5` or `mapper=name`.
+    /// Returns how many difficulties match.
+    pub fn set_filter(&mut self, filter: Option<&str>) -> Result<usize> {
+        if self.beatmaps.is_empty() {
+            return Err(anyhow!("No beatmap set is selected"));
+        }
+
+        self.filter = filter.map(|filter| filter.to_string());
+        self.visible = match filter {
+            Some(filter) => filter_beatmaps(&self.beatmaps, filter),
+            None => (0..self.beatmaps.len()).collect(),
+        };
+
+        Ok(self.visible.len())
+    }
+
+    /// Random difficulty matching the filter which is not longer than `max_length`
+    pub fn random_beatmap(&self, max_length: Duration) -> Option<&BeatmapFile> {
+        let playable: Vec<_> = self
+            .visible_beatmaps()
+            .filter(|beatmap| beatmap.length <= max_length && beatmap.problem().is_none())
+            .collect();
+
+        playable.choose(&mut rand::thread_rng()).copied()
+    }
+
+    fn visible_beatmaps(&self) -> impl Iterator<Item = &BeatmapFile> {
+        self.visible
+            .iter()
+            .filter_map(|&idx| self.beatmaps.get(idx))
+    }
+}
+
+/// Indices of the beatmaps matching the filter, best fuzzy matches first
+fn filter_beatmaps(beatmaps: &[BeatmapFile], filter: &str) -> Vec<usize> {
+    let query = FilterQuery::parse(filter, &FILTER_KEYS);
+    let matcher = SkimMatcherV2::default().ignore_case();
+
+    let mut matches: Vec<_> = beatmaps
+        .iter()
+        .enumerate()
+        .filter(|(_, beatmap)| {
+            query
+                .conditions
+                .iter()
+                .all(|condition| beatmap.matches(condition))
+        })
+        .filter_map(|(idx, beatmap)| {
+            if query.keywords.is_empty() {
+                return Some((0, idx));
+            }
+
+            let searched = format!("{} {}", beatmap.difficulty_name(), beatmap.mapper());
+            matcher
+                .fuzzy_match(&searched, &query.keywords)
+                .map(|fuzzy_score| (fuzzy_score, idx))
+        })
+        .collect();
+
+    // Stable sort, so difficulties with the same score are still sorted by star rating
+    matches.sort_by_key(|(fuzzy_score, _)| Reverse(*fuzzy_score));
+
+    matches.into_iter().map(|(_, idx)| idx).collect()
+}
+
+/// Reads all the beatmaps (`.osu` files) of a song directory
+pub fn read_beatmap_dir(dir: &PathBuf) -> Result<Vec<BeatmapFile>> {
+    read_beatmap_dir_with_warnings(dir).map(|(beatmaps, _)| beatmaps)
+}
+
+/// Reads all the beatmaps of a song directory, along with why the `.osu` files which couldn't be read were skipped
+pub fn read_beatmap_dir_with_warnings(dir: &PathBuf) -> Result<(Vec<BeatmapFile>, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let beatmaps: Vec<_> = read_dir(dir)?
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+
+            if let Some(extension) = path.extension() {
+                if extension == "osu" {
+                    return Some(path);
                 }
+            }
 
-                None
-            })
-            .filter_map(|osu_file_path| {
-                Some(BeatmapFile {
-                    osu_file: read_to_string(&osu_file_path)
-                        .ok()?
-                        .parse::<OsuFile>()
-                        .ok()?,
-                    path: osu_file_path,
+            None
+        })
+        .filter_map(|osu_file_path| {
+            let read = || -> Result<BeatmapFile> {
+                let osu_file = parse_osu_file(&read_to_string(&osu_file_path)?)?;
+
+                Ok(BeatmapFile {
+                    length: beatmap_length(&osu_file)?,
+                    osu_file,
+                    path: osu_file_path.clone(),
+                    stats: OnceLock::new(),
+                    modded_star_rating: Mutex::new(None),
+                    problem: OnceLock::new(),
                 })
-            })
-            .collect();
+            };
 
-        if beatmaps.is_empty() {
-            Err(anyhow!(
-                "No beatmap found in directory: '{}'",
-                dir.display()
-            ))
-        } else {
-            self.beatmaps = beatmaps;
-            Ok(&self.beatmaps)
-        }
+            read()
+                .map_err(|error| {
+                    let file_name = osu_file_path.file_name().unwrap_or_default();
+                    warn!("Skipped beatmap '{}': {}", osu_file_path.display(), error);
+                    warnings.push(format!(
+                        "'{}' was skipped: {}",
+                        file_name.to_string_lossy(),
+                        error
+                    ));
+                })
+                .ok()
+        })
+        .collect();
+
+    if beatmaps.is_empty() {
+        Err(anyhow!(
+            "No beatmap found in directory: '{}'",
+            dir.display()
+        ))
+    } else {
+        Ok((beatmaps, warnings))
     }
 }
 
@@ -89,6 +229,116 @@ impl BeatmapFile {
     pub fn osu_file(&self) -> &OsuFile {
         &self.osu_file
     }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+
+    /// Why the beatmap can't be played (e.g. its audio file is missing), checked like when it's started
+    pub fn problem(&self) -> Option<&str> {
+        self.problem
+            .get_or_init(|| {
+                let beatmap_dir = self.path.parent()?.to_path_buf();
+                let error = Beatmap::try_from(self.osu_file.clone(), beatmap_dir).err()?;
+
+                Some(match error.downcast_ref::<OsuError>() {
+                    Some(OsuError::BadBeatmap { reason }) => reason.clone(),
+                    _ => error.to_string(),
+                })
+            })
+            .as_deref()
+    }
+
+    pub fn stats(&self) -> Option<&BeatmapStats> {
+        self.stats
+            .get_or_init(|| BeatmapStats::from(&self.osu_file).ok())
+            .as_ref()
+    }
+
+    /// Star rating of the beatmap played with `mods`
+    pub fn star_rating(&self, mods: Mods) -> Option<f64> {
+        if mods.is_empty() {
+            return self.stats().map(|stats| stats.star_rating);
+        }
+
+        let mut cached = self.modded_star_rating.lock().ok()?;
+        match *cached {
+            Some((cached_mods, star_rating)) if cached_mods == mods => star_rating,
+            _ => {
+                let star_rating = BeatmapStats::modded_star_rating(&self.osu_file, mods).ok();
+                *cached = Some((mods, star_rating));
+                star_rating
+            }
+        }
+    }
+
+    pub fn difficulty_name(&self) -> String {
+        self.osu_file
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.version.clone())
+            .map(|version| version.into())
+            .unwrap_or("Not named".to_string())
+    }
+
+    pub fn mapper(&self) -> String {
+        self.osu_file
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.creator.clone())
+            .map(|creator| creator.into())
+            .unwrap_or_default()
+    }
+
+    /// Whether the difficulty matches a filter condition (see `FILTER_KEYS`)
+    fn matches(&self, condition: &Condition) -> bool {
+        let difficulty = self.osu_file.difficulty.as_ref();
+        let to_f64 =
+            |decimal: Option<Decimal>| -> Option<f64> { decimal?.to_string().parse().ok() };
+
+        let value = match condition.key.as_str() {
+            "mapper" => return condition.matches_text(&self.mapper()),
+            "ar" => to_f64(
+                difficulty
+                    .and_then(|difficulty| difficulty.approach_rate.clone())
+                    .map(Into::into),
+            ),
+            "od" => to_f64(
+                difficulty
+                    .and_then(|difficulty| difficulty.overall_difficulty.clone())
+                    .map(Into::into),
+            ),
+            "cs" => to_f64(
+                difficulty
+                    .and_then(|difficulty| difficulty.circle_size.clone())
+                    .map(Into::into),
+            ),
+            "hp" => to_f64(
+                difficulty
+                    .and_then(|difficulty| difficulty.hp_drain_rate.clone())
+                    .map(Into::into),
+            ),
+            "stars" => self.stats().map(|stats| stats.star_rating),
+            _ => None,
+        };
+
+        value.is_some_and(|value| condition.matches_number(value))
+    }
+
+    /// Name displayed to players: `title [difficulty]`
+    pub fn display_name(&self) -> String {
+        let metadata = self.osu_file.metadata.as_ref();
+        let title: String = metadata
+            .and_then(|metadata| metadata.title.clone())
+            .map(|title| title.into())
+            .unwrap_or("Not named".to_string());
+
+        format!("{title} [{}]", self.difficulty_name())
+    }
 }
 
 pub fn update_beatmap_selection_inventory(
@@ -96,6 +346,8 @@ pub fn update_beatmap_selection_inventory(
         (&BeatmapSelectionInventory, &mut Inventory),
         Changed<BeatmapSelectionInventory>,
     >,
+    configs: Res<Configs>,
+    mods: Res<Mods>,
 ) {
     for (beatmap_selection, mut inventory) in &mut beatmap_selections {
         // Clear inventory
@@ -104,7 +356,18 @@ pub fn update_beatmap_selection_inventory(
         }
 
         // Set inventories slots
-        for (slot, beatmap) in beatmap_selection.beatmaps.iter().enumerate() {
+        let title = "Beatmaps".color(Color::DARK_BLUE);
+        let title = if let Some(filter) = &beatmap_selection.filter {
+            title
+                + " (filter: '".color(Color::DARK_GRAY)
+                + filter.clone().color(Color::DARK_PURPLE)
+                + "')".color(Color::DARK_GRAY)
+        } else {
+            title
+        };
+        inventory.replace_title(title);
+
+        for (slot, beatmap) in beatmap_selection.visible_beatmaps().enumerate() {
             let Some(metadata) = beatmap.osu_file.metadata.clone() else { continue };
             let Some(difficulty) = beatmap.osu_file.difficulty.clone() else {continue};
 
@@ -120,47 +383,107 @@ pub fn update_beatmap_selection_inventory(
                 .artist
                 .map(|artist| artist.into())
                 .unwrap_or("Not named".to_string());
-            let od: String = difficulty
-                .overall_difficulty
-                .map(|this| {
-                    let decimal: Decimal = this.into();
-                    decimal.to_string()
-                })
-                .unwrap_or("Not defined".to_string());
-            let ar: String = difficulty
-                .approach_rate
-                .map(|this| {
-                    let decimal: Decimal = this.into();
-                    decimal.to_string()
-                })
-                .unwrap_or("Not defined".to_string());
-            let cs: String = difficulty
-                .circle_size
-                .map(|this| {
-                    let decimal: Decimal = this.into();
-                    decimal.to_string()
-                })
-                .unwrap_or("Not defined".to_string());
-            let hp: String = difficulty
-                .hp_drain_rate
-                .map(|this| {
-                    let decimal: Decimal = this.into();
-                    decimal.to_string()
-                })
-                .unwrap_or("Not defined".to_string());
+            // Settings are shown as they are with the selected mods
+            let attribute = |decimal: Option<Decimal>, apply_mods: &dyn Fn(f64) -> f64| {
+                let Some(decimal) = decimal else {
+                    return "Not defined".to_string();
+                };
+                match decimal.to_string().parse::<f64>() {
+                    Ok(value) if !mods.is_empty() => format_attribute(apply_mods(value)),
+                    _ => decimal.to_string(),
+                }
+            };
+            let od = attribute(difficulty.overall_difficulty.map(Into::into), &|od| {
+                mods.od(OverallDifficulty(od)).0
+            });
+            let ar = attribute(difficulty.approach_rate.map(Into::into), &|ar| {
+                mods.ar(ApproachRate(ar)).0
+            });
+            let cs = attribute(difficulty.circle_size.map(Into::into), &|cs| {
+                mods.cs(CircleSize(cs)).0
+            });
+            let hp = attribute(difficulty.hp_drain_rate.map(Into::into), &|hp| {
+                mods.hp(HpDrainRate(hp)).0
+            });
+            let difficulty_header = if mods.is_empty() {
+                "======= Difficulty =======".to_string()
+            } else {
+                format!("=== Difficulty ({}) ===", *mods)
+            };
+
+            let mut lore = vec![
+                format!(r#"{{"text": "Artist: {artist}", "color": "gray"}}"#),
+                format!(r#"{{"text": ""}}"#),
+                format!(r#"{{"text": "{difficulty_header}", "color": "gray"}}"#),
+                format!(
+                    r#"{{"text": "AR: {ar}   OD: {od}   HP: {hp}   CS: {cs}", "color": "gray"}}"#
+                ),
+            ];
+
+            if let Some(stats) = beatmap.stats() {
+                let length = format_duration(beatmap.length);
+                let drain_time = format_duration(stats.drain_time);
+                let bpm = match stats.bpm {
+                    Some((min, max)) if min.round() != max.round() => {
+                        format!("{:.0}-{:.0}", min, max)
+                    }
+                    Some((bpm, _)) => format!("{:.0}", bpm),
+                    None => "Not defined".to_string(),
+                };
+
+                lore.push(format!(
+                    r#"{{"text": "Length: {length} (drain: {drain_time})   BPM: {bpm}", "color": "gray"}}"#
+                ));
+                lore.push(format!(
+                    r#"{{"text": "Circles: {}   Sliders: {}   Spinners: {}", "color": "gray"}}"#,
+                    stats.circles, stats.sliders, stats.spinners
+                ));
+            }
+
+            if let Some(problem) = beatmap.problem() {
+                lore.push(r#"{"text": ""}"#.to_string());
+                // The reason can have quotes or backslashes (e.g. Windows paths)
+                let reason = format!("Can't be played: {}", problem);
+                lore.push(serde_json::json!({ "text": reason, "color": "red" }).to_string());
+            }
+
+            if beatmap.length > configs.max_map_length() {
+                let max_length = format_duration(configs.max_map_length());
+                lore.push(r#"{"text": ""}"#.to_string());
+                lore.push(format!(
+                    r#"{{"text": "Map too long to be played (max: {max_length})", "color": "red"}}"#
+                ));
+            }
+
+            let (item_kind, name) = match beatmap.star_rating(*mods) {
+                _ if beatmap.problem().is_some() => (
+                    ItemKind::GrayDye,
+                    format!(r#"{{"text": "{title} [{difficulty_name}]", "color": "dark_gray"}}"#),
+                ),
+                Some(star_rating) => {
+                    let color = star_rating_color(star_rating);
+                    (
+                        DifficultyTier::from(star_rating).item_kind(),
+                        format!(
+                            r#"{{"text": "{title} [{difficulty_name}] ({:.2}*)", "color": "{}"}}"#,
+                            star_rating,
+                            color.to_hex()
+                        ),
+                    )
+                }
+                None => (
+                    ItemKind::Map,
+                    format!(r#"{{"text": "{title} [{difficulty_name}]", "color": "gold"}}"#),
+                ),
+            };
 
             let item = ItemStack::new(
-                ItemKind::Map,
+                item_kind,
                 1,
                 Some(compound! {
                     "display" => compound! {
-                        "Name" => format!(r#"{{"text": "{title} [{difficulty_name}]", "color": "gold"}}"#),
-                        "Lore" => List::String(vec![
-                            format!(r#"{{"text": "Artist: {artist}", "color": "gray"}}"#),
-                            format!(r#"{{"text": ""}}"#),
-                            format!(r#"{{"text": "======= Difficulty =======", "color": "gray"}}"#),
-                            format!(r#"{{"text": "AR: {ar}   OD: {od}   HP: {hp}   CS: {cs}", "color": "gray"}}"#),
-                        ])
+                        "Name" => name,
+                        "Lore" => List::String(lore)
                     }
                 }),
             );
@@ -188,9 +511,12 @@ pub fn handle_beatmap_selection_clicks(
     song_selections: Query<Entity, (With<SongSelectionInventory>, With<Inventory>)>,
     open_inventories: Query<&OpenInventory, With<Client>>,
     mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
     mut osu: ResMut<Osu>,
     mut inventories_to_open: ResMut<InventoriesToOpen>,
     mut click_events: EventReader<ClickContainer>,
+    configs: Res<Configs>,
+    mut lobby: ResMut<Lobby>,
 ) {
     for click in click_events.iter() {
         // Check if the click occured on a beatmap selection
@@ -218,23 +544,100 @@ pub fn handle_beatmap_selection_clicks(
                         );
                     }
                 }
-            } else if let Some(selected_beatmap) = beatmap_selection.beatmaps.get(slot as usize) {
-                // Close beatmap selection
-                commands.entity(click.client).remove::<OpenInventory>();
+            } else if let Some(selected_beatmap) =
+                beatmap_selection.visible_beatmaps().nth(slot as usize)
+            {
+                // Refuse maps which would fail to start, with the reason
+                if let Some(problem) = selected_beatmap.problem() {
+                    if let Ok(mut client) = clients.get_mut(click.client) {
+                        client.send_message(OsuError::bad_beatmap(problem).message());
+                    }
+                    continue;
+                }
+
+                // Refuse maps longer than the configured max length
+                if selected_beatmap.length > configs.max_map_length() {
+                    if let Ok(mut client) = clients.get_mut(click.client) {
+                        client.send_message(
+                            format!(
+                                "This map is too long to be played in this server ({} > {}). Increase 'max_map_length_secs' in '{}' to play it.",
+                                format_duration(selected_beatmap.length),
+                                format_duration(configs.max_map_length()),
+                                Configs::path().display()
+                            )
+                            .color(Color::RED),
+                        );
+                    }
+                    continue;
+                }
+
+                // The lobby host picks the map and starts it once everyone is ready (with `/lobby start`)
+                if lobby.is_active() {
+                    let name = selected_beatmap.display_name();
+                    match lobby.pick(click.client, selected_beatmap.path.clone(), name.clone()) {
+                        Ok(_) => {
+                            let message = "The host picked ".color(Color::YELLOW)
+                                + name.color(Color::GREEN)
+                                + ", use /lobby ready".color(Color::GRAY);
+                            for mut client in &mut clients {
+                                client.send_message(message.clone());
+                            }
+                        }
+                        Err(error) => {
+                            if let Ok(mut client) = clients.get_mut(click.client) {
+                                client.send_message(
+                                    format!("Error while picking the map: '{}'", error)
+                                        .color(Color::RED),
+                                );
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                let is_operator = player_names
+                    .get(click.client)
+                    .map_or(false, |player_name| configs.is_operator(player_name));
 
                 // Play map
-                if let Err(error) = osu.change_state(
+                if let Err(error) = osu.change_state_for(
                     OsuStateChange::PrePlaying {
                         beatmap_path: selected_beatmap.path.clone(),
                     },
+                    is_operator,
                     &mut clients,
                 ) {
-                    error!(
-                        "Error while changing to Playing state while on beatmap selection: '{}'",
-                        error
-                    );
+                    // The players can pick another difficulty for the errors of the beatmap
+                    if error.downcast_ref::<OsuError>().is_some() {
+                        if let Ok(mut client) = clients.get_mut(click.client) {
+                            client.send_message(error_message(&error));
+                        }
+                    } else {
+                        error!(
+                            "Error while changing to Playing state while on beatmap selection: '{}'",
+                            error
+                        );
+                    }
+                } else {
+                    // Close beatmap selection
+                    commands.entity(click.client).remove::<OpenInventory>();
                 }
             }
         }
     }
 }
+
+/// Formats a difficulty setting with at most 2 decimals (e.g. `9.33` or `10`)
+pub fn format_attribute(value: f64) -> String {
+    let formatted = format!("{:.2}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Formats the duration as `m:ss`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
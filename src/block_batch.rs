@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use valence::prelude::{Block, BlockPos, BlockState, Instance};
+
+/// Block changes collected to be applied at once. Repeated positions are merged (the last block wins),
+/// changes are applied chunk by chunk and blocks which are already in place are skipped, so they don't generate block update packets.
+#[derive(Default)]
+pub struct BlockBatch {
+    blocks: HashMap<BlockPos, BlockState>,
+}
+
+impl BlockBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, pos: BlockPos, block: BlockState) {
+        self.blocks.insert(pos, block);
+    }
+
+    /// Sets every position in `positions` to `block`
+    pub fn fill(&mut self, positions: impl IntoIterator<Item = BlockPos>, block: BlockState) {
+        self.blocks
+            .extend(positions.into_iter().map(|pos| (pos, block)));
+    }
+
+    /// Adds the changes of `other`, which win over the ones already in the batch
+    pub fn merge(&mut self, other: BlockBatch) {
+        self.blocks.extend(other.blocks);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Applies the changes to `instance`, returning how many blocks were actually changed
+    pub fn apply(mut self, instance: &mut Instance) -> usize {
+        self.apply_limited(instance, usize::MAX)
+    }
+
+    /// Applies at most `max_changes` block changes to `instance` and keeps the remaining ones in the batch,
+    /// so they can be applied in the next ticks. Returns how many blocks were actually changed.
+    pub fn apply_limited(&mut self, instance: &mut Instance, max_changes: usize) -> usize {
+        let mut blocks: Vec<_> = self.blocks.drain().collect();
+        blocks.sort_unstable_by_key(|(pos, _)| chunk_order(*pos));
+
+        let mut changed = 0;
+        for (pos, block) in blocks {
+            if changed >= max_changes {
+                self.blocks.insert(pos, block);
+            } else if instance.block(pos).map(|current| current.state()) != Some(block) {
+                instance.set_block(pos, Block::new(block));
+                changed += 1;
+            }
+        }
+
+        changed
+    }
+}
+
+/// Sort key which keeps the blocks of the same chunk together
+fn chunk_order(pos: BlockPos) -> (i32, i32, i32, i32, i32) {
+    (
+        pos.x.div_euclid(16),
+        pos.z.div_euclid(16),
+        pos.y,
+        pos.x,
+        pos.z,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_repeated_positions() {
+        let mut batch = BlockBatch::new();
+        let pos = BlockPos { x: 1, y: 2, z: 3 };
+
+        batch.fill(
+            [pos, BlockPos { x: 2, y: 2, z: 3 }],
+            BlockState::BLACK_CONCRETE,
+        );
+        batch.set(pos, BlockState::WHITE_CONCRETE);
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.blocks[&pos], BlockState::WHITE_CONCRETE);
+
+        let mut newer = BlockBatch::new();
+        newer.set(pos, BlockState::RED_CONCRETE);
+        batch.merge(newer);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.blocks[&pos], BlockState::RED_CONCRETE);
+    }
+
+    #[test]
+    fn chunk_ordering() {
+        let mut positions = vec![
+            BlockPos { x: 17, y: 0, z: 0 },
+            BlockPos { x: -1, y: 0, z: 0 },
+            BlockPos { x: 0, y: 5, z: 0 },
+            BlockPos { x: 15, y: 1, z: 0 },
+        ];
+        positions.sort_unstable_by_key(|&pos| chunk_order(pos));
+
+        assert_eq!(
+            positions,
+            vec![
+                BlockPos { x: -1, y: 0, z: 0 },
+                BlockPos { x: 15, y: 1, z: 0 },
+                BlockPos { x: 0, y: 5, z: 0 },
+                BlockPos { x: 17, y: 0, z: 0 },
+            ]
+        );
+    }
+}
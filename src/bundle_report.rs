@@ -0,0 +1,169 @@
+use anyhow::Result;
+use std::{
+    backtrace::Backtrace,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    panic,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    configs::Configs, minecraft::MINECRAFT_VERSION, song_selection::SongSelectionInventory,
+};
+
+pub const LOG_PATH: &str = "osucraft.log";
+pub const CRASH_REPORT_PATH: &str = "crash-report.txt";
+/// Only the end of the log file is bundled, since it may grow large on long running servers
+const RECENT_LOG_BYTES: u64 = 256 * 1024;
+/// Configs left out of the bundled configs, since the bundle is meant to be attached to public issues (the webhook url
+/// includes its token)
+const SECRET_CONFIGS: [&str; 2] = ["webhook_url", "leaderboard_secret"];
+
+/// Log file where the server logs are written in addition to the terminal
+pub fn open_log_file() -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LOG_PATH)?)
+}
+
+/// Writes a crash report with the panic message and backtrace whenever the server panics
+pub fn install_crash_reporter() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let report = format!(
+            "{}\n\n{}\n\n{}",
+            version_info(),
+            info,
+            Backtrace::force_capture()
+        );
+        if let Err(error) = fs::write(CRASH_REPORT_PATH, report) {
+            eprintln!("Error while writing crash report: {}", error);
+        }
+    }));
+}
+
+/// Zips everything needed to report an issue (configs, recent logs, library summary, version info and the last crash report).
+/// Returns the path of the created file.
+pub fn write_bundle_report(
+    configs: &Configs,
+    song_selection: Option<&SongSelectionInventory>,
+) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = PathBuf::from(format!("bundle-report-{timestamp}.zip"));
+
+    let mut zip = ZipWriter::new(File::create(&path)?);
+    let options = FileOptions::default();
+
+    zip.start_file("version.txt", options)?;
+    zip.write_all(version_info().as_bytes())?;
+
+    zip.start_file("configs.json", options)?;
+    zip.write_all(redacted_configs(configs)?.as_bytes())?;
+
+    zip.start_file("library.txt", options)?;
+    zip.write_all(library_summary(configs, song_selection).as_bytes())?;
+
+    if let Ok(logs) = read_recent_logs() {
+        zip.start_file(LOG_PATH, options)?;
+        zip.write_all(&logs)?;
+    }
+
+    if let Ok(crash_report) = fs::read(CRASH_REPORT_PATH) {
+        zip.start_file(CRASH_REPORT_PATH, options)?;
+        zip.write_all(&crash_report)?;
+    }
+
+    zip.finish()?;
+
+    Ok(path)
+}
+
+/// Configs file without the `SECRET_CONFIGS`
+fn redacted_configs(configs: &Configs) -> Result<String> {
+    let mut json = serde_json::to_value(configs)?;
+    if let Some(fields) = json.as_object_mut() {
+        for secret in SECRET_CONFIGS {
+            fields.remove(secret);
+        }
+    }
+
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+fn version_info() -> String {
+    format!(
+        "osucraft: {}\nMinecraft: {}\nOS: {} ({})",
+        env!("CARGO_PKG_VERSION"),
+        MINECRAFT_VERSION,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn library_summary(configs: &Configs, song_selection: Option<&SongSelectionInventory>) -> String {
+    let songs_dir = PathBuf::from(configs.songs_directory());
+    let mut summary = format!(
+        "Songs directory: {} (exists: {})\n",
+        songs_dir.display(),
+        songs_dir.is_dir()
+    );
+
+    match song_selection {
+        Some(song_selection) => {
+            let songs = song_selection.songs();
+            summary += &format!("Songs matching the current filters: {}\n\n", songs.len());
+            for song in songs {
+                summary += &format!("{}\n", song.display());
+            }
+        }
+        None => summary += "Song selection not loaded\n",
+    }
+
+    summary
+}
+
+fn read_recent_logs() -> Result<Vec<u8>> {
+    let mut file = File::open(LOG_PATH)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(RECENT_LOG_BYTES)))?;
+
+    let mut logs = Vec::new();
+    file.read_to_end(&mut logs)?;
+
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configs_without_secrets() {
+        let configs: Configs = serde_json::from_value(serde_json::json!({
+            "songs_directory": "songs",
+            "webhook_url": "https://discord.com/api/webhooks/123/token",
+            "leaderboard_url": "https://leaderboard.example.com",
+            "leaderboard_secret": "secret",
+        }))
+        .unwrap();
+
+        let redacted = redacted_configs(&configs).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&redacted).unwrap();
+        for secret in SECRET_CONFIGS {
+            assert!(json.get(secret).is_none());
+        }
+        assert!(!redacted.contains("token"));
+        assert!(!redacted.contains("\"secret\""));
+        assert_eq!(json["songs_directory"], "songs");
+        assert_eq!(json["leaderboard_url"], "https://leaderboard.example.com");
+    }
+}
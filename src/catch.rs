@@ -0,0 +1,332 @@
+use anyhow::Result;
+use std::{collections::VecDeque, time::Duration};
+
+use bevy_ecs::prelude::Entity;
+use valence::prelude::{BlockPos, BlockState, Client, DVec3};
+
+use crate::{
+    beatmap::{Beatmap, CircleSize},
+    block_batch::BlockBatch,
+    game_mode::{GameModeContext, GameModeHandler, GameModeHit, GameModeInput},
+    hit_object::HitObject,
+    hit_score::HitScore,
+    hitcircle::HitcircleRadius,
+    playfield::Playfield,
+};
+
+/// Width in osu!pixels of the catcher with CS 5 (https://osu.ppy.sh/wiki/en/Beatmap/Circle_size)
+const CATCHER_BASE_WIDTH: f64 = 106.75;
+/// Part of the catcher width which catches fruits, like in osu!
+const CATCHER_CATCH_RANGE: f64 = 0.8;
+/// osu!pixels of the playfield width
+const PLAYFIELD_WIDTH: f64 = 512.0;
+/// Blocks in front of and behind the rail where players are still considered on it
+const RAIL_REACH: f64 = 4.0;
+
+/// osu!catch: the fruits fall down the screen at the x of their hit objects and are caught at the bottom of the screen by the
+/// catchers, which follow the players walking left and right along a rail in front of the playfield. The rail spans the width
+/// of the screen, so the position of a player on the rail is the position of their catcher under the screen.
+///
+/// A fruit is caught (300) if it reaches the bottom of the screen over the catcher of a player on the rail, otherwise it's a miss.
+#[derive(Default)]
+pub struct CatchMode {
+    fruits: Vec<Fruit>,
+    /// Catcher width in osu!pixels of the beatmap being played
+    catcher_width: f64,
+    /// Blocks drawn in the previous tick
+    drawn: Vec<BlockPos>,
+    /// The rail is built in the first play and stays in the instance
+    rail_built: bool,
+}
+
+struct Fruit {
+    entity: Entity,
+    x: u32,
+    time: u32,
+    block: BlockState,
+    /// It reached the bottom of the screen without being caught
+    missed: bool,
+}
+
+impl CatchMode {
+    /// Row of blocks under the players, on the playfield spawn
+    fn rail_positions(playfield: &Playfield) -> impl Iterator<Item = BlockPos> {
+        let spawn_pos = playfield.player_spawn_pos();
+        let (screen_x, _) = playfield.screen_size();
+        let (min_x, y, z) = (
+            playfield.origin().x,
+            spawn_pos.y.floor() as i32 - 1,
+            spawn_pos.z.floor() as i32,
+        );
+
+        (min_x..=min_x + screen_x)
+            .flat_map(move |x| (z - 1..=z + 1).map(move |z| BlockPos { x, y, z }))
+    }
+
+    fn build_rail(context: &mut GameModeContext) {
+        let playfield = context.playfield;
+        let spawn_pos = playfield.player_spawn_pos();
+        let (screen_x, _) = playfield.screen_size();
+        let rail_center = DVec3::new(
+            playfield.origin().x as f64 + screen_x as f64 / 2.0,
+            spawn_pos.y,
+            spawn_pos.z,
+        );
+        // Loads the chunks under the rail
+        for z_offset in [-1.0, 1.0] {
+            playfield.secure_hit_object_pos(
+                rail_center + DVec3::new(0.0, 0.0, z_offset),
+                screen_x as f64 / 2.0,
+                context.instance.1,
+            );
+        }
+
+        let mut batch = BlockBatch::new();
+        batch.fill(Self::rail_positions(playfield), BlockState::BLACK_CONCRETE);
+        batch.apply(context.instance.1);
+    }
+
+    /// Catcher x in osu!pixels of the players standing on the rail
+    fn catchers<'a>(
+        playfield: &'a Playfield,
+        players: &'a [(Entity, DVec3)],
+    ) -> impl Iterator<Item = (Entity, f64)> + 'a {
+        let rail_z = playfield.player_spawn_pos().z;
+        players
+            .iter()
+            .filter(move |(_, pos)| (pos.z - rail_z).abs() <= RAIL_REACH)
+            .map(move |&(player, pos)| (player, playfield.osu_x(pos.x)))
+    }
+
+    /// Draws the fruits falling down the screen and the catchers at the bottom
+    fn draw(&mut self, play_ms: i64, look_ahead_ms: i64, context: &mut GameModeContext) {
+        let playfield = context.playfield;
+        let origin = playfield.origin();
+        let (screen_x, screen_y) = playfield.screen_size();
+        let (_, margin_y) = playfield.screen_margin();
+        let (line_y, top_y) = (margin_y, margin_y + screen_y);
+        let fruit_radius = (HitcircleRadius::from(context.beatmap.cs(), playfield.scale()).circle
+            / 2.0)
+            .max(1.0) as i32;
+        let block_x = |osu_x: f64| origin.x + screen_x - (osu_x * playfield.scale()).round() as i32;
+
+        // Blocks of the previous tick which aren't drawn again are cleared
+        let mut batch = BlockBatch::new();
+        batch.fill(self.drawn.drain(..), BlockState::AIR);
+        let mut frame = Vec::new();
+
+        for fruit in self.fruits.iter().filter(|fruit| !fruit.missed) {
+            let center_x = block_x(fruit.x as f64);
+            let center_y = fruit_row(fruit.time as i64, play_ms, look_ahead_ms, line_y, screen_y)
+                + fruit_radius
+                + 1;
+            for y in
+                (center_y - fruit_radius).max(line_y + 1)..=(center_y + fruit_radius).min(top_y)
+            {
+                for x in center_x - fruit_radius..=center_x + fruit_radius {
+                    frame.push((
+                        BlockPos {
+                            x,
+                            y: origin.y + y,
+                            z: origin.z,
+                        },
+                        fruit.block,
+                    ));
+                }
+            }
+        }
+
+        let half_width = (self.catcher_width / 2.0 * playfield.scale()).round() as i32;
+        for (_, catcher_x) in Self::catchers(playfield, context.players) {
+            let center_x = block_x(catcher_x.clamp(0.0, PLAYFIELD_WIDTH));
+            for x in center_x - half_width..=center_x + half_width {
+                frame.push((
+                    BlockPos {
+                        x,
+                        y: origin.y + line_y,
+                        z: origin.z,
+                    },
+                    BlockState::OAK_PLANKS,
+                ));
+            }
+        }
+
+        for (pos, block) in frame {
+            batch.set(pos, block);
+            self.drawn.push(pos);
+        }
+        batch.apply(context.instance.1);
+    }
+}
+
+impl GameModeHandler for CatchMode {
+    fn name(&self) -> &str {
+        "osu!catch"
+    }
+
+    fn start(&mut self, beatmap: &Beatmap) {
+        self.fruits.clear();
+        self.catcher_width = catcher_width(beatmap.data.cs);
+        // The playfield was reset after the previous play
+        self.drawn.clear();
+    }
+
+    fn player_start_pos(&self, playfield: &Playfield) -> Option<DVec3> {
+        let (screen_x, _) = playfield.screen_size();
+        let spawn_pos = playfield.player_spawn_pos();
+
+        Some(DVec3::new(
+            playfield.origin().x as f64 + screen_x as f64 / 2.0,
+            spawn_pos.y,
+            spawn_pos.z,
+        ))
+    }
+
+    fn spawn_hit_object(
+        &mut self,
+        hit_object: &HitObject,
+        context: &mut GameModeContext,
+    ) -> Result<Entity> {
+        let entity = context.commands.spawn_empty().id();
+        self.fruits.push(Fruit {
+            entity,
+            x: hit_object.x(),
+            time: hit_object.time(),
+            block: hit_object.color().to_block_color().block().state(),
+            missed: false,
+        });
+
+        Ok(entity)
+    }
+
+    /// Fruits are caught by moving, so clicks and hotbar slots are ignored
+    fn judge_input(
+        &mut self,
+        _input: GameModeInput,
+        _player: Entity,
+        _client: &Client,
+        _input_offset_ms: i32,
+        _active_hit_objects: &VecDeque<Entity>,
+        _context: &mut GameModeContext,
+    ) -> Vec<GameModeHit> {
+        Vec::new()
+    }
+
+    fn update(&mut self, context: &mut GameModeContext) -> Vec<GameModeHit> {
+        if !self.rail_built {
+            Self::build_rail(context);
+            self.rail_built = true;
+        }
+
+        let play_ms = context.beatmap.state.play_time.as_millis() as i64;
+        let look_ahead_ms = self.look_ahead(context.beatmap).as_millis().max(1) as i64;
+
+        // Fruits reaching the bottom of the screen
+        let mut hits = Vec::new();
+        for fruit in self
+            .fruits
+            .iter_mut()
+            .filter(|fruit| !fruit.missed && fruit.time as i64 <= play_ms)
+        {
+            let catcher = Self::catchers(context.playfield, context.players)
+                .map(|(player, catcher_x)| (player, (catcher_x - fruit.x as f64).abs()))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .filter(|&(_, distance)| is_caught(distance, self.catcher_width));
+
+            match catcher {
+                Some((player, _)) => hits.push(GameModeHit {
+                    entity: fruit.entity,
+                    player,
+                    hit: HitScore::Hit300,
+                    hit_error_ms: None,
+                }),
+                // Judged as a miss by `is_expired`
+                None => fruit.missed = true,
+            }
+        }
+
+        let mut missed = Vec::new();
+        self.fruits.retain(|fruit| {
+            if fruit.missed {
+                missed.push(fruit.entity);
+            }
+            !fruit.missed
+        });
+        for entity in missed {
+            context.commands.entity(entity).despawn();
+        }
+
+        self.draw(play_ms, look_ahead_ms, context);
+
+        hits
+    }
+
+    fn is_expired(&self, entity: Entity, _play_time: Duration) -> bool {
+        !self.fruits.iter().any(|fruit| fruit.entity == entity)
+    }
+
+    fn despawn_hit_object(
+        &mut self,
+        entity: Entity,
+        _hit: HitScore,
+        context: &mut GameModeContext,
+    ) {
+        self.fruits.retain(|fruit| fruit.entity != entity);
+        context.commands.entity(entity).despawn();
+    }
+}
+
+/// Width in osu!pixels of the catcher (https://osu.ppy.sh/wiki/en/Beatmap/Circle_size)
+fn catcher_width(cs: CircleSize) -> f64 {
+    CATCHER_BASE_WIDTH * (1.0 - 0.7 * (cs.0 - 5.0) / 5.0)
+}
+
+/// Whether a fruit `distance` osu!pixels away from the center of a catcher of `catcher_width` is caught
+fn is_caught(distance: f64, catcher_width: f64) -> bool {
+    distance <= catcher_width * CATCHER_CATCH_RANGE / 2.0
+}
+
+/// Row of a fruit of `time` at `play_ms`: it reaches `line_y` on time, after falling from `height` rows above for `look_ahead_ms`
+fn fruit_row(time: i64, play_ms: i64, look_ahead_ms: i64, line_y: i32, height: i32) -> i32 {
+    line_y + ((time - play_ms) * height as i64 / look_ahead_ms) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catching_fruits() {
+        let width = catcher_width(CircleSize(5.0));
+        assert_eq!(width, CATCHER_BASE_WIDTH);
+        assert!(catcher_width(CircleSize(7.0)) < width);
+        assert!(is_caught(0.0, width));
+        assert!(is_caught(40.0, width));
+        assert!(!is_caught(60.0, width));
+
+        assert_eq!(fruit_row(1000, 1000, 800, 72, 144), 72);
+        assert_eq!(fruit_row(1000, 200, 800, 72, 144), 216);
+    }
+
+    #[test]
+    fn catcher_follows_player_on_rail() {
+        let playfield = Playfield::new(BlockPos { x: 500, y: 0, z: 0 }, 0.3);
+        let (screen_x, _) = playfield.screen_size();
+        let spawn_pos = playfield.player_spawn_pos();
+        let player = Entity::from_raw(0);
+
+        let left = DVec3::new(
+            (playfield.origin().x + screen_x) as f64,
+            spawn_pos.y,
+            spawn_pos.z,
+        );
+        let away = DVec3::new(left.x, spawn_pos.y, spawn_pos.z - 50.0);
+        let players = [(player, left), (Entity::from_raw(1), away)];
+
+        let catchers: Vec<_> = CatchMode::catchers(&playfield, &players).collect();
+        assert_eq!(catchers.len(), 1);
+        assert_eq!(catchers[0], (player, 0.0));
+        let fruit_x = playfield.hit_object_pos(300.0, 0.0, 0.0).x;
+        assert!((playfield.osu_x(fruit_x) - 300.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Query, Res, ResMut, Resource},
+};
+use tracing::warn;
+use valence::{
+    client::event::ClickContainer,
+    nbt::{compound, List},
+    prelude::{Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{ItemKind, ItemStack, TextFormat},
+};
+
+use crate::{
+    inventory::{open_new_inventory, InventoriesToOpen},
+    osu::{Osu, OsuStateChange},
+    song_selection::SongSelectionInventory,
+};
+
+/// Collection where the songs shift-clicked in the song selection are added
+pub const FAVORITES: &str = "Favorites";
+const ALL_SONGS_SLOT: u16 = 53;
+/// Maximum number of collections shown in the browser (the last slot goes back to all songs)
+const MAX_DISPLAYED_COLLECTIONS: usize = ALL_SONGS_SLOT as usize;
+
+/// Named lists of song directories (e.g. favorites or the map pool of a match), persisted to `collections.json`.
+/// Collections are shared by every player, like the song selection.
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct Collections {
+    collections: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Collections {
+    pub fn path() -> PathBuf {
+        PathBuf::from("collections.json")
+    }
+
+    pub fn open() -> Self {
+        let path = Self::path();
+        if !path.exists() {
+            return Self::default();
+        }
+
+        Self::read().unwrap_or_else(|error| {
+            warn!("Error while reading collections file: {}", error);
+            Self::default()
+        })
+    }
+
+    fn read() -> Result<Self> {
+        let file_data = fs::read(Self::path())?;
+        let json = str::from_utf8(file_data.as_slice())?;
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Collections sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[PathBuf])> {
+        self.collections
+            .iter()
+            .map(|(name, songs)| (name.as_str(), songs.as_slice()))
+    }
+
+    /// Songs of the collection in the order they were added
+    pub fn get(&self, name: &str) -> Option<&[PathBuf]> {
+        self.collections.get(name).map(|songs| songs.as_slice())
+    }
+
+    pub fn create(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            bail!("collection name can't be empty");
+        }
+        if self.collections.contains_key(name) {
+            bail!("collection '{}' already exists", name);
+        }
+
+        self.collections.insert(name.to_string(), Vec::new());
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        self.collections
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    /// The favorites collection is created when the first song is added
+    pub fn add(&mut self, name: &str, song: &Path) -> Result<()> {
+        let songs = if name == FAVORITES {
+            self.collections.entry(name.to_string()).or_default()
+        } else {
+            self.collections
+                .get_mut(name)
+                .ok_or_else(|| not_found(name))?
+        };
+
+        if songs.iter().any(|other| other == song) {
+            bail!("the song is already in '{}'", name);
+        }
+
+        songs.push(song.to_path_buf());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str, song: &Path) -> Result<()> {
+        let songs = self
+            .collections
+            .get_mut(name)
+            .ok_or_else(|| not_found(name))?;
+        let idx = songs
+            .iter()
+            .position(|other| other == song)
+            .ok_or_else(|| anyhow!("the song is not in '{}'", name))?;
+
+        songs.remove(idx);
+        Ok(())
+    }
+
+    /// Adds the song to the favorites or removes it if it was already there, returning whether it's a favorite now
+    pub fn toggle_favorite(&mut self, song: &Path) -> bool {
+        match self.remove(FAVORITES, song) {
+            Ok(_) => false,
+            Err(_) => self.add(FAVORITES, song).is_ok(),
+        }
+    }
+}
+
+fn not_found(name: &str) -> anyhow::Error {
+    anyhow!("collection '{}' not found", name)
+}
+
+/// Arguments of the `/collection` command
+#[derive(Debug, PartialEq, Eq)]
+pub enum CollectionCommand {
+    /// Opens the collection browser
+    Browse,
+    Create(String),
+    Delete(String),
+    /// Adds the selected song to a collection
+    Add(String),
+    /// Removes the selected song from a collection
+    Remove(String),
+    /// Shows only the songs of a collection in the song selection, in its order
+    Play(String),
+}
+
+impl CollectionCommand {
+    pub fn parse(args: &str) -> Result<Self> {
+        let args = args.trim();
+        let (action, name) = args.split_once(' ').unwrap_or((args, ""));
+        let name = name.trim().to_string();
+
+        let command: fn(String) -> Self = match action {
+            "" => return Ok(Self::Browse),
+            "create" => Self::Create,
+            "delete" => Self::Delete,
+            "add" => Self::Add,
+            "remove" => Self::Remove,
+            "play" => Self::Play,
+            _ => bail!(
+                "unknown action '{}' (expected create, delete, add, remove or play)",
+                action
+            ),
+        };
+
+        if name.is_empty() {
+            bail!("missing collection name (e.g. /collection {} pool)", action);
+        }
+
+        Ok(command(name))
+    }
+}
+
+/// Inventory listing the collections. Clicking one shows only its songs in the song selection.
+#[derive(Component)]
+pub struct CollectionBrowserInventory {
+    /// Collection displayed in each slot
+    names: Vec<String>,
+}
+
+impl CollectionBrowserInventory {
+    fn new() -> (Self, Inventory) {
+        (
+            Self { names: Vec::new() },
+            Inventory::with_title(
+                InventoryKind::Generic9x6,
+                "Collections".color(Color::DARK_BLUE),
+            ),
+        )
+    }
+
+    fn draw(&mut self, collections: &Collections, inventory: &mut Inventory) {
+        for slot in 0..=ALL_SONGS_SLOT {
+            inventory.replace_slot(slot, None);
+        }
+
+        self.names.clear();
+        for (slot, (name, songs)) in collections
+            .iter()
+            .take(MAX_DISPLAYED_COLLECTIONS)
+            .enumerate()
+        {
+            let item_kind = if name == FAVORITES {
+                ItemKind::NetherStar
+            } else {
+                ItemKind::Bookshelf
+            };
+            let item = ItemStack::new(
+                item_kind,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => format!(r#"{{"text": "{}", "color": "gold"}}"#, name),
+                        "Lore" => List::String(vec![
+                            format!(r#"{{"text": "{} songs", "color": "gray"}}"#, songs.len()),
+                        ])
+                    }
+                }),
+            );
+
+            inventory.replace_slot(slot as u16, Some(item));
+            self.names.push(name.to_string());
+        }
+
+        let item = ItemStack::new(
+            ItemKind::Jukebox,
+            1,
+            Some(compound! {"display" => compound! {
+                "Name" => r#"{"text": "All songs", "color": "green"}"#.to_string(),
+                "Lore" => List::String(vec![r#"{"text": "Show every song in the song selection", "color": "gray"}"#.to_string()]),
+            }}),
+        );
+        inventory.replace_slot(ALL_SONGS_SLOT, Some(item));
+    }
+}
+
+/// Opens the collection browser, creating it if needed
+pub fn open_collection_browser(
+    commands: &mut Commands,
+    client: Entity,
+    collections: &Collections,
+    inventories_to_open: &mut ResMut<InventoriesToOpen>,
+    browsers: &mut Query<(Entity, &mut CollectionBrowserInventory, &mut Inventory)>,
+) {
+    let inventory_entity = match browsers.iter_mut().next() {
+        Some((entity, mut browser, mut inventory)) => {
+            browser.draw(collections, &mut inventory);
+            entity
+        }
+        None => {
+            let (mut browser, mut inventory) = CollectionBrowserInventory::new();
+            browser.draw(collections, &mut inventory);
+            commands.spawn((browser, inventory)).id()
+        }
+    };
+
+    open_new_inventory(commands, client, inventories_to_open, inventory_entity);
+}
+
+/// Shows only the songs of the collection `name` (or every song if `None`) in the song selection and opens it for `client`
+pub fn play_collection(
+    name: Option<&str>,
+    client: Entity,
+    collections: &Collections,
+    (song_selection_entity, song_selection): (Entity, &mut SongSelectionInventory),
+    commands: &mut Commands,
+    inventories_to_open: &mut ResMut<InventoriesToOpen>,
+    osu: &mut Osu,
+    clients: &mut Query<&mut Client>,
+) -> Result<()> {
+    let collection = match name {
+        Some(name) => {
+            let songs = collections.get(name).ok_or_else(|| not_found(name))?;
+            Some((name.to_string(), songs.to_vec()))
+        }
+        None => None,
+    };
+    song_selection.set_collection(collection)?;
+
+    open_new_inventory(commands, client, inventories_to_open, song_selection_entity);
+    osu.change_state(OsuStateChange::SongSelection, clients)
+}
+
+pub fn handle_collection_browser_clicks(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    mut osu: ResMut<Osu>,
+    collections: Res<Collections>,
+    open_inventories: Query<&OpenInventory, With<Client>>,
+    browsers: Query<&CollectionBrowserInventory>,
+    mut song_selections: Query<(Entity, &mut SongSelectionInventory)>,
+    mut clients: Query<&mut Client>,
+    mut clicks: EventReader<ClickContainer>,
+) {
+    for click in clicks.iter() {
+        let Ok(browser) = open_inventories
+            .get(click.client)
+            .and_then(|open_inventory| browsers.get(open_inventory.entity()))
+        else {
+            continue;
+        };
+
+        let slot = click.slot_id.unsigned_abs();
+        let name = if slot == ALL_SONGS_SLOT {
+            None
+        } else {
+            match browser.names.get(slot as usize) {
+                Some(name) => Some(name.as_str()),
+                None => continue,
+            }
+        };
+
+        if !osu.is_selecting_beatmap() {
+            if let Ok(mut client) = clients.get_mut(click.client) {
+                client.send_message(
+                    "Collections can only be opened while selecting a beatmap".color(Color::RED),
+                );
+            }
+            continue;
+        }
+
+        let Ok((song_selection_entity, mut song_selection)) = song_selections.get_single_mut()
+        else {
+            continue;
+        };
+
+        if let Err(error) = play_collection(
+            name,
+            click.client,
+            &collections,
+            (song_selection_entity, &mut song_selection),
+            &mut commands,
+            &mut inventories_to_open,
+            &mut osu,
+            &mut clients,
+        ) {
+            if let Ok(mut client) = clients.get_mut(click.client) {
+                client.send_message(
+                    format!("Error while opening the collection: '{}'", error).color(Color::RED),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn manage_collections() {
+        let mut collections = Collections::default();
+        let song = Path::new("songs/1 Artist - Title");
+
+        assert!(collections.add("pool", song).is_err());
+        collections.create("pool").unwrap();
+        assert!(collections.create("pool").is_err());
+        collections.add("pool", song).unwrap();
+        assert!(collections.add("pool", song).is_err());
+        assert_eq!(collections.get("pool"), Some(&[song.to_path_buf()][..]));
+
+        assert!(collections.toggle_favorite(song));
+        assert_eq!(collections.get(FAVORITES).map(|songs| songs.len()), Some(1));
+        assert!(!collections.toggle_favorite(song));
+        assert_eq!(collections.get(FAVORITES).map(|songs| songs.len()), Some(0));
+
+        collections.remove("pool", song).unwrap();
+        collections.delete("pool").unwrap();
+        assert!(collections.delete("pool").is_err());
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(
+            CollectionCommand::parse("").unwrap(),
+            CollectionCommand::Browse
+        );
+        assert_eq!(
+            CollectionCommand::parse("create  tournament pool ").unwrap(),
+            CollectionCommand::Create("tournament pool".to_string())
+        );
+        assert!(CollectionCommand::parse("play").is_err());
+        assert!(CollectionCommand::parse("rename pool").is_err());
+    }
+}
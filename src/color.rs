@@ -24,8 +24,41 @@ impl Color {
             + self.b.abs_diff(color.b) as u32
     }
 
+    /// Perceived brightness, from 0 (black) to 1 (white)
+    pub fn luminance(self) -> f64 {
+        (0.2126 * self.r as f64 + 0.7152 * self.g as f64 + 0.0722 * self.b as f64) / 255.0
+    }
+
+    /// `#rrggbb`, as used by text components
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Color between `self` (`t = 0`) and `other` (`t = 1`), interpolated with gamma 2.2 like the osu! website
+    pub fn lerp(self, other: Color, t: f64) -> Color {
+        const GAMMA: f64 = 2.2;
+        let channel = |a: u8, b: u8| {
+            let a = (a as f64 / 255.0).powf(GAMMA);
+            let b = (b as f64 / 255.0).powf(GAMMA);
+            let value = (a + (b - a) * t.clamp(0.0, 1.0)).powf(1.0 / GAMMA);
+
+            (value * 255.0).round() as u8
+        };
+
+        Color {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+        }
+    }
+
     pub fn to_block_color(self) -> BlockColor {
-        MC_PALLETE
+        self.to_block_color_in(&MC_PALLETE)
+    }
+
+    /// Block of `palette` with the closest color (see `BlockSkin::combo_palette`)
+    pub fn to_block_color_in(self, palette: &[BlockColor]) -> BlockColor {
+        palette
             .iter()
             .min_by_key(|block| block.color.dist(self))
             .unwrap()
@@ -34,6 +67,10 @@ impl Color {
 }
 
 impl BlockColor {
+    pub fn new(block: BlockState, item: ItemKind, color: Color) -> Self {
+        Self { block, item, color }
+    }
+
     pub fn block(&self) -> Block {
         Block::new(self.block)
     }
@@ -82,6 +119,11 @@ pub const DEFAULT_COMBO_COLORS: [Color; 4] = [
     },
 ];
 
+/// Concrete blocks the combo colors are drawn with when no skin is selected
+pub fn default_palette() -> Vec<BlockColor> {
+    MC_PALLETE.to_vec()
+}
+
 const MC_PALLETE: [BlockColor; 14] = [
     // BlockColor {
     //     block: BlockState::WHITE_CONCRETE,
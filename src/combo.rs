@@ -0,0 +1,122 @@
+use bevy_ecs::{
+    prelude::{Component, Entity},
+    system::{Commands, Query},
+    world::Mut,
+};
+use valence::{
+    prelude::{Client, Instance},
+    protocol::{types::SoundCategory, BlockPos, BlockState, Sound},
+    Despawned,
+};
+
+use crate::digit::{TextPosition, TextWriter};
+
+const COMBO_MILESTONES: [usize; 4] = [50, 100, 250, 500];
+/// After the last milestone in `COMBO_MILESTONES`, a new milestone is reached every `COMBO_MILESTONE_INTERVAL` combo
+const COMBO_MILESTONE_INTERVAL: usize = 500;
+
+/// Combo number flashed in the corner of the screen when a combo milestone is reached
+#[derive(Component, Clone)]
+pub struct ComboMilestoneNumber {
+    ticks: usize,
+    combo: usize,
+    origin: BlockPos,
+    scale: usize,
+    instance: Entity,
+}
+
+/// Returns the milestone level (starting at 0) if `combo` is a combo milestone
+pub fn combo_milestone_level(combo: usize) -> Option<usize> {
+    if let Some(level) = COMBO_MILESTONES
+        .iter()
+        .position(|&milestone| milestone == combo)
+    {
+        return Some(level);
+    }
+
+    let last_milestone = *COMBO_MILESTONES.last().unwrap();
+    (combo > last_milestone && combo % COMBO_MILESTONE_INTERVAL == 0)
+        .then(|| COMBO_MILESTONES.len() - 1 + (combo - last_milestone) / COMBO_MILESTONE_INTERVAL)
+}
+
+/// Plays a sound which gets higher pitched the higher the milestone `level` is
+pub fn play_combo_milestone_sound(client: &mut Mut<Client>, level: usize) {
+    let pitch = (0.8 + 0.2 * level as f32).min(2.0);
+    let position = client.position();
+    client.play_sound(
+        Sound::EntityPlayerLevelup,
+        SoundCategory::Player,
+        position,
+        1.0,
+        pitch,
+    );
+}
+
+impl ComboMilestoneNumber {
+    pub fn new(
+        combo: usize,
+        origin: BlockPos,
+        scale: usize,
+        ticks: usize,
+        mut instance: (Entity, Mut<Instance>),
+    ) -> Self {
+        let combo_milestone_number = Self {
+            ticks,
+            combo,
+            origin,
+            scale,
+            instance: instance.0,
+        };
+
+        combo_milestone_number.draw(BlockState::GOLD_BLOCK, &mut instance.1);
+
+        combo_milestone_number
+    }
+
+    pub fn despawn(&self, instances: &mut Query<&mut Instance>) {
+        if let Ok(mut instance) = instances.get_mut(self.instance) {
+            self.draw(BlockState::AIR, &mut instance);
+        }
+    }
+
+    fn draw(&self, block: BlockState, instance: &mut Mut<Instance>) {
+        TextWriter {
+            scale: self.scale,
+            position: TextPosition::Center,
+        }
+        .draw(self.combo, self.origin, block, instance);
+    }
+}
+
+pub fn update_combo_milestone_numbers(
+    mut commands: Commands,
+    mut combo_milestone_numbers: Query<(Entity, &mut ComboMilestoneNumber)>,
+    mut instances: Query<&mut Instance>,
+) {
+    for (entity, mut combo_milestone_number) in &mut combo_milestone_numbers {
+        if combo_milestone_number.ticks == 0 {
+            combo_milestone_number.despawn(&mut instances);
+            commands.entity(entity).insert(Despawned);
+        } else {
+            combo_milestone_number.ticks -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn combo_milestones() {
+        assert_eq!(combo_milestone_level(0), None);
+        assert_eq!(combo_milestone_level(49), None);
+        assert_eq!(combo_milestone_level(50), Some(0));
+        assert_eq!(combo_milestone_level(100), Some(1));
+        assert_eq!(combo_milestone_level(250), Some(2));
+        assert_eq!(combo_milestone_level(500), Some(3));
+        assert_eq!(combo_milestone_level(750), None);
+        assert_eq!(combo_milestone_level(1000), Some(4));
+        assert_eq!(combo_milestone_level(1500), Some(5));
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::anyhow;
+use bevy_ecs::{
+    prelude::{Entity, EventReader, EventWriter},
+    schedule::IntoSystemDescriptor,
+    system::{Query, Res, Resource},
+};
+use tracing::warn;
+use valence::{
+    bevy_app::App,
+    client::event::{ChatCommand, RequestCommandCompletions},
+    prelude::Client,
+    protocol::{
+        packets::s2c::{
+            commands::{Node, NodeData, Parser, StringArg, Suggestion},
+            play::{CommandSuggestionResponse, Commands as CommandsPacket},
+        },
+        VarInt,
+    },
+};
+
+use crate::{error::error_message, song_selection::SongSelectionInventory};
+
+/// Maximum suggestions sent for each completion request (the client only shows a few of them at once)
+const MAX_SUGGESTIONS: usize = 20;
+
+/// How the client parses the argument of a command
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgKind {
+    /// A single word
+    Word,
+    /// Everything after the command name
+    Phrase,
+}
+
+/// Completions of an argument, which the client asks the server for while the player types it
+#[derive(Clone, PartialEq, Debug)]
+pub enum Suggestions {
+    /// Fixed values, suggested when they start with what was typed
+    Values(Vec<&'static str>),
+    /// Titles of the songs of the songs directory, fuzzy matched (see `SongSelectionInventory::title_suggestions`)
+    Songs,
+}
+
+/// Fixed values starting with the argument typed so far
+fn value_matches(values: &[&'static str], typed: &str) -> Vec<String> {
+    let typed = typed.to_lowercase();
+    values
+        .iter()
+        .filter(|value| value.starts_with(&typed))
+        .take(MAX_SUGGESTIONS)
+        .map(|value| value.to_string())
+        .collect()
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ArgSpec {
+    name: &'static str,
+    kind: ArgKind,
+    suggestions: Option<Suggestions>,
+}
+
+impl ArgSpec {
+    pub fn word(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgKind::Word,
+            suggestions: None,
+        }
+    }
+
+    pub fn phrase(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgKind::Phrase,
+            suggestions: None,
+        }
+    }
+
+    pub fn suggest(mut self, suggestions: Suggestions) -> Self {
+        self.suggestions = Some(suggestions);
+        self
+    }
+}
+
+/// Name and arguments of a chat command, registered with `RegisterCommands::add_commands`. They are sent to the clients to
+/// validate and complete the commands as the players type them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommandSpec {
+    name: &'static str,
+    subcommands: Vec<&'static str>,
+    arg: Option<ArgSpec>,
+    required: bool,
+}
+
+impl CommandSpec {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            subcommands: Vec::new(),
+            arg: None,
+            required: false,
+        }
+    }
+
+    /// Words accepted right after the name (e.g. `/lobby join`). Nested subcommands are separated by spaces (e.g. `start force`).
+    pub fn subcommands(mut self, subcommands: impl IntoIterator<Item = &'static str>) -> Self {
+        self.subcommands = subcommands.into_iter().collect();
+        self
+    }
+
+    pub fn arg(mut self, arg: ArgSpec) -> Self {
+        self.arg = Some(arg);
+        self
+    }
+
+    /// The command can't be executed without its argument or one of its subcommands
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Every chat command of the server. Chat commands which aren't registered are answered with an error by `dispatch_commands`.
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: Vec<CommandSpec>,
+}
+
+impl CommandRegistry {
+    pub fn register(&mut self, command: CommandSpec) {
+        if self.get(command.name).is_some() {
+            warn!("The command '/{}' was registered twice", command.name);
+            return;
+        }
+
+        self.commands.push(command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CommandSpec> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    /// Command tree sent to the clients when they join
+    pub fn commands_packet(&self) -> CommandsPacket<'static> {
+        let mut nodes = vec![Node {
+            children: vec![],
+            data: NodeData::Root,
+            executable: false,
+            redirect_node: None,
+        }];
+
+        for command in &self.commands {
+            let index = push_command_nodes(&mut nodes, command);
+            nodes[0].children.push(index);
+        }
+
+        CommandsPacket {
+            commands: nodes,
+            root_index: VarInt(0),
+        }
+    }
+
+    /// Suggestions of the argument being typed in `text` (e.g. `/hitsound dr`), with the position of the argument in it
+    fn suggestion_target<'a>(&self, text: &'a str) -> Option<(&Suggestions, usize, &'a str)> {
+        let command = text.strip_prefix('/').unwrap_or(text);
+        let (name, typed) = command.split_once(' ')?;
+        let arg = self.get(name)?.arg.as_ref()?;
+        if arg.kind == ArgKind::Word && typed.contains(' ') {
+            return None;
+        }
+
+        let suggestions = arg.suggestions.as_ref()?;
+        Some((suggestions, text.len() - typed.len(), typed))
+    }
+}
+
+/// Pushes the literal node of `command` followed by the nodes of its subcommands and argument, returning its index
+fn push_command_nodes(nodes: &mut Vec<Node<'static>>, command: &CommandSpec) -> VarInt {
+    let index = nodes.len();
+    nodes.push(Node {
+        children: vec![],
+        data: NodeData::Literal { name: command.name },
+        executable: !command.required,
+        redirect_node: None,
+    });
+
+    let mut children = Vec::new();
+    for &subcommand in &command.subcommands {
+        // Each word is a literal below the previous one, shared with the subcommands starting with the same words
+        let mut parent: Option<usize> = None;
+        for word in subcommand.split(' ') {
+            let siblings = match parent {
+                Some(parent) => &nodes[parent].children,
+                None => &children,
+            };
+            let existing = siblings.iter().map(|VarInt(child)| *child as usize).find(
+                |child| matches!(nodes[*child].data, NodeData::Literal { name } if name == word),
+            );
+
+            let node = existing.unwrap_or_else(|| {
+                let node = nodes.len();
+                nodes.push(Node {
+                    children: vec![],
+                    data: NodeData::Literal { name: word },
+                    executable: true,
+                    redirect_node: None,
+                });
+                match parent {
+                    Some(parent) => nodes[parent].children.push(VarInt(node as i32)),
+                    None => children.push(VarInt(node as i32)),
+                }
+                node
+            });
+            parent = Some(node);
+        }
+    }
+    if let Some(arg) = &command.arg {
+        children.push(VarInt(nodes.len() as i32));
+        nodes.push(Node {
+            children: vec![],
+            data: NodeData::Argument {
+                name: arg.name,
+                parser: Parser::String(match arg.kind {
+                    ArgKind::Word => StringArg::SingleWord,
+                    ArgKind::Phrase => StringArg::GreedyPhrase,
+                }),
+                suggestion: arg.suggestions.as_ref().map(|_| Suggestion::AskServer),
+            },
+            executable: true,
+            redirect_node: None,
+        });
+    }
+    nodes[index].children = children;
+
+    VarInt(index as i32)
+}
+
+/// Chat command of a registered command, sent by `dispatch_commands` to the handlers
+pub struct OsuCommand {
+    pub client: Entity,
+    pub name: &'static str,
+    /// Everything after the command name
+    pub args: String,
+}
+
+pub trait RegisterCommands {
+    /// Registers `commands` and adds `handler`, the system executing them from the `OsuCommand` events
+    fn add_commands<Params>(
+        &mut self,
+        commands: impl IntoIterator<Item = CommandSpec>,
+        handler: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self;
+}
+
+impl RegisterCommands for App {
+    fn add_commands<Params>(
+        &mut self,
+        commands: impl IntoIterator<Item = CommandSpec>,
+        handler: impl IntoSystemDescriptor<Params>,
+    ) -> &mut Self {
+        let mut registry = self
+            .world
+            .get_resource_or_insert_with(CommandRegistry::default);
+        for command in commands {
+            registry.register(command);
+        }
+
+        self.add_system(handler.after(dispatch_commands))
+    }
+}
+
+/// Sends the chat commands of the registered commands to their handlers and answers the other ones with an error
+pub fn dispatch_commands(
+    registry: Res<CommandRegistry>,
+    mut clients: Query<&mut Client>,
+    mut chat_commands: EventReader<ChatCommand>,
+    mut osu_commands: EventWriter<OsuCommand>,
+) {
+    for chat_command in chat_commands.iter() {
+        let command = chat_command.command.trim();
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+
+        match registry.get(name) {
+            Some(command) => osu_commands.send(OsuCommand {
+                client: chat_command.client,
+                name: command.name,
+                args: args.to_string(),
+            }),
+            None => {
+                if let Ok(mut client) = clients.get_mut(chat_command.client) {
+                    client.send_message(error_message(&anyhow!("Unknown command: '{}'", name)));
+                }
+            }
+        }
+    }
+}
+
+/// Answers the tab-completion requests of the arguments with suggestions
+pub fn answer_command_suggestions(
+    registry: Res<CommandRegistry>,
+    song_selections: Query<&SongSelectionInventory>,
+    mut clients: Query<&mut Client>,
+    mut requests: EventReader<RequestCommandCompletions>,
+) {
+    for request in requests.iter() {
+        let Some((suggestions, start, typed)) = registry.suggestion_target(&request.text) else {
+            continue;
+        };
+        let Ok(mut client) = clients.get_mut(request.client) else {
+            continue;
+        };
+
+        let matches = match suggestions {
+            Suggestions::Values(values) => value_matches(values, typed),
+            Suggestions::Songs => song_selections
+                .get_single()
+                .map(|song_selection| song_selection.title_suggestions(typed, MAX_SUGGESTIONS))
+                .unwrap_or_default(),
+        };
+
+        // The client counts the positions in UTF-16 characters
+        client.write_packet(&CommandSuggestionResponse {
+            id: VarInt(request.transaction_id),
+            start: VarInt(request.text[..start].encode_utf16().count() as i32),
+            length: VarInt(typed.encode_utf16().count() as i32),
+            matches: matches
+                .iter()
+                .map(|suggestion| (suggestion.as_str(), None))
+                .collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            CommandSpec::new("lobby")
+                .subcommands(["create", "join"])
+                .required(),
+        );
+        registry.register(
+            CommandSpec::new("hitsound")
+                .arg(ArgSpec::word("hitsound").suggest(Suggestions::Values(vec!["drum", "soft"]))),
+        );
+        registry.register(CommandSpec::new("hitsound"));
+        registry
+    }
+
+    #[test]
+    fn command_tree() {
+        let packet = registry().commands_packet();
+        let children: Vec<_> = packet
+            .commands
+            .iter()
+            .map(|node| {
+                node.children
+                    .iter()
+                    .map(|VarInt(child)| *child)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        assert_eq!(
+            children,
+            vec![vec![1, 4], vec![2, 3], vec![], vec![], vec![5], vec![]]
+        );
+        assert!(!packet.commands[1].executable);
+        assert!(packet.commands[4].executable);
+    }
+
+    #[test]
+    fn nested_subcommands() {
+        let mut registry = CommandRegistry::default();
+        registry.register(CommandSpec::new("lobby").subcommands(["start", "start force", "leave"]));
+        let packet = registry.commands_packet();
+
+        let children: Vec<_> = packet
+            .commands
+            .iter()
+            .map(|node| node.children.len())
+            .collect();
+        assert_eq!(children, vec![1, 2, 1, 0, 0]);
+        assert!(matches!(
+            packet.commands[3].data,
+            NodeData::Literal { name: "force" }
+        ));
+    }
+
+    #[test]
+    fn suggestions_of_typed_argument() {
+        let registry = registry();
+
+        let (suggestions, start, typed) = registry.suggestion_target("/hitsound D").unwrap();
+        assert_eq!((start, typed), (10, "D"));
+        let Suggestions::Values(values) = suggestions else {
+            panic!("expected fixed values");
+        };
+        assert_eq!(value_matches(values, typed), vec!["drum"]);
+
+        assert!(registry.suggestion_target("/hitsound").is_none());
+        assert!(registry.suggestion_target("/hitsound drum soft").is_none());
+        assert!(registry.suggestion_target("/lobby j").is_none());
+        assert!(registry.suggestion_target("/unknown a").is_none());
+    }
+}
@@ -1,113 +1,552 @@
-use anyhow::anyhow;
+use std::{path::PathBuf, time::Instant};
+
+use anyhow::{anyhow, Result};
 use bevy_ecs::{
+    prelude::Entity,
     prelude::EventReader,
-    query::{Added, With},
-    system::Query,
+    query::{Added, With, Without},
+    system::{Commands, Query, Res, ResMut},
 };
+use rand::seq::SliceRandom;
 use valence::{
-    client::event::ChatCommand,
-    prelude::{Client, Color, Inventory},
-    protocol::{
-        packets::s2c::{
-            commands::{Node, NodeData, Parser, StringArg},
-            play::Commands,
-        },
-        TextFormat, VarInt,
+    prelude::{Client, Color, Inventory, OpenInventory},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    admin::maintenance_announcement,
+    beatmap_selection::BeatmapSelectionInventory,
+    bundle_report::write_bundle_report,
+    collections::{
+        open_collection_browser, play_collection, CollectionBrowserInventory, CollectionCommand,
+        Collections,
     },
+    command_registry::{ArgSpec, CommandRegistry, CommandSpec, OsuCommand, Suggestions},
+    configs::Configs,
+    error::{error_message, OsuError},
+    hud::{open_hud_settings_inventory, HudSettings, HudSettingsInventory},
+    hype::{fire_hype, Hype, HypeCooldowns},
+    inventory::InventoriesToOpen,
+    marathon::Marathon,
+    osu::{Osu, OsuStateChange},
+    player_name::PlayerName,
+    progress::LongOperations,
+    song_selection::{open_beatmap_selection, SongSelectionInventory},
 };
 
-use crate::song_selection::SongSelectionInventory;
+/// Commands handled by `execute_commands`
+pub fn osu_commands() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec::new("filter-songs")
+            .arg(ArgSpec::phrase("keywords").suggest(Suggestions::Songs)),
+        CommandSpec::new("reset-filter"),
+        CommandSpec::new("set-songs-dir")
+            .arg(ArgSpec::phrase("path"))
+            .required(),
+        CommandSpec::new("hud"),
+        CommandSpec::new("filter-tags")
+            .arg(ArgSpec::phrase("tags"))
+            .required(),
+        CommandSpec::new("bundle-report"),
+        CommandSpec::new("warmup"),
+        CommandSpec::new("cancel"),
+        CommandSpec::new("filter-diffs").arg(ArgSpec::phrase("query")),
+        CommandSpec::new("random"),
+        CommandSpec::new("random-diff"),
+        CommandSpec::new("adaptive"),
+        CommandSpec::new("collection").arg(ArgSpec::phrase("args").suggest(Suggestions::Values(
+            vec!["create", "delete", "add", "remove", "play"],
+        ))),
+        CommandSpec::new("marathon")
+            .arg(ArgSpec::phrase("source").suggest(Suggestions::Values(vec![
+                "collection",
+                "filter",
+                "stop",
+            ])))
+            .required(),
+        CommandSpec::new("hype"),
+        CommandSpec::new("gg"),
+        CommandSpec::new("maintenance"),
+    ]
+}
 
-pub fn register_mc_commands(mut new_clients: Query<&mut Client, Added<Client>>) {
+/// Sends the registered commands to the joining players, so their client validates and completes the commands
+pub fn register_mc_commands(
+    registry: Res<CommandRegistry>,
+    mut new_clients: Query<&mut Client, Added<Client>>,
+) {
     for mut client in &mut new_clients {
-        client.write_packet(&Commands {
-            commands: vec![
-                Node {
-                    children: vec![VarInt(1), VarInt(3)],
-                    data: NodeData::Root,
-                    executable: false,
-                    redirect_node: None,
-                },
-                Node {
-                    children: vec![VarInt(2)],
-                    data: NodeData::Literal {
-                        name: "filter-songs",
-                    },
-                    executable: true,
-                    redirect_node: None,
-                },
-                Node {
-                    children: vec![],
-                    data: NodeData::Argument {
-                        name: "keywords",
-                        parser: Parser::String(StringArg::GreedyPhrase),
-                        suggestion: None,
-                    },
-                    executable: false,
-                    redirect_node: None,
-                },
-                Node {
-                    children: vec![],
-                    data: NodeData::Literal {
-                        name: "reset-filter",
-                    },
-                    executable: true,
-                    redirect_node: None,
-                },
-            ],
-            root_index: VarInt(0),
-        });
+        client.write_packet(&registry.commands_packet());
     }
 }
 
 pub fn execute_commands(
+    mut commands: Commands,
     mut clients: Query<&mut Client>,
-    mut command_events: EventReader<ChatCommand>,
+    mut command_events: EventReader<OsuCommand>,
     mut song_selections: Query<&mut SongSelectionInventory, With<Inventory>>,
+    song_selection_entities: Query<Entity, (With<SongSelectionInventory>, With<Inventory>)>,
+    mut beatmap_selections: Query<(Entity, &mut BeatmapSelectionInventory), With<Inventory>>,
+    hud_settings: Query<&HudSettings>,
+    mut hud_inventories: Query<(Entity, &HudSettingsInventory, &mut Inventory)>,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    mut configs: ResMut<Configs>,
+    mut osu: ResMut<Osu>,
+    mut operations: ResMut<LongOperations>,
+    mut collections: ResMut<Collections>,
+    mut marathon: ResMut<Marathon>,
+    (mut hype_cooldowns, player_names): (ResMut<HypeCooldowns>, Query<&PlayerName>),
+    mut collection_browsers: Query<
+        (Entity, &mut CollectionBrowserInventory, &mut Inventory),
+        Without<HudSettingsInventory>,
+    >,
 ) {
     for command_event in command_events.iter() {
-        let match_client = clients.get_mut(command_event.client);
-
-        let result = match command_event
-            .command
-            .split_once(' ')
-            .map(|(command_name, args)| (command_name, args.replace('"', "")))
-            .unwrap_or((command_event.command.as_ref(), String::new()))
-        {
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        // Message sent to every player after the command result
+        let mut announcement: Option<Text> = None;
+
+        let result = match (command_event.name, command_event.args.replace('"', "")) {
             ("filter-songs", keywords) => {
                 if let Ok(mut song_selection) = song_selections.get_single_mut() {
-                    song_selection.set_filter(Some(keywords.as_str())).map(|_| {
-                        "Songs selection filtered by the keywords: ".color(Color::YELLOW)
-                            + format!("'{}'", keywords).color(Color::GREEN)
-                    })
+                    song_selection
+                        .set_filter(
+                            Some(keywords.as_str()),
+                            Some(command_event.client),
+                            &mut operations,
+                        )
+                        .map(|indexing| match indexing {
+                            Some(songs) => indexing_message(songs),
+                            None => {
+                                "Songs selection filtered by: ".color(Color::YELLOW)
+                                    + format!("'{}'", keywords).color(Color::GREEN)
+                            }
+                        })
+                } else {
+                    Err(anyhow!("Song selection not found"))
+                }
+            }
+            ("filter-tags", tags) => {
+                if let Ok(mut song_selection) = song_selections.get_single_mut() {
+                    song_selection
+                        .set_tags_filter(
+                            Some(tags.as_str()),
+                            Some(command_event.client),
+                            &mut operations,
+                        )
+                        .map(|indexing| match indexing {
+                            Some(songs) => indexing_message(songs),
+                            None => {
+                                "Songs selection filtered by the tags: ".color(Color::YELLOW)
+                                    + format!("'{}'", tags).color(Color::GREEN)
+                            }
+                        })
                 } else {
                     Err(anyhow!("Song selection not found"))
                 }
             }
+            ("filter-diffs", query) => {
+                if let Ok((_, mut beatmap_selection)) = beatmap_selections.get_single_mut() {
+                    let query = query.trim();
+                    let filter = (!query.is_empty()).then_some(query);
+
+                    beatmap_selection
+                        .set_filter(filter)
+                        .map(|matches| match filter {
+                            Some(filter) => {
+                                "Difficulties filtered by: ".color(Color::YELLOW)
+                                    + format!("'{}'", filter).color(Color::GREEN)
+                                    + format!(" ({} matches)", matches).color(Color::GRAY)
+                            }
+                            None => {
+                                "Difficulty filter reset ".color(Color::YELLOW)
+                                    + "successfully".color(Color::GREEN)
+                            }
+                        })
+                } else {
+                    Err(anyhow!("Beatmap selection not found"))
+                }
+            }
             ("reset-filter", _) => {
                 if let Ok(mut song_selection) = song_selections.get_single_mut() {
-                    song_selection.set_filter(None).map(|_| {
+                    song_selection.reset_filters().map(|_| {
                         "Song filter reset ".color(Color::YELLOW) + "succefully".color(Color::GREEN)
                     })
                 } else {
                     Err(anyhow!("Song selection not found"))
                 }
             }
-            (command_name, _) => Err(anyhow!("Unknown command: '{}'", command_name)),
+            ("set-songs-dir", path) => {
+                if is_operator {
+                    set_songs_directory(
+                        PathBuf::from(path.trim()),
+                        &mut configs,
+                        &mut song_selections,
+                        &mut commands,
+                    )
+                } else {
+                    Err(OsuError::OperatorOnly {
+                        action: "change the songs directory",
+                    }
+                    .into())
+                }
+            }
+            ("hud", _) => {
+                if let Ok(settings) = hud_settings.get(command_event.client) {
+                    open_hud_settings_inventory(
+                        &mut commands,
+                        command_event.client,
+                        settings,
+                        &mut inventories_to_open,
+                        &mut hud_inventories,
+                    );
+                    Ok("Opened ".color(Color::YELLOW) + "HUD settings".color(Color::GREEN))
+                } else {
+                    Err(anyhow!("HUD settings not found"))
+                }
+            }
+            ("bundle-report", _) => {
+                if is_operator {
+                    write_bundle_report(&configs, song_selections.get_single().ok()).map(|path| {
+                        "Bundle report written to: ".color(Color::YELLOW)
+                            + format!("'{}'", path.display()).color(Color::GREEN)
+                            + " (attach it to your GitHub issue)".color(Color::GRAY)
+                    })
+                } else {
+                    Err(OsuError::OperatorOnly {
+                        action: "create bundle reports",
+                    }
+                    .into())
+                }
+            }
+            ("warmup", _) => {
+                if is_operator {
+                    let warmup = !osu.is_warmup();
+                    osu.set_warmup(warmup);
+
+                    announcement = Some(if warmup {
+                        "Warmup started: ".color(Color::YELLOW)
+                            + "scores are not recorded until the warmup ends".color(Color::GRAY)
+                    } else {
+                        "Warmup ended: ".color(Color::YELLOW)
+                            + "scores are recorded again".color(Color::GREEN)
+                    });
+
+                    Ok(if warmup {
+                        "Warmup ".color(Color::YELLOW) + "enabled".color(Color::GREEN)
+                    } else {
+                        "Warmup ".color(Color::YELLOW) + "disabled".color(Color::RED)
+                    })
+                } else {
+                    Err(OsuError::OperatorOnly {
+                        action: "toggle the warmup",
+                    }
+                    .into())
+                }
+            }
+            ("maintenance", _) => {
+                if is_operator {
+                    let maintenance = !osu.is_maintenance();
+                    osu.set_maintenance(maintenance);
+                    announcement = Some(maintenance_announcement(maintenance));
+
+                    Ok(if maintenance {
+                        "Maintenance ".color(Color::YELLOW) + "enabled".color(Color::GREEN)
+                    } else {
+                        "Maintenance ".color(Color::YELLOW) + "disabled".color(Color::RED)
+                    })
+                } else {
+                    Err(OsuError::OperatorOnly {
+                        action: "toggle the maintenance",
+                    }
+                    .into())
+                }
+            }
+            ("adaptive", _) => {
+                if is_operator {
+                    let adaptive = !osu.is_adaptive();
+                    osu.set_adaptive(adaptive);
+
+                    announcement = Some(if adaptive {
+                        "Adaptive mode enabled: ".color(Color::LIGHT_PURPLE)
+                            + "AR and CS tighten while you hit 300s and relax after misses (unranked)"
+                                .color(Color::GRAY)
+                    } else {
+                        "Adaptive mode disabled".color(Color::LIGHT_PURPLE)
+                    });
+
+                    Ok(if adaptive {
+                        "Adaptive mode ".color(Color::YELLOW)
+                            + "enabled".color(Color::GREEN)
+                            + " (from the next beatmap)".color(Color::GRAY)
+                    } else {
+                        "Adaptive mode ".color(Color::YELLOW)
+                            + "disabled".color(Color::RED)
+                            + " (from the next beatmap)".color(Color::GRAY)
+                    })
+                } else {
+                    Err(OsuError::OperatorOnly {
+                        action: "toggle the adaptive mode",
+                    }
+                    .into())
+                }
+            }
+            ("random", _) => {
+                match (
+                    song_selections.get_single(),
+                    beatmap_selections.get_single_mut(),
+                ) {
+                    _ if !osu.is_selecting_beatmap() => Err(anyhow!(
+                        "A random song can only be picked while selecting a beatmap"
+                    )),
+                    (Ok(song_selection), Ok((beatmap_selection_entity, mut beatmap_selection))) => {
+                        match song_selection
+                            .songs()
+                            .choose(&mut rand::thread_rng())
+                            .cloned()
+                        {
+                            Some(song_dir) => open_beatmap_selection(
+                                &song_dir,
+                                command_event.client,
+                                is_operator,
+                                (beatmap_selection_entity, &mut beatmap_selection),
+                                &mut commands,
+                                &mut inventories_to_open,
+                                &mut osu,
+                                &mut clients,
+                            )
+                            .map(|_| {
+                                let song_name = song_dir
+                                    .file_name()
+                                    .map(|name| name.to_string_lossy().to_string())
+                                    .unwrap_or_default();
+
+                                "Random song: ".color(Color::YELLOW) + song_name.color(Color::GREEN)
+                            }),
+                            None => Err(anyhow!("No song matches the current filters")),
+                        }
+                    }
+                    _ => Err(anyhow!("Song selection not found")),
+                }
+            }
+            ("random-diff", _) => {
+                let random_beatmap = beatmap_selections
+                    .get_single()
+                    .ok()
+                    .filter(|_| osu.is_selecting_beatmap())
+                    .and_then(|(_, beatmap_selection)| {
+                        beatmap_selection.random_beatmap(configs.max_map_length())
+                    })
+                    .map(|beatmap| (beatmap.path().clone(), beatmap.display_name()));
+
+                match random_beatmap {
+                    Some((beatmap_path, name)) => {
+                        // Close beatmap selection
+                        commands
+                            .entity(command_event.client)
+                            .remove::<OpenInventory>();
+
+                        osu.change_state_for(
+                            OsuStateChange::PrePlaying { beatmap_path },
+                            is_operator,
+                            &mut clients,
+                        )
+                        .map(|_| {
+                            "Random difficulty: ".color(Color::YELLOW) + name.color(Color::GREEN)
+                        })
+                    }
+                    None => Err(anyhow!(
+                        "Select a song with playable difficulties first (e.g. with /random)"
+                    )),
+                }
+            }
+            ("collection", args) => match CollectionCommand::parse(&args) {
+                Ok(CollectionCommand::Browse) => {
+                    open_collection_browser(
+                        &mut commands,
+                        command_event.client,
+                        &collections,
+                        &mut inventories_to_open,
+                        &mut collection_browsers,
+                    );
+                    Ok("Opened ".color(Color::YELLOW) + "collections".color(Color::GREEN))
+                }
+                Ok(CollectionCommand::Create(name)) => collections
+                    .create(&name)
+                    .and_then(|_| collections.save())
+                    .map(|_| "Created collection ".color(Color::YELLOW) + name.color(Color::GREEN)),
+                Ok(CollectionCommand::Delete(name)) => collections
+                    .delete(&name)
+                    .and_then(|_| collections.save())
+                    .map(|_| "Deleted collection ".color(Color::YELLOW) + name.color(Color::GREEN)),
+                Ok(CollectionCommand::Add(name)) => match osu.selected_song_dir().cloned() {
+                    Some(song) => collections
+                        .add(&name, &song)
+                        .and_then(|_| collections.save())
+                        .map(|_| {
+                            "Added the selected song to ".color(Color::YELLOW)
+                                + name.color(Color::GREEN)
+                        }),
+                    None => Err(anyhow!("Open the song to add first")),
+                },
+                Ok(CollectionCommand::Remove(name)) => match osu.selected_song_dir().cloned() {
+                    Some(song) => collections
+                        .remove(&name, &song)
+                        .and_then(|_| collections.save())
+                        .map(|_| {
+                            "Removed the selected song from ".color(Color::YELLOW)
+                                + name.color(Color::GREEN)
+                        }),
+                    None => Err(anyhow!("Open the song to remove first")),
+                },
+                Ok(CollectionCommand::Play(name)) => match (
+                    song_selection_entities.get_single(),
+                    song_selections.get_single_mut(),
+                ) {
+                    _ if !osu.is_selecting_beatmap() => Err(anyhow!(
+                        "Collections can only be opened while selecting a beatmap"
+                    )),
+                    (Ok(song_selection_entity), Ok(mut song_selection)) => play_collection(
+                        Some(&name),
+                        command_event.client,
+                        &collections,
+                        (song_selection_entity, &mut song_selection),
+                        &mut commands,
+                        &mut inventories_to_open,
+                        &mut osu,
+                        &mut clients,
+                    )
+                    .map(|_| "Playing collection ".color(Color::YELLOW) + name.color(Color::GREEN)),
+                    _ => Err(anyhow!("Song selection not found")),
+                },
+                Err(error) => Err(error),
+            },
+            ("marathon", source) => {
+                let source = source.trim();
+                let songs = match source.split_once(' ').unwrap_or((source, "")) {
+                    ("stop", _) => None,
+                    ("filter", _) => Some(
+                        song_selections
+                            .get_single()
+                            .map(|song_selection| song_selection.songs().to_vec())
+                            .map_err(|_| anyhow!("Song selection not found")),
+                    ),
+                    ("collection", name) => Some(
+                        collections
+                            .get(name.trim())
+                            .map(|songs| songs.to_vec())
+                            .ok_or_else(|| anyhow!("Collection '{}' not found", name.trim())),
+                    ),
+                    _ => Some(Err(anyhow!(
+                        "Usage: /marathon [collection <name>|filter|stop]"
+                    ))),
+                };
+
+                match songs {
+                    None if marathon.is_running() => {
+                        announcement = marathon
+                            .stop()
+                            .into_iter()
+                            .reduce(|summary, line| summary + "\n".color(Color::WHITE) + line);
+                        Ok("Marathon ".color(Color::YELLOW) + "stopped".color(Color::RED))
+                    }
+                    None => Err(anyhow!("No marathon is running")),
+                    Some(_) if !osu.is_selecting_beatmap() => Err(anyhow!(
+                        "A marathon can only be started while selecting a beatmap"
+                    )),
+                    Some(songs) => songs.and_then(|songs| {
+                        let songs_count = songs.len();
+                        // The first map is started by `update_marathon` once it's found
+                        osu.check_can_pick_maps(is_operator)?;
+                        marathon.start(songs, configs.max_map_length(), &mut operations)?;
+
+                        announcement = Some(
+                            "Marathon started: ".color(Color::GOLD)
+                                + format!("{} songs", songs_count).color(Color::WHITE),
+                        );
+                        Ok("Marathon ".color(Color::YELLOW) + "started".color(Color::GREEN))
+                    }),
+                }
+            }
+            (command @ ("hype" | "gg"), _) => {
+                let hype = if command == "hype" {
+                    Hype::Hype
+                } else {
+                    Hype::GoodGame
+                };
+
+                match hype_cooldowns.try_use(command_event.client, Instant::now()) {
+                    Ok(_) => {
+                        let username = clients
+                            .get(command_event.client)
+                            .map(|client| client.username().to_string())
+                            .unwrap_or_default();
+                        fire_hype(hype, &username, osu.playfield(), &mut clients);
+                        // Everyone already got the hype message
+                        continue;
+                    }
+                    Err(wait) => Err(anyhow!(
+                        "Wait {} seconds before cheering again",
+                        wait.as_secs() + 1
+                    )),
+                }
+            }
+            ("cancel", _) => match operations.cancel_started_by(command_event.client) {
+                0 => Err(anyhow!("You have no operation running")),
+                cancelled => Ok("Cancelled ".color(Color::YELLOW)
+                    + format!("{} operation(s)", cancelled).color(Color::GREEN)),
+            },
+            // Registered by the other handlers
+            _ => continue,
         };
 
         // Send command result to client
-        match (result, match_client) {
+        match (result, clients.get_mut(command_event.client)) {
             (Ok(message), Ok(mut client)) => {
                 client.send_message(message);
             }
             (Err(error), Ok(mut client)) => {
-                client.send_message(
-                    format!("Error occurred while executing the command: '{}'", error)
-                        .color(Color::RED),
-                );
+                client.send_message(error_message(&error));
             }
             _ => (),
         }
+
+        if let Some(announcement) = announcement {
+            for mut client in &mut clients {
+                client.send_message(announcement.clone());
+            }
+        }
     }
 }
+
+fn indexing_message(songs: usize) -> Text {
+    "Indexing ".color(Color::YELLOW)
+        + songs.to_string().color(Color::GREEN)
+        + " songs, the filter will be applied once it finishes".color(Color::YELLOW)
+}
+
+fn set_songs_directory(
+    songs_dir: PathBuf,
+    configs: &mut Configs,
+    song_selections: &mut Query<&mut SongSelectionInventory, With<Inventory>>,
+    commands: &mut Commands,
+) -> Result<Text> {
+    if !songs_dir.is_dir() {
+        return Err(OsuError::SongsDirMissing { path: songs_dir }.into());
+    }
+
+    // Rebuild song index
+    match song_selections.get_single_mut() {
+        Ok(mut song_selection) => song_selection.set_songs_dir(songs_dir.clone())?,
+        Err(_) => {
+            commands.spawn(SongSelectionInventory::new(songs_dir.clone())?);
+        }
+    }
+
+    configs.set_songs_directory(songs_dir.display().to_string())?;
+
+    Ok("Songs directory changed to: ".color(Color::YELLOW)
+        + format!("'{}'", songs_dir.display()).color(Color::GREEN))
+}
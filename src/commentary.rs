@@ -0,0 +1,264 @@
+use serde_json::{json, Value};
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
+use tracing::warn;
+
+use bevy_ecs::system::{Local, Query, Res, Resource};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{beatmap::Beatmap, combo::combo_milestone_level, osu::Osu};
+
+/// Name shown as the author of the webhook messages
+const WEBHOOK_USERNAME: &str = "osucraft";
+
+/// Gameplay events relayed to the server chat and to the webhook configured in `configs.json`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchEvent {
+    MapStarted {
+        beatmap: String,
+        players: Vec<String>,
+    },
+    ComboMilestone {
+        combo: usize,
+    },
+    Failed {
+        beatmap: String,
+        score: usize,
+        accuracy: f32,
+    },
+    Finished {
+        beatmap: String,
+        score: usize,
+        accuracy: f32,
+        max_combo: usize,
+        grade: &'static str,
+    },
+}
+
+impl MatchEvent {
+    fn title(&self) -> String {
+        match self {
+            MatchEvent::MapStarted { beatmap, .. } => format!("Now playing: {}", beatmap),
+            MatchEvent::ComboMilestone { combo } => format!("{} combo!", combo),
+            MatchEvent::Failed { beatmap, .. } => format!("Failed: {}", beatmap),
+            MatchEvent::Finished { beatmap, .. } => format!("Cleared: {}", beatmap),
+        }
+    }
+
+    /// Embed color of the webhook message
+    fn color(&self) -> u32 {
+        match self {
+            MatchEvent::MapStarted { .. } => 0x55ffff,
+            MatchEvent::ComboMilestone { .. } => 0xffaa00,
+            MatchEvent::Failed { .. } => 0xff5555,
+            MatchEvent::Finished { .. } => 0x55ff55,
+        }
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            MatchEvent::MapStarted { players, .. } => vec![("Players", players.join(", "))],
+            MatchEvent::ComboMilestone { .. } => vec![],
+            MatchEvent::Failed {
+                score, accuracy, ..
+            } => vec![
+                ("Score", score.to_string()),
+                ("Accuracy", format!("{:.2}%", accuracy)),
+            ],
+            MatchEvent::Finished {
+                score,
+                accuracy,
+                max_combo,
+                grade,
+                ..
+            } => vec![
+                ("Grade", grade.to_string()),
+                ("Score", score.to_string()),
+                ("Accuracy", format!("{:.2}%", accuracy)),
+                ("Max combo", format!("{}x", max_combo)),
+            ],
+        }
+    }
+
+    fn chat_text(&self) -> Text {
+        let title_color = match self {
+            MatchEvent::MapStarted { .. } => Color::AQUA,
+            MatchEvent::ComboMilestone { .. } => Color::GOLD,
+            MatchEvent::Failed { .. } => Color::RED,
+            MatchEvent::Finished { .. } => Color::GREEN,
+        };
+
+        self.fields().into_iter().fold(
+            "[Live] ".color(Color::DARK_GRAY) + self.title().color(title_color),
+            |text, (name, value)| {
+                text + format!("  {}: ", name).color(Color::GRAY) + value.color(Color::WHITE)
+            },
+        )
+    }
+
+    /// Discord-compatible webhook payload (https://discord.com/developers/docs/resources/webhook#execute-webhook)
+    fn webhook_payload(&self) -> Value {
+        let fields: Vec<_> = self
+            .fields()
+            .into_iter()
+            .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+            .collect();
+
+        json!({
+            "username": WEBHOOK_USERNAME,
+            "embeds": [{
+                "title": self.title(),
+                "color": self.color(),
+                "fields": fields,
+            }],
+        })
+    }
+}
+
+/// Posts the match events to the chat and to the webhook. Requests are sent from a background thread so a slow webhook doesn't lag the game.
+#[derive(Resource, Default)]
+pub struct Commentary {
+    webhook: Option<Sender<Value>>,
+}
+
+impl Commentary {
+    pub fn new(webhook_url: Option<&str>) -> Self {
+        let webhook = webhook_url.map(|url| {
+            let url = url.to_string();
+            let (sender, receiver) = mpsc::channel::<Value>();
+
+            thread::spawn(move || {
+                for payload in receiver {
+                    if let Err(error) = ureq::post(&url).send_json(payload) {
+                        warn!("Error while posting match event to the webhook: {}", error);
+                    }
+                }
+            });
+
+            sender
+        });
+
+        Self { webhook }
+    }
+
+    pub fn emit(&self, event: MatchEvent, clients: &mut Query<&mut Client>) {
+        let text = event.chat_text();
+        for mut client in clients.iter_mut() {
+            client.send_message(text.clone());
+        }
+
+        if let Some(webhook) = &self.webhook {
+            if webhook.send(event.webhook_payload()).is_err() {
+                warn!("Webhook thread stopped, the match event was not posted");
+            }
+        }
+    }
+}
+
+/// Progress of the current map already commented
+#[derive(Default)]
+pub struct CommentaryState {
+    started: bool,
+    ended: bool,
+    combo: usize,
+}
+
+/// Emits the match events by watching the osu state
+pub fn update_commentary(
+    commentary: Res<Commentary>,
+    osu: Res<Osu>,
+    mut clients: Query<&mut Client>,
+    mut state: Local<CommentaryState>,
+) {
+    if let Some(beatmap) = osu.playing_beatmap() {
+        if !state.started {
+            *state = CommentaryState {
+                started: true,
+                ..Default::default()
+            };
+
+            let players = clients
+                .iter()
+                .map(|client| client.username().to_string())
+                .collect();
+            commentary.emit(
+                MatchEvent::MapStarted {
+                    beatmap: beatmap_name(beatmap),
+                    players,
+                },
+                &mut clients,
+            );
+        }
+
+        let combo = beatmap.state.combo;
+        if combo > state.combo && combo_milestone_level(combo).is_some() {
+            commentary.emit(MatchEvent::ComboMilestone { combo }, &mut clients);
+        }
+        state.combo = combo;
+    } else if let Some(beatmap) = osu.failed_beatmap() {
+        if !state.ended {
+            state.ended = true;
+            commentary.emit(
+                MatchEvent::Failed {
+                    beatmap: beatmap_name(beatmap),
+                    score: beatmap.state.score,
+                    accuracy: beatmap.state.accuracy(),
+                },
+                &mut clients,
+            );
+        }
+    } else if let Some(beatmap) = osu.finished_beatmap() {
+        if !state.ended {
+            state.ended = true;
+            commentary.emit(
+                MatchEvent::Finished {
+                    beatmap: beatmap_name(beatmap),
+                    score: beatmap.state.score,
+                    accuracy: beatmap.state.accuracy(),
+                    max_combo: beatmap.state.max_combo,
+                    grade: beatmap.state.grade().name(),
+                },
+                &mut clients,
+            );
+        }
+    } else {
+        *state = CommentaryState::default();
+    }
+}
+
+fn beatmap_name(beatmap: &Beatmap) -> String {
+    format!(
+        "{} - {} [{}]",
+        beatmap.data.artist, beatmap.data.title, beatmap.data.difficulty_name
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn webhook_payload() {
+        let event = MatchEvent::Finished {
+            beatmap: "Artist - Title [Insane]".to_string(),
+            score: 123456,
+            accuracy: 98.5,
+            max_combo: 321,
+            grade: "S",
+        };
+        let payload = event.webhook_payload();
+
+        assert_eq!(payload["username"], WEBHOOK_USERNAME);
+        assert_eq!(
+            payload["embeds"][0]["title"],
+            "Cleared: Artist - Title [Insane]"
+        );
+        assert_eq!(payload["embeds"][0]["fields"][0]["value"], "S");
+        assert_eq!(payload["embeds"][0]["fields"][2]["value"], "98.50%");
+    }
+}
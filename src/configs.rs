@@ -1,41 +1,260 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use colored::Colorize;
 use directories::BaseDirs;
 use std::fmt::Display;
 use std::str;
-use std::{fs, path::PathBuf};
+use std::time::Duration;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use bevy_ecs::system::Resource;
 use serde::{Deserialize, Serialize};
-use tracing::warn;
+use serde_json::{Map, Value};
+use tracing::{error, info, warn};
+
+use crate::{
+    aim_assist::NO_AIM_ASSIST,
+    game_mode::GameModeKind,
+    hitsound::HitsoundKind,
+    mania::{DEFAULT_LANE_SLOTS, MAX_KEYS},
+    player_name::PlayerName,
+    resets::ResetClock,
+    ring::ApproachCircleStyle,
+    storage::StorageKind,
+    volume::Volume,
+};
+
+/// Version of the configs file format. Bump it and add a migration to `MIGRATIONS` whenever a field is renamed or changes meaning.
+const CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` upgrades a configs file from version `i` to version `i + 1`
+const MIGRATIONS: [fn(&mut Map<String, Value>); CONFIG_VERSION as usize] = [
+    // Files created before versioning: the missing fields get their defaults
+    |_| {},
+];
 
 #[derive(Resource, Serialize, Deserialize, Debug)]
 pub struct Configs {
+    /// Files without it were created before versioning (version 0)
+    #[serde(default)]
+    config_version: u32,
     songs_directory: String,
+    #[serde(default = "default_max_map_length_secs")]
+    max_map_length_secs: u64,
+    /// Usernames allowed to run operator commands (`/set-songs-dir`, `/admin`, `/force-play`...). Nobody is an operator
+    /// while it's empty, add the usernames here and restart the server. Players who join with the username of a player who
+    /// is already online get another `PlayerName`, so they are not operators. In offline mode anyone can join with any
+    /// username though, so an operator name can be taken while the operator is away: only rely on it in trusted networks.
+    #[serde(default)]
+    operators: Vec<String>,
+    /// Where scores and player settings are persisted
+    #[serde(default)]
+    storage: StorageKind,
+    /// Offset from UTC of the server timezone, used for the daily and weekly resets and the daily challenge
+    #[serde(default)]
+    timezone_utc_offset_minutes: i32,
+    #[serde(default)]
+    skin: Skin,
+    /// Skin file of the `skins` folder the playfield is drawn with, without `.json` (also changed with `/skin`)
+    #[serde(default)]
+    block_skin: Option<String>,
+    /// Idle players are moved to spectator mode after this time (0 disables it)
+    #[serde(default = "default_afk_timeout_secs")]
+    afk_timeout_secs: u64,
+    /// Lobby members who aren't ready this long after the host picked the map are moved to spectator mode (0 disables it)
+    #[serde(default = "default_lobby_ready_timeout_secs")]
+    lobby_ready_timeout_secs: u64,
+    /// Screen redraws changing more blocks than this are spread over several ticks, so slow connections don't choke (0 disables the limit)
+    #[serde(default = "default_max_screen_block_updates")]
+    max_screen_block_updates: usize,
+    /// Discord-compatible webhook where the match events (map started, combo milestones, fails and final scores) are posted
+    #[serde(default)]
+    webhook_url: Option<String>,
+    /// Self-hosted leaderboard shared with other servers: the cleared scores are posted to `<url>/scores` and `/leaderboard`
+    /// shows its top scores
+    #[serde(default)]
+    leaderboard_url: Option<String>,
+    /// Key of the HMAC-SHA256 signature of the submitted scores, shared with the leaderboard server
+    #[serde(default)]
+    leaderboard_secret: String,
+    /// Port of the HTTP/WebSocket API serving the live game state as JSON, e.g. for stream overlays (0 disables it)
+    #[serde(default)]
+    api_port: u16,
+    /// Address the API listens on. Only the host can reach it by default, since the API has no authentication
+    /// (`0.0.0.0` exposes it to the network).
+    #[serde(default = "default_api_bind_address")]
+    api_bind_address: String,
+    /// Run without playing any audio, e.g. on headless servers. The music is timed with the clock instead.
+    /// It's also the case when there is no audio output device.
+    #[serde(default)]
+    disable_audio: bool,
+    /// Volumes of the server audio (also changed with `/volume`)
+    #[serde(default)]
+    volume: Volume,
+    /// Folder with the sound effects played in the server audio (`failsound`, `applause` and `menuclick`), e.g. an osu! skin folder
+    #[serde(default)]
+    sounds_directory: Option<String>,
+    /// The oldest replays are deleted when the `replays` folder takes more than this (0 disables the limit)
+    #[serde(default = "default_replays_quota_mb")]
+    replays_quota_mb: u64,
+    /// File where the beatmap being played and the live accuracy are written every second, e.g. for OBS text sources.
+    /// It's written as JSON if the name ends with `.json`.
+    #[serde(default)]
+    now_playing_file: Option<String>,
+    /// osu! beatmap mirror searched by `/browse` (it must serve `/api/v2/search` and `/d/<beatmapset id>`)
+    #[serde(default = "default_mirror_url")]
+    mirror_url: String,
+    /// Game mode of the beatmaps (`standard`, `mania` or `catch`)
+    #[serde(default)]
+    game_mode: GameModeKind,
+    /// Hotbar slots (1 to 9) pressing the osu!mania lanes, from left to right. Beatmaps with fewer keys use the first ones.
+    #[serde(default = "default_mania_lane_slots")]
+    mania_lane_slots: Vec<u8>,
+    /// Highest aim assist the players can choose with `/assist` (1 disables it)
+    #[serde(default = "default_max_aim_assist")]
+    max_aim_assist: f64,
+    /// Play each slider as a hitcircle at its head and another one at its tail, instead of only its head
+    #[serde(default)]
+    slider_ends: bool,
+}
+
+/// Visual settings of the playfield shared by every player
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Skin {
+    /// Pulse the hitcircle rings on each beat of the song
+    #[serde(default = "default_beat_pulse")]
+    pub beat_pulse: bool,
+    /// Hitsound of the players who didn't choose one with `/hitsound`
+    #[serde(default)]
+    pub hitsound: HitsoundKind,
+    /// Also play the `normal-hitnormal` sample of the beatmaps in the server audio, along with the music
+    #[serde(default)]
+    pub beatmap_hitsounds: bool,
+    /// Draw the combo numbers one block in front of the hitcircles instead of inside their filling
+    #[serde(default)]
+    pub combo_number_in_front: bool,
+    /// `blocks` or `particles`, which cuts the entities of crowded maps
+    #[serde(default)]
+    pub approach_circle: ApproachCircleStyle,
+    /// Draw every beatmap with the combo colors of the block skin, like the osu! option (also toggled with `/skin beatmap-colors`)
+    #[serde(default)]
+    pub ignore_beatmap_colors: bool,
+}
+
+fn default_beat_pulse() -> bool {
+    true
+}
+
+impl Default for Skin {
+    fn default() -> Self {
+        Self {
+            beat_pulse: default_beat_pulse(),
+            hitsound: HitsoundKind::default(),
+            beatmap_hitsounds: false,
+            combo_number_in_front: false,
+            approach_circle: ApproachCircleStyle::default(),
+            ignore_beatmap_colors: false,
+        }
+    }
+}
+
+fn default_max_map_length_secs() -> u64 {
+    15 * 60
+}
+
+fn default_afk_timeout_secs() -> u64 {
+    3 * 60
+}
+
+fn default_lobby_ready_timeout_secs() -> u64 {
+    60
+}
+
+fn default_max_screen_block_updates() -> usize {
+    4096
+}
+
+fn default_replays_quota_mb() -> u64 {
+    1024
+}
+
+fn default_api_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_mirror_url() -> String {
+    "https://catboy.best".to_string()
+}
+
+fn default_mania_lane_slots() -> Vec<u8> {
+    DEFAULT_LANE_SLOTS.iter().map(|slot| slot + 1).collect()
+}
+
+fn default_max_aim_assist() -> f64 {
+    1.5
 }
 
 impl Configs {
     pub fn open() -> Self {
-        Self::read().unwrap_or_else(|_| {
-            let default_configs = Self::default();
+        let path = Self::path();
+        if path.exists() {
+            match Self::read() {
+                Ok(configs) => return configs,
+                Err(error) => {
+                    error!(
+                        "Error while reading configs file '{}', using the default configs: {}",
+                        path.display(),
+                        error
+                    );
 
-            if let Err(error) = default_configs.save() {
-                warn!("Error while saving configs file: {}", error);
+                    // Keep the invalid file so the user can fix it
+                    match backup(&path, "invalid") {
+                        Ok(backup_path) => {
+                            warn!("Invalid configs file moved to '{}'", backup_path.display())
+                        }
+                        Err(error) => {
+                            warn!("Error while backing up the configs file: {}", error);
+                            return Self::default();
+                        }
+                    }
+                }
             }
+        }
+
+        let default_configs = Self::default();
+        if let Err(error) = default_configs.save() {
+            warn!("Error while saving configs file: {}", error);
+        }
 
-            default_configs
-        })
+        default_configs
     }
 
     pub fn path() -> PathBuf {
         PathBuf::from("configs.json")
     }
 
+    /// Files written by older versions are upgraded in place, keeping a backup of the original file
     fn read() -> Result<Self> {
         let path = Self::path();
-        let file_data = fs::read(path)?;
-        let json = str::from_utf8(file_data.as_slice())?;
-        Ok(serde_json::from_str(json)?)
+        let file_data = fs::read(&path)?;
+        let mut json = serde_json::from_str(str::from_utf8(file_data.as_slice())?)?;
+        let file_version = migrate(&mut json)?;
+        let configs: Self = serde_json::from_value(json)?;
+
+        if file_version < CONFIG_VERSION {
+            let backup_path = backup(&path, &format!("v{}", file_version))?;
+            configs.save()?;
+            info!(
+                "Configs file upgraded from version {} to {} (backup: '{}')",
+                file_version,
+                CONFIG_VERSION,
+                backup_path.display()
+            );
+        }
+
+        Ok(configs)
     }
 
     fn save(&self) -> Result<()> {
@@ -48,6 +267,168 @@ impl Configs {
     pub fn songs_directory(&self) -> &str {
         &self.songs_directory
     }
+
+    pub fn set_songs_directory(&mut self, songs_directory: String) -> Result<()> {
+        self.songs_directory = songs_directory;
+        self.save()
+    }
+
+    pub fn storage(&self) -> StorageKind {
+        self.storage
+    }
+
+    /// Checks the unique name of the player rather than their username, so duplicated usernames aren't operators
+    pub fn is_operator(&self, player_name: &PlayerName) -> bool {
+        self.operators.iter().any(|op| op == player_name.as_str())
+    }
+
+    pub fn has_operators(&self) -> bool {
+        !self.operators.is_empty()
+    }
+
+    /// Beatmaps longer than this can't be played, since they may not fit in memory in low-RAM hosts
+    pub fn max_map_length(&self) -> Duration {
+        Duration::from_secs(self.max_map_length_secs)
+    }
+
+    pub fn afk_timeout(&self) -> Option<Duration> {
+        (self.afk_timeout_secs > 0).then(|| Duration::from_secs(self.afk_timeout_secs))
+    }
+
+    pub fn lobby_ready_timeout(&self) -> Option<Duration> {
+        (self.lobby_ready_timeout_secs > 0)
+            .then(|| Duration::from_secs(self.lobby_ready_timeout_secs))
+    }
+
+    /// Maximum number of screen blocks changed per tick
+    pub fn max_screen_block_updates(&self) -> Option<usize> {
+        (self.max_screen_block_updates > 0).then_some(self.max_screen_block_updates)
+    }
+
+    pub fn webhook_url(&self) -> Option<&str> {
+        self.webhook_url.as_deref().filter(|url| !url.is_empty())
+    }
+
+    pub fn leaderboard_url(&self) -> Option<&str> {
+        self.leaderboard_url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/'))
+            .filter(|url| !url.is_empty())
+    }
+
+    pub fn leaderboard_secret(&self) -> &str {
+        &self.leaderboard_secret
+    }
+
+    pub fn disable_audio(&self) -> bool {
+        self.disable_audio
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: Volume) -> Result<()> {
+        self.volume = volume;
+        self.save()
+    }
+
+    pub fn sounds_directory(&self) -> Option<&Path> {
+        self.sounds_directory
+            .as_deref()
+            .filter(|dir| !dir.is_empty())
+            .map(Path::new)
+    }
+
+    pub fn now_playing_file(&self) -> Option<&Path> {
+        self.now_playing_file
+            .as_deref()
+            .filter(|file| !file.is_empty())
+            .map(Path::new)
+    }
+
+    pub fn replays_quota_bytes(&self) -> Option<u64> {
+        (self.replays_quota_mb > 0).then_some(self.replays_quota_mb * 1024 * 1024)
+    }
+
+    pub fn mirror_url(&self) -> &str {
+        self.mirror_url.trim_end_matches('/')
+    }
+
+    pub fn game_mode(&self) -> GameModeKind {
+        self.game_mode
+    }
+
+    /// Hotbar slots of the osu!mania lanes (0 to 8), or the default ones if the configured slots are invalid
+    pub fn mania_lane_slots(&self) -> Vec<u8> {
+        let slots = &self.mania_lane_slots;
+        let valid = slots.len() == MAX_KEYS
+            && slots.iter().all(|slot| (1..=9).contains(slot))
+            && slots
+                .iter()
+                .enumerate()
+                .all(|(i, slot)| !slots[..i].contains(slot));
+
+        if valid {
+            slots.iter().map(|slot| slot - 1).collect()
+        } else {
+            warn!(
+                "Invalid mania_lane_slots {:?} (there must be {} different slots from 1 to 9), using the default ones",
+                slots, MAX_KEYS
+            );
+            DEFAULT_LANE_SLOTS.to_vec()
+        }
+    }
+
+    pub fn max_aim_assist(&self) -> f64 {
+        self.max_aim_assist.max(NO_AIM_ASSIST)
+    }
+
+    pub fn slider_ends(&self) -> bool {
+        self.slider_ends
+    }
+
+    pub fn api_port(&self) -> Option<u16> {
+        (self.api_port > 0).then_some(self.api_port)
+    }
+
+    pub fn api_bind_address(&self) -> &str {
+        &self.api_bind_address
+    }
+
+    pub fn skin(&self) -> Skin {
+        self.skin
+    }
+
+    pub fn set_ignore_beatmap_colors(&mut self, ignore_beatmap_colors: bool) -> Result<()> {
+        self.skin.ignore_beatmap_colors = ignore_beatmap_colors;
+        self.save()
+    }
+
+    pub fn block_skin(&self) -> Option<&str> {
+        self.block_skin.as_deref().filter(|name| !name.is_empty())
+    }
+
+    pub fn set_block_skin(&mut self, block_skin: Option<String>) -> Result<()> {
+        self.block_skin = block_skin;
+        self.save()
+    }
+
+    pub fn reset_clock(&self) -> ResetClock {
+        ResetClock::new(self.timezone_utc_offset_minutes)
+    }
+
+    /// Server timezone formatted as `UTC+hh:mm`
+    pub fn timezone(&self) -> String {
+        let offset = self.timezone_utc_offset_minutes;
+        let sign = if offset < 0 { '-' } else { '+' };
+        format!(
+            "UTC{}{:02}:{:02}",
+            sign,
+            offset.abs() / 60,
+            offset.abs() % 60
+        )
+    }
 }
 
 impl Default for Configs {
@@ -58,13 +439,247 @@ impl Default for Configs {
         let songs_directory = local_dir.join("osu!").join("Songs");
 
         Self {
+            config_version: CONFIG_VERSION,
             songs_directory: songs_directory.to_str().unwrap().to_owned(),
+            max_map_length_secs: default_max_map_length_secs(),
+            operators: Vec::new(),
+            storage: StorageKind::default(),
+            timezone_utc_offset_minutes: 0,
+            skin: Skin::default(),
+            block_skin: None,
+            afk_timeout_secs: default_afk_timeout_secs(),
+            lobby_ready_timeout_secs: default_lobby_ready_timeout_secs(),
+            max_screen_block_updates: default_max_screen_block_updates(),
+            webhook_url: None,
+            leaderboard_url: None,
+            leaderboard_secret: String::new(),
+            api_port: 0,
+            api_bind_address: default_api_bind_address(),
+            disable_audio: false,
+            volume: Volume::default(),
+            sounds_directory: None,
+            replays_quota_mb: default_replays_quota_mb(),
+            now_playing_file: None,
+            mirror_url: default_mirror_url(),
+            game_mode: GameModeKind::default(),
+            mania_lane_slots: default_mania_lane_slots(),
+            max_aim_assist: default_max_aim_assist(),
+            slider_ends: false,
         }
     }
 }
 
+/// Upgrades configs in the format of an older version to the current one, returning the version they had
+fn migrate(json: &mut Value) -> Result<u32> {
+    let fields = json
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("configs file is not a JSON object"))?;
+    let version = match fields.get("config_version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid config_version: {}", version))?
+            as u32,
+        None => 0,
+    };
+
+    if version > CONFIG_VERSION {
+        bail!(
+            "configs file version {} is newer than the supported version {}",
+            version,
+            CONFIG_VERSION
+        );
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(fields);
+    }
+    fields.insert("config_version".to_string(), CONFIG_VERSION.into());
+
+    Ok(version)
+}
+
+/// Copies `path` to `<path>.<suffix>.bak`
+fn backup(path: &PathBuf, suffix: &str) -> Result<PathBuf> {
+    let mut backup_path = path.clone().into_os_string();
+    backup_path.push(format!(".{}.bak", suffix));
+    let backup_path = PathBuf::from(backup_path);
+    fs::copy(path, &backup_path)?;
+
+    Ok(backup_path)
+}
+
 impl Display for Configs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}", "Songs directory".cyan(), self.songs_directory)
+        writeln!(f, "{}: {}", "Songs directory".cyan(), self.songs_directory)?;
+        writeln!(
+            f,
+            "{}: {}s",
+            "Max map length".cyan(),
+            self.max_map_length_secs
+        )?;
+
+        let operators = if self.operators.is_empty() {
+            "nobody".to_string()
+        } else {
+            self.operators.join(", ")
+        };
+        writeln!(f, "{}: {}", "Operators".cyan(), operators)?;
+        writeln!(f, "{}: {:?}", "Storage".cyan(), self.storage)?;
+        writeln!(f, "{}: {}", "Timezone".cyan(), self.timezone())?;
+        writeln!(f, "{}: {}s", "AFK timeout".cyan(), self.afk_timeout_secs)?;
+        writeln!(
+            f,
+            "{}: {}s",
+            "Lobby ready timeout".cyan(),
+            self.lobby_ready_timeout_secs
+        )?;
+        writeln!(
+            f,
+            "{}: {}",
+            "Max screen block updates per tick".cyan(),
+            self.max_screen_block_updates
+        )?;
+        // The URL is a secret, anyone with it can post to the channel
+        writeln!(
+            f,
+            "{}: {}",
+            "Webhook".cyan(),
+            if self.webhook_url().is_some() {
+                "on"
+            } else {
+                "off"
+            }
+        )?;
+        match self.leaderboard_url() {
+            Some(url) => writeln!(f, "{}: {}", "Global leaderboard".cyan(), url)?,
+            None => writeln!(f, "{}: off", "Global leaderboard".cyan())?,
+        }
+        if self.disable_audio {
+            writeln!(f, "{}: disabled", "Audio".cyan())?;
+        }
+        writeln!(
+            f,
+            "{}: master {}, music {}, effects {}",
+            "Volume".cyan(),
+            self.volume.master,
+            self.volume.music,
+            self.volume.effects
+        )?;
+        match self.sounds_directory() {
+            Some(dir) => writeln!(f, "{}: {}", "Sounds directory".cyan(), dir.display())?,
+            None => writeln!(f, "{}: none", "Sounds directory".cyan())?,
+        }
+        writeln!(f, "{}: {}", "Beatmap mirror".cyan(), self.mirror_url())?;
+        writeln!(f, "{}: {:?}", "Game mode".cyan(), self.game_mode)?;
+        writeln!(
+            f,
+            "{}: up to x{}",
+            "Aim assist".cyan(),
+            self.max_aim_assist()
+        )?;
+        writeln!(
+            f,
+            "{}: {}",
+            "Sliders".cyan(),
+            if self.slider_ends {
+                "head and tail"
+            } else {
+                "head only"
+            }
+        )?;
+        match self.now_playing_file() {
+            Some(file) => writeln!(f, "{}: {}", "Now playing file".cyan(), file.display())?,
+            None => writeln!(f, "{}: off", "Now playing file".cyan())?,
+        }
+        match self.replays_quota_bytes() {
+            Some(_) => writeln!(
+                f,
+                "{}: {} MB",
+                "Replays quota".cyan(),
+                self.replays_quota_mb
+            )?,
+            None => writeln!(f, "{}: unlimited", "Replays quota".cyan())?,
+        }
+        match self.api_port() {
+            Some(port) => writeln!(
+                f,
+                "{}: http://{}:{}/state",
+                "API".cyan(),
+                self.api_bind_address,
+                port
+            )?,
+            None => writeln!(f, "{}: off", "API".cyan())?,
+        }
+        writeln!(
+            f,
+            "{}: {}",
+            "Skin".cyan(),
+            self.block_skin().unwrap_or("default")
+        )?;
+        writeln!(
+            f,
+            "{}: {}",
+            "Beatmap combo colors".cyan(),
+            if self.skin.ignore_beatmap_colors {
+                "ignored"
+            } else {
+                "on"
+            }
+        )?;
+        writeln!(
+            f,
+            "{}: {}",
+            "Beat pulse".cyan(),
+            if self.skin.beat_pulse { "on" } else { "off" }
+        )?;
+        writeln!(
+            f,
+            "{}: {}",
+            "Combo numbers".cyan(),
+            if self.skin.combo_number_in_front {
+                "in front of the circles"
+            } else {
+                "inside the circles"
+            }
+        )?;
+        writeln!(
+            f,
+            "{}: {:?}",
+            "Approach circles".cyan(),
+            self.skin.approach_circle
+        )?;
+        write!(
+            f,
+            "{}: {}{}",
+            "Hitsound".cyan(),
+            self.skin.hitsound.name(),
+            if self.skin.beatmap_hitsounds {
+                " (+ beatmap samples)"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrate_unversioned_configs() {
+        let mut json = serde_json::json!({ "songs_directory": "songs", "operators": ["peppy"] });
+
+        assert_eq!(migrate(&mut json).unwrap(), 0);
+        let configs: Configs = serde_json::from_value(json).unwrap();
+        assert_eq!(configs.config_version, CONFIG_VERSION);
+        assert_eq!(configs.songs_directory(), "songs");
+        assert!(configs.is_operator(&PlayerName::new("peppy")));
+        assert!(!configs.is_operator(&PlayerName::new("cookiezi")));
+        assert!(!configs.is_operator(&PlayerName::new("peppy#3cb1")));
+        assert_eq!(configs.max_map_length(), Duration::from_secs(15 * 60));
+
+        let mut newer = serde_json::json!({ "config_version": CONFIG_VERSION + 1 });
+        assert!(migrate(&mut newer).is_err());
     }
 }
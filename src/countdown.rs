@@ -0,0 +1,49 @@
+use bevy_ecs::{
+    query::With,
+    system::{Local, Query, Res},
+};
+use valence::prelude::{BlockState, Instance};
+
+use crate::{
+    digit::{TextPosition, TextWriter},
+    osu::{Osu, OsuInstance},
+};
+
+const COUNTDOWN_SECS: u64 = 3;
+
+/// Shows an osu!-style 3-2-1 countdown in the top of the screen before the first hit object
+pub fn update_countdown(
+    osu: Res<Osu>,
+    mut instances: Query<&mut Instance, With<OsuInstance>>,
+    mut shown_digit: Local<Option<usize>>,
+) {
+    let Ok(mut instance) = instances.get_single_mut() else {
+        return;
+    };
+
+    let digit = osu
+        .playing_beatmap()
+        .and_then(|beatmap| beatmap.time_to_first_hit_object())
+        .map(|time_left| time_left.as_millis() as u64)
+        .filter(|&ms_left| ms_left > 0 && ms_left <= COUNTDOWN_SECS * 1000)
+        .map(|ms_left| ms_left.div_ceil(1000) as usize);
+
+    if digit == *shown_digit {
+        return;
+    }
+
+    let writer = TextWriter {
+        scale: osu.playfield().hud_digit_scale(),
+        position: TextPosition::Center,
+    };
+    let origin = osu.playfield().countdown_pos();
+
+    if let Some(prev_digit) = *shown_digit {
+        writer.draw(prev_digit, origin, BlockState::AIR, &mut instance);
+    }
+    if let Some(digit) = digit {
+        writer.draw(digit, origin, BlockState::YELLOW_CONCRETE, &mut instance);
+    }
+
+    *shown_digit = digit;
+}
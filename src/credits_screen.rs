@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use bevy_ecs::system::{Local, Res, ResMut};
+use valence::prelude::{BlockPos, BlockState};
+
+use crate::{now_playing::draw_lines, osu::Osu, playfield::PlayfieldSurface};
+
+/// Time the credits are shown before the beatmap starts (players can skip it by sneaking)
+pub const CREDITS_SCREEN_DURATION: Duration = Duration::from_secs(3);
+
+/// Shows the title, artist, mapper and source of the beatmap in the middle of the screen before it starts (like the osu! loading screen)
+pub fn update_credits_screen(
+    osu: Res<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut drawn_positions: Local<Vec<BlockPos>>,
+) {
+    match osu.credits_beatmap() {
+        Some(beatmap) if drawn_positions.is_empty() => {
+            let mapper = format!("Mapped by {}", beatmap.data.creator);
+            let source = format!("Source: {}", beatmap.data.source);
+
+            let mut lines = vec![
+                (beatmap.data.title.as_str(), BlockState::WHITE_CONCRETE),
+                (
+                    beatmap.data.artist.as_str(),
+                    BlockState::LIGHT_GRAY_CONCRETE,
+                ),
+                (mapper.as_str(), BlockState::YELLOW_CONCRETE),
+            ];
+            if !beatmap.data.source.is_empty() {
+                lines.push((source.as_str(), BlockState::GRAY_CONCRETE));
+            }
+
+            let playfield = osu.playfield();
+            let (screen_x, screen_y) = playfield.screen_size();
+            let area = (
+                playfield.screen_center(),
+                screen_x as usize,
+                screen_y as usize / 2,
+            );
+
+            draw_lines(&lines, area, &mut surface, &mut drawn_positions);
+        }
+        None if !drawn_positions.is_empty() => {
+            surface.fill(drawn_positions.drain(..), BlockState::AIR);
+        }
+        _ => {}
+    }
+}
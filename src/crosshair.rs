@@ -0,0 +1,106 @@
+use bevy_ecs::{
+    prelude::{Component, Entity},
+    query::{With, Without},
+    system::{Commands, Query, Res},
+};
+use valence::{
+    equipment::{Equipment, EquipmentSlot},
+    prelude::{Client, DVec3, EntityKind, McEntity, TrackedData},
+    protocol::{ItemKind, ItemStack},
+    Despawned,
+};
+
+use crate::{
+    afk::Afk,
+    osu::{Osu, OsuInstance},
+    ring::client_aim_point,
+};
+
+/// Armor stand flag of half sized armor stands
+const SMALL_ARMOR_STAND: u8 = 0x01;
+/// Armor stand flag which removes its hitbox, so the crosshairs don't block the clicks on the hitcircles
+const MARKER_ARMOR_STAND: u8 = 0x10;
+/// Offset of a small armor stand from the center of its helmet item (small armor stands are half the size, see `ARMOR_STAND_OFFSET`)
+const SMALL_ARMOR_STAND_OFFSET: DVec3 = DVec3::new(0.25, -1.1, 0.25);
+/// Distance of the crosshair in front of the hitcircle plane, so it is not hidden inside the circles
+const CROSSHAIR_Z_OFFSET: f64 = 0.5;
+
+/// In-world cursor of a player: a small armor stand wearing a target on the point where the player is aiming in the hitcircle
+/// plane (see `client_aim_point`). Every player sees the crosshairs of everyone, like the spectators of an osu! multiplayer room.
+#[derive(Component)]
+pub struct Crosshair {
+    player: Entity,
+}
+
+/// Moves the crosshairs of the players to where they are aiming each tick while a beatmap is played. The crosshairs are removed
+/// outside the `Playing` state and while the players are not aiming at the screen (or are AFK).
+pub fn update_crosshairs(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    instances: Query<Entity, With<OsuInstance>>,
+    clients: Query<(Entity, &Client), Without<Afk>>,
+    mut crosshairs: Query<(Entity, &Crosshair, &mut McEntity), Without<Despawned>>,
+) {
+    let playfield = osu.playfield();
+    let plane_z = playfield.origin().z as f64;
+    let aim_point = |player: Entity| {
+        let (_, client) = clients.get(player).ok()?;
+        client_aim_point(client, plane_z).filter(|&aim_point| playfield.is_in_screen(aim_point))
+    };
+    let playing = osu.playing_beatmap().is_some();
+
+    let mut players_with_crosshair = Vec::new();
+    for (entity, crosshair, mut armor_stand) in &mut crosshairs {
+        match aim_point(crosshair.player).filter(|_| playing) {
+            Some(aim_point) => {
+                armor_stand.set_position(crosshair_position(aim_point));
+                players_with_crosshair.push(crosshair.player);
+            }
+            None => {
+                commands.entity(entity).insert(Despawned);
+            }
+        }
+    }
+
+    let Ok(instance) = instances.get_single() else {
+        return;
+    };
+    if !playing {
+        return;
+    }
+    for (player, _) in &clients {
+        if players_with_crosshair.contains(&player) {
+            continue;
+        }
+        if let Some(aim_point) = aim_point(player) {
+            commands.spawn(create_crosshair(player, aim_point, instance));
+        }
+    }
+}
+
+fn create_crosshair(
+    player: Entity,
+    aim_point: DVec3,
+    instance: Entity,
+) -> (McEntity, Equipment, Crosshair) {
+    let mut equipment = Equipment::new();
+    equipment.set(
+        ItemStack::new(ItemKind::Target, 1, None),
+        EquipmentSlot::Helmet,
+    );
+
+    let mut armor_stand = McEntity::new(EntityKind::ArmorStand, instance);
+    if let TrackedData::ArmorStand(armor_stand) = armor_stand.data_mut() {
+        armor_stand.set_invisible(true);
+        armor_stand.set_no_gravity(true);
+        armor_stand.set_armor_stand_flags(SMALL_ARMOR_STAND | MARKER_ARMOR_STAND);
+    }
+    armor_stand.set_position(crosshair_position(aim_point));
+
+    (armor_stand, equipment, Crosshair { player })
+}
+
+/// Position of the armor stand of a crosshair whose helmet is on `aim_point`
+fn crosshair_position(aim_point: DVec3) -> DVec3 {
+    aim_point - DVec3::new(0.0, 0.0, CROSSHAIR_Z_OFFSET) + SMALL_ARMOR_STAND_OFFSET
+}
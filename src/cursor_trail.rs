@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use bevy_ecs::{
+    prelude::Entity,
+    system::{Local, Query, Res},
+};
+use valence::{
+    prelude::{Client, DVec3, Vec3},
+    protocol::packets::s2c::particle::Particle,
+};
+
+use crate::{afk::Afk, hud::HudSettings, osu::Osu, ring::client_aim_point};
+
+/// Blocks between the particles of a trail
+const TRAIL_SPACING: f64 = 1.0;
+/// Particles emitted for each cursor per tick, so fast flicks don't flood the clients
+const MAX_TRAIL_PARTICLES: usize = 8;
+/// Distance of the trail in front of the hitcircle plane, so the particles are not hidden inside the circles
+const TRAIL_Z_OFFSET: f64 = 0.5;
+
+/// Emits particles where the players are aiming in the hitcircle plane (like `Ring::raycast_client`) while a beatmap is played,
+/// for the players and spectators who enabled the cursor trail in their `HudSettings`. Each tick the particles fill the path
+/// from the previous aim point, so fast movements still leave a continuous trail.
+pub fn update_cursor_trails(
+    osu: Res<Osu>,
+    mut clients: Query<(Entity, &mut Client, Option<&HudSettings>, Option<&Afk>)>,
+    mut last_aim_points: Local<HashMap<Entity, DVec3>>,
+) {
+    if osu.playing_beatmap().is_none() {
+        last_aim_points.clear();
+        return;
+    }
+
+    let playfield = osu.playfield();
+    let plane_z = playfield.origin().z as f64;
+    let mut aim_points = HashMap::new();
+    let mut trail = Vec::new();
+    for (entity, client, _, afk) in &clients {
+        let Some(aim_point) = client_aim_point(client, plane_z)
+            .filter(|&aim_point| afk.is_none() && playfield.is_in_screen(aim_point))
+        else {
+            continue;
+        };
+
+        trail.extend(trail_points(
+            last_aim_points.get(&entity).copied(),
+            aim_point,
+        ));
+        aim_points.insert(entity, aim_point);
+    }
+    *last_aim_points = aim_points;
+
+    if trail.is_empty() {
+        return;
+    }
+    for (_, mut client, settings, _) in &mut clients {
+        if !settings.map_or(false, |settings| settings.cursor_trail) {
+            continue;
+        }
+
+        for &point in &trail {
+            client.play_particle(
+                &Particle::EndRod,
+                true,
+                point - DVec3::new(0.0, 0.0, TRAIL_Z_OFFSET),
+                Vec3::ZERO,
+                0.0,
+                1,
+            );
+        }
+    }
+}
+
+/// Points of the trail from the aim point of the previous tick to `to` (only `to` if the player wasn't aiming at the screen)
+fn trail_points(from: Option<DVec3>, to: DVec3) -> Vec<DVec3> {
+    let Some(from) = from else {
+        return vec![to];
+    };
+    let count = ((from.distance(to) / TRAIL_SPACING).ceil() as usize).clamp(1, MAX_TRAIL_PARTICLES);
+
+    (1..=count)
+        .map(|i| from.lerp(to, i as f64 / count as f64))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trail_fills_cursor_path() {
+        let to = DVec3::new(4.0, 0.0, 0.0);
+        assert_eq!(trail_points(None, to), vec![to]);
+
+        let points = trail_points(Some(DVec3::ZERO), to);
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[0], DVec3::new(1.0, 0.0, 0.0));
+        assert_eq!(*points.last().unwrap(), to);
+
+        // Still aiming at the same point
+        assert_eq!(trail_points(Some(to), to), vec![to]);
+
+        let flick = trail_points(Some(DVec3::ZERO), DVec3::new(100.0, 0.0, 0.0));
+        assert_eq!(flick.len(), MAX_TRAIL_PARTICLES);
+    }
+}
@@ -0,0 +1,512 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use bevy_ecs::{
+    prelude::EventReader,
+    query::Without,
+    system::{Commands, Local, Query, Res, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, Color, OpenInventory, Server},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    afk::Afk,
+    beatmap::{file_hash, Beatmap},
+    beatmap_selection::read_beatmap_dir,
+    command_registry::{CommandSpec, OsuCommand},
+    configs::Configs,
+    error::error_message,
+    osu::{Osu, OsuStateChange},
+    player_name::PlayerName,
+    progress::{LongOperation, LongOperations},
+    resets::ResetClock,
+};
+
+/// Scores shown by `/daily`
+const DAILY_LEADERBOARD_SIZE: usize = 10;
+/// `/daily play` starts the map of the day
+const PLAY_SUBCOMMAND: &str = "play";
+/// Delay before picking the map of the day again after the first failure (e.g. before the songs were downloaded)
+const MIN_PICK_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// The delay doubles after each failure, up to this one
+const MAX_PICK_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Difficulty picked as the map of the day
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DailyMap {
+    pub path: PathBuf,
+    /// `title [difficulty]`
+    pub name: String,
+    /// See `BeatmapData::file_hash`
+    pub map_hash: String,
+}
+
+/// Best score of a player (or of the players who played together) on the map of the day
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DailyScore {
+    pub player: String,
+    pub score: usize,
+    pub accuracy: f32,
+    pub max_combo: usize,
+    /// e.g. `+HRHD` or `NM`
+    pub mods: String,
+}
+
+/// Daily challenge: a map of the day picked from the songs directory (the same one for every server with the same songs) with
+/// its own leaderboard, whose winner is announced when the day changes (in the server timezone, see `Configs::reset_clock`).
+/// It's saved to `daily.json`, so a restart doesn't lose the scores of the day.
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct DailyChallenge {
+    /// Days since the Unix epoch
+    day: u64,
+    /// `None` if no playable map could be picked
+    map: Option<DailyMap>,
+    /// Highest first
+    scores: Vec<DailyScore>,
+    /// Map being picked in a background thread (scanning the songs directory can take a while)
+    #[serde(skip)]
+    picking: Option<LongOperation<DailyMap>>,
+    /// When the map is picked again after a failure, and the delay until the next retry
+    #[serde(skip)]
+    retry: Option<(Instant, Duration)>,
+}
+
+fn today(clock: &ResetClock) -> u64 {
+    clock.day(ResetClock::now()).max(0) as u64
+}
+
+/// Time left until the next day in the server timezone
+fn time_until_rollover(clock: &ResetClock) -> Duration {
+    clock.time_to_daily_reset(ResetClock::now())
+}
+
+/// Deterministic pseudo-random number of `day` (SplitMix64), the same on every platform and version of the `rand` crate
+fn day_seed(day: u64) -> u64 {
+    let mut z = day.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl DailyChallenge {
+    pub fn path() -> PathBuf {
+        PathBuf::from("daily.json")
+    }
+
+    pub fn open() -> Self {
+        let path = Self::path();
+        if !path.exists() {
+            return Self::default();
+        }
+
+        Self::read().unwrap_or_else(|error| {
+            warn!("Error while reading the daily challenge file: {}", error);
+            Self::default()
+        })
+    }
+
+    fn read() -> Result<Self> {
+        let file_data = fs::read(Self::path())?;
+        let json = str::from_utf8(file_data.as_slice())?;
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn map(&self) -> Option<&DailyMap> {
+        self.map.as_ref()
+    }
+
+    pub fn winner(&self) -> Option<&DailyScore> {
+        self.scores.first()
+    }
+
+    /// Keeps the best score of each player
+    pub fn record(&mut self, score: DailyScore) {
+        if let Some(previous) = self
+            .scores
+            .iter()
+            .position(|other| other.player == score.player)
+        {
+            if self.scores[previous].score >= score.score {
+                return;
+            }
+            self.scores.remove(previous);
+        }
+
+        let position = self
+            .scores
+            .iter()
+            .take_while(|other| other.score >= score.score)
+            .count();
+        self.scores.insert(position, score);
+    }
+
+    /// Starts the challenge of `day`, dropping the map and the scores of the previous one (see `DailyChallenge::start_picking`)
+    fn roll_over(&mut self, day: u64) {
+        self.day = day;
+        self.map = None;
+        self.scores.clear();
+        self.picking = None;
+        self.retry = None;
+    }
+
+    /// Picks the map of the day in a background thread, unless it's already being picked or the retry delay hasn't passed
+    fn start_picking(
+        &mut self,
+        songs_dir: PathBuf,
+        max_length: Duration,
+        operations: &mut LongOperations,
+    ) {
+        if self.picking.is_some()
+            || self
+                .retry
+                .map_or(false, |(retry_at, _)| Instant::now() < retry_at)
+        {
+            return;
+        }
+
+        let day = self.day;
+        self.picking = Some(LongOperation::start(
+            "Picking the map of the day",
+            None,
+            operations,
+            move |_| pick_daily_map(&songs_dir, day, max_length),
+        ));
+    }
+
+    /// Map of the day if it has just been picked. After a failure, it's picked again with an exponential backoff.
+    fn finish_picking(&mut self) -> Option<&DailyMap> {
+        match self.picking.as_mut()?.try_finish()? {
+            Ok(map) => {
+                self.picking = None;
+                self.retry = None;
+                self.map = Some(map);
+                self.map.as_ref()
+            }
+            Err(error) => {
+                self.picking = None;
+                let delay = self.retry.map_or(MIN_PICK_RETRY_DELAY, |(_, delay)| {
+                    (delay * 2).min(MAX_PICK_RETRY_DELAY)
+                });
+                warn!(
+                    "Could not pick the map of the day, retrying in {}m: {}",
+                    delay.as_secs() / 60,
+                    error
+                );
+                self.retry = Some((Instant::now() + delay, delay));
+                None
+            }
+        }
+    }
+
+    fn lines(&self, clock: &ResetClock) -> Vec<Text> {
+        let Some(map) = &self.map else {
+            let message = if self.picking.is_some() {
+                "The map of the day is being picked"
+            } else {
+                "No map of the day could be picked from the songs directory"
+            };
+            return vec![message.color(Color::GRAY)];
+        };
+
+        let time_left = time_until_rollover(clock).as_secs() / 60;
+        let mut lines = vec![
+            "Map of the day: ".color(Color::GOLD)
+                + map.name.clone().color(Color::AQUA)
+                + format!("  ({}h {:02}m left)", time_left / 60, time_left % 60).color(Color::GRAY),
+        ];
+        if self.scores.is_empty() {
+            lines.push("No scores yet, play it with /daily play".color(Color::GRAY));
+        }
+
+        for (idx, score) in self.scores.iter().take(DAILY_LEADERBOARD_SIZE).enumerate() {
+            lines.push(
+                format!("#{} ", idx + 1).color(Color::YELLOW)
+                    + score.player.clone().color(Color::WHITE)
+                    + format!("  {}", score.score).color(Color::GOLD)
+                    + format!("  {:.2}%", score.accuracy).color(Color::GREEN)
+                    + format!("  x{}", score.max_combo).color(Color::LIGHT_PURPLE)
+                    + format!("  {}", score.mods).color(Color::GRAY),
+            );
+        }
+
+        lines
+    }
+}
+
+/// Picks the map of `day`: a song folder chosen by the seed of the day (the next ones if it has no playable difficulty)
+/// and one of its playable difficulties
+fn pick_daily_map(songs_dir: &Path, day: u64, max_length: Duration) -> Result<DailyMap> {
+    let mut songs: Vec<PathBuf> = fs::read_dir(songs_dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    songs.sort();
+
+    let seed = day_seed(day);
+    let first = daily_index(seed, songs.len()).ok_or_else(|| anyhow!("there are no songs"))?;
+
+    for song_dir in songs.iter().cycle().skip(first).take(songs.len()) {
+        let Ok(mut beatmaps) = read_beatmap_dir(song_dir) else {
+            continue;
+        };
+        beatmaps.retain(|beatmap| beatmap.length() <= max_length && beatmap.problem().is_none());
+        beatmaps.sort_by(|a, b| a.path().cmp(b.path()));
+
+        if let Some(idx) = daily_index(seed >> 32, beatmaps.len()) {
+            let beatmap = &beatmaps[idx];
+            return Ok(DailyMap {
+                path: beatmap.path().clone(),
+                name: beatmap.display_name(),
+                map_hash: file_hash(&fs::read(beatmap.path())?),
+            });
+        }
+    }
+
+    Err(anyhow!("none of the songs has a playable beatmap"))
+}
+
+fn daily_index(seed: u64, len: usize) -> Option<usize> {
+    (len > 0).then(|| (seed % len as u64) as usize)
+}
+
+/// Picks the map of the day when the day changes (or on startup), announcing the winner of the previous one
+pub fn update_daily_challenge(
+    mut daily: ResMut<DailyChallenge>,
+    configs: Res<Configs>,
+    server: Res<Server>,
+    mut operations: ResMut<LongOperations>,
+    mut clients: Query<&mut Client>,
+    mut ticks: Local<usize>,
+) {
+    if let Some(map) = daily.finish_picking() {
+        info!("Map of the day: {}", map.name);
+        let message = "New daily challenge: ".color(Color::GOLD)
+            + map.name.clone().color(Color::AQUA)
+            + " (/daily play)".color(Color::GRAY);
+        for mut client in &mut clients {
+            client.send_message(message.clone());
+        }
+        if let Err(error) = daily.save() {
+            warn!("Error while saving the daily challenge: {}", error);
+        }
+    }
+
+    // Checked once per second
+    if *ticks > 0 {
+        *ticks -= 1;
+        return;
+    }
+    *ticks = server.shared().tps() as usize;
+
+    let today = today(&configs.reset_clock());
+    if daily.day != today {
+        if let (Some(map), Some(winner)) = (daily.map(), daily.winner()) {
+            let message = "Daily challenge winner of ".color(Color::GOLD)
+                + map.name.clone().color(Color::AQUA)
+                + ": ".color(Color::GOLD)
+                + winner.player.clone().color(Color::GREEN)
+                + format!(" with {}", winner.score).color(Color::YELLOW);
+            info!(
+                "Daily challenge winner: {} ({})",
+                winner.player, winner.score
+            );
+            for mut client in &mut clients {
+                client.send_message(message.clone());
+            }
+        }
+
+        daily.roll_over(today);
+        if let Err(error) = daily.save() {
+            warn!("Error while saving the daily challenge: {}", error);
+        }
+    }
+
+    // Nothing was playable yet, e.g. before the songs were downloaded
+    if daily.map.is_none() {
+        daily.start_picking(
+            PathBuf::from(configs.songs_directory()),
+            configs.max_map_length(),
+            &mut operations,
+        );
+    }
+}
+
+/// Records the cleared plays of the map of the day (except the warmup, adaptive and unranked plays, like the local scores)
+pub fn record_daily_scores(
+    osu: Res<Osu>,
+    mut daily: ResMut<DailyChallenge>,
+    player_names: Query<&PlayerName, Without<Afk>>,
+    mut recorded: Local<bool>,
+) {
+    let Some(beatmap) = osu.finished_beatmap() else {
+        *recorded = false;
+        return;
+    };
+    if *recorded {
+        return;
+    }
+    *recorded = true;
+
+    if osu.is_warmup() || beatmap.state.adaptive.is_some() || beatmap.data.mods.is_unranked() {
+        return;
+    }
+    if daily.map().map(|map| &map.map_hash) != Some(&beatmap.data.file_hash) {
+        return;
+    }
+
+    let player = player_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    daily.record(daily_score(beatmap, player));
+    if let Err(error) = daily.save() {
+        warn!("Error while saving the daily challenge: {}", error);
+    }
+}
+
+fn daily_score(beatmap: &Beatmap, player: String) -> DailyScore {
+    DailyScore {
+        player,
+        score: beatmap.state.score,
+        accuracy: beatmap.state.accuracy(),
+        max_combo: beatmap.state.max_combo,
+        mods: beatmap.data.mods.to_string(),
+    }
+}
+
+pub fn daily_command() -> CommandSpec {
+    CommandSpec::new("daily").subcommands([PLAY_SUBCOMMAND])
+}
+
+/// Handles `/daily [play]`, which shows the map of the day and its leaderboard, or starts it while players are selecting a
+/// beatmap
+pub fn execute_daily_commands(
+    mut commands: Commands,
+    daily: Res<DailyChallenge>,
+    configs: Res<Configs>,
+    mut osu: ResMut<Osu>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "daily" {
+            continue;
+        }
+
+        let result: Result<Vec<Text>> = match command_event.args.trim() {
+            "" => Ok(daily.lines(&configs.reset_clock())),
+            PLAY_SUBCOMMAND => match daily.map() {
+                None => Err(anyhow!("There is no map of the day")),
+                Some(_) if !osu.is_selecting_beatmap() => Err(anyhow!(
+                    "The map of the day can only be started while selecting a beatmap"
+                )),
+                Some(map) => {
+                    // Close beatmap selection
+                    commands
+                        .entity(command_event.client)
+                        .remove::<OpenInventory>();
+
+                    let is_operator = player_names
+                        .get(command_event.client)
+                        .map_or(false, |player_name| configs.is_operator(player_name));
+                    let beatmap_path = map.path.clone();
+                    osu.change_state_for(
+                        OsuStateChange::PrePlaying { beatmap_path },
+                        is_operator,
+                        &mut clients,
+                    )
+                    .map(|_| {
+                        vec![
+                            "Daily challenge: ".color(Color::YELLOW)
+                                + map.name.clone().color(Color::GREEN),
+                        ]
+                    })
+                }
+            },
+            _ => Err(anyhow!("Usage: /daily [play]")),
+        };
+
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    client.send_message(line);
+                }
+            }
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn player_score(player: &str, score: usize) -> DailyScore {
+        DailyScore {
+            player: player.to_string(),
+            score,
+            accuracy: 100.0,
+            max_combo: 0,
+            mods: "NM".to_string(),
+        }
+    }
+
+    #[test]
+    fn map_of_the_day() {
+        assert_eq!(day_seed(19_000), day_seed(19_000));
+        assert_ne!(day_seed(19_000), day_seed(19_001));
+
+        assert_eq!(daily_index(day_seed(19_000), 0), None);
+        assert!((0..100).all(|day| daily_index(day_seed(day), 7).unwrap() < 7));
+        // Every song gets picked eventually
+        let mut picked = [false; 7];
+        for day in 0..100 {
+            picked[daily_index(day_seed(day), 7).unwrap()] = true;
+        }
+        assert!(picked.iter().all(|&picked| picked));
+    }
+
+    #[test]
+    fn daily_leaderboard() {
+        let mut daily = DailyChallenge::default();
+        assert!(daily.winner().is_none());
+
+        daily.record(player_score("a", 500));
+        daily.record(player_score("b", 800));
+        daily.record(player_score("a", 300));
+        daily.record(player_score("c", 600));
+        daily.record(player_score("a", 900));
+
+        let scores: Vec<_> = daily
+            .scores
+            .iter()
+            .map(|score| (score.player.as_str(), score.score))
+            .collect();
+        assert_eq!(scores, vec![("a", 900), ("b", 800), ("c", 600)]);
+        assert_eq!(daily.winner().unwrap().player, "a");
+    }
+}
@@ -2,30 +2,74 @@ use std::cmp::max;
 
 use valence::prelude::*;
 
+use crate::block_batch::BlockBatch;
+
 pub enum TextPosition {
     Right,
     Center,
     Left,
 }
 
-pub struct DigitWriter {
+/// Draws numbers and text with a 3x5 block font
+pub struct TextWriter {
     pub scale: usize,
     pub position: TextPosition,
 }
 
-impl DigitWriter {
+impl TextWriter {
     pub fn draw(
         &self,
         number: usize,
         origin: BlockPos,
-        block: Block,
+        block: BlockState,
+        instance: &mut Mut<Instance>,
+    ) {
+        let mut batch = BlockBatch::new();
+        self.batch(number, origin, block, &mut batch);
+        batch.apply(instance);
+    }
+
+    /// Adds the blocks of `number` to `batch`, so it can be drawn together with other blocks
+    pub fn batch(
+        &self,
+        number: usize,
+        origin: BlockPos,
+        block: BlockState,
+        batch: &mut BlockBatch,
+    ) {
+        batch.fill(self.iter_block_positions(number, origin).flatten(), block);
+    }
+
+    /// Draws `text` made of digits, letters and basic punctuation (unsupported characters are skipped)
+    pub fn draw_text(
+        &self,
+        text: &str,
+        origin: BlockPos,
+        block: BlockState,
         instance: &mut Mut<Instance>,
     ) {
-        self.iter_block_positions(number, origin)
-            .flatten()
-            .for_each(|pos| {
-                instance.set_block(pos, block.clone());
-            });
+        let mut batch = BlockBatch::new();
+        batch.fill(
+            self.iter_text_block_positions(text, origin).flatten(),
+            block,
+        );
+        batch.apply(instance);
+    }
+
+    /// Width and height in blocks of `number` when drawn
+    pub fn size(&self, number: usize) -> (usize, usize) {
+        self.masks_size(DigitsIter::new(number).len() as usize)
+    }
+
+    /// Width and height in blocks of `text` when drawn
+    pub fn text_size(&self, text: &str) -> (usize, usize) {
+        self.masks_size(text.chars().filter_map(char_mask).count())
+    }
+
+    fn masks_size(&self, chars: usize) -> (usize, usize) {
+        let width = CHAR_SIZE.0 * self.scale * chars + self.scale * chars.saturating_sub(1);
+
+        (width, CHAR_SIZE.1 * self.scale)
     }
 
     pub fn iter_block_positions(
@@ -33,56 +77,76 @@ impl DigitWriter {
         number: usize,
         origin: BlockPos,
     ) -> impl Iterator<Item = impl Iterator<Item = BlockPos>> + '_ {
-        // Get number of digits from number
-        let digits_iter = DigitsIter::new(number);
-        let digits = digits_iter.len() as i32;
+        let masks = DigitsIter::new(number)
+            .map(|digit| &DIGIT_MASKS[digit as usize])
+            .collect();
 
-        // Calculate offset for each digit
+        self.iter_masks_block_positions(masks, origin)
+    }
+
+    pub fn iter_text_block_positions(
+        &self,
+        text: &str,
+        origin: BlockPos,
+    ) -> impl Iterator<Item = impl Iterator<Item = BlockPos>> + '_ {
+        let masks = text.chars().filter_map(char_mask).collect();
+
+        self.iter_masks_block_positions(masks, origin)
+    }
+
+    fn iter_masks_block_positions(
+        &self,
+        masks: Vec<&'static CharMask>,
+        origin: BlockPos,
+    ) -> impl Iterator<Item = impl Iterator<Item = BlockPos>> + '_ {
+        let chars = masks.len() as i32;
+
+        // Calculate offset for each char
         let scale = self.scale;
-        let digit_spacing = scale as i32;
+        let char_spacing = scale as i32;
 
-        let digit_size = ((DIGIT_SIZE.0 * scale) as i32, (DIGIT_SIZE.1 * scale) as i32);
+        let char_size = ((CHAR_SIZE.0 * scale) as i32, (CHAR_SIZE.1 * scale) as i32);
         let position_offset: BlockPos = match self.position {
             TextPosition::Right => BlockPos { x: 0, y: 0, z: 0 },
             TextPosition::Center => BlockPos {
-                x: (digit_size.0 * digits + digit_spacing * (digits - 1)) / 2,
-                y: -digit_size.1 / 2 + (1 - digit_size.1 % 2),
+                x: (char_size.0 * chars + char_spacing * (chars - 1)) / 2,
+                y: -char_size.1 / 2 + (1 - char_size.1 % 2),
                 z: 0,
             },
             TextPosition::Left => BlockPos {
-                x: digit_size.0 * digits + digit_spacing * (digits - 1),
+                x: char_size.0 * chars + char_spacing * (chars - 1),
                 y: 0,
                 z: 0,
             },
         };
 
-        digits_iter
+        masks
+            .into_iter()
             .enumerate()
-            .map(move |(i, digit)| {
-                let digit_offset = BlockPos {
-                    x: i as i32 * -(digit_size.0 + digit_spacing),
+            .map(move |(i, mask)| {
+                let char_offset = BlockPos {
+                    x: i as i32 * -(char_size.0 + char_spacing),
                     y: 0,
                     z: 0,
                 };
 
-                (digit, digit_offset + position_offset + origin)
+                (mask, char_offset + position_offset + origin)
             })
-            .map(|(digit, digit_origin)| self.iter_digit_block_positions(digit, digit_origin))
+            .map(|(mask, char_origin)| self.iter_char_block_positions(mask, char_origin))
     }
 
-    /// `base` is the position of the digit's bottom left block
-    fn iter_digit_block_positions(
+    /// `base` is the position of the char's bottom left block
+    fn iter_char_block_positions(
         &self,
-        digit: u8,
+        mask: &'static CharMask,
         origin: BlockPos,
     ) -> impl Iterator<Item = BlockPos> {
         let scale = self.scale;
-        let digit = digit as usize;
-        let x_mov = -((DIGIT_SIZE.0 * scale) as i32 - 1);
+        let x_mov = -((CHAR_SIZE.0 * scale) as i32 - 1);
 
-        (0..DIGIT_SIZE.1).flat_map(move |y| {
-            (0..DIGIT_SIZE.0)
-                .filter(move |&x| has_block(digit, x, y))
+        (0..CHAR_SIZE.1).flat_map(move |y| {
+            (0..CHAR_SIZE.0)
+                .filter(move |&x| has_block(mask, x, y))
                 .flat_map(move |x| {
                     (0..scale as i32).flat_map(move |x_offset| {
                         (0..scale as i32).map(move |y_offset| BlockPos {
@@ -138,12 +202,43 @@ impl Iterator for DigitsIter {
     }
 }
 
-fn has_block(digit: usize, x: usize, y: usize) -> bool {
-    DIGIT_MASKS[digit][DIGIT_SIZE.1 - y - 1][DIGIT_SIZE.0 - x - 1]
+fn has_block(mask: &CharMask, x: usize, y: usize) -> bool {
+    mask[CHAR_SIZE.1 - y - 1][CHAR_SIZE.0 - x - 1]
+}
+
+/// Mask of a character, from top to bottom and left to right as seen from the player.
+/// Lowercase letters are drawn as uppercase and unsupported characters return `None`.
+pub fn char_mask(c: char) -> Option<&'static CharMask> {
+    match c.to_ascii_uppercase() {
+        c @ '0'..='9' => Some(&DIGIT_MASKS[c as usize - '0' as usize]),
+        c @ 'A'..='Z' => Some(&LETTER_MASKS[c as usize - 'A' as usize]),
+        c => PUNCTUATION_MASKS
+            .iter()
+            .find(|(punctuation, _)| *punctuation == c)
+            .map(|(_, mask)| mask),
+    }
+}
+
+/// Builds a mask from rows of bits (most significant bit on the left)
+const fn mask_from_rows(rows: [u8; CHAR_SIZE.1]) -> CharMask {
+    let mut mask = [[false; CHAR_SIZE.0]; CHAR_SIZE.1];
+    let mut y = 0;
+    while y < CHAR_SIZE.1 {
+        let mut x = 0;
+        while x < CHAR_SIZE.0 {
+            mask[y][x] = rows[y] & (1 << (CHAR_SIZE.0 - x - 1)) != 0;
+            x += 1;
+        }
+        y += 1;
+    }
+
+    mask
 }
 
-const DIGIT_SIZE: (usize, usize) = (3, 5);
-const DIGIT_MASKS: [[[bool; DIGIT_SIZE.0]; DIGIT_SIZE.1]; 10] = [
+pub type CharMask = [[bool; CHAR_SIZE.0]; CHAR_SIZE.1];
+
+pub const CHAR_SIZE: (usize, usize) = (3, 5);
+static DIGIT_MASKS: [CharMask; 10] = [
     // 0
     [
         [true, true, true],
@@ -226,6 +321,73 @@ const DIGIT_MASKS: [[[bool; DIGIT_SIZE.0]; DIGIT_SIZE.1]; 10] = [
     ],
 ];
 
+/// Letters from A to Z
+static LETTER_MASKS: [CharMask; 26] = [
+    // A
+    mask_from_rows([0b010, 0b101, 0b111, 0b101, 0b101]),
+    // B
+    mask_from_rows([0b110, 0b101, 0b110, 0b101, 0b110]),
+    // C
+    mask_from_rows([0b111, 0b100, 0b100, 0b100, 0b111]),
+    // D
+    mask_from_rows([0b110, 0b101, 0b101, 0b101, 0b110]),
+    // E
+    mask_from_rows([0b111, 0b100, 0b111, 0b100, 0b111]),
+    // F
+    mask_from_rows([0b111, 0b100, 0b111, 0b100, 0b100]),
+    // G
+    mask_from_rows([0b111, 0b100, 0b101, 0b101, 0b111]),
+    // H
+    mask_from_rows([0b101, 0b101, 0b111, 0b101, 0b101]),
+    // I
+    mask_from_rows([0b111, 0b010, 0b010, 0b010, 0b111]),
+    // J
+    mask_from_rows([0b001, 0b001, 0b001, 0b101, 0b111]),
+    // K
+    mask_from_rows([0b101, 0b101, 0b110, 0b101, 0b101]),
+    // L
+    mask_from_rows([0b100, 0b100, 0b100, 0b100, 0b111]),
+    // M
+    mask_from_rows([0b101, 0b111, 0b111, 0b101, 0b101]),
+    // N
+    mask_from_rows([0b110, 0b101, 0b101, 0b101, 0b101]),
+    // O
+    mask_from_rows([0b111, 0b101, 0b101, 0b101, 0b111]),
+    // P
+    mask_from_rows([0b111, 0b101, 0b111, 0b100, 0b100]),
+    // Q
+    mask_from_rows([0b111, 0b101, 0b101, 0b111, 0b001]),
+    // R
+    mask_from_rows([0b111, 0b101, 0b110, 0b101, 0b101]),
+    // S
+    mask_from_rows([0b111, 0b100, 0b111, 0b001, 0b111]),
+    // T
+    mask_from_rows([0b111, 0b010, 0b010, 0b010, 0b010]),
+    // U
+    mask_from_rows([0b101, 0b101, 0b101, 0b101, 0b111]),
+    // V
+    mask_from_rows([0b101, 0b101, 0b101, 0b101, 0b010]),
+    // W
+    mask_from_rows([0b101, 0b101, 0b111, 0b111, 0b101]),
+    // X
+    mask_from_rows([0b101, 0b101, 0b010, 0b101, 0b101]),
+    // Y
+    mask_from_rows([0b101, 0b101, 0b010, 0b010, 0b010]),
+    // Z
+    mask_from_rows([0b111, 0b001, 0b010, 0b100, 0b111]),
+];
+
+static PUNCTUATION_MASKS: [(char, CharMask); 8] = [
+    (' ', mask_from_rows([0b000, 0b000, 0b000, 0b000, 0b000])),
+    ('!', mask_from_rows([0b010, 0b010, 0b010, 0b000, 0b010])),
+    ('?', mask_from_rows([0b111, 0b001, 0b011, 0b000, 0b010])),
+    ('.', mask_from_rows([0b000, 0b000, 0b000, 0b000, 0b010])),
+    (',', mask_from_rows([0b000, 0b000, 0b000, 0b010, 0b100])),
+    (':', mask_from_rows([0b000, 0b010, 0b000, 0b010, 0b000])),
+    ('-', mask_from_rows([0b000, 0b000, 0b111, 0b000, 0b000])),
+    ('\'', mask_from_rows([0b010, 0b010, 0b000, 0b000, 0b000])),
+];
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -238,4 +400,36 @@ mod test {
         assert_eq!(DigitsIter::new(666).collect::<Vec<_>>(), vec![6, 6, 6]);
         assert_eq!(DigitsIter::new(1000).collect::<Vec<_>>(), vec![1, 0, 0, 0]);
     }
+
+    #[test]
+    fn digits_size() {
+        let writer = TextWriter {
+            scale: 2,
+            position: TextPosition::Center,
+        };
+
+        assert_eq!(writer.size(7), (6, 10));
+        assert_eq!(writer.size(42), (14, 10));
+        assert_eq!(writer.text_size("SS"), (14, 10));
+        assert_eq!(writer.text_size("GO!"), (22, 10));
+        assert_eq!(writer.text_size("~"), (0, 10));
+    }
+
+    #[test]
+    fn char_masks() {
+        assert_eq!(char_mask('a'), char_mask('A'));
+        assert_eq!(char_mask('O'), char_mask('0'));
+        assert_eq!(
+            char_mask('T'),
+            Some(&[
+                [true, true, true],
+                [false, true, false],
+                [false, true, false],
+                [false, true, false],
+                [false, true, false],
+            ])
+        );
+        assert!(char_mask('!').is_some());
+        assert!(char_mask('~').is_none());
+    }
 }
@@ -0,0 +1,158 @@
+use std::{fmt, path::PathBuf};
+
+use anyhow::Error;
+use tracing::{error, info, warn, Level};
+use valence::{
+    prelude::Color,
+    protocol::{Text, TextFormat},
+};
+
+/// Errors which players can do something about. They are shown in the chat with what to do next instead of the raw error,
+/// other errors (`anyhow`) are still shown as they are.
+#[derive(Debug)]
+pub enum OsuError {
+    /// The audio file of a beatmap is not in its song folder
+    MissingAudio { path: Option<PathBuf> },
+    /// Beatmap of a game mode which can't be played in osucraft (e.g. taiko)
+    UnsupportedMode { mode: String },
+    /// The .osu file of a beatmap can't be played
+    BadBeatmap { reason: String },
+    /// The songs directory set with `/songs-dir` does not exist
+    SongsDirMissing { path: PathBuf },
+    /// `action` can only be done by the operators in the configs
+    OperatorOnly { action: &'static str },
+    /// The server is in maintenance (see `/maintenance`)
+    Maintenance,
+}
+
+impl OsuError {
+    pub fn bad_beatmap(reason: impl Into<String>) -> Self {
+        Self::BadBeatmap {
+            reason: reason.into(),
+        }
+    }
+
+    /// Message shown to the player in the chat, with how to fix the error
+    pub fn message(&self) -> Text {
+        match self {
+            OsuError::MissingAudio { path } => {
+                let missing = match path {
+                    Some(path) => format!("'{}' was not found", path.display()),
+                    None => "the beatmap does not name its audio file".to_string(),
+                };
+                "The song can't be played: ".color(Color::RED)
+                    + missing.color(Color::GRAY)
+                    + ". Re-download the beatmap set or pick another song".color(Color::YELLOW)
+            }
+            OsuError::UnsupportedMode { mode } => {
+                "The beatmap can't be played: ".color(Color::RED)
+                    + format!("{} beatmaps are not supported", mode).color(Color::GRAY)
+                    + ". Pick another difficulty".color(Color::YELLOW)
+            }
+            OsuError::BadBeatmap { reason } => {
+                "The beatmap can't be played: ".color(Color::RED)
+                    + reason.clone().color(Color::GRAY)
+                    + ". Pick another difficulty or re-download the beatmap set"
+                        .color(Color::YELLOW)
+            }
+            OsuError::SongsDirMissing { path } => {
+                "The songs directory was not found: ".color(Color::RED)
+                    + format!("'{}'", path.display()).color(Color::GRAY)
+                    + ". Use the absolute path of your osu! Songs folder".color(Color::YELLOW)
+            }
+            OsuError::OperatorOnly { action } => {
+                format!("Only operators can {}", action).color(Color::RED)
+                    + ". Ask an operator or add your name to 'operators' in the configs"
+                        .color(Color::YELLOW)
+            }
+            OsuError::Maintenance => {
+                "The server is in maintenance: ".color(Color::RED)
+                    + "new maps can't be started until it ends".color(Color::GRAY)
+            }
+        }
+    }
+
+    /// Level of the server log: errors of the players are not errors of the server
+    pub fn log_level(&self) -> Level {
+        match self {
+            OsuError::OperatorOnly { .. } | OsuError::Maintenance => Level::INFO,
+            OsuError::MissingAudio { .. }
+            | OsuError::UnsupportedMode { .. }
+            | OsuError::BadBeatmap { .. } => Level::WARN,
+            OsuError::SongsDirMissing { .. } => Level::ERROR,
+        }
+    }
+}
+
+impl fmt::Display for OsuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsuError::MissingAudio { path: Some(path) } => {
+                write!(f, "beatmap audio file not found: '{}'", path.display())
+            }
+            OsuError::MissingAudio { path: None } => write!(f, "beatmap audio file not found"),
+            OsuError::UnsupportedMode { mode } => write!(f, "unsupported game mode: {}", mode),
+            OsuError::BadBeatmap { reason } => write!(f, "bad beatmap: {}", reason),
+            OsuError::SongsDirMissing { path } => {
+                write!(f, "could not find directory: '{}'", path.display())
+            }
+            OsuError::OperatorOnly { action } => write!(f, "only operators can {}", action),
+            OsuError::Maintenance => {
+                write!(f, "the server is in maintenance, new maps can't be started")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OsuError {}
+
+/// Chat message of an error of a command (or a click in an inventory). `OsuError`s are logged at their level.
+pub fn error_message(error: &Error) -> Text {
+    match error.downcast_ref::<OsuError>() {
+        Some(osu_error) => {
+            match osu_error.log_level() {
+                Level::ERROR => error!("{:#}", error),
+                Level::WARN => warn!("{:#}", error),
+                _ => info!("{:#}", error),
+            }
+            osu_error.message()
+        }
+        None => {
+            format!("Error occurred while executing the command: '{}'", error).color(Color::RED)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn osu_errors_are_downcast_through_context() {
+        let error = Err::<(), _>(OsuError::Maintenance)
+            .context("while starting the map")
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OsuError>(),
+            Some(OsuError::Maintenance)
+        ));
+
+        assert_eq!(
+            OsuError::OperatorOnly {
+                action: "force a map"
+            }
+            .log_level(),
+            Level::INFO
+        );
+        assert_eq!(
+            OsuError::bad_beatmap("no circle size").log_level(),
+            Level::WARN
+        );
+        assert_eq!(
+            OsuError::bad_beatmap("no circle size").to_string(),
+            "bad beatmap: no circle size"
+        );
+    }
+}
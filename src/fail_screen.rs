@@ -0,0 +1,143 @@
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Local, Query, Res, ResMut},
+};
+use tracing::error;
+use valence::{
+    client::event::{ClickContainer, StartSneaking},
+    nbt::compound,
+    prelude::{BlockState, Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{ItemKind, ItemStack, TextFormat},
+    Despawned,
+};
+
+use crate::{
+    osu::{Osu, OsuStateChange},
+    playfield::PlayfieldSurface,
+};
+
+const RETRY_SLOT: u16 = 3;
+const BACK_SLOT: u16 = 5;
+
+/// Inventory with the "Retry" and "Back" options shown after failing a beatmap
+#[derive(Component)]
+pub struct FailScreenInventory;
+
+impl FailScreenInventory {
+    pub fn new() -> (Self, Inventory) {
+        let mut inventory = Inventory::with_title(
+            InventoryKind::Generic9x1,
+            "Beatmap failed".color(Color::DARK_RED),
+        );
+
+        inventory.replace_slot(
+            RETRY_SLOT,
+            Some(ItemStack::new(
+                ItemKind::LimeConcrete,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => r#"{"text": "Retry", "color": "green"}"#
+                    }
+                }),
+            )),
+        );
+        inventory.replace_slot(
+            BACK_SLOT,
+            Some(ItemStack::new(
+                ItemKind::RedConcrete,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => r#"{"text": "Back to beatmap selection", "color": "red"}"#
+                    }
+                }),
+            )),
+        );
+
+        (Self, inventory)
+    }
+}
+
+/// Dims the screen and opens the fail screen inventory while the failed state lasts
+pub fn update_fail_screen(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    clients: Query<(Entity, Option<&OpenInventory>), With<Client>>,
+    fail_screens: Query<Entity, With<FailScreenInventory>>,
+    mut sneaking_events: EventReader<StartSneaking>,
+    mut dimmed: Local<bool>,
+) {
+    match (osu.is_failed(), *dimmed) {
+        (true, false) => {
+            osu.playfield()
+                .paint_screen(&mut surface, BlockState::GRAY_CONCRETE);
+
+            let fail_screen = commands.spawn(FailScreenInventory::new()).id();
+            for (client, _) in &clients {
+                commands
+                    .entity(client)
+                    .insert(OpenInventory::new(fail_screen));
+            }
+
+            *dimmed = true;
+        }
+        (true, true) => {
+            for sneaking_event in sneaking_events.iter() {
+                match fail_screens.get_single() {
+                    Ok(fail_screen) => {
+                        commands
+                            .entity(sneaking_event.client)
+                            .insert(OpenInventory::new(fail_screen));
+                    }
+                    Err(_) => error!("Could not find a FailScreenInventory component"),
+                }
+            }
+        }
+        (false, true) => {
+            osu.playfield()
+                .paint_screen(&mut surface, osu.playfield().background());
+
+            for fail_screen in &fail_screens {
+                for (client, open_inventory) in &clients {
+                    if open_inventory.is_some_and(|inventory| inventory.entity() == fail_screen) {
+                        commands.entity(client).remove::<OpenInventory>();
+                    }
+                }
+                commands.entity(fail_screen).insert(Despawned);
+            }
+
+            *dimmed = false;
+        }
+        (false, false) => {}
+    }
+}
+
+pub fn handle_fail_screen_clicks(
+    mut osu: ResMut<Osu>,
+    mut clients: Query<&mut Client>,
+    open_inventories: Query<&OpenInventory, With<Client>>,
+    fail_screens: Query<&FailScreenInventory>,
+    mut click_events: EventReader<ClickContainer>,
+) {
+    for click in click_events.iter() {
+        let Ok(open_inventory) = open_inventories.get(click.client) else {
+            continue;
+        };
+        if fail_screens.get(open_inventory.entity()).is_err() || !osu.is_failed() {
+            continue;
+        }
+
+        let state_change = match click.slot_id.unsigned_abs() {
+            RETRY_SLOT => OsuStateChange::Retry,
+            BACK_SLOT => OsuStateChange::BackToSelection,
+            _ => continue,
+        };
+
+        if let Err(error) = osu.change_state(state_change, &mut clients) {
+            error!("Error while leaving the failed state: '{}'", error);
+        }
+    }
+}
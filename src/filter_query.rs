@@ -0,0 +1,147 @@
+/// Numbers are considered equal if they differ less than this (e.g. `ar=9` matches AR 9.0)
+const EQUALITY_TOLERANCE: f64 = 0.05;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/// `key<comparison>value` condition of a filter query (e.g. `ar>9` or `artist=camellia`)
+#[derive(Clone, PartialEq, Debug)]
+pub struct Condition {
+    pub key: String,
+    pub comparison: Comparison,
+    pub value: String,
+}
+
+/// osu!-style filter query: conditions mixed with fuzzy keywords (e.g. `ar>9 od>=8 insane`)
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FilterQuery {
+    pub conditions: Vec<Condition>,
+    /// Words which are not conditions, separated by spaces
+    pub keywords: String,
+}
+
+impl FilterQuery {
+    /// Only words whose key is one of `keys` (case insensitive) are parsed as conditions, the other ones are keywords
+    pub fn parse(query: &str, keys: &[&str]) -> Self {
+        let mut conditions = Vec::new();
+        let mut keywords = Vec::new();
+
+        for word in query.split_whitespace() {
+            match Condition::parse(word).filter(|condition| keys.contains(&condition.key.as_str()))
+            {
+                Some(condition) => conditions.push(condition),
+                None => keywords.push(word),
+            }
+        }
+
+        Self {
+            conditions,
+            keywords: keywords.join(" "),
+        }
+    }
+}
+
+impl Condition {
+    fn parse(word: &str) -> Option<Self> {
+        let start = word.find(|c| matches!(c, '<' | '>' | '='))?;
+        let (key, rest) = word.split_at(start);
+
+        let (comparison, value) = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ]
+        .into_iter()
+        .find_map(|(operator, comparison)| {
+            rest.strip_prefix(operator).map(|value| (comparison, value))
+        })?;
+
+        if key.is_empty() || value.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            key: key.to_lowercase(),
+            comparison,
+            value: value.to_string(),
+        })
+    }
+
+    /// `false` if the condition value is not a number
+    pub fn matches_number(&self, value: f64) -> bool {
+        let Ok(expected) = self.value.parse::<f64>() else {
+            return false;
+        };
+
+        match self.comparison {
+            Comparison::Less => value < expected,
+            Comparison::LessOrEqual => value <= expected + EQUALITY_TOLERANCE,
+            Comparison::Equal => (value - expected).abs() < EQUALITY_TOLERANCE,
+            Comparison::GreaterOrEqual => value >= expected - EQUALITY_TOLERANCE,
+            Comparison::Greater => value > expected,
+        }
+    }
+
+    /// Case insensitive. `=` checks whether `value` contains the condition value, the other comparisons are alphabetical.
+    pub fn matches_text(&self, value: &str) -> bool {
+        let value = value.to_lowercase();
+        let expected = self.value.to_lowercase();
+
+        match self.comparison {
+            Comparison::Less => value < expected,
+            Comparison::LessOrEqual => value <= expected,
+            Comparison::Equal => value.contains(&expected),
+            Comparison::GreaterOrEqual => value >= expected,
+            Comparison::Greater => value > expected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_query() {
+        let query = FilterQuery::parse("AR>9 insane od<=8.5 bpm>180 hard", &["ar", "od"]);
+
+        assert_eq!(
+            query.conditions,
+            vec![
+                Condition {
+                    key: "ar".to_string(),
+                    comparison: Comparison::Greater,
+                    value: "9".to_string(),
+                },
+                Condition {
+                    key: "od".to_string(),
+                    comparison: Comparison::LessOrEqual,
+                    value: "8.5".to_string(),
+                },
+            ]
+        );
+        assert_eq!(query.keywords, "insane bpm>180 hard");
+        assert_eq!(FilterQuery::parse("ar> =5", &["ar"]).conditions, vec![]);
+    }
+
+    #[test]
+    fn match_conditions() {
+        let condition = |word| Condition::parse(word).unwrap();
+
+        assert!(condition("ar=9").matches_number(9.0));
+        assert!(!condition("ar=9").matches_number(9.5));
+        assert!(condition("ar>=9").matches_number(9.0));
+        assert!(!condition("ar>9").matches_number(9.0));
+        assert!(!condition("ar<x").matches_number(1.0));
+        assert!(condition("mapper=pERo").matches_text("Peppy, Peroperp"));
+        assert!(condition("artist<b").matches_text("Alpha"));
+    }
+}
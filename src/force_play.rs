@@ -0,0 +1,228 @@
+use anyhow::{anyhow, bail, Result};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use std::{fs::read_dir, path::PathBuf};
+use tracing::info;
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::With,
+    system::{Commands, Query, Res, ResMut},
+};
+use valence::{
+    prelude::{Client, Color, OpenInventory},
+    protocol::TextFormat,
+};
+
+use crate::{
+    beatmap_selection::{read_beatmap_dir, BeatmapFile},
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    configs::Configs,
+    error::{error_message, OsuError},
+    lobby::Lobby,
+    marathon::Marathon,
+    mods::Mods,
+    osu::{Osu, OsuStateChange},
+    player_name::PlayerName,
+};
+
+/// Arguments of `/force-play <song> [difficulty] [+mods]`, e.g. `/force-play camellia exit this earth [extra] +hrdt`
+#[derive(Debug, PartialEq)]
+pub struct ForcePlay {
+    /// Fuzzy matched against the song folder names
+    song: String,
+    /// Fuzzy matched against the difficulty names (required if the song has several difficulties)
+    difficulty: Option<String>,
+    mods: Option<Mods>,
+}
+
+impl ForcePlay {
+    pub fn parse(args: &str) -> Result<Self> {
+        let mut rest = args.trim();
+
+        let mut mods = None;
+        if let Some((song, last_word)) = rest.rsplit_once(' ') {
+            if last_word.starts_with('+') {
+                mods = Some(Mods::parse(last_word)?);
+                rest = song.trim_end();
+            }
+        }
+
+        let mut difficulty = None;
+        if let Some(song) = rest.strip_suffix(']') {
+            let (song, name) = song
+                .rsplit_once('[')
+                .ok_or_else(|| anyhow!("missing '[' before the difficulty name"))?;
+            difficulty = Some(name.trim().to_string());
+            rest = song.trim_end();
+        }
+
+        if rest.is_empty() {
+            bail!("Usage: /force-play <song> [difficulty] [+mods]");
+        }
+
+        Ok(Self {
+            song: rest.to_string(),
+            difficulty,
+            mods,
+        })
+    }
+
+    /// Finds the beatmap in `songs_dir`
+    fn find_beatmap(&self, songs_dir: &str) -> Result<BeatmapFile> {
+        let matcher = SkimMatcherV2::default().ignore_case();
+        let song_dir = read_dir(songs_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .filter_map(|path| {
+                let name = path.file_name()?.to_string_lossy().to_string();
+                let score = matcher.fuzzy_match(&name, &self.song)?;
+                Some((score, path))
+            })
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, path)| path)
+            .ok_or_else(|| anyhow!("no song matches '{}'", self.song))?;
+
+        let mut beatmaps = read_beatmap_dir(&song_dir)?;
+        let idx = match &self.difficulty {
+            Some(difficulty) => beatmaps
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, beatmap)| {
+                    let score = matcher.fuzzy_match(&beatmap.difficulty_name(), difficulty)?;
+                    Some((score, idx))
+                })
+                .max_by_key(|(score, _)| *score)
+                .map(|(_, idx)| idx)
+                .ok_or_else(|| anyhow!("no difficulty matches '{}'", difficulty))?,
+            None if beatmaps.len() == 1 => 0,
+            None => {
+                let names: Vec<_> = beatmaps
+                    .iter()
+                    .map(|beatmap| beatmap.difficulty_name())
+                    .collect();
+                bail!(
+                    "choose a difficulty between brackets: [{}]",
+                    names.join("], [")
+                );
+            }
+        };
+
+        Ok(beatmaps.swap_remove(idx))
+    }
+}
+
+pub fn force_play_command() -> CommandSpec {
+    CommandSpec::new("force-play")
+        .arg(ArgSpec::phrase("map"))
+        .required()
+}
+
+/// Handles `/force-play <song> [difficulty] [+mods]` (operators only): everyone is sent to the countdown of the map,
+/// whatever they are doing. Used in events where all the players must start the same map at the same moment.
+pub fn execute_force_play(
+    mut commands: Commands,
+    mut command_events: EventReader<OsuCommand>,
+    configs: Res<Configs>,
+    mut osu: ResMut<Osu>,
+    mut mods: ResMut<Mods>,
+    mut marathon: ResMut<Marathon>,
+    mut lobby: ResMut<Lobby>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    client_inventories: Query<Entity, (With<Client>, With<OpenInventory>)>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "force-play" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+
+        let username = clients
+            .get(command_event.client)
+            .map(|client| client.username().to_string())
+            .unwrap_or_default();
+
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        let result = if is_operator {
+            ForcePlay::parse(args).and_then(|force_play| {
+                let beatmap = force_play.find_beatmap(configs.songs_directory())?;
+                if beatmap.length() > configs.max_map_length() {
+                    bail!("the map is longer than 'max_map_length_secs'");
+                }
+                Ok((beatmap, force_play.mods))
+            })
+        } else {
+            Err(OsuError::OperatorOnly {
+                action: "force a map",
+            }
+            .into())
+        };
+
+        let (beatmap, forced_mods) = match result {
+            Ok(found) => found,
+            Err(error) => {
+                if let Ok(mut client) = clients.get_mut(command_event.client) {
+                    client.send_message(error_message(&error));
+                }
+                continue;
+            }
+        };
+
+        for client in &client_inventories {
+            commands.entity(client).remove::<OpenInventory>();
+        }
+        if marathon.is_running() {
+            marathon.stop();
+        }
+        lobby.reset_round();
+        if let Some(forced_mods) = forced_mods {
+            *mods = forced_mods;
+            osu.set_mods(forced_mods);
+        }
+
+        let beatmap_path: PathBuf = beatmap.path().clone();
+        if let Err(error) =
+            osu.change_state(OsuStateChange::PrePlaying { beatmap_path }, &mut clients)
+        {
+            if let Ok(mut client) = clients.get_mut(command_event.client) {
+                client.send_message(error_message(&error));
+            }
+            continue;
+        }
+
+        let message = username.clone().color(Color::AQUA)
+            + " forced ".color(Color::YELLOW)
+            + beatmap.display_name().color(Color::GREEN)
+            + format!(" {}", *mods).color(Color::GRAY);
+        for mut client in &mut clients {
+            client.send_message(message.clone());
+        }
+
+        info!("'{}' forced '{}'", username, beatmap.display_name());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_force_play() {
+        let force_play = ForcePlay::parse("camellia exit this earth [Extra] +HRHD").unwrap();
+        assert_eq!(force_play.song, "camellia exit this earth");
+        assert_eq!(force_play.difficulty.as_deref(), Some("Extra"));
+        assert_eq!(force_play.mods, Some(Mods::parse("hr hd").unwrap()));
+
+        let force_play = ForcePlay::parse(" freedom dive ").unwrap();
+        assert_eq!(force_play.song, "freedom dive");
+        assert_eq!(force_play.difficulty, None);
+        assert_eq!(force_play.mods, None);
+
+        assert!(ForcePlay::parse("[Insane]").is_err());
+        assert!(ForcePlay::parse("song Insane]").is_err());
+        assert!(ForcePlay::parse("song +HD").is_err());
+    }
+}
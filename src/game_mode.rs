@@ -0,0 +1,126 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, time::Duration};
+
+use bevy_ecs::{
+    prelude::Entity,
+    system::{Commands, Resource},
+};
+use valence::prelude::{Client, DVec3, Instance};
+
+use crate::{beatmap::Beatmap, hit_object::HitObject, hit_score::HitScore, playfield::Playfield};
+
+/// Extension point for game modes other than osu!standard (e.g. taiko or catch), registered with `OsuPlugin::with_game_mode`.
+/// A game mode reuses the song selection, audio, screens and scoring of osucraft, but supplies how the hit objects are spawned,
+/// judged and despawned instead of the hitcircles of `update_osu`.
+///
+/// An active hit object is judged once: either when `judge_input` or `update` return it, or as a miss once it expires.
+pub trait GameModeHandler: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Called when a beatmap starts playing (also on retries), so the state of the previous play can be cleared
+    fn start(&mut self, _beatmap: &Beatmap) {}
+
+    /// Where the players are moved when a beatmap starts playing, if the game mode is played from a specific place
+    fn player_start_pos(&self, _playfield: &Playfield) -> Option<DVec3> {
+        None
+    }
+
+    /// How long before its time a hit object is spawned
+    fn look_ahead(&self, beatmap: &Beatmap) -> Duration {
+        beatmap.ar().to_mc_duration()
+    }
+
+    /// Spawns the entity representing `hit_object`
+    fn spawn_hit_object(
+        &mut self,
+        hit_object: &HitObject,
+        context: &mut GameModeContext,
+    ) -> Result<Entity>;
+
+    /// Hit objects judged by an `input` of `player`, usually the oldest of the `active_hit_objects` (ordered by time).
+    /// The input is judged as if it arrived `input_offset_ms` earlier (see `HudSettings::input_offset_ms`).
+    fn judge_input(
+        &mut self,
+        input: GameModeInput,
+        player: Entity,
+        client: &Client,
+        input_offset_ms: i32,
+        active_hit_objects: &VecDeque<Entity>,
+        context: &mut GameModeContext,
+    ) -> Vec<GameModeHit>;
+
+    /// Called every tick while the beatmap is played, before the inputs are judged (e.g. to move the hit objects).
+    /// Returns the hit objects judged without an input, e.g. long notes held until their end.
+    fn update(&mut self, _context: &mut GameModeContext) -> Vec<GameModeHit> {
+        Vec::new()
+    }
+
+    /// Whether the active hit object `entity` can't be hit anymore, so it's judged as a miss
+    fn is_expired(&self, entity: Entity, play_time: Duration) -> bool;
+
+    /// Removes a hit object once it has been judged. Expired hit objects are only judged, removing them is up to the game mode.
+    fn despawn_hit_object(&mut self, entity: Entity, hit: HitScore, context: &mut GameModeContext);
+}
+
+/// Input of a player which can hit the hit objects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameModeInput {
+    /// Attack, drop item or swap hands
+    Click,
+    /// Hotbar slot (0 to 8) selected by the player, e.g. with the number keys
+    SelectSlot(u8),
+}
+
+/// Judgement of an active hit object
+pub struct GameModeHit {
+    pub entity: Entity,
+    /// Player who hit it
+    pub player: Entity,
+    pub hit: HitScore,
+    /// Timing error in milliseconds (negative if early, positive if late), `None` if the player doesn't time it (e.g. caught fruits)
+    pub hit_error_ms: Option<i32>,
+}
+
+/// Game modes which can be selected in the configs file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GameModeKind {
+    #[default]
+    Standard,
+    /// See `ManiaMode`
+    Mania,
+    /// See `CatchMode`
+    Catch,
+}
+
+/// What a `GameModeHandler` can use while the beatmap is played
+pub struct GameModeContext<'a, 'w, 's> {
+    /// Its `state.play_time` is the play time of the current tick
+    pub beatmap: &'a Beatmap,
+    pub playfield: &'a Playfield,
+    pub tps: usize,
+    /// Players in the server and their positions
+    pub players: &'a [(Entity, DVec3)],
+    /// Instance of the playfield
+    pub instance: (Entity, &'a mut Instance),
+    pub commands: &'a mut Commands<'w, 's>,
+}
+
+/// Game mode registered with `OsuPlugin::with_game_mode`, osu!standard is played if there is none
+#[derive(Resource, Default)]
+pub struct CustomGameMode(Option<Box<dyn GameModeHandler>>);
+
+impl CustomGameMode {
+    pub fn new(handler: Option<Box<dyn GameModeHandler>>) -> Self {
+        Self(handler)
+    }
+
+    pub fn handler(&self) -> Option<&dyn GameModeHandler> {
+        self.0.as_deref()
+    }
+
+    pub fn handler_mut(&mut self) -> Option<&mut (dyn GameModeHandler + 'static)> {
+        self.0.as_deref_mut()
+    }
+}
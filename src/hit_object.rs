@@ -1,26 +1,58 @@
 use anyhow::Result;
-use osu_file_parser::{colours::Colour, OsuFile};
+use osu_file_parser::{colours::Colour, Decimal, OsuFile};
 
 use crate::{
     beatmap::CircleSize,
     color::{Color, DEFAULT_COMBO_COLORS},
     hitcircle::HitcircleRadius,
+    skin::BlockSkin,
 };
 
 const OVERLAP_THRESHOLD_MS: u32 = 1200;
+/// Size of the playfield in osu!pixels
+pub const PLAYFIELD_WIDTH: i32 = 512;
+pub const PLAYFIELD_HEIGHT: i32 = 384;
+/// Slider multiplier of the beatmaps which don't set it
+const DEFAULT_SLIDER_MULTIPLIER: f64 = 1.4;
+/// Hit objects closer than this (in osu!pixels) are stacked (https://osu.ppy.sh/wiki/en/Beatmap/Stack_leniency)
+const STACK_DISTANCE: f64 = 3.0;
+/// Stack leniency of the beatmaps which don't set it
+pub const DEFAULT_STACK_LENIENCY: f64 = 0.7;
 
 #[derive(Default, Clone)]
 /// https://osu.ppy.sh/wiki/en/Client/File_formats/Osu_%28file_format%29#hit-objects
 pub struct HitObject {
+    /// In osu!pixels (it can be outside the playfield until `Preprocessor::apply` moves it inside)
+    x: i32,
     /// In osu!pixels
-    x: u32,
-    // In osu!pixels
-    y: u32,
+    y: i32,
     /// In milliseconds since the start of the beatmap
     time: u32,
+    /// In milliseconds since the start of the beatmap (sliders end after going through all their slides, osu!mania long notes end when
+    /// released)
+    end_time: u32,
     combo_number: u32,
     color: Color,
+    /// Combos started before this hit object (counting the skipped colors), which picks its color in the skin colors
+    combo_idx: usize,
+    /// Whether `color` is one of the beatmap colors, instead of the default ones
+    beatmap_color: bool,
     params: HitObjectParams,
+    /// Number of hit objects stacked on top of it (see `apply_stacking`)
+    stack_height: u32,
+    /// Where and when the slider ends (`None` for the other hit objects)
+    slider_end: Option<SliderEnd>,
+}
+
+/// End of a slider, approximated by the last point of its curve (or its head if it goes back and forth an even number of times)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SliderEnd {
+    /// In osu!pixels
+    pub x: i32,
+    /// In osu!pixels
+    pub y: i32,
+    /// In milliseconds since the start of the beatmap
+    pub time: u32,
 }
 
 #[derive(Clone)]
@@ -43,7 +75,7 @@ impl HitObject {
         let hitobjects = osu_file.hitobjects.clone().unwrap_or_default().0;
 
         let mut result = Vec::with_capacity(hitobjects.len());
-        let colors = osu_file
+        let beatmap_colors = osu_file
             .colours
             .clone()
             .map(|colors| {
@@ -67,29 +99,76 @@ impl HitObject {
                     .collect::<Vec<_>>();
 
                 colors.sort_by_key(|(combo, _)| **combo);
-                colors.into_iter().map(|(_, color)| color).collect()
+                colors
+                    .into_iter()
+                    .map(|(_, color)| color)
+                    .collect::<Vec<_>>()
             })
-            .unwrap_or_else(|| DEFAULT_COMBO_COLORS.to_vec());
+            .filter(|colors| !colors.is_empty());
+        let beatmap_color = beatmap_colors.is_some();
+        let colors = beatmap_colors.unwrap_or_else(|| DEFAULT_COMBO_COLORS.to_vec());
+
+        let slider_velocities = SliderVelocities::from(osu_file)?;
 
         let mut cur_color = colors.len() - 1;
+        let mut combo_idx: Option<usize> = None;
 
         for hitobject in hitobjects {
             // Update combo
             if hitobject.new_combo {
                 combo_number = 1;
-                cur_color =
-                    (cur_color + 1 + hitobject.combo_skip_count.get() as usize) % colors.len();
+                let skip = hitobject.combo_skip_count.get() as usize;
+                cur_color = (cur_color + 1 + skip) % colors.len();
+                combo_idx = Some(combo_idx.map_or(0, |idx| idx + 1) + skip);
             } else {
                 combo_number += 1;
             }
 
+            let time = hitobject.time.to_string().parse()?;
+            let x = hitobject.position.x.to_string().parse()?;
+            let y = hitobject.position.y.to_string().parse()?;
+            let slider_end = match &hitobject.obj_params {
+                osu_file_parser::hitobjects::HitObjectParams::Slider(slider) => {
+                    let slides: u32 = slider.slides.to_string().parse()?;
+                    let length: f64 = slider.length.to_string().parse()?;
+                    let (end_x, end_y) = match slider.curve_points.last() {
+                        Some(point) if slides % 2 == 1 => (
+                            point.0.x.to_string().parse::<f64>()? as i32,
+                            point.0.y.to_string().parse::<f64>()? as i32,
+                        ),
+                        _ => (x, y),
+                    };
+
+                    Some(SliderEnd {
+                        x: end_x,
+                        y: end_y,
+                        time: time
+                            + (slider_velocities.slide_duration(time, length) * slides as f64)
+                                as u32,
+                    })
+                }
+                _ => None,
+            };
+            let end_time = match &hitobject.obj_params {
+                osu_file_parser::hitobjects::HitObjectParams::Spinner { end_time }
+                | osu_file_parser::hitobjects::HitObjectParams::OsuManiaHold { end_time } => {
+                    end_time.to_string().parse()?
+                }
+                _ => slider_end.map_or(time, |slider_end| slider_end.time),
+            };
+
             result.push(Self {
-                x: hitobject.position.x.to_string().parse()?,
-                y: hitobject.position.y.to_string().parse()?,
+                x,
+                y,
                 color: colors[cur_color],
-                time: hitobject.time.to_string().parse()?,
+                combo_idx: combo_idx.unwrap_or_default(),
+                beatmap_color,
+                time,
+                end_time,
                 combo_number,
                 params: hitobject.obj_params.clone().into(),
+                stack_height: 0,
+                slider_end,
             });
         }
 
@@ -99,6 +178,33 @@ impl HitObject {
     // Calculate z value such that there is no overlap with other hitcircles
     //
     // `remaining`: is the list of the remaining hitobjects in the song ordered in chronological order.
+    /// Hitcircle at the center of the playfield, for synthetic beatmaps (see `test_support`)
+    #[cfg(test)]
+    pub fn hitcircle_at(time: u32) -> Self {
+        Self {
+            x: 256,
+            y: 192,
+            time,
+            end_time: time,
+            combo_number: 1,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_pos(mut self, x: i32, y: i32) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_slider_end(mut self, slider_end: SliderEnd) -> Self {
+        self.params = HitObjectParams::Slider;
+        self.slider_end = Some(slider_end);
+        self
+    }
+
     pub fn z(&self, remaining: &[HitObject], _cs: CircleSize) -> i32 {
         match remaining
             .iter()
@@ -113,6 +219,19 @@ impl HitObject {
         }
     }
 
+    /// Position in osu!pixels where it's drawn: stacked hit objects are moved up and to the left, like in osu!, so they don't hide
+    /// each other. Each level of the stack moves at least one block with the playfield `scale`.
+    pub fn stacked_pos(&self, cs: CircleSize, scale: f64) -> (f64, f64) {
+        let stack_offset = (HitcircleRadius::from(cs, 1.0).circle / 10.0).max(1.0 / scale);
+        let offset = self.stack_height as f64 * stack_offset;
+
+        (self.x as f64 - offset, self.y as f64 - offset)
+    }
+
+    fn distance(&self, other: &HitObject) -> f64 {
+        ((self.x.abs_diff(other.x).pow(2) + self.y.abs_diff(other.y).pow(2)) as f64).sqrt()
+    }
+
     pub fn intersect(&self, other: &HitObject, cs: CircleSize) -> bool {
         let radius = HitcircleRadius::from(cs, 1.0).circle;
         let dist = (self.x.abs_diff(other.x).pow(2) + self.y.abs_diff(other.y).pow(2)) as f64;
@@ -122,17 +241,21 @@ impl HitObject {
     }
 
     pub fn x(&self) -> u32 {
-        self.x
+        self.x.max(0) as u32
     }
 
     pub fn y(&self) -> u32 {
-        self.y
+        self.y.max(0) as u32
     }
 
     pub fn time(&self) -> u32 {
         self.time
     }
 
+    pub fn end_time(&self) -> u32 {
+        self.end_time
+    }
+
     pub fn combo_number(&self) -> u32 {
         self.combo_number
     }
@@ -141,9 +264,157 @@ impl HitObject {
         self.color
     }
 
+    /// Combo color with the colors of `skin`. Like in osu!, the beatmap colors win over the skin ones unless
+    /// `ignore_beatmap_colors` (see `Skin::ignore_beatmap_colors`).
+    pub fn skin_color(&self, skin: &BlockSkin, ignore_beatmap_colors: bool) -> Color {
+        if (self.beatmap_color && !ignore_beatmap_colors) || skin.combo_colors.is_empty() {
+            return self.color;
+        }
+
+        skin.combo_colors[self.combo_idx % skin.combo_colors.len()]
+    }
+
     pub fn params(&self) -> &HitObjectParams {
         &self.params
     }
+
+    pub fn slider_end(&self) -> Option<SliderEnd> {
+        self.slider_end
+    }
+
+    /// Moves it (and its slider end) to the closest position inside the playfield
+    pub fn clamp_to_playfield(&mut self) {
+        self.x = self.x.clamp(0, PLAYFIELD_WIDTH);
+        self.y = self.y.clamp(0, PLAYFIELD_HEIGHT);
+        if let Some(slider_end) = &mut self.slider_end {
+            slider_end.x = slider_end.x.clamp(0, PLAYFIELD_WIDTH);
+            slider_end.y = slider_end.y.clamp(0, PLAYFIELD_HEIGHT);
+        }
+    }
+
+    /// Mirrors it (and its slider end) upside down in the playfield, like HR
+    pub fn flip_vertically(&mut self) {
+        self.y = PLAYFIELD_HEIGHT - self.y;
+        if let Some(slider_end) = &mut self.slider_end {
+            slider_end.y = PLAYFIELD_HEIGHT - slider_end.y;
+        }
+    }
+
+    /// Hitcircles at the head and at the tail of a slider (`None` for the other hit objects). The tail keeps the combo of the
+    /// head, with the next combo number.
+    pub fn slider_ends(&self) -> Option<(HitObject, HitObject)> {
+        let slider_end = self.slider_end?;
+        let head = Self {
+            end_time: self.time,
+            params: HitObjectParams::Hitcircle,
+            slider_end: None,
+            ..self.clone()
+        };
+        let tail = Self {
+            x: slider_end.x,
+            y: slider_end.y,
+            time: slider_end.time,
+            end_time: slider_end.time,
+            combo_number: self.combo_number + 1,
+            ..head.clone()
+        };
+
+        Some((head, tail))
+    }
+
+    /// Shifts its combo number, e.g. when a hit object is inserted before it in its combo
+    pub fn shift_combo_number(&mut self, shift: u32) {
+        self.combo_number += shift;
+    }
+}
+
+/// Beat length and slider velocity of each timing point, to know when the sliders end
+/// https://osu.ppy.sh/wiki/en/Client/File_formats/Osu_%28file_format%29#sliders
+struct SliderVelocities {
+    slider_multiplier: f64,
+    /// Time, beat length and slider velocity multiplier, in chronological order
+    timing_points: Vec<(f64, f64, f64)>,
+}
+
+impl SliderVelocities {
+    fn from(osu_file: &OsuFile) -> Result<Self> {
+        let slider_multiplier = osu_file
+            .difficulty
+            .as_ref()
+            .and_then(|difficulty| difficulty.slider_multiplier.clone())
+            .map(|slider_multiplier| {
+                let slider_multiplier: Decimal = slider_multiplier.into();
+                slider_multiplier.to_string().parse::<f64>()
+            })
+            .transpose()?
+            .filter(|&slider_multiplier| slider_multiplier > 0.0)
+            .unwrap_or(DEFAULT_SLIDER_MULTIPLIER);
+
+        let mut timing_points = Vec::new();
+        let mut beat_length = None;
+        for timing_point in osu_file.timing_points.clone().unwrap_or_default().0 {
+            let time: f64 = timing_point.time().to_string().parse()?;
+            if timing_point.uninherited() {
+                let Some(bpm) = timing_point.calc_bpm() else {
+                    continue;
+                };
+                let bpm: f64 = bpm.to_string().parse()?;
+                if bpm > 0.0 {
+                    beat_length = Some(60_000.0 / bpm);
+                    timing_points.push((time, 60_000.0 / bpm, 1.0));
+                }
+            } else if let (Some(beat_length), Some(velocity)) =
+                (beat_length, timing_point.calc_slider_velocity_multiplier())
+            {
+                let velocity: f64 = velocity.to_string().parse()?;
+                timing_points.push((time, beat_length, velocity.max(0.1)));
+            }
+        }
+
+        Ok(Self {
+            slider_multiplier,
+            timing_points,
+        })
+    }
+
+    /// Milliseconds to go once through a slider starting at `time` which is `length` osu!pixels long
+    fn slide_duration(&self, time: u32, length: f64) -> f64 {
+        let (_, beat_length, velocity) = self
+            .timing_points
+            .iter()
+            .rev()
+            .find(|(point_time, _, _)| *point_time <= time as f64)
+            .or_else(|| self.timing_points.first())
+            .copied()
+            .unwrap_or((0.0, 500.0, 1.0));
+
+        length / (self.slider_multiplier * 100.0 * velocity) * beat_length
+    }
+}
+
+/// Stacks the hit objects in the same position which are less than `stack_threshold_ms` apart (the preempt time scaled by the
+/// stack leniency of the beatmap). It's the stacking algorithm of osu! for hitcircles, the later hit objects stay on top.
+pub fn apply_stacking(hit_objects: &mut [HitObject], stack_threshold_ms: u32) {
+    for i in (0..hit_objects.len()).rev() {
+        if hit_objects[i].stack_height != 0 {
+            continue;
+        }
+
+        let mut top = i;
+        for n in (0..i).rev() {
+            if hit_objects[top]
+                .time
+                .saturating_sub(hit_objects[n].end_time)
+                > stack_threshold_ms
+            {
+                break;
+            }
+            if hit_objects[n].distance(&hit_objects[top]) < STACK_DISTANCE {
+                hit_objects[n].stack_height = hit_objects[top].stack_height + 1;
+                top = n;
+            }
+        }
+    }
 }
 
 impl From<osu_file_parser::hitobjects::HitObjectParams> for HitObjectParams {
@@ -165,14 +436,46 @@ impl From<osu_file_parser::hitobjects::HitObjectParams> for HitObjectParams {
 #[cfg(test)]
 mod test {
 
-    use crate::{beatmap::CircleSize, hitcircle::HitcircleRadius};
+    use crate::{
+        beatmap::CircleSize, color::Color, hitcircle::HitcircleRadius, osu_file::parse_osu_file,
+        skin::BlockSkin,
+    };
+
+    use super::{apply_stacking, HitObject};
+
+    #[test]
+    fn slider_end_time() {
+        // 140 osu!pixels at 1.4 * 100 osu!pixels per beat take one beat (500ms) to slide, half of it with the doubled SV
+        let osu_file = parse_osu_file(
+            "osu file format v14\n\
+            \n\
+            [Difficulty]\n\
+            SliderMultiplier:1.4\n\
+            \n\
+            [TimingPoints]\n\
+            0,500,4,2,0,100,1,0\n\
+            2000,-50,4,2,0,100,0,0\n\
+            \n\
+            [HitObjects]\n\
+            100,100,1000,2,0,L|240:100,2,140,0|0|0,0:0|0:0|0:0,0:0:0:0:\n\
+            100,100,3000,2,0,L|240:100,3,140,0|0|0|0,0:0|0:0|0:0|0:0,0:0:0:0:\n\
+            256,192,5000,1,0,0:0:0:0:\n",
+        )
+        .unwrap();
+        let hit_objects = HitObject::from(&osu_file).unwrap();
+
+        assert_eq!(hit_objects[0].end_time(), 2000);
+        assert_eq!(hit_objects[1].end_time(), 3750);
+        assert_eq!(hit_objects[2].end_time(), 5000);
 
-    use super::HitObject;
+        let (head, tail) = hit_objects[0].slider_ends().unwrap();
+        assert_eq!((head.end_time(), tail.time()), (1000, 2000));
+    }
 
     #[test]
     fn hitobject_z() {
         let cs = CircleSize(5.0);
-        let radius = HitcircleRadius::from(cs, 1.0).circle as u32;
+        let radius = HitcircleRadius::from(cs, 1.0).circle as i32;
 
         let hitobjects = vec![
             HitObject {
@@ -202,4 +505,56 @@ mod test {
         assert_eq!(hitobjects[2].z(&hitobjects[3..], cs), 0);
         assert_eq!(hitobjects[3].z(&hitobjects[4..], cs), 0);
     }
+
+    #[test]
+    fn skin_combo_colors() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        let green = Color { r: 0, g: 255, b: 0 };
+        let blue = Color { r: 0, g: 0, b: 255 };
+        let mut skin = BlockSkin::default();
+        skin.combo_colors = vec![green, blue];
+        let hitobject = |combo_idx: usize, beatmap_color: bool| HitObject {
+            color: red,
+            combo_idx,
+            beatmap_color,
+            ..Default::default()
+        };
+
+        assert_eq!(hitobject(3, true).skin_color(&skin, false), red);
+        assert_eq!(hitobject(3, true).skin_color(&skin, true), blue);
+        assert_eq!(hitobject(4, false).skin_color(&skin, false), green);
+    }
+
+    #[test]
+    fn stacked_hitobjects() {
+        let hitobject = |x: i32, time: u32| HitObject {
+            x,
+            y: 100,
+            time,
+            end_time: time,
+            ..Default::default()
+        };
+        let mut hitobjects = vec![
+            hitobject(100, 0),
+            hitobject(100, 200),
+            hitobject(101, 400),
+            hitobject(300, 600),
+            // Too late to be stacked on the previous ones
+            hitobject(100, 2000),
+        ];
+        apply_stacking(&mut hitobjects, 840);
+
+        let stack_heights: Vec<_> = hitobjects.iter().map(|h| h.stack_height).collect();
+        assert_eq!(stack_heights, vec![2, 1, 0, 0, 0]);
+
+        let cs = CircleSize(4.0);
+        let stack_offset = HitcircleRadius::from(cs, 1.0).circle / 10.0;
+        let (x, y) = hitobjects[0].stacked_pos(cs, 1.0);
+        assert!((x - (100.0 - 2.0 * stack_offset)).abs() < 1e-9);
+        assert!((y - (100.0 - 2.0 * stack_offset)).abs() < 1e-9);
+        assert_eq!(hitobjects[2].stacked_pos(cs, 1.0), (101.0, 100.0));
+
+        // At least one block per level in small playfields
+        assert_eq!(hitobjects[1].stacked_pos(cs, 0.25), (96.0, 96.0));
+    }
 }
@@ -5,12 +5,15 @@ use bevy_ecs::{
 };
 
 use valence::{
-    prelude::{Block, Instance},
+    prelude::Instance,
     protocol::{BlockPos, BlockState},
     Despawned,
 };
 
-use crate::digit::{DigitWriter, TextPosition};
+use crate::{
+    block_batch::BlockBatch,
+    digit::{TextPosition, TextWriter},
+};
 
 #[derive(Debug, Copy, Clone)]
 pub enum HitScore {
@@ -20,6 +23,37 @@ pub enum HitScore {
     Miss,
 }
 
+/// Blocks of the hit score numbers (see `BlockSkin::hit_scores`)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HitScoreBlocks {
+    pub hit300: BlockState,
+    pub hit100: BlockState,
+    pub hit50: BlockState,
+    pub miss: BlockState,
+}
+
+impl Default for HitScoreBlocks {
+    fn default() -> Self {
+        Self {
+            hit300: BlockState::LIGHT_BLUE_STAINED_GLASS,
+            hit100: BlockState::LIME_STAINED_GLASS,
+            hit50: BlockState::ORANGE_STAINED_GLASS,
+            miss: BlockState::RED_STAINED_GLASS,
+        }
+    }
+}
+
+impl HitScoreBlocks {
+    pub fn block(&self, hit_score: HitScore) -> BlockState {
+        match hit_score {
+            HitScore::Hit300 => self.hit300,
+            HitScore::Hit100 => self.hit100,
+            HitScore::Hit50 => self.hit50,
+            HitScore::Miss => self.miss,
+        }
+    }
+}
+
 #[derive(Component, Clone)]
 pub struct HitScoreNumber {
     ticks: usize,
@@ -42,6 +76,7 @@ impl HitScore {
 impl HitScoreNumber {
     pub fn new(
         hit_score: HitScore,
+        blocks: HitScoreBlocks,
         origin: BlockPos,
         ticks: usize,
         mut instance: (Entity, Mut<Instance>),
@@ -53,33 +88,26 @@ impl HitScoreNumber {
             instance: instance.0,
         };
 
-        let block_state = match hit_score_number.score {
-            HitScore::Hit300 => BlockState::LIGHT_BLUE_STAINED_GLASS,
-            HitScore::Hit100 => BlockState::LIME_STAINED_GLASS,
-            HitScore::Hit50 => BlockState::ORANGE_STAINED_GLASS,
-            HitScore::Miss => BlockState::RED_STAINED_GLASS,
-        };
-        let block = Block::new(block_state);
-
-        hit_score_number.draw(block, &mut instance.1);
+        hit_score_number.draw(blocks.block(hit_score), &mut instance.1);
 
         hit_score_number
     }
 
     pub fn despawn(&self, instances: &mut Query<&mut Instance>) {
         if let Ok(mut instance) = instances.get_mut(self.instance) {
-            self.draw(Block::new(BlockState::AIR), &mut instance);
+            self.draw(BlockState::AIR, &mut instance);
         }
     }
 
-    fn draw(&self, block: Block, instance: &mut Mut<Instance>) {
+    fn draw(&self, block: BlockState, instance: &mut Mut<Instance>) {
         let origin = self.origin;
         let number = match self.score {
             HitScore::Hit300 => 300,
             HitScore::Hit100 => 100,
             HitScore::Hit50 => 50,
             HitScore::Miss => {
-                [
+                let mut batch = BlockBatch::new();
+                let cross = [
                     (2, 2),
                     (1, 1),
                     (0, 0),
@@ -90,21 +118,20 @@ impl HitScoreNumber {
                     (1, -1),
                     (2, -2),
                 ]
-                .iter()
+                .into_iter()
                 .map(|offset| BlockPos {
                     x: origin.x + offset.0,
                     y: origin.y + offset.1,
                     z: origin.z,
-                })
-                .for_each(|pos| {
-                    instance.set_block(pos, block.clone());
                 });
+                batch.fill(cross, block);
+                batch.apply(instance);
 
                 return;
             }
         };
 
-        DigitWriter {
+        TextWriter {
             scale: 1,
             position: TextPosition::Center,
         }
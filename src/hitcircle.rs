@@ -1,22 +1,42 @@
 use anyhow::Result;
 use tracing::warn;
-use valence::{prelude::*, Despawned};
+use valence::{
+    prelude::*,
+    protocol::{packets::s2c::play::BlockUpdate, VarInt},
+    Despawned,
+};
 
-use std::cmp::max;
+use std::{
+    cmp::max,
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
 
 use crate::{
-    beatmap::{BeatmapData, CircleSize},
+    beatmap::{Beatmap, CircleSize},
+    block_batch::BlockBatch,
     color::Color,
-    digit::{DigitWriter, TextPosition},
-    hit_score::{HitScore, HitScoreNumber},
+    configs::Skin,
+    digit::{TextPosition, TextWriter},
+    hit_score::{HitScore, HitScoreBlocks, HitScoreNumber},
+    hud::HudSettings,
+    lag::LagCompensation,
     minecraft::to_ticks,
     osu::Hitwindow,
-    ring::Ring,
+    ring::{ApproachCircleStyle, ParticleRing, Ring},
+    skin::BlockSkin,
 };
 
+/// How much darker the circles are drawn while they fade in, from 0 (same color) to 1 (black)
+const FADE_IN_SHADE: f64 = 0.5;
+/// With HD, the fillings are cleared after this fraction of the preempt time, halfway through the fade out of osu!
+/// (https://osu.ppy.sh/wiki/en/Gameplay/Game_modifier/Hidden)
+const HIDDEN_FILLING_FRACTION: f64 = 0.55;
+
 #[derive(Component)]
 pub struct Hitcircle {
-    approach_circle: Entity,
+    /// `None` with HD
+    approach_circle: Option<Entity>,
     circle_ring: Entity,
     instance: Entity,
     center: DVec3,
@@ -24,7 +44,19 @@ pub struct Hitcircle {
     ticks: usize,
     hitwindow: HitwindowTicks,
     filling_block: BlockState,
+    /// Darker filling drawn while the circle fades in (see `HitcircleBlocks::fade_in_filling`)
+    fade_in_filling_block: BlockState,
+    /// Ticks left before the circle is drawn with `filling_block`
+    fade_in_ticks: usize,
+    /// Ticks left before the filling is cleared with HD (`Some(0)` once it's cleared). The circle can still be hit.
+    hidden_ticks: Option<usize>,
+    /// Contrasting the filling, see `update_hitcircle_outlines`
+    outline_block: BlockState,
     combo_number: u32,
+    combo_number_block: BlockState,
+    hit_score_blocks: HitScoreBlocks,
+    /// Blocks between the combo number and the circle, towards the players (see `Skin::combo_number_in_front`)
+    combo_number_z_offset: i32,
 }
 
 pub struct HitwindowTicks {
@@ -40,8 +72,15 @@ pub struct HitcircleRadius {
 
 pub struct HitcircleBlocks {
     pub approach_circle: ItemKind,
+    /// Color of the particles of `ApproachCircleStyle::Particles`
+    pub approach_circle_color: Color,
     pub circle_ring: ItemKind,
     pub filling: Block,
+    /// Darker shade of the filling, approximating the transparency of the circles fading in
+    pub fade_in_filling: Block,
+    pub outline: BlockState,
+    pub combo_number: BlockState,
+    pub hit_scores: HitScoreBlocks,
 }
 
 pub fn update_hitcircle(
@@ -49,9 +88,15 @@ pub fn update_hitcircle(
     mut hitcircles: Query<(Entity, &mut Hitcircle), Without<Despawned>>,
     rings: Query<&Ring>,
     mut instances: Query<(Entity, &mut Instance)>,
+    lag: Res<LagCompensation>,
 ) {
     for (entity, mut hitcircle) in &mut hitcircles {
         if hitcircle.ticks == 0 {
+            // Don't judge misses while recovering from a server lag spike
+            if lag.grace_ticks() > 0 {
+                continue;
+            }
+
             commands.entity(entity).insert(Despawned);
             if let Err(error) =
                 hitcircle.despawn(&mut commands, &rings, &mut instances, HitScore::Miss)
@@ -61,9 +106,32 @@ pub fn update_hitcircle(
         } else {
             hitcircle.ticks -= 1;
         }
+
+        if hitcircle.fade_in_ticks > 0 {
+            hitcircle.fade_in_ticks -= 1;
+            if hitcircle.fade_in_ticks == 0 && hitcircle.hidden_ticks != Some(0) {
+                if let Ok(mut instance) = instances.get_mut(hitcircle.instance) {
+                    hitcircle.draw_circle(&mut instance.1);
+                }
+            }
+        }
+
+        if let Some(hidden_ticks) = hitcircle.hidden_ticks.filter(|&ticks| ticks > 0) {
+            hitcircle.hidden_ticks = Some(hidden_ticks - 1);
+            if hidden_ticks == 1 {
+                if let Ok(mut instance) = instances.get_mut(hitcircle.instance) {
+                    hitcircle.clear_filling(&mut instance.1);
+                }
+            }
+        }
     }
 }
 
+/// Ticks after which the filling of a hitcircle approaching for `preempt_ticks` is cleared with HD
+pub fn hidden_ticks(preempt_ticks: usize) -> usize {
+    ((preempt_ticks as f64 * HIDDEN_FILLING_FRACTION).round() as usize).max(1)
+}
+
 impl Hitcircle {
     pub fn new(
         center: impl Into<DVec3>,
@@ -71,21 +139,43 @@ impl Hitcircle {
         blocks: HitcircleBlocks,
         hitwindow: HitwindowTicks,
         preempt_ticks: usize,
+        fade_in_ticks: usize,
+        hidden_ticks: Option<usize>,
         combo_number: u32,
+        skin: Skin,
+        tps: usize,
         mut instance: (Entity, Mut<Instance>),
         commands: &mut Commands,
     ) -> Result<Self> {
         let center = center.into().floor();
-        let approach_circle = Ring::with_speed(
-            center,
-            radius.approach_circle,
-            radius.circle,
-            blocks.approach_circle,
-            preempt_ticks,
-            instance.0,
-            commands,
-        )?;
-        let approach_circle = commands.spawn(approach_circle).id();
+        let approach_circle = match skin.approach_circle {
+            // No approach circles with HD
+            _ if hidden_ticks.is_some() => None,
+            ApproachCircleStyle::Blocks => {
+                let approach_circle = Ring::with_speed(
+                    center,
+                    radius.approach_circle,
+                    radius.circle,
+                    blocks.approach_circle,
+                    preempt_ticks,
+                    tps,
+                    instance.0,
+                    commands,
+                )?;
+                Some(commands.spawn(approach_circle).id())
+            }
+            ApproachCircleStyle::Particles => Some(
+                commands
+                    .spawn(ParticleRing::with_speed(
+                        center,
+                        radius.approach_circle,
+                        radius.circle,
+                        blocks.approach_circle_color,
+                        preempt_ticks,
+                    ))
+                    .id(),
+            ),
+        };
 
         let mut circle_ring_center = center;
         circle_ring_center.z = center.z.floor() - 0.25;
@@ -110,7 +200,14 @@ impl Hitcircle {
             ticks: circle_ticks,
             hitwindow,
             filling_block: blocks.filling.state(),
+            fade_in_filling_block: blocks.fade_in_filling.state(),
+            fade_in_ticks,
+            hidden_ticks,
+            outline_block: blocks.outline,
             combo_number,
+            combo_number_block: blocks.combo_number,
+            hit_score_blocks: blocks.hit_scores,
+            combo_number_z_offset: if skin.combo_number_in_front { 1 } else { 0 },
         };
 
         hitcircle.draw_circle(&mut instance.1);
@@ -120,18 +217,24 @@ impl Hitcircle {
 
     pub fn from_beatmap(
         center: impl Into<DVec3>,
-        beatmap: &BeatmapData,
-        color: Color,
+        beatmap: &Beatmap,
+        blocks: HitcircleBlocks,
         scale: f64,
         combo_number: u32,
+        skin: Skin,
         tps: usize,
         instance: (Entity, Mut<Instance>),
         commands: &mut Commands,
     ) -> Result<Self> {
-        let radius = HitcircleRadius::from(beatmap.cs, scale);
-        let hitwindow = HitwindowTicks::from(&beatmap.od.into(), tps);
-        let preempt_ticks = beatmap.ar.to_mc_ticks(tps);
-        let blocks: HitcircleBlocks = color.into();
+        let radius = HitcircleRadius::from(beatmap.cs(), scale);
+        let (hitwindow, preempt_ticks) = Self::timing(beatmap, tps);
+        let fade_in_ticks =
+            preempt_ticks.saturating_sub(to_ticks(tps, beatmap.ar().to_opaque_duration()));
+        let hidden_ticks = beatmap
+            .data
+            .mods
+            .hidden
+            .then(|| hidden_ticks(preempt_ticks));
 
         Self::new(
             center,
@@ -139,20 +242,89 @@ impl Hitcircle {
             blocks,
             hitwindow,
             preempt_ticks,
+            fade_in_ticks,
+            hidden_ticks,
             combo_number,
+            skin,
+            tps,
             instance,
             commands,
         )
     }
 
-    pub fn hit_score(&self, client: &Client, rings: &Query<&Ring>) -> Option<HitScore> {
-        rings.get(self.circle_ring).ok().and_then(|ring| {
-            ring.raycast_client(client)
-                .is_some()
-                .then_some(self.hitwindow.hit_score(self.ticks as u32))
+    /// Hitwindows and preempt ticks of the hitcircles of `beatmap`
+    fn timing(beatmap: &Beatmap, tps: usize) -> (HitwindowTicks, usize) {
+        (
+            HitwindowTicks::from(&beatmap.od().into(), tps),
+            beatmap.ar().to_mc_ticks(tps),
+        )
+    }
+
+    /// Hitcircle of `beatmap` which isn't drawn in any instance, to play beatmaps without a server (see `test_support::play`)
+    #[cfg(test)]
+    pub fn undrawn(beatmap: &Beatmap, tps: usize) -> Self {
+        let (hitwindow, preempt_ticks) = Self::timing(beatmap, tps);
+        let placeholder = Entity::from_raw(u32::MAX);
+
+        Self {
+            approach_circle: None,
+            circle_ring: placeholder,
+            instance: placeholder,
+            center: DVec3::ZERO,
+            radius: 0.0,
+            ticks: preempt_ticks + hitwindow.window_50 as usize,
+            hitwindow,
+            filling_block: BlockState::AIR,
+            fade_in_filling_block: BlockState::AIR,
+            fade_in_ticks: 0,
+            hidden_ticks: None,
+            outline_block: BlockState::AIR,
+            combo_number: 1,
+            combo_number_block: BlockState::AIR,
+            hit_score_blocks: HitScoreBlocks::default(),
+            combo_number_z_offset: 0,
+        }
+    }
+
+    /// Score of a hit by `client`, if it's aiming at the circle (with its radius multiplied by `aim_assist`, see `HudSettings::aim_assist`).
+    /// Hitwindows are extended by `grace_ticks` (see `LagCompensation`) and the hit is judged as if it arrived `input_offset_ms`
+    /// earlier (see `HudSettings::input_offset_ms`).
+    pub fn hit_score(
+        &self,
+        client: &Client,
+        rings: &Query<&Ring>,
+        grace_ticks: usize,
+        input_offset_ms: i32,
+        aim_assist: f64,
+        tps: usize,
+    ) -> Option<HitScore> {
+        self.is_aimed_by(client, rings, aim_assist)
+            .then(|| self.judge(grace_ticks, input_offset_ms, tps))
+    }
+
+    /// Whether `client` is aiming at the circle (see `Hitcircle::hit_score`)
+    pub fn is_aimed_by(&self, client: &Client, rings: &Query<&Ring>, aim_assist: f64) -> bool {
+        rings.get(self.circle_ring).map_or(false, |ring| {
+            ring.raycast_client(client, aim_assist).is_some()
         })
     }
 
+    /// Score of a hit now, wherever the player aims (e.g. with AP)
+    pub fn judge(&self, grace_ticks: usize, input_offset_ms: i32, tps: usize) -> HitScore {
+        self.hitwindow
+            .judge(self.ticks, grace_ticks, input_offset_ms, tps)
+    }
+
+    /// The time it should be hit has come (RX hits it then, see `Mods::relax`)
+    pub fn is_due(&self) -> bool {
+        self.ticks <= self.hitwindow.window_50()
+    }
+
+    /// Hit timing error in milliseconds (negative if early, positive if late), after removing the player's `input_offset_ms`
+    pub fn hit_error(&self, tps: usize, input_offset_ms: i32) -> i32 {
+        self.hitwindow.hit_error(self.ticks, tps, input_offset_ms)
+    }
+
     pub fn despawn(
         &self,
         commands: &mut Commands,
@@ -161,17 +333,29 @@ impl Hitcircle {
         hit: HitScore,
     ) -> Result<()> {
         let mut instance = instances.get_mut(self.instance)?;
-        self.fill(&mut instance.1, &Block::new(BlockState::AIR));
+        let mut batch = BlockBatch::new();
+        batch.fill(self.circle_block_positions(), BlockState::AIR);
+        if self.combo_number_z_offset != 0 {
+            batch.fill(self.combo_number_positions(), BlockState::AIR);
+        }
+        batch.apply(&mut instance.1);
 
         if let Ok(ring) = rings.get(self.circle_ring) {
             ring.despawn(commands);
         }
-        if let Ok(approach_circle) = rings.get(self.approach_circle) {
-            approach_circle.despawn(commands);
+        if let Some(approach_circle) = self.approach_circle {
+            if let Ok(ring) = rings.get(approach_circle) {
+                ring.despawn(commands);
+            }
+            // Particle rings have no parts
+            if let Some(mut approach_circle) = commands.get_entity(approach_circle) {
+                approach_circle.insert(Despawned);
+            }
         }
 
         commands.spawn(HitScoreNumber::new(
             hit,
+            self.hit_score_blocks,
             BlockPos::at(self.center() + DVec3::new(0.0, 0.0, -1.0)),
             5,
             instance,
@@ -181,12 +365,27 @@ impl Hitcircle {
     }
 
     pub fn draw_circle(&self, instance: &mut Mut<Instance>) {
-        self.fill(instance, &Block::new(self.filling_block));
-        self.draw_combo_number(
-            instance,
-            self.combo_number,
-            Block::new(BlockState::WHITE_CONCRETE),
-        );
+        let mut batch = BlockBatch::new();
+        let filling_block = if self.fade_in_ticks > 0 {
+            self.fade_in_filling_block
+        } else {
+            self.filling_block
+        };
+        batch.fill(self.circle_block_positions(), filling_block);
+        batch.fill(self.combo_number_positions(), self.combo_number_block);
+        batch.apply(instance);
+    }
+
+    /// Clears the filling and the combo number, leaving the circle ring (see `Hitcircle::hidden_ticks`)
+    fn clear_filling(&self, instance: &mut Mut<Instance>) {
+        let mut batch = BlockBatch::new();
+        batch.fill(self.circle_block_positions(), BlockState::AIR);
+        batch.fill(self.combo_number_positions(), BlockState::AIR);
+        batch.apply(instance);
+    }
+
+    pub fn circle_ring(&self) -> Entity {
+        self.circle_ring
     }
 
     pub fn instance(&self) -> Entity {
@@ -197,47 +396,177 @@ impl Hitcircle {
         self.center
     }
 
-    fn fill(&self, instance: &mut Mut<Instance>, block: &Block) {
-        self.circle_block_positions().for_each(|pos| {
-            instance.set_block(pos, block.clone());
-        });
+    /// Positions of the outline around the circle (overlapping circles are not covered)
+    pub fn outline_positions(&self, instance: &Instance) -> Vec<BlockPos> {
+        self.outline_block_positions()
+            .filter(|&pos| instance.block(pos).map(|block| block.state()) == Some(BlockState::AIR))
+            .collect()
     }
 
-    fn draw_combo_number(&self, instance: &mut Mut<Instance>, combo_number: u32, block: Block) {
-        let origin = BlockPos::at(self.center);
-
-        DigitWriter {
-            scale: max((self.radius / 5.5) as usize, 1),
-            position: TextPosition::Center,
+    /// Draws the outline in `positions` only for `client` (see `update_hitcircle_outlines`)
+    pub fn send_outline(&self, client: &mut Client, positions: &[BlockPos]) {
+        for &position in positions {
+            client.write_packet(&BlockUpdate {
+                position,
+                block_id: VarInt(self.outline_block.to_raw() as i32),
+            });
         }
-        .draw(combo_number as usize, origin, block, instance);
+    }
+
+    /// The combo number is omitted when it doesn't fit inside the circle even at the smallest scale (small radius at low scales
+    /// or high CS), since it would degenerate to a couple of unreadable blocks
+    fn combo_number_positions(&self) -> Vec<BlockPos> {
+        let Some(writer) = combo_number_writer(self.combo_number, self.radius) else {
+            return Vec::new();
+        };
+        let mut origin = BlockPos::at(self.center);
+        origin.z -= self.combo_number_z_offset;
+
+        writer
+            .iter_block_positions(self.combo_number as usize, origin)
+            .flatten()
+            .collect()
     }
 
     fn circle_block_positions(&self) -> impl Iterator<Item = BlockPos> {
+        self.block_positions(circle_offsets(self.radius as i32))
+    }
+
+    fn outline_block_positions(&self) -> impl Iterator<Item = BlockPos> {
+        self.block_positions(outline_offsets(self.radius as i32))
+    }
+
+    fn block_positions(&self, offsets: Arc<[(i32, i32)]>) -> impl Iterator<Item = BlockPos> {
         let (center_x, center_y, center_z) = (
             self.center.x as i32,
             self.center.y as i32,
             self.center.z as i32,
         );
-        let radius = self.radius as i32;
-
-        (center_x - radius..=center_x + radius).flat_map(move |x| {
-            (center_y - radius..=center_y + radius).filter_map(move |y| {
-                let rel_x = center_x - x;
-                let rel_y = center_y - y;
-
-                (rel_x.pow(2) + rel_y.pow(2) <= radius.pow(2)).then_some(BlockPos {
-                    x,
-                    y: y - 1,
-                    z: center_z,
-                })
-            })
+
+        (0..offsets.len()).map(move |i| {
+            let (x, y) = offsets[i];
+
+            BlockPos {
+                x: center_x + x,
+                y: center_y + y - 1,
+                z: center_z,
+            }
         })
     }
 }
 
+/// Draws a 1 block outline around the hitcircles for the players who enabled it in their `HudSettings`, so circles don't blend into
+/// the background. The outline is only sent to those players, the blocks of the instance are not changed.
+pub fn update_hitcircle_outlines(
+    hitcircles: Query<(Entity, &Hitcircle), Without<Despawned>>,
+    instances: Query<&Instance>,
+    mut clients: Query<(&mut Client, &HudSettings)>,
+    mut outlines: Local<HashMap<Entity, (Entity, Vec<BlockPos>)>>,
+) {
+    for (entity, hitcircle) in &hitcircles {
+        if outlines.contains_key(&entity) {
+            continue;
+        }
+        let Ok(instance) = instances.get(hitcircle.instance) else {
+            continue;
+        };
+
+        let positions = hitcircle.outline_positions(instance);
+        for (mut client, settings) in &mut clients {
+            if settings.circle_outline {
+                hitcircle.send_outline(&mut client, &positions);
+            }
+        }
+
+        outlines.insert(entity, (hitcircle.instance, positions));
+    }
+
+    // Restores the blocks of the instance where the outlines of the removed circles were
+    outlines.retain(|&entity, (instance, positions)| {
+        if hitcircles.contains(entity) {
+            return true;
+        }
+        let Ok(instance) = instances.get(*instance) else {
+            return false;
+        };
+
+        for (mut client, _) in &mut clients {
+            for &position in positions.iter() {
+                let block = instance
+                    .block(position)
+                    .map(|block| block.state())
+                    .unwrap_or(BlockState::AIR);
+                client.write_packet(&BlockUpdate {
+                    position,
+                    block_id: VarInt(block.to_raw() as i32),
+                });
+            }
+        }
+
+        false
+    });
+}
+
+/// Offsets of the blocks inside a circle of `radius`. Rasterizations are cached, since every circle of a beatmap has the same radius.
+fn circle_offsets(radius: i32) -> Arc<[(i32, i32)]> {
+    static CIRCLE_RASTERS: OnceLock<Mutex<HashMap<i32, Arc<[(i32, i32)]>>>> = OnceLock::new();
+
+    let mut rasters = CIRCLE_RASTERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    rasters
+        .entry(radius)
+        .or_insert_with(|| {
+            (-radius..=radius)
+                .flat_map(|x| (-radius..=radius).map(move |y| (x, y)))
+                .filter(|(x, y)| x.pow(2) + y.pow(2) <= radius.pow(2))
+                .collect()
+        })
+        .clone()
+}
+
+/// Offsets of the blocks surrounding a circle of `radius` (outside it, but next to one of its blocks)
+fn outline_offsets(radius: i32) -> Arc<[(i32, i32)]> {
+    let inside = |x: i32, y: i32| x.pow(2) + y.pow(2) <= radius.pow(2);
+    let outline_radius = radius + 1;
+
+    (-outline_radius..=outline_radius)
+        .flat_map(|x| (-outline_radius..=outline_radius).map(move |y| (x, y)))
+        .filter(|&(x, y)| {
+            !inside(x, y)
+                && [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .any(|(dx, dy)| inside(x + dx, y + dy))
+        })
+        .collect()
+}
+
+/// Writer of the combo number with the biggest scale fitting in a circle of `radius`, so numbers with more digits are drawn
+/// smaller instead of overflowing the circle
+fn combo_number_writer(combo_number: u32, radius: f64) -> Option<TextWriter> {
+    let max_scale = max((radius / 5.5) as usize, 1);
+
+    (1..=max_scale)
+        .rev()
+        .map(|scale| TextWriter {
+            scale,
+            position: TextPosition::Center,
+        })
+        .find(|writer| is_combo_number_legible(writer, combo_number, radius))
+}
+
+/// Whether the combo number fits in the square inscribed in a circle of `radius`
+fn is_combo_number_legible(writer: &TextWriter, combo_number: u32, radius: f64) -> bool {
+    let (width, height) = writer.size(combo_number as usize);
+    let inscribed_side = radius * std::f64::consts::SQRT_2;
+
+    width.max(height) as f64 <= inscribed_side
+}
+
 impl HitwindowTicks {
-    fn from(hitwindow: &Hitwindow, tps: usize) -> Self {
+    pub fn from(hitwindow: &Hitwindow, tps: usize) -> Self {
         Self {
             window_300: to_ticks(tps, hitwindow.window_300) as u32,
             window_100: to_ticks(tps, hitwindow.window_100) as u32,
@@ -245,7 +574,30 @@ impl HitwindowTicks {
         }
     }
 
-    fn hit_score(&self, ticks_left: u32) -> HitScore {
+    /// Ticks a hitcircle stays on the playfield after the time it should be hit
+    pub fn window_50(&self) -> usize {
+        self.window_50 as usize
+    }
+
+    /// Score of a click on a hitcircle with `ticks_left` ticks before it expires (see `Hitcircle::hit_score`)
+    pub fn judge(
+        &self,
+        ticks_left: usize,
+        grace_ticks: usize,
+        input_offset_ms: i32,
+        tps: usize,
+    ) -> HitScore {
+        let offset_ticks = (input_offset_ms as f64 * tps as f64 / 1000.0).round() as i32;
+        let ticks_left = (ticks_left as i32 + offset_ticks).max(0) as u32;
+        self.hit_score(ticks_left, grace_ticks as u32)
+    }
+
+    /// Timing error of a click with `ticks_left` ticks before the hitcircle expires (see `Hitcircle::hit_error`)
+    pub fn hit_error(&self, ticks_left: usize, tps: usize, input_offset_ms: i32) -> i32 {
+        (self.window_50 as i32 - ticks_left as i32) * 1000 / tps as i32 - input_offset_ms
+    }
+
+    fn hit_score(&self, ticks_left: u32, grace_ticks: u32) -> HitScore {
         let hit_time = self.window_50;
         for (window, score) in [
             (self.window_300, HitScore::Hit300),
@@ -254,7 +606,8 @@ impl HitwindowTicks {
         ]
         .into_iter()
         {
-            if (hit_time - window..=hit_time + window).contains(&ticks_left) {
+            let window = window + grace_ticks;
+            if (hit_time.saturating_sub(window)..=hit_time + window).contains(&ticks_left) {
                 return score;
             }
         }
@@ -274,15 +627,30 @@ impl HitcircleRadius {
     }
 }
 
-impl From<Color> for HitcircleBlocks {
-    fn from(color: Color) -> Self {
-        let block_color = color.to_block_color();
+impl HitcircleBlocks {
+    /// Blocks of a hitcircle of the combo `color` drawn with `skin`
+    pub fn new(color: Color, skin: &BlockSkin) -> Self {
+        let block_color = color.to_block_color_in(&skin.combo_palette);
+        let fade_in_color = color
+            .lerp(Color::default(), FADE_IN_SHADE)
+            .to_block_color_in(&skin.combo_palette);
         let (block, item) = (block_color.block(), block_color.item());
 
+        let outline = if color.luminance() > 0.5 {
+            BlockState::BLACK_CONCRETE
+        } else {
+            BlockState::WHITE_CONCRETE
+        };
+
         Self {
-            approach_circle: item,
-            circle_ring: ItemKind::WhiteConcrete,
+            approach_circle: skin.approach_circle.unwrap_or(item),
+            approach_circle_color: color,
+            circle_ring: skin.circle_ring,
             filling: block,
+            fade_in_filling: fade_in_color.block(),
+            outline,
+            combo_number: skin.combo_number,
+            hit_scores: skin.hit_scores,
         }
     }
 }
@@ -299,4 +667,70 @@ mod test {
         let radius = HitcircleRadius::from(cs, scale);
         assert_eq!(radius.circle, 36.0);
     }
+
+    #[test]
+    fn hidden_filling() {
+        // AR 9 at 20 TPS: cleared 350ms after spawning, 250ms before the hit
+        assert_eq!(hidden_ticks(12), 7);
+        assert_eq!(hidden_ticks(1), 1);
+    }
+
+    #[test]
+    fn circle_rasterization() {
+        assert_eq!(circle_offsets(0).len(), 1);
+        assert_eq!(circle_offsets(1).len(), 5);
+        assert_eq!(circle_offsets(2).len(), 13);
+        assert!(Arc::ptr_eq(&circle_offsets(2), &circle_offsets(2)));
+    }
+
+    #[test]
+    fn circle_outline() {
+        assert_eq!(outline_offsets(0).len(), 4);
+        assert_eq!(outline_offsets(1).len(), 8);
+
+        let circle = circle_offsets(5);
+        let outline = outline_offsets(5);
+        assert!(outline.iter().all(|offset| !circle.contains(offset)));
+    }
+
+    #[test]
+    fn combo_number_legibility() {
+        let writer = TextWriter {
+            scale: 1,
+            position: TextPosition::Center,
+        };
+
+        assert!(is_combo_number_legible(&writer, 1, 4.0));
+        assert!(!is_combo_number_legible(&writer, 1, 3.0));
+        assert!(!is_combo_number_legible(&writer, 12, 4.0));
+        assert!(is_combo_number_legible(&writer, 12, 5.0));
+    }
+
+    #[test]
+    fn combo_number_shrinks_with_digits() {
+        let scale = |combo_number, radius| {
+            combo_number_writer(combo_number, radius).map(|writer| writer.scale)
+        };
+
+        assert_eq!(scale(1, 11.0), Some(2));
+        assert_eq!(scale(12, 11.0), Some(2));
+        assert_eq!(scale(123, 11.0), Some(1));
+        assert_eq!(scale(123, 5.0), None);
+        assert_eq!(scale(1, 3.0), None);
+    }
+
+    #[test]
+    fn hitwindow_grace_ticks() {
+        let hitwindow = HitwindowTicks {
+            window_300: 1,
+            window_100: 2,
+            window_50: 4,
+        };
+
+        assert!(matches!(hitwindow.hit_score(4, 0), HitScore::Hit300));
+        assert!(matches!(hitwindow.hit_score(6, 0), HitScore::Hit100));
+        assert!(matches!(hitwindow.hit_score(9, 0), HitScore::Miss));
+        assert!(matches!(hitwindow.hit_score(6, 2), HitScore::Hit300));
+        assert!(matches!(hitwindow.hit_score(9, 1), HitScore::Hit50));
+    }
 }
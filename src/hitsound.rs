@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::warn;
+
+use bevy_ecs::{
+    prelude::{EventReader, Mut},
+    system::{Query, Res},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{types::SoundCategory, Sound, Text, TextFormat},
+};
+
+use crate::{
+    audio::{find_sample, AudioPlayer, EffectSample},
+    command_registry::{ArgSpec, CommandSpec, OsuCommand, Suggestions},
+    configs::{Configs, Skin},
+    error::error_message,
+    hit_score::HitScore,
+    hud::{save_hud_settings, HudSettings},
+    osu::Osu,
+    player_name::PlayerName,
+};
+
+/// Name of the beatmap sample played with `Skin::beatmap_hitsounds` (https://osu.ppy.sh/wiki/en/Skinning/Sounds)
+const BEATMAP_SAMPLE: &str = "normal-hitnormal";
+
+/// Minecraft sounds played when hitting a circle. Each player can choose one with `/hitsound` (the server default is `Skin::hitsound`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HitsoundKind {
+    #[default]
+    Chicken,
+    Hat,
+    Bell,
+    Drum,
+    Xylophone,
+    Click,
+}
+
+impl HitsoundKind {
+    pub const ALL: [HitsoundKind; 6] = [
+        HitsoundKind::Chicken,
+        HitsoundKind::Hat,
+        HitsoundKind::Bell,
+        HitsoundKind::Drum,
+        HitsoundKind::Xylophone,
+        HitsoundKind::Click,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HitsoundKind::Chicken => "chicken",
+            HitsoundKind::Hat => "hat",
+            HitsoundKind::Bell => "bell",
+            HitsoundKind::Drum => "drum",
+            HitsoundKind::Xylophone => "xylophone",
+            HitsoundKind::Click => "click",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn sound(&self, hit: HitScore) -> Sound {
+        match (self, hit) {
+            (HitsoundKind::Chicken, HitScore::Miss) => Sound::EntityChickenHurt,
+            (HitsoundKind::Chicken, _) => Sound::EntityChickenEgg,
+            (_, HitScore::Miss) => Sound::BlockNoteBlockBass,
+            (HitsoundKind::Hat, _) => Sound::BlockNoteBlockHat,
+            (HitsoundKind::Bell, _) => Sound::BlockNoteBlockBell,
+            (HitsoundKind::Drum, _) => Sound::BlockNoteBlockBasedrum,
+            (HitsoundKind::Xylophone, _) => Sound::BlockNoteBlockXylophone,
+            (HitsoundKind::Click, _) => Sound::UiButtonClick,
+        }
+    }
+}
+
+/// Better hits sound higher
+fn pitch(hit: HitScore) -> f32 {
+    match hit {
+        HitScore::Hit300 => 1.2,
+        HitScore::Hit100 => 1.0,
+        HitScore::Hit50 => 0.8,
+        HitScore::Miss => 0.5,
+    }
+}
+
+/// Hitsounds of the server: the default hitsound of the players and the sample of the beatmap being played (see `Skin`)
+#[derive(Default)]
+pub struct Hitsounds {
+    default_kind: HitsoundKind,
+    beatmap_samples: bool,
+    beatmap_sample: Option<EffectSample>,
+}
+
+impl Hitsounds {
+    pub fn new(skin: Skin) -> Self {
+        Self {
+            default_kind: skin.hitsound,
+            beatmap_samples: skin.beatmap_hitsounds,
+            beatmap_sample: None,
+        }
+    }
+
+    /// Loads the `normal-hitnormal` sample of the beatmap in `beatmap_dir` (if enabled and the beatmap has one)
+    pub fn load_beatmap_sample(&mut self, beatmap_dir: &Path) {
+        if !self.beatmap_samples {
+            return;
+        }
+
+        self.beatmap_sample = find_sample(beatmap_dir, BEATMAP_SAMPLE).and_then(|path| {
+            match EffectSample::open(&path) {
+                Ok(sample) => Some(sample),
+                Err(error) => {
+                    warn!(
+                        "Error while loading hitsound sample '{}': {}",
+                        path.display(),
+                        error
+                    );
+                    None
+                }
+            }
+        });
+    }
+
+    /// Plays the hitsound chosen by the player (or the server default)
+    pub fn play(&self, client: &mut Mut<Client>, kind: Option<HitsoundKind>, hit: HitScore) {
+        let kind = kind.unwrap_or(self.default_kind);
+        let position = client.position();
+        client.play_sound(
+            kind.sound(hit),
+            SoundCategory::Block,
+            position,
+            3.0,
+            pitch(hit),
+        );
+    }
+
+    /// Plays the beatmap sample in the server audio, mixed with the music
+    pub fn play_beatmap_sample(&self, audio_player: &dyn AudioPlayer, hit: HitScore) {
+        if matches!(hit, HitScore::Miss) {
+            return;
+        }
+
+        if let Some(sample) = &self.beatmap_sample {
+            if let Err(error) = audio_player.play_effect(sample) {
+                warn!("Error while playing the beatmap hitsound: {}", error);
+            }
+        }
+    }
+}
+
+pub fn hitsound_command() -> CommandSpec {
+    let names = HitsoundKind::ALL.iter().map(|kind| kind.name());
+
+    CommandSpec::new("hitsound").arg(
+        ArgSpec::word("hitsound").suggest(Suggestions::Values(names.chain(["default"]).collect())),
+    )
+}
+
+/// Handles `/hitsound [name|default]`
+pub fn execute_hitsound_commands(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "hitsound" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(command_event.client)
+        else {
+            continue;
+        };
+
+        let names: Vec<_> = HitsoundKind::ALL.iter().map(|kind| kind.name()).collect();
+        let result: Result<Text> = match args.trim() {
+            "" => {
+                let kind = settings.hitsound.unwrap_or(configs.skin().hitsound);
+                Ok("Hitsound: ".color(Color::YELLOW)
+                    + kind.name().color(Color::GREEN)
+                    + format!(" (available: {}, default)", names.join(", ")).color(Color::GRAY))
+            }
+            "default" => {
+                settings.hitsound = None;
+                save_hud_settings(&osu, player_name, &settings);
+                Ok("Hitsound set to the server default: ".color(Color::YELLOW)
+                    + configs.skin().hitsound.name().color(Color::GREEN))
+            }
+            name => match HitsoundKind::parse(name) {
+                Some(kind) => {
+                    settings.hitsound = Some(kind);
+                    save_hud_settings(&osu, player_name, &settings);
+                    Ok("Hitsound set to ".color(Color::YELLOW) + kind.name().color(Color::GREEN))
+                }
+                None => Err(anyhow!(
+                    "unknown hitsound '{}' (expected {} or default)",
+                    name,
+                    names.join(", ")
+                )),
+            },
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_hitsound_kind() {
+        assert_eq!(HitsoundKind::parse("Bell"), Some(HitsoundKind::Bell));
+        assert_eq!(HitsoundKind::parse(" drum"), Some(HitsoundKind::Drum));
+        assert_eq!(HitsoundKind::parse("cowbell"), None);
+
+        for kind in HitsoundKind::ALL {
+            assert_eq!(HitsoundKind::parse(kind.name()), Some(kind));
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::{Added, With},
+    system::{Commands, Query, Res, ResMut},
+};
+use valence::{
+    client::event::ClickContainer,
+    nbt::{compound, List},
+    prelude::{Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{
+        packets::s2c::play::BossBar, types::BossBarAction, ItemKind, ItemStack, Text, TextFormat,
+    },
+};
+
+use crate::{
+    aim_assist::NO_AIM_ASSIST,
+    hit_score::HitScore,
+    hitsound::HitsoundKind,
+    inventory::{open_new_inventory, ClientInventory, InventoriesToOpen},
+    key_overlay::KeyOverlay,
+    osu::{Hitwindow, Osu},
+    player_name::PlayerName,
+    replays::ReplayRetention,
+    scoreboard::SidebarHud,
+};
+
+const HIT_ERROR_BAR_WIDTH: usize = 21;
+
+/// HUD elements each player chose to display. Changed through the `/hud` inventory.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct HudSettings {
+    pub boss_bar: bool,
+    pub action_bar: bool,
+    pub sidebar: bool,
+    pub hit_error_bar: bool,
+    pub combo_burst: bool,
+    #[serde(default)]
+    pub key_overlay: bool,
+    #[serde(default)]
+    pub circle_outline: bool,
+    #[serde(default)]
+    pub cursor_trail: bool,
+    /// Milliseconds by which the player's taps arrive late (measured with `/latencytest`), subtracted from their hit timings
+    #[serde(default)]
+    pub input_offset_ms: i32,
+    /// Chosen with `/hitsound` (`None` uses the server default)
+    #[serde(default)]
+    pub hitsound: Option<HitsoundKind>,
+    /// Chosen with `/replays`
+    #[serde(default)]
+    pub replay_retention: ReplayRetention,
+    /// Multiplier of the hitcircle radius when checking if the player is aiming at a circle (chosen with `/assist`)
+    #[serde(default = "default_aim_assist")]
+    pub aim_assist: f64,
+    /// Dim of the screen background from 0 to 100 (chosen with `/dim`, `None` shows the background of the skin)
+    #[serde(default)]
+    pub background_dim: Option<u8>,
+}
+
+fn default_aim_assist() -> f64 {
+    NO_AIM_ASSIST
+}
+
+/// Inventory used by `client` to toggle its `HudSettings`
+#[derive(Component)]
+pub struct HudSettingsInventory {
+    client: Entity,
+}
+
+#[derive(Clone, Copy)]
+enum HudElement {
+    BossBar,
+    ActionBar,
+    Sidebar,
+    HitErrorBar,
+    ComboBurst,
+    KeyOverlay,
+    CircleOutline,
+    CursorTrail,
+}
+
+const HUD_ELEMENTS: [HudElement; 8] = [
+    HudElement::BossBar,
+    HudElement::ActionBar,
+    HudElement::Sidebar,
+    HudElement::HitErrorBar,
+    HudElement::ComboBurst,
+    HudElement::KeyOverlay,
+    HudElement::CircleOutline,
+    HudElement::CursorTrail,
+];
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            boss_bar: true,
+            action_bar: true,
+            sidebar: false,
+            hit_error_bar: true,
+            combo_burst: true,
+            key_overlay: false,
+            circle_outline: false,
+            cursor_trail: false,
+            input_offset_ms: 0,
+            hitsound: None,
+            replay_retention: ReplayRetention::default(),
+            aim_assist: default_aim_assist(),
+            background_dim: None,
+        }
+    }
+}
+
+impl HudSettings {
+    fn element_mut(&mut self, element: HudElement) -> &mut bool {
+        match element {
+            HudElement::BossBar => &mut self.boss_bar,
+            HudElement::ActionBar => &mut self.action_bar,
+            HudElement::Sidebar => &mut self.sidebar,
+            HudElement::HitErrorBar => &mut self.hit_error_bar,
+            HudElement::ComboBurst => &mut self.combo_burst,
+            HudElement::KeyOverlay => &mut self.key_overlay,
+            HudElement::CircleOutline => &mut self.circle_outline,
+            HudElement::CursorTrail => &mut self.cursor_trail,
+        }
+    }
+
+    fn element(&self, element: HudElement) -> bool {
+        match element {
+            HudElement::BossBar => self.boss_bar,
+            HudElement::ActionBar => self.action_bar,
+            HudElement::Sidebar => self.sidebar,
+            HudElement::HitErrorBar => self.hit_error_bar,
+            HudElement::ComboBurst => self.combo_burst,
+            HudElement::KeyOverlay => self.key_overlay,
+            HudElement::CircleOutline => self.circle_outline,
+            HudElement::CursorTrail => self.cursor_trail,
+        }
+    }
+}
+
+impl HudElement {
+    fn name(&self) -> &'static str {
+        match self {
+            HudElement::BossBar => "Boss bar",
+            HudElement::ActionBar => "Action bar",
+            HudElement::Sidebar => "Sidebar",
+            HudElement::HitErrorBar => "Hit error bar",
+            HudElement::ComboBurst => "Combo burst",
+            HudElement::KeyOverlay => "Key overlay",
+            HudElement::CircleOutline => "Circle outline",
+            HudElement::CursorTrail => "Cursor trail",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            HudElement::BossBar => "Score, combo and accuracy above the screen",
+            HudElement::ActionBar => "Judgement of the last hit",
+            HudElement::Sidebar => "Live stats in the scoreboard sidebar",
+            HudElement::HitErrorBar => "Timing of the last hits",
+            HudElement::ComboBurst => "Sound played on combo milestones",
+            HudElement::KeyOverlay => "Hit inputs pressed and their tap counts",
+            HudElement::CircleOutline => "Contrasting border around the hitcircles",
+            HudElement::CursorTrail => "Particles where the players are aiming",
+        }
+    }
+}
+
+impl ClientInventory for HudSettingsInventory {
+    fn client(&self) -> Entity {
+        self.client
+    }
+}
+
+impl HudSettingsInventory {
+    pub fn new(client: Entity) -> (Self, Inventory) {
+        (
+            Self { client },
+            Inventory::with_title(InventoryKind::Generic9x1, "HUD".color(Color::DARK_BLUE)),
+        )
+    }
+
+    fn draw(&self, settings: &HudSettings, inventory: &mut Inventory) {
+        for (slot, element) in HUD_ELEMENTS.iter().enumerate() {
+            let (item_kind, status) = if settings.element(*element) {
+                (
+                    ItemKind::LimeDye,
+                    r#"{"text": "Enabled", "color": "green"}"#,
+                )
+            } else {
+                (ItemKind::GrayDye, r#"{"text": "Disabled", "color": "red"}"#)
+            };
+
+            let item = ItemStack::new(
+                item_kind,
+                1,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => format!(r#"{{"text": "{}", "color": "gold"}}"#, element.name()),
+                        "Lore" => List::String(vec![
+                            format!(r#"{{"text": "{}", "color": "gray"}}"#, element.description()),
+                            status.to_string(),
+                        ])
+                    }
+                }),
+            );
+
+            inventory.replace_slot(slot as u16, Some(item));
+        }
+    }
+}
+
+pub fn init_hud_settings(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    new_clients: Query<(Entity, &PlayerName), Added<PlayerName>>,
+) {
+    for (client_entity, player_name) in &new_clients {
+        let settings = osu
+            .storage()
+            .load_hud_settings(player_name.as_str())
+            .unwrap_or_else(|error| {
+                warn!("Error while loading HUD settings: {}", error);
+                None
+            })
+            .unwrap_or_default();
+
+        commands
+            .entity(client_entity)
+            .insert((settings, SidebarHud::default(), KeyOverlay::new()));
+    }
+}
+
+/// Persists the settings of `player_name`, logging the error if they couldn't be saved
+pub fn save_hud_settings(osu: &Osu, player_name: &PlayerName, settings: &HudSettings) {
+    if let Err(error) = osu
+        .storage()
+        .save_hud_settings(player_name.as_str(), settings)
+    {
+        warn!("Error while saving HUD settings: {}", error);
+    }
+}
+
+/// Opens the HUD settings inventory of `client`, creating it if needed
+pub fn open_hud_settings_inventory(
+    commands: &mut Commands,
+    client: Entity,
+    settings: &HudSettings,
+    inventories_to_open: &mut ResMut<InventoriesToOpen>,
+    hud_inventories: &mut Query<(Entity, &HudSettingsInventory, &mut Inventory)>,
+) {
+    let inventory_entity = match hud_inventories
+        .iter_mut()
+        .find(|(_, hud_inventory, _)| hud_inventory.client == client)
+    {
+        Some((entity, hud_inventory, mut inventory)) => {
+            hud_inventory.draw(settings, &mut inventory);
+            entity
+        }
+        None => {
+            let (hud_inventory, mut inventory) = HudSettingsInventory::new(client);
+            hud_inventory.draw(settings, &mut inventory);
+            commands.spawn((hud_inventory, inventory)).id()
+        }
+    };
+
+    open_new_inventory(commands, client, inventories_to_open, inventory_entity);
+}
+
+pub fn handle_hud_settings_clicks(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    open_inventories: Query<&OpenInventory, With<Client>>,
+    mut hud_inventories: Query<(&HudSettingsInventory, &mut Inventory)>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    osu: Res<Osu>,
+    mut clicks: EventReader<ClickContainer>,
+) {
+    for click in clicks.iter() {
+        let Ok(open_inventory) = open_inventories.get(click.client) else {
+            continue;
+        };
+        let inventory_entity = open_inventory.entity();
+        let Ok((hud_inventory, mut inventory)) = hud_inventories.get_mut(inventory_entity) else {
+            continue;
+        };
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(hud_inventory.client)
+        else {
+            continue;
+        };
+        let Some(&element) = HUD_ELEMENTS.get(click.slot_id.unsigned_abs() as usize) else {
+            continue;
+        };
+
+        let enabled = settings.element_mut(element);
+        *enabled = !*enabled;
+
+        if matches!(element, HudElement::BossBar) && !settings.boss_bar {
+            client.write_packet(&BossBar {
+                id: osu.life_bar_uuid(),
+                action: BossBarAction::Remove,
+            });
+        }
+
+        save_hud_settings(&osu, player_name, &settings);
+
+        hud_inventory.draw(&settings, &mut inventory);
+        open_new_inventory(
+            &mut commands,
+            click.client,
+            &mut inventories_to_open,
+            inventory_entity,
+        );
+    }
+}
+
+pub fn update_action_bar_hud(osu: Res<Osu>, mut clients: Query<(&mut Client, &HudSettings)>) {
+    let Some(beatmap) = osu.playing_beatmap() else {
+        return;
+    };
+    let hitwindow: Hitwindow = beatmap.od().into();
+    let window_ms = hitwindow.window_50.as_millis() as i32;
+
+    for (mut client, settings) in &mut clients {
+        let mut text: Text = "".into();
+
+        if settings.action_bar {
+            if let Some(hit) = beatmap.state.last_hit {
+                let judgement = match hit {
+                    HitScore::Hit300 => "300".color(Color::AQUA),
+                    HitScore::Hit100 => "100".color(Color::GREEN),
+                    HitScore::Hit50 => "50".color(Color::GOLD),
+                    HitScore::Miss => "X".color(Color::RED),
+                };
+                text = text + judgement + "   ";
+            }
+        }
+
+        if settings.hit_error_bar {
+            let errors: Vec<_> = beatmap.state.hit_errors.iter().copied().collect();
+            let cells = hit_error_bar_cells(&errors, window_ms, HIT_ERROR_BAR_WIDTH);
+            for (idx, has_hit) in cells.into_iter().enumerate() {
+                let cell = if has_hit {
+                    "|".color(Color::AQUA)
+                } else if idx == HIT_ERROR_BAR_WIDTH / 2 {
+                    "|".color(Color::WHITE)
+                } else {
+                    "-".color(Color::DARK_GRAY)
+                };
+                text = text + cell;
+            }
+        }
+
+        if settings.action_bar || settings.hit_error_bar {
+            client.set_action_bar(text);
+        }
+    }
+}
+
+/// Cells of the hit error bar containing a hit. The left most cell represents hits `window_ms` early and the right most hits `window_ms` late.
+fn hit_error_bar_cells(errors: &[i32], window_ms: i32, width: usize) -> Vec<bool> {
+    let mut cells = vec![false; width];
+    let window_ms = window_ms.max(1);
+
+    for &error in errors {
+        let error = error.clamp(-window_ms, window_ms);
+        let cell = ((error + window_ms) as f64 / (2 * window_ms) as f64 * (width - 1) as f64)
+            .round() as usize;
+        cells[cell] = true;
+    }
+
+    cells
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hit_error_bar() {
+        assert_eq!(hit_error_bar_cells(&[], 100, 5), vec![false; 5]);
+        assert_eq!(
+            hit_error_bar_cells(&[0], 100, 5),
+            vec![false, false, true, false, false]
+        );
+        assert_eq!(
+            hit_error_bar_cells(&[-100, 500], 100, 5),
+            vec![true, false, false, false, true]
+        );
+        assert_eq!(
+            hit_error_bar_cells(&[-50, 49], 100, 5),
+            vec![false, true, false, true, false]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::{
+    prelude::Entity,
+    system::{Query, Resource},
+};
+use valence::{
+    prelude::{Client, Color, DVec3, Vec3},
+    protocol::{packets::s2c::particle::Particle, types::SoundCategory, Sound, Text, TextFormat},
+};
+
+use crate::playfield::Playfield;
+
+/// Time a player has to wait between their hype commands
+const PLAYER_COOLDOWN: Duration = Duration::from_secs(10);
+/// Time between any two hype commands, so several players can't flood the playfield together
+const GLOBAL_COOLDOWN: Duration = Duration::from_secs(2);
+const PARTICLE_COUNT: i32 = 300;
+
+/// Social commands which fire particles over the playfield and play crowd sounds for everyone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hype {
+    /// `/hype`: fireworks
+    Hype,
+    /// `/gg`: cheering villagers
+    GoodGame,
+}
+
+impl Hype {
+    fn particle(self) -> Particle {
+        match self {
+            Hype::Hype => Particle::Firework,
+            Hype::GoodGame => Particle::HappyVillager,
+        }
+    }
+
+    fn sound(self) -> Sound {
+        match self {
+            Hype::Hype => Sound::EntityFireworkRocketLargeBlast,
+            Hype::GoodGame => Sound::EntityVillagerCelebrate,
+        }
+    }
+
+    fn message(self, username: &str) -> Text {
+        match self {
+            Hype::Hype => username.to_string().color(Color::AQUA) + " is hyped!".color(Color::GOLD),
+            Hype::GoodGame => username.to_string().color(Color::AQUA) + ": GG!".color(Color::GREEN),
+        }
+    }
+}
+
+/// Last time each player used a hype command
+#[derive(Resource, Default)]
+pub struct HypeCooldowns {
+    last_used: HashMap<Entity, Instant>,
+    last_global: Option<Instant>,
+}
+
+impl HypeCooldowns {
+    /// Registers a hype command by `client`, or returns how long it has to wait to use it
+    pub fn try_use(&mut self, client: Entity, now: Instant) -> Result<(), Duration> {
+        let remaining = |last: Option<&Instant>, cooldown: Duration| {
+            last.map_or(Duration::ZERO, |last| {
+                cooldown.saturating_sub(now.duration_since(*last))
+            })
+        };
+        let wait = remaining(self.last_used.get(&client), PLAYER_COOLDOWN)
+            .max(remaining(self.last_global.as_ref(), GLOBAL_COOLDOWN));
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        self.last_used.insert(client, now);
+        self.last_global = Some(now);
+        Ok(())
+    }
+}
+
+/// Fires the particles of `hype` over the playfield and plays its sound for every player
+pub fn fire_hype(
+    hype: Hype,
+    username: &str,
+    playfield: &Playfield,
+    clients: &mut Query<&mut Client>,
+) {
+    let center = playfield.screen_center();
+    let (screen_x, screen_y) = playfield.screen_size();
+    // In front of the screen, so particles are not hidden by its blocks
+    let position = DVec3::new(center.x as f64, center.y as f64, center.z as f64 - 2.0);
+    let offset = Vec3::new(screen_x as f32 / 3.0, screen_y as f32 / 3.0, 1.0);
+    let message = hype.message(username);
+
+    for mut client in clients.iter_mut() {
+        client.play_particle(
+            &hype.particle(),
+            true,
+            position,
+            offset,
+            0.1,
+            PARTICLE_COUNT,
+        );
+
+        let client_position = client.position();
+        client.play_sound(
+            hype.sound(),
+            SoundCategory::Player,
+            client_position,
+            1.0,
+            1.0,
+        );
+        client.send_message(message.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limit() {
+        let mut cooldowns = HypeCooldowns::default();
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let start = Instant::now();
+
+        assert_eq!(cooldowns.try_use(a, start), Ok(()));
+        assert_eq!(
+            cooldowns.try_use(b, start + Duration::from_secs(1)),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(cooldowns.try_use(b, start + GLOBAL_COOLDOWN), Ok(()));
+        assert_eq!(
+            cooldowns.try_use(a, start + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+        assert_eq!(cooldowns.try_use(a, start + PLAYER_COOLDOWN), Ok(()));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy_ecs::{prelude::Entity, system::Resource};
+
+/// Inputs of a player judged in `INPUT_RATE_WINDOW_MS`: 20 per second is about the speed of 300 BPM streams,
+/// faster inputs are ignored so mashing the 3 hit keys every tick doesn't snipe the circles
+const MAX_INPUTS_PER_WINDOW: usize = 20;
+const INPUT_RATE_WINDOW_MS: u64 = 1000;
+/// Clicks in a row which don't hit any circle (while there is one to hit) breaking the combo
+const MAX_EMPTY_CLICKS: usize = 4;
+
+/// Penalties against mashing while a beatmap is played, like osu! does: inputs above a rate are ignored and repeated clicks on
+/// empty space break the combo. The times are the play time of the beatmap in milliseconds.
+#[derive(Resource, Default)]
+pub struct InputGuard {
+    players: HashMap<Entity, PlayerInputs>,
+}
+
+#[derive(Default)]
+struct PlayerInputs {
+    /// Times of the inputs judged in the last `INPUT_RATE_WINDOW_MS`
+    recent_ms: VecDeque<u64>,
+    empty_clicks: usize,
+}
+
+impl InputGuard {
+    /// Forgets the inputs of the previous play
+    pub fn clear(&mut self) {
+        self.players.clear();
+    }
+
+    /// Records an input of `player` at `time_ms`, returning whether it can be judged (it's ignored if the player is mashing)
+    pub fn accept(&mut self, player: Entity, time_ms: u64) -> bool {
+        let inputs = self.players.entry(player).or_default();
+        while inputs.recent_ms.front().map_or(false, |&input_ms| {
+            input_ms + INPUT_RATE_WINDOW_MS <= time_ms
+        }) {
+            inputs.recent_ms.pop_front();
+        }
+
+        if inputs.recent_ms.len() >= MAX_INPUTS_PER_WINDOW {
+            return false;
+        }
+        inputs.recent_ms.push_back(time_ms);
+
+        true
+    }
+
+    /// Records whether a judged click of `player` hit a circle. Returns `true` when the player clicked on empty space too many
+    /// times in a row, so their combo is broken.
+    pub fn record_click(&mut self, player: Entity, hit: bool) -> bool {
+        let inputs = self.players.entry(player).or_default();
+        if hit {
+            inputs.empty_clicks = 0;
+            return false;
+        }
+
+        inputs.empty_clicks += 1;
+        if inputs.empty_clicks >= MAX_EMPTY_CLICKS {
+            inputs.empty_clicks = 0;
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mashing_inputs_are_ignored() {
+        let mut guard = InputGuard::default();
+        let (player, other) = (Entity::from_raw(0), Entity::from_raw(1));
+
+        // 3 keys every 50ms tick
+        let accepted = (0..60)
+            .filter(|input| guard.accept(player, input / 3 * 50))
+            .count();
+        assert_eq!(accepted, MAX_INPUTS_PER_WINDOW);
+        assert!(guard.accept(other, 950));
+
+        // The window moved past the first inputs
+        assert!(guard.accept(player, 1000));
+        assert!(guard.accept(player, 1100));
+
+        // Streaming at 16 clicks per second
+        let mut guard = InputGuard::default();
+        assert!((0..48).all(|click| guard.accept(player, click * 62)));
+    }
+
+    #[test]
+    fn empty_clicks_break_combo() {
+        let mut guard = InputGuard::default();
+        let player = Entity::from_raw(0);
+
+        for _ in 0..MAX_EMPTY_CLICKS - 1 {
+            assert!(!guard.record_click(player, false));
+        }
+        assert!(!guard.record_click(player, true));
+        for _ in 0..MAX_EMPTY_CLICKS - 1 {
+            assert!(!guard.record_click(player, false));
+        }
+        assert!(guard.record_click(player, false));
+        assert!(!guard.record_click(player, false));
+    }
+}
@@ -1,10 +1,14 @@
 use std::mem;
 
 use bevy_ecs::{
-    prelude::Entity,
-    system::{Commands, ResMut, Resource},
+    prelude::{Component, Entity},
+    query::{With, Without},
+    system::{Commands, Query, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, OpenInventory},
+    Despawned,
 };
-use valence::prelude::OpenInventory;
 
 #[derive(Resource, Default)]
 pub struct InventoriesToOpen {
@@ -39,6 +43,29 @@ pub fn open_new_inventory(
     })
 }
 
+/// Inventory spawned for a single player, despawned by `despawn_orphaned_inventories` once they disconnect
+pub trait ClientInventory: Component {
+    fn client(&self) -> Entity;
+
+    /// Whether the inventory still has work to finish after its player left (e.g. a download)
+    fn is_busy(&self) -> bool {
+        false
+    }
+}
+
+/// Despawns the inventories of the players who disconnected
+pub fn despawn_orphaned_inventories<T: ClientInventory>(
+    mut commands: Commands,
+    inventories: Query<(Entity, &T), Without<Despawned>>,
+    clients: Query<(), With<Client>>,
+) {
+    for (entity, inventory) in &inventories {
+        if !clients.contains(inventory.client()) && !inventory.is_busy() {
+            commands.entity(entity).insert(Despawned);
+        }
+    }
+}
+
 pub fn open_queued_inventories(mut commands: Commands, mut to_open: ResMut<InventoriesToOpen>) {
     let mut inventories_to_open = Vec::new();
     mem::swap(&mut inventories_to_open, &mut to_open.inventories);
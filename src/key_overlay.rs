@@ -0,0 +1,142 @@
+use bevy_ecs::{
+    prelude::{Component, EventReader},
+    system::{Query, Res},
+};
+use valence::{
+    client::event::{DropItem, SwapItemInHand, SwingArm},
+    prelude::{Client, Color, Uuid},
+    protocol::{
+        packets::s2c::play::BossBar,
+        types::{BossBarAction, BossBarColor, BossBarDivision, BossBarFlags},
+        Text, TextFormat,
+    },
+};
+
+use crate::{hud::HudSettings, osu::Osu};
+
+/// Ticks a key stays highlighted after being pressed
+const KEY_HIGHLIGHT_TICKS: usize = 3;
+const KEY_NAMES: [&str; 3] = ["LMB", "Q", "F"];
+
+#[derive(Clone, Copy)]
+enum HitKey {
+    Attack,
+    DropItem,
+    SwapItem,
+}
+
+/// Boss bar showing which of the hit inputs the player is pressing and how many times each was tapped in the current play.
+/// Useful for streamers and to diagnose input issues remotely.
+#[derive(Component)]
+pub struct KeyOverlay {
+    uuid: Uuid,
+    shown: bool,
+    taps: [usize; 3],
+    highlight_ticks: [usize; 3],
+}
+
+impl KeyOverlay {
+    pub fn new() -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            shown: false,
+            taps: [0; 3],
+            highlight_ticks: [0; 3],
+        }
+    }
+
+    fn press(&mut self, key: HitKey) {
+        self.taps[key as usize] += 1;
+        self.highlight_ticks[key as usize] = KEY_HIGHLIGHT_TICKS;
+    }
+
+    fn tick(&mut self) {
+        for ticks in &mut self.highlight_ticks {
+            *ticks = ticks.saturating_sub(1);
+        }
+    }
+
+    fn reset(&mut self, client: &mut Client) {
+        if self.shown {
+            client.write_packet(&BossBar {
+                id: self.uuid,
+                action: BossBarAction::Remove,
+            });
+        }
+
+        self.shown = false;
+        self.taps = [0; 3];
+        self.highlight_ticks = [0; 3];
+    }
+
+    fn title(&self) -> Text {
+        let mut title: Text = "".into();
+
+        for (idx, name) in KEY_NAMES.iter().enumerate() {
+            let key = if self.highlight_ticks[idx] > 0 {
+                format!(" {name} ").color(Color::GOLD)
+            } else {
+                format!(" {name} ").color(Color::DARK_GRAY)
+            };
+            title = title + key + format!("{}   ", self.taps[idx]).color(Color::WHITE);
+        }
+
+        title
+    }
+}
+
+impl Default for KeyOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn update_key_overlay(
+    osu: Res<Osu>,
+    mut clients: Query<(&mut Client, &HudSettings, &mut KeyOverlay)>,
+    mut swing_arm_events: EventReader<SwingArm>,
+    mut drop_item_events: EventReader<DropItem>,
+    mut swap_item_hand_events: EventReader<SwapItemInHand>,
+) {
+    let presses = swing_arm_events
+        .iter()
+        .map(|e| (e.client, HitKey::Attack))
+        .chain(
+            drop_item_events
+                .iter()
+                .map(|e| (e.client, HitKey::DropItem)),
+        )
+        .chain(
+            swap_item_hand_events
+                .iter()
+                .map(|e| (e.client, HitKey::SwapItem)),
+        );
+
+    let is_playing = osu.playing_beatmap().is_some();
+    for (client, key) in presses {
+        if let (true, Ok((_, _, mut key_overlay))) = (is_playing, clients.get_mut(client)) {
+            key_overlay.press(key);
+        }
+    }
+
+    for (mut client, settings, mut key_overlay) in &mut clients {
+        if !is_playing || !settings.key_overlay {
+            key_overlay.reset(&mut client);
+            continue;
+        }
+
+        client.write_packet(&BossBar {
+            id: key_overlay.uuid,
+            action: BossBarAction::Add {
+                title: key_overlay.title(),
+                health: 0.0,
+                color: BossBarColor::White,
+                division: BossBarDivision::NoDivision,
+                flags: BossBarFlags::new(),
+            },
+        });
+
+        key_overlay.shown = true;
+        key_overlay.tick();
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use bevy_ecs::system::{Res, ResMut, Resource};
+use tracing::warn;
+use valence::prelude::Server;
+
+use crate::osu::Osu;
+
+/// A tick taking longer than this many ticks is considered a lag spike
+const LAG_SPIKE_TICKS: u32 = 3;
+/// Upper bound of grace ticks given after a single lag spike, so a stalled server doesn't freeze judgments for too long
+const MAX_GRACE_TICKS: usize = 40;
+/// Upper bound of the ping compensation, so clients delaying their keepalives can't widen their hitwindows
+const MAX_PING_COMPENSATION_MS: i32 = 150;
+
+/// Tracks tick times to detect server lag spikes (e.g. disk IO while loading songs) during a play.
+/// After a spike, hit judgments are relaxed for as many ticks as were lost so players aren't given unavoidable misses.
+#[derive(Resource, Default)]
+pub struct LagCompensation {
+    last_tick: Option<Instant>,
+    grace_ticks: usize,
+}
+
+impl LagCompensation {
+    /// Number of ticks by which hitwindows are currently extended
+    pub fn grace_ticks(&self) -> usize {
+        self.grace_ticks
+    }
+
+    /// Registers a new tick and returns the number of ticks lost if it was a lag spike
+    fn record_tick(&mut self, now: Instant, tps: usize) -> Option<usize> {
+        let last_tick = self.last_tick.replace(now);
+        self.grace_ticks = self.grace_ticks.saturating_sub(1);
+
+        let tick_duration = Duration::from_secs(1) / tps.max(1) as u32;
+        let elapsed = now.duration_since(last_tick?);
+        if elapsed <= tick_duration * LAG_SPIKE_TICKS {
+            return None;
+        }
+
+        let lost_ticks = (elapsed.as_millis() / tick_duration.as_millis()) as usize - 1;
+        self.grace_ticks = self.grace_ticks.max(lost_ticks.min(MAX_GRACE_TICKS));
+
+        Some(lost_ticks)
+    }
+}
+
+/// Milliseconds by which the inputs of a player with `ping_ms` (keepalive round trip) arrive late to the server: the clicks take
+/// half of the round trip to arrive. They are judged this much earlier, like the `HudSettings::input_offset_ms`, so remote players
+/// aren't judged late compared to the server audio.
+pub fn ping_compensation_ms(ping_ms: i32) -> i32 {
+    (ping_ms / 2).clamp(0, MAX_PING_COMPENSATION_MS)
+}
+
+pub fn update_lag_compensation(
+    osu: Res<Osu>,
+    server: Res<Server>,
+    mut lag: ResMut<LagCompensation>,
+) {
+    let tps = server.shared().tps() as usize;
+    let lost_ticks = lag.record_tick(Instant::now(), tps);
+
+    if osu.playing_beatmap().is_none() {
+        lag.grace_ticks = 0;
+    } else if let Some(lost_ticks) = lost_ticks {
+        warn!(
+            "Server lag spike of {} ticks while playing, extending hitwindows",
+            lost_ticks
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lag_spike_grace_ticks() {
+        let start = Instant::now();
+        let mut lag = LagCompensation::default();
+
+        assert_eq!(lag.record_tick(start, 20), None);
+        assert_eq!(lag.record_tick(start + Duration::from_millis(60), 20), None);
+        assert_eq!(lag.grace_ticks(), 0);
+
+        // 500ms tick: 9 ticks lost
+        assert_eq!(
+            lag.record_tick(start + Duration::from_millis(560), 20),
+            Some(9)
+        );
+        assert_eq!(lag.grace_ticks(), 9);
+
+        assert_eq!(
+            lag.record_tick(start + Duration::from_millis(610), 20),
+            None
+        );
+        assert_eq!(lag.grace_ticks(), 8);
+
+        // Long stalls are capped
+        assert_eq!(
+            lag.record_tick(start + Duration::from_secs(10), 20),
+            Some(186)
+        );
+        assert_eq!(lag.grace_ticks(), MAX_GRACE_TICKS);
+    }
+
+    #[test]
+    fn ping_compensation() {
+        assert_eq!(ping_compensation_ms(0), 0);
+        assert_eq!(ping_compensation_ms(81), 40);
+        // Not measured yet
+        assert_eq!(ping_compensation_ms(-1), 0);
+        assert_eq!(ping_compensation_ms(2000), MAX_PING_COMPENSATION_MS);
+    }
+}
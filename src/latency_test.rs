@@ -0,0 +1,282 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    system::{Query, Res, ResMut, Resource},
+};
+use valence::{
+    client::event::{DropItem, SwapItemInHand, SwingArm},
+    prelude::{Client, Color, Server},
+    protocol::{types::SoundCategory, Sound, Text, TextFormat},
+};
+
+use crate::{
+    command_registry::{CommandSpec, OsuCommand},
+    error::error_message,
+    hud::{save_hud_settings, HudSettings},
+    lag::ping_compensation_ms,
+    osu::Osu,
+    player_name::PlayerName,
+};
+
+/// Duration of the test
+const TEST_SECS: usize = 30;
+/// Beats per minute of the metronome
+const METRONOME_BPM: usize = 120;
+/// Taps needed for the result to be meaningful
+const MIN_TAPS: usize = 10;
+
+/// Metronome played to a single player, measuring how late (or early) their taps arrive compared to the beats
+#[derive(Debug, Default)]
+pub struct LatencyTest {
+    ticks: usize,
+    /// Distance in milliseconds of each tap to the nearest beat (positive if late)
+    deltas_ms: Vec<i32>,
+}
+
+impl LatencyTest {
+    fn beat_ticks(tps: usize) -> usize {
+        (tps * 60 / METRONOME_BPM).max(1)
+    }
+
+    fn is_beat(&self, tps: usize) -> bool {
+        self.ticks % Self::beat_ticks(tps) == 0
+    }
+
+    fn is_finished(&self, tps: usize) -> bool {
+        self.ticks >= TEST_SECS * tps
+    }
+
+    /// The `ping_compensation_ms` of the player is removed, since it's compensated separately when the hits are judged
+    fn record_tap(&mut self, tps: usize, ping_compensation_ms: i32) {
+        let beat_ticks = Self::beat_ticks(tps);
+        let since_beat = (self.ticks % beat_ticks) as i32;
+        // Taps closer to the next beat are early
+        let delta_ticks = if since_beat * 2 > beat_ticks as i32 {
+            since_beat - beat_ticks as i32
+        } else {
+            since_beat
+        };
+
+        self.deltas_ms
+            .push(delta_ticks * 1000 / tps as i32 - ping_compensation_ms);
+    }
+
+    /// Average latency in milliseconds, if there were enough taps
+    fn average_ms(&self) -> Option<i32> {
+        (self.deltas_ms.len() >= MIN_TAPS)
+            .then(|| self.deltas_ms.iter().sum::<i32>() / self.deltas_ms.len() as i32)
+    }
+}
+
+/// Arguments of the `/latencytest` command
+#[derive(Debug, PartialEq, Eq)]
+pub enum LatencyTestCommand {
+    Start,
+    /// Uses the last measured latency as input offset
+    Apply,
+    /// Removes the input offset
+    Reset,
+}
+
+impl LatencyTestCommand {
+    pub fn parse(args: &str) -> Result<Self> {
+        Ok(match args.trim() {
+            "" => Self::Start,
+            "apply" => Self::Apply,
+            "reset" => Self::Reset,
+            action => bail!("unknown action '{}' (expected apply or reset)", action),
+        })
+    }
+}
+
+/// Running latency tests and the last result of each player, which they can apply as input offset
+#[derive(Resource, Default)]
+pub struct LatencyTests {
+    running: HashMap<Entity, LatencyTest>,
+    results: HashMap<Entity, i32>,
+}
+
+pub fn latency_test_command() -> CommandSpec {
+    CommandSpec::new("latencytest").subcommands(["apply", "reset"])
+}
+
+/// Handles `/latencytest [apply|reset]`
+pub fn execute_latency_test_commands(
+    mut tests: ResMut<LatencyTests>,
+    osu: Res<Osu>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "latencytest" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(command_event.client)
+        else {
+            continue;
+        };
+
+        let result: Result<Text> = match LatencyTestCommand::parse(args) {
+            Ok(LatencyTestCommand::Start) if !osu.is_selecting_beatmap() => Err(anyhow!(
+                "The latency test can only be started while selecting a beatmap"
+            )),
+            Ok(LatencyTestCommand::Start) => {
+                tests
+                    .running
+                    .insert(command_event.client, LatencyTest::default());
+                Ok("Latency test started: ".color(Color::YELLOW)
+                    + format!(
+                        "tap (left click, Q or F) on every beat for {} seconds",
+                        TEST_SECS
+                    )
+                    .color(Color::GRAY))
+            }
+            Ok(LatencyTestCommand::Apply) => match tests.results.remove(&command_event.client) {
+                Some(latency_ms) => {
+                    settings.input_offset_ms = latency_ms;
+                    save_hud_settings(&osu, player_name, &settings);
+                    Ok("Input offset set to ".color(Color::YELLOW)
+                        + format!("{}ms", latency_ms).color(Color::GREEN))
+                }
+                None => Err(anyhow!("Run /latencytest first")),
+            },
+            Ok(LatencyTestCommand::Reset) => {
+                settings.input_offset_ms = 0;
+                save_hud_settings(&osu, player_name, &settings);
+                Ok("Input offset ".color(Color::YELLOW) + "removed".color(Color::GREEN))
+            }
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+/// Plays the metronome of the running tests, records the taps and reports the results
+pub fn update_latency_tests(
+    mut tests: ResMut<LatencyTests>,
+    osu: Res<Osu>,
+    server: Res<Server>,
+    mut clients: Query<&mut Client>,
+    mut swing_arm_events: EventReader<SwingArm>,
+    mut drop_item_events: EventReader<DropItem>,
+    mut swap_item_hand_events: EventReader<SwapItemInHand>,
+) {
+    let tps = server.shared().tps() as usize;
+    let taps: Vec<_> = swing_arm_events
+        .iter()
+        .map(|event| event.client)
+        .chain(drop_item_events.iter().map(|event| event.client))
+        .chain(swap_item_hand_events.iter().map(|event| event.client))
+        .collect();
+
+    if tests.running.is_empty() {
+        return;
+    }
+
+    // The metronome would be mixed up with the taps and the HUD of the beatmap
+    if !osu.is_selecting_beatmap() {
+        for (client, _) in tests.running.drain() {
+            if let Ok(mut client) = clients.get_mut(client) {
+                client.send_message("Latency test cancelled: a beatmap started".color(Color::RED));
+            }
+        }
+        return;
+    }
+
+    let LatencyTests { running, results } = &mut *tests;
+    running.retain(|&entity, test| {
+        // Disconnected
+        let Ok(mut client) = clients.get_mut(entity) else {
+            return false;
+        };
+
+        let ping_compensation_ms = ping_compensation_ms(client.ping());
+        for _ in taps.iter().filter(|&&tap| tap == entity) {
+            test.record_tap(tps, ping_compensation_ms);
+        }
+
+        if test.is_finished(tps) {
+            match test.average_ms() {
+                Some(latency_ms) => {
+                    results.insert(entity, latency_ms);
+                    client.send_message(
+                        "Average latency: ".color(Color::YELLOW)
+                            + format!("{}ms", latency_ms).color(Color::GREEN)
+                            + format!(" ({} taps)", test.deltas_ms.len()).color(Color::GRAY),
+                    );
+                    client.send_message(
+                        "Use ".color(Color::YELLOW)
+                            + "/latencytest apply".color(Color::GREEN)
+                            + " to use it as your input offset".color(Color::YELLOW),
+                    );
+                }
+                None => client.send_message(
+                    format!("Latency test failed: at least {} taps are needed", MIN_TAPS)
+                        .color(Color::RED),
+                ),
+            }
+            client.set_action_bar("".into());
+            return false;
+        }
+
+        if test.is_beat(tps) {
+            let position = client.position();
+            client.play_sound(
+                Sound::BlockNoteBlockHat,
+                SoundCategory::Record,
+                position,
+                1.0,
+                1.0,
+            );
+            client.set_action_bar("\u{2588}\u{2588}\u{2588}".color(Color::WHITE));
+        } else if test.ticks % LatencyTest::beat_ticks(tps) == 1 {
+            client.set_action_bar("\u{2588}\u{2588}\u{2588}".color(Color::DARK_GRAY));
+        }
+
+        test.ticks += 1;
+        true
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn measure_latency() {
+        let tps = 20;
+        let mut test = LatencyTest::default();
+
+        for tick in 0..TEST_SECS * tps {
+            test.ticks = tick;
+            // Taps arrive 2 ticks after every beat
+            if tick % LatencyTest::beat_ticks(tps) == 2 {
+                test.record_tap(tps, 0);
+            }
+        }
+        assert_eq!(test.average_ms(), Some(100));
+
+        let mut early = LatencyTest {
+            ticks: LatencyTest::beat_ticks(tps) - 1,
+            ..Default::default()
+        };
+        early.record_tap(tps, 0);
+        assert_eq!(early.deltas_ms, vec![-50]);
+
+        // 60ms of the 100ms are the ping compensation
+        let mut remote = LatencyTest {
+            ticks: 2,
+            ..Default::default()
+        };
+        remote.record_tap(tps, 60);
+        assert_eq!(remote.deltas_ms, vec![40]);
+        assert_eq!(early.average_ms(), None);
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Duration,
+};
+use tracing::{error, warn};
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::Without,
+    system::{Local, Query, Res, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    afk::Afk,
+    beatmap::Beatmap,
+    command_registry::{CommandSpec, OsuCommand},
+    error::error_message,
+    osu::Osu,
+    player_name::PlayerName,
+    progress::{LongOperation, LongOperations},
+};
+
+/// Scores shown by `/leaderboard`
+const LEADERBOARD_SIZE: usize = 10;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Header with the HMAC-SHA256 of the body of the submissions, in hexadecimal
+const SIGNATURE_HEADER: &str = "X-Osucraft-Signature";
+
+/// Score posted to `<leaderboard_url>/scores` when a beatmap is cleared
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScoreSubmission {
+    /// SHA-256 of the .osu file (see `BeatmapData::file_hash`), which identifies the beatmap on every server
+    pub map_hash: String,
+    pub beatmap: String,
+    pub player: String,
+    pub score: usize,
+    pub accuracy: f32,
+    pub max_combo: usize,
+    /// e.g. `+HRHD` or `NM`
+    pub mods: String,
+}
+
+impl ScoreSubmission {
+    pub fn new(beatmap: &Beatmap, player: String) -> Self {
+        let data = &beatmap.data;
+        Self {
+            map_hash: data.file_hash.clone(),
+            beatmap: format!(
+                "{} - {} [{}]",
+                data.artist, data.title, data.difficulty_name
+            ),
+            player,
+            score: beatmap.state.score,
+            accuracy: beatmap.state.accuracy(),
+            max_combo: beatmap.state.max_combo,
+            mods: data.mods.to_string(),
+        }
+    }
+}
+
+/// Score of the global leaderboard, returned by `<leaderboard_url>/scores?map_hash=<hash>&limit=<count>` (highest first)
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct GlobalScore {
+    pub player: String,
+    pub score: usize,
+    pub accuracy: f32,
+    pub max_combo: usize,
+    #[serde(default)]
+    pub mods: String,
+}
+
+/// HMAC-SHA256 of `body` with the shared `secret`, so the leaderboard server can check the scores come from a trusted server
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Self-hosted leaderboard shared by several servers (`leaderboard_url` in `configs.json`). Scores are submitted from a
+/// background thread so a slow leaderboard server doesn't lag the game, and only if `leaderboard_secret` is set (the top
+/// scores can still be shown without it).
+#[derive(Resource, Default)]
+pub struct GlobalLeaderboard {
+    url: Option<String>,
+    submissions: Option<Sender<ScoreSubmission>>,
+    /// `/leaderboard` requests of each player, with the name of their beatmap
+    requests: Vec<(Entity, String, LongOperation<Vec<GlobalScore>>)>,
+}
+
+impl GlobalLeaderboard {
+    pub fn new(url: Option<&str>, secret: &str) -> Self {
+        let Some(url) = url else {
+            return Self::default();
+        };
+        if secret.is_empty() {
+            error!("The leaderboard secret is empty, scores won't be submitted to the leaderboard (set 'leaderboard_secret' in the configs)");
+            return Self {
+                url: Some(url.to_string()),
+                submissions: None,
+                requests: Vec::new(),
+            };
+        }
+        let (sender, receiver) = mpsc::channel::<ScoreSubmission>();
+
+        let scores_url = format!("{}/scores", url);
+        let secret = secret.to_string();
+        thread::spawn(move || {
+            for submission in receiver {
+                if let Err(error) = submit(&scores_url, &secret, &submission) {
+                    warn!(
+                        "Error while submitting the score of '{}' to the leaderboard: {}",
+                        submission.player, error
+                    );
+                }
+            }
+        });
+
+        Self {
+            url: Some(url.to_string()),
+            submissions: Some(sender),
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn submit(&self, submission: ScoreSubmission) {
+        if let Some(submissions) = &self.submissions {
+            if submissions.send(submission).is_err() {
+                warn!("Leaderboard thread stopped, the score was not submitted");
+            }
+        }
+    }
+}
+
+fn submit(scores_url: &str, secret: &str, submission: &ScoreSubmission) -> Result<()> {
+    let body = serde_json::to_string(submission)?;
+    ureq::post(scores_url)
+        .timeout(REQUEST_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .set(SIGNATURE_HEADER, &sign(secret, &body))
+        .send_string(&body)?;
+
+    Ok(())
+}
+
+fn fetch_top_scores(url: &str, map_hash: &str) -> Result<Vec<GlobalScore>> {
+    Ok(ureq::get(&format!("{}/scores", url))
+        .timeout(REQUEST_TIMEOUT)
+        .query("map_hash", map_hash)
+        .query("limit", &LEADERBOARD_SIZE.to_string())
+        .call()?
+        .into_json()?)
+}
+
+/// Submits the scores of the cleared beatmaps (except the warmup, adaptive and unranked plays)
+pub fn submit_scores(
+    leaderboard: Res<GlobalLeaderboard>,
+    osu: Res<Osu>,
+    player_names: Query<&PlayerName, Without<Afk>>,
+    mut submitted: Local<bool>,
+) {
+    let Some(beatmap) = osu.finished_beatmap() else {
+        *submitted = false;
+        return;
+    };
+    if *submitted || leaderboard.submissions.is_none() {
+        return;
+    }
+    *submitted = true;
+
+    if osu.is_warmup()
+        || beatmap.state.adaptive.is_some()
+        || beatmap.data.mods.is_unranked()
+        || beatmap.data.file_hash.is_empty()
+    {
+        return;
+    }
+    let player = player_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    leaderboard.submit(ScoreSubmission::new(beatmap, player));
+}
+
+pub fn leaderboard_command() -> CommandSpec {
+    CommandSpec::new("leaderboard")
+}
+
+/// Handles `/leaderboard`, which fetches the global top scores of the current beatmap. They are shown once they arrive.
+pub fn execute_leaderboard_commands(
+    osu: Res<Osu>,
+    mut leaderboard: ResMut<GlobalLeaderboard>,
+    mut operations: ResMut<LongOperations>,
+    mut clients: Query<&mut Client>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "leaderboard" {
+            continue;
+        }
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+
+        let request = match (&leaderboard.url, osu.current_beatmap()) {
+            (None, _) => Err(anyhow!(
+                "No global leaderboard is configured on this server"
+            )),
+            (_, None) => Err(anyhow!("No beatmap is being played")),
+            (Some(url), Some(beatmap)) => Ok((url.clone(), beatmap)),
+        };
+        let (url, beatmap) = match request {
+            Ok(request) => request,
+            Err(error) => {
+                client.send_message(error_message(&error));
+                continue;
+            }
+        };
+
+        let beatmap_name = format!("{} [{}]", beatmap.data.title, beatmap.data.difficulty_name);
+        let map_hash = beatmap.data.file_hash.clone();
+        let operation = LongOperation::start(
+            format!("Fetching the global leaderboard of {}", beatmap_name),
+            Some(command_event.client),
+            &mut operations,
+            move |_| fetch_top_scores(&url, &map_hash),
+        );
+        leaderboard
+            .requests
+            .push((command_event.client, beatmap_name, operation));
+    }
+}
+
+/// Shows the fetched leaderboards to the players who asked for them
+pub fn update_leaderboard_requests(
+    mut leaderboard: ResMut<GlobalLeaderboard>,
+    mut clients: Query<&mut Client>,
+) {
+    leaderboard
+        .requests
+        .retain_mut(|(client_entity, beatmap_name, operation)| {
+            let Some(result) = operation.try_finish() else {
+                return true;
+            };
+            let Ok(mut client) = clients.get_mut(*client_entity) else {
+                return false;
+            };
+
+            match result {
+                Ok(scores) => {
+                    for line in leaderboard_lines(beatmap_name, &scores) {
+                        client.send_message(line);
+                    }
+                }
+                Err(error) => {
+                    warn!("Error while fetching the global leaderboard: {}", error);
+                    client.send_message(error_message(&anyhow!(
+                        "Could not fetch the global leaderboard: {}",
+                        error
+                    )));
+                }
+            }
+
+            false
+        });
+}
+
+fn leaderboard_lines(beatmap_name: &str, scores: &[GlobalScore]) -> Vec<Text> {
+    let mut lines = vec![
+        "Global leaderboard of ".color(Color::GOLD) + beatmap_name.to_string().color(Color::AQUA),
+    ];
+    if scores.is_empty() {
+        lines.push("No scores yet".color(Color::GRAY));
+    }
+
+    for (idx, score) in scores.iter().take(LEADERBOARD_SIZE).enumerate() {
+        lines.push(
+            format!("#{} ", idx + 1).color(Color::YELLOW)
+                + score.player.clone().color(Color::WHITE)
+                + format!("  {}", score.score).color(Color::GOLD)
+                + format!("  {:.2}%", score.accuracy).color(Color::GREEN)
+                + format!("  x{}", score.max_combo).color(Color::LIGHT_PURPLE)
+                + format!("  {}", score.mods).color(Color::GRAY),
+        );
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn submission_signature() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn global_scores_format() {
+        let scores: Vec<GlobalScore> = serde_json::from_str(
+            r#"[{ "player": "peppy", "score": 1000, "accuracy": 99.5, "max_combo": 50, "mods": "+HR" },
+                { "player": "cookiezi", "score": 900, "accuracy": 98.0, "max_combo": 45 }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(scores[1].mods, "");
+        assert_eq!(leaderboard_lines("Title [Insane]", &scores).len(), 3);
+        assert_eq!(leaderboard_lines("Title [Insane]", &[]).len(), 2);
+    }
+}
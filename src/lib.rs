@@ -1,19 +1,87 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::type_complexity)]
 
+pub mod adaptive;
+pub mod admin;
+pub mod afk;
+pub mod aim_assist;
+pub mod api;
+pub mod arena;
 pub mod audio;
+pub mod audio_clock;
+pub mod background_dim;
+pub mod beat_pulse;
 pub mod beatmap;
+pub mod beatmap_browser;
 pub mod beatmap_selection;
+pub mod block_batch;
+pub mod bundle_report;
+pub mod catch;
+pub mod collections;
 pub mod color;
+pub mod combo;
+pub mod command_registry;
 pub mod commands;
+pub mod commentary;
 pub mod configs;
+pub mod countdown;
+pub mod credits_screen;
+pub mod crosshair;
+pub mod cursor_trail;
+pub mod daily;
 pub mod digit;
+pub mod error;
+pub mod fail_screen;
+pub mod filter_query;
+pub mod force_play;
+pub mod game_mode;
 pub mod hit_object;
 pub mod hit_score;
 pub mod hitcircle;
+pub mod hitsound;
+pub mod hud;
+pub mod hype;
+pub mod input;
 pub mod inventory;
+pub mod key_overlay;
+pub mod lag;
+pub mod latency_test;
+pub mod leaderboard;
+pub mod lobby;
+pub mod local_leaderboard;
+pub mod mania;
+pub mod map_info;
+pub mod map_vote;
+pub mod marathon;
 pub mod minecraft;
+pub mod mods;
+pub mod now_playing;
 pub mod osu;
+pub mod osu_file;
+pub mod player_list;
+pub mod player_name;
+pub mod playfield;
 pub mod plugin;
+pub mod prelude;
+pub mod preprocess;
+pub mod profile;
+pub mod progress;
+pub mod progress_bar;
+pub mod replays;
+pub mod resets;
+pub mod resync;
 pub mod ring;
+pub mod score_screen;
+pub mod scoreboard;
+pub mod scores;
+pub mod session;
+pub mod skin;
 pub mod song_selection;
+pub mod sound_effects;
+pub mod star_rating;
+pub mod storage;
+#[cfg(test)]
+pub mod test_support;
+pub mod timing;
+pub mod volume;
+pub mod waveform;
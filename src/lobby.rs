@@ -0,0 +1,545 @@
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashSet, path::PathBuf};
+use tracing::error;
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::With,
+    system::{Commands, Local, Query, Res, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, Color, GameMode, Server},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    afk::Afk,
+    command_registry::{CommandSpec, OsuCommand},
+    configs::Configs,
+    error::error_message,
+    osu::{Osu, OsuStateChange},
+    player_name::PlayerName,
+};
+
+/// Ticks between two refreshes of the lobby status in the action bar (it fades after a few seconds)
+const ACTION_BAR_REFRESH_TICKS: usize = 20;
+
+/// Group of players taking turns to pick the map. The host picks a difficulty in the beatmap selection,
+/// starts it with `/lobby start` once every member is ready and the next member becomes the host when the map ends.
+/// Members who are AFK don't hold the start back, and `/lobby start force` starts the map without waiting for anyone.
+#[derive(Resource, Default)]
+pub struct Lobby {
+    /// Members in join order. The first one is the host.
+    members: Vec<Entity>,
+    ready: HashSet<Entity>,
+    /// Members in spectator mode for being idle (see `Afk`), who are left out of the ready check
+    away: HashSet<Entity>,
+    /// Ticks since the host picked the map, used to move the members who don't get ready to spectator mode
+    waiting_ticks: usize,
+    /// Beatmap picked by the host and its display name
+    picked: Option<(PathBuf, String)>,
+    /// Whether the map being played was started by the lobby, so the host is rotated when it ends
+    playing: bool,
+}
+
+/// Arguments of the `/lobby` command
+#[derive(Debug, PartialEq, Eq)]
+pub enum LobbyCommand {
+    Create,
+    Join,
+    Leave,
+    Ready,
+    Start,
+    ForceStart,
+}
+
+impl LobbyCommand {
+    pub fn parse(args: &str) -> Result<Self> {
+        Ok(match args.trim() {
+            "create" => Self::Create,
+            "join" => Self::Join,
+            "leave" => Self::Leave,
+            "ready" => Self::Ready,
+            "start" => Self::Start,
+            "start force" => Self::ForceStart,
+            action => bail!(
+                "unknown action '{}' (expected create, join, leave, ready, start or start force)",
+                action
+            ),
+        })
+    }
+}
+
+impl Lobby {
+    pub fn is_active(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    pub fn host(&self) -> Option<Entity> {
+        self.members.first().copied()
+    }
+
+    pub fn is_member(&self, client: Entity) -> bool {
+        self.members.contains(&client)
+    }
+
+    /// The host doesn't need to be ready, starting the map is their ready check
+    pub fn is_ready(&self, client: Entity) -> bool {
+        self.host() == Some(client) || self.ready.contains(&client)
+    }
+
+    /// Members taking part in the ready check
+    fn present_members(&self) -> impl Iterator<Item = &Entity> {
+        self.members
+            .iter()
+            .filter(|member| !self.away.contains(member))
+    }
+
+    pub fn present_count(&self) -> usize {
+        self.present_members().count()
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.present_members()
+            .filter(|member| self.is_ready(**member))
+            .count()
+    }
+
+    /// Present members who aren't ready yet
+    pub fn unready_members(&self) -> Vec<Entity> {
+        self.present_members()
+            .copied()
+            .filter(|member| !self.is_ready(*member))
+            .collect()
+    }
+
+    /// Replaces the members who are AFK
+    pub fn set_away(&mut self, away: impl IntoIterator<Item = Entity>) {
+        self.away = away
+            .into_iter()
+            .filter(|client| self.is_member(*client))
+            .collect();
+    }
+
+    pub fn picked_name(&self) -> Option<&str> {
+        self.picked.as_ref().map(|(_, name)| name.as_str())
+    }
+
+    pub fn create(&mut self, client: Entity) -> Result<()> {
+        if self.is_active() {
+            bail!("a lobby already exists (use /lobby join)");
+        }
+
+        self.members.push(client);
+        Ok(())
+    }
+
+    pub fn join(&mut self, client: Entity) -> Result<()> {
+        if !self.is_active() {
+            bail!("there is no lobby (use /lobby create)");
+        }
+        if self.is_member(client) {
+            bail!("you are already in the lobby");
+        }
+
+        self.members.push(client);
+        Ok(())
+    }
+
+    /// Removes the member, returning the new host if the host left. The lobby is closed when its last member leaves.
+    pub fn leave(&mut self, client: Entity) -> Result<Option<Entity>> {
+        let idx = self
+            .members
+            .iter()
+            .position(|member| *member == client)
+            .ok_or_else(|| anyhow!("you are not in the lobby"))?;
+
+        self.members.remove(idx);
+        self.ready.remove(&client);
+        self.away.remove(&client);
+        if self.members.is_empty() {
+            *self = Self::default();
+            return Ok(None);
+        }
+
+        // The new host picks the next map
+        if idx == 0 {
+            self.picked = None;
+            return Ok(self.host());
+        }
+
+        Ok(None)
+    }
+
+    /// Toggles the ready state of the member, returning whether they are ready now
+    pub fn toggle_ready(&mut self, client: Entity) -> Result<bool> {
+        if !self.is_member(client) {
+            bail!("you are not in the lobby");
+        }
+        if self.host() == Some(client) {
+            bail!("the host starts the map with /lobby start");
+        }
+
+        let ready = !self.ready.remove(&client);
+        if ready {
+            self.ready.insert(client);
+        }
+
+        Ok(ready)
+    }
+
+    pub fn pick(&mut self, client: Entity, beatmap_path: PathBuf, name: String) -> Result<()> {
+        if self.host() != Some(client) {
+            bail!("only the lobby host can pick the map");
+        }
+
+        self.picked = Some((beatmap_path, name));
+        self.waiting_ticks = 0;
+        Ok(())
+    }
+
+    /// Returns the picked map if every present member is ready (or if `force`d by the host). Call `mark_started` once it is actually playing.
+    pub fn start(&self, client: Entity, force: bool) -> Result<PathBuf> {
+        if self.host() != Some(client) {
+            bail!("only the lobby host can start the map");
+        }
+        let Some((beatmap_path, _)) = self.picked.clone() else {
+            bail!("pick a difficulty in the beatmap selection first");
+        };
+        let waiting = self.unready_members().len();
+        if waiting > 0 && !force {
+            bail!(
+                "waiting for {} player(s) to be ready (use /lobby start force to start anyway)",
+                waiting
+            );
+        }
+
+        Ok(beatmap_path)
+    }
+
+    /// Marks the picked map as being played, so the host is rotated when it ends
+    pub fn mark_started(&mut self) {
+        self.playing = true;
+    }
+
+    /// Passes the host to the next member and clears the ready checks for the next map
+    pub fn rotate_host(&mut self) -> Option<Entity> {
+        self.members.rotate_left(1);
+        self.reset_round();
+
+        self.host()
+    }
+
+    /// Clears the picked map and the ready checks, keeping the members and the host
+    pub fn reset_round(&mut self) {
+        self.ready.clear();
+        self.picked = None;
+        self.waiting_ticks = 0;
+        self.playing = false;
+    }
+}
+
+pub fn lobby_command() -> CommandSpec {
+    CommandSpec::new("lobby")
+        .subcommands(["create", "join", "leave", "ready", "start", "start force"])
+        .required()
+}
+
+/// Handles `/lobby create|join|leave|ready|start|start force`
+pub fn execute_lobby_commands(
+    mut lobby: ResMut<Lobby>,
+    mut osu: ResMut<Osu>,
+    configs: Res<Configs>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "lobby" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let client = command_event.client;
+        let username = username_of(client, &clients);
+        let is_operator = player_names
+            .get(client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        // Message sent to every player after the command result
+        let mut announcement: Option<Text> = None;
+
+        let result = match LobbyCommand::parse(args) {
+            Ok(LobbyCommand::Create) => lobby.create(client).map(|_| {
+                announcement = Some(
+                    username.clone().color(Color::AQUA)
+                        + " created a lobby, use ".color(Color::YELLOW)
+                        + "/lobby join".color(Color::GREEN)
+                        + " to take turns picking maps".color(Color::YELLOW),
+                );
+                "Lobby created, ".color(Color::YELLOW)
+                    + "you are the host".color(Color::GREEN)
+                    + " (pick a difficulty in the beatmap selection)".color(Color::GRAY)
+            }),
+            Ok(LobbyCommand::Join) => lobby.join(client).map(|_| {
+                announcement = Some(
+                    username.clone().color(Color::AQUA) + " joined the lobby".color(Color::YELLOW),
+                );
+                "Joined the lobby, ".color(Color::YELLOW)
+                    + "use /lobby ready once the host picks a map".color(Color::GRAY)
+            }),
+            Ok(LobbyCommand::Leave) => lobby.leave(client).map(|new_host| {
+                let mut text =
+                    username.clone().color(Color::AQUA) + " left the lobby".color(Color::YELLOW);
+                if let Some(new_host) = new_host {
+                    text = text + host_message(&username_of(new_host, &clients));
+                }
+                announcement = Some(text);
+                "Left the lobby".color(Color::YELLOW)
+            }),
+            Ok(LobbyCommand::Ready) => lobby.toggle_ready(client).map(|ready| {
+                let status = if ready {
+                    " is ready".color(Color::GREEN)
+                } else {
+                    " is not ready".color(Color::RED)
+                };
+                announcement = Some(
+                    username.clone().color(Color::AQUA)
+                        + status
+                        + format!(" ({}/{})", lobby.ready_count(), lobby.present_count())
+                            .color(Color::GRAY),
+                );
+                "Ready state ".color(Color::YELLOW) + "changed".color(Color::GREEN)
+            }),
+            Ok(LobbyCommand::Start | LobbyCommand::ForceStart) if !osu.is_selecting_beatmap() => {
+                Err(anyhow!(
+                    "The map can only be started while selecting a beatmap"
+                ))
+            }
+            Ok(command @ (LobbyCommand::Start | LobbyCommand::ForceStart)) => lobby
+                .start(client, command == LobbyCommand::ForceStart)
+                .and_then(|beatmap_path| {
+                    osu.change_state_for(
+                        OsuStateChange::PrePlaying { beatmap_path },
+                        is_operator,
+                        &mut clients,
+                    )?;
+                    lobby.mark_started();
+                    Ok("Map ".color(Color::YELLOW) + "started".color(Color::GREEN))
+                }),
+            Err(error) => Err(error),
+        };
+
+        match (result, clients.get_mut(client)) {
+            (Ok(message), Ok(mut client)) => client.send_message(message),
+            (Err(error), Ok(mut client)) => client.send_message(error_message(&error)),
+            _ => (),
+        }
+
+        if let Some(announcement) = announcement {
+            for mut client in &mut clients {
+                client.send_message(announcement.clone());
+            }
+        }
+    }
+}
+
+/// Removes disconnected members, moves the members who don't get ready in time to spectator mode, rotates the host
+/// after each lobby map and shows the ready checks in the action bar
+pub fn update_lobby(
+    mut commands: Commands,
+    mut lobby: ResMut<Lobby>,
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    server: Res<Server>,
+    mut clients: Query<&mut Client>,
+    afk_clients: Query<Entity, With<Afk>>,
+    mut ticks: Local<usize>,
+) {
+    if !lobby.is_active() {
+        return;
+    }
+    lobby.set_away(afk_clients.iter());
+
+    let disconnected: Vec<_> = lobby
+        .members
+        .iter()
+        .copied()
+        .filter(|member| clients.get(*member).is_err())
+        .collect();
+    for member in disconnected {
+        match lobby.leave(member) {
+            Ok(Some(new_host)) => {
+                let message = "The lobby host disconnected".color(Color::YELLOW)
+                    + host_message(&username_of(new_host, &clients));
+                for mut client in &mut clients {
+                    client.send_message(message.clone());
+                }
+            }
+            Ok(None) => (),
+            Err(error) => error!(
+                "Error while removing disconnected lobby member: '{}'",
+                error
+            ),
+        }
+    }
+
+    let map_ended = osu.finished_beatmap().is_some() || osu.failed_beatmap().is_some();
+    if lobby.playing && (map_ended || osu.is_selecting_beatmap()) {
+        if let Some(new_host) = lobby.rotate_host() {
+            let message =
+                "Map over!".color(Color::YELLOW) + host_message(&username_of(new_host, &clients));
+            for mut client in &mut clients {
+                client.send_message(message.clone());
+            }
+        }
+    }
+
+    let waiting_for_ready = lobby.picked.is_some() && !lobby.playing && osu.is_selecting_beatmap();
+    if let Some(timeout) = configs.lobby_ready_timeout().filter(|_| waiting_for_ready) {
+        lobby.waiting_ticks += 1;
+        let timeout_ticks = timeout.as_secs() as usize * server.shared().tps() as usize;
+        // Only once per pick, the members who come back can still get ready or be left behind with `/lobby start force`
+        if lobby.waiting_ticks == timeout_ticks {
+            for member in lobby.unready_members() {
+                if let Ok(mut client) = clients.get_mut(member) {
+                    client.set_game_mode(GameMode::Spectator);
+                    client.send_message(
+                        "You were moved to spectator mode for not getting ready in time. Move to play again."
+                            .color(Color::GRAY),
+                    );
+                    commands.entity(member).insert(Afk);
+                }
+            }
+        }
+    }
+
+    // The action bar shows the hit judgements while playing
+    *ticks += 1;
+    if !osu.is_selecting_beatmap() || *ticks < ACTION_BAR_REFRESH_TICKS {
+        return;
+    }
+    *ticks = 0;
+
+    let host = lobby
+        .host()
+        .map(|host| username_of(host, &clients))
+        .unwrap_or_default();
+    let map = lobby
+        .picked_name()
+        .map(|name| name.to_string().color(Color::GREEN))
+        .unwrap_or_else(|| "waiting for the host".color(Color::GRAY));
+    let status = "Host: ".color(Color::YELLOW)
+        + host.color(Color::AQUA)
+        + "  Ready: ".color(Color::YELLOW)
+        + format!("{}/{}", lobby.ready_count(), lobby.present_count()).color(Color::WHITE)
+        + "  Map: ".color(Color::YELLOW)
+        + map;
+
+    for member in lobby.members.iter() {
+        if let Ok(mut client) = clients.get_mut(*member) {
+            let ready = if lobby.is_ready(*member) {
+                "  [READY]".color(Color::GREEN)
+            } else {
+                "  [/lobby ready]".color(Color::RED)
+            };
+            client.set_action_bar(status.clone() + ready);
+        }
+    }
+}
+
+fn host_message(host: &str) -> Text {
+    " ".color(Color::WHITE)
+        + host.to_string().color(Color::AQUA)
+        + " is the lobby host now".color(Color::YELLOW)
+}
+
+fn username_of(client: Entity, clients: &Query<&mut Client>) -> String {
+    clients
+        .get(client)
+        .map(|client| client.username().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn host_rotation() {
+        let mut lobby = Lobby::default();
+        let (a, b, c) = (
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+
+        assert!(lobby.join(a).is_err());
+        lobby.create(a).unwrap();
+        lobby.join(b).unwrap();
+        lobby.join(c).unwrap();
+        assert!(lobby.join(b).is_err());
+
+        assert!(lobby
+            .pick(b, PathBuf::from("map.osu"), "Map".to_string())
+            .is_err());
+        assert!(lobby.start(a, false).is_err());
+        lobby
+            .pick(a, PathBuf::from("map.osu"), "Map".to_string())
+            .unwrap();
+        assert!(lobby.start(a, false).is_err());
+
+        assert!(lobby.toggle_ready(b).unwrap());
+        assert!(lobby.toggle_ready(c).unwrap());
+        assert!(!lobby.toggle_ready(c).unwrap());
+        assert_eq!(lobby.ready_count(), 2);
+        lobby.toggle_ready(c).unwrap();
+        assert!(lobby.toggle_ready(a).is_err());
+        assert_eq!(lobby.start(a, false).unwrap(), PathBuf::from("map.osu"));
+
+        assert_eq!(lobby.rotate_host(), Some(b));
+        assert_eq!(lobby.ready_count(), 1);
+        assert_eq!(lobby.picked_name(), None);
+
+        assert_eq!(lobby.leave(c).unwrap(), None);
+        assert_eq!(lobby.leave(b).unwrap(), Some(a));
+        assert_eq!(lobby.leave(a).unwrap(), None);
+        assert!(!lobby.is_active());
+    }
+
+    #[test]
+    fn away_and_forced_start() {
+        let mut lobby = Lobby::default();
+        let (a, b, c) = (
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+
+        lobby.create(a).unwrap();
+        lobby.join(b).unwrap();
+        lobby.join(c).unwrap();
+        lobby
+            .pick(a, PathBuf::from("map.osu"), "Map".to_string())
+            .unwrap();
+        lobby.toggle_ready(b).unwrap();
+        assert_eq!(lobby.unready_members(), vec![c]);
+        assert!(lobby.start(a, false).is_err());
+        assert!(lobby.start(b, true).is_err());
+        assert_eq!(lobby.start(a, true).unwrap(), PathBuf::from("map.osu"));
+
+        lobby.set_away([c, Entity::from_raw(4)]);
+        assert_eq!(lobby.present_count(), 2);
+        assert_eq!(lobby.ready_count(), 2);
+        assert_eq!(lobby.start(a, false).unwrap(), PathBuf::from("map.osu"));
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(LobbyCommand::parse(" start").unwrap(), LobbyCommand::Start);
+        assert_eq!(
+            LobbyCommand::parse("start force").unwrap(),
+            LobbyCommand::ForceStart
+        );
+        assert!(LobbyCommand::parse("").is_err());
+        assert!(LobbyCommand::parse("kick").is_err());
+    }
+}
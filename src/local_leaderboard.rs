@@ -0,0 +1,143 @@
+use bevy_ecs::system::{Local, Res, ResMut, Resource};
+use valence::prelude::{BlockPos, BlockState};
+
+use crate::{
+    now_playing::draw_lines,
+    osu::Osu,
+    playfield::PlayfieldSurface,
+    scores::{LocalScore, LocalScores},
+};
+
+/// Scores drawn beside the screen before a beatmap starts
+const LEADERBOARD_SIZE: usize = 5;
+
+/// Top local scores of the beatmap about to be played, taken when its pre playing state starts. The score screen compares
+/// the finished play with them, since the local scores already include it by then.
+#[derive(Resource, Default, Debug)]
+pub struct LocalLeaderboard {
+    /// See `LocalScores::beatmap_key`
+    beatmap_key: String,
+    top_scores: Vec<LocalScore>,
+}
+
+impl LocalLeaderboard {
+    pub fn new(beatmap_key: String, local_scores: &LocalScores) -> Self {
+        let top_scores = local_scores.top(&beatmap_key, LEADERBOARD_SIZE).to_vec();
+
+        Self {
+            beatmap_key,
+            top_scores,
+        }
+    }
+
+    pub fn top_scores(&self) -> &[LocalScore] {
+        &self.top_scores
+    }
+
+    /// Ranks (starting at 1) of the leaderboard shown before the beatmap `score` beats
+    pub fn beaten_ranks(&self, beatmap_key: &str, score: usize) -> Vec<usize> {
+        if beatmap_key != self.beatmap_key {
+            return Vec::new();
+        }
+
+        self.top_scores
+            .iter()
+            .enumerate()
+            .filter(|(_, local_score)| local_score.score < score)
+            .map(|(idx, _)| idx + 1)
+            .collect()
+    }
+}
+
+/// Draws the top local scores of the beatmap in the left margin of the screen (from the player's perspective) before it starts
+pub fn update_local_leaderboard(
+    osu: Res<Osu>,
+    mut leaderboard: ResMut<LocalLeaderboard>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut drawn_positions: Local<Vec<BlockPos>>,
+) {
+    match osu.pre_playing_beatmap() {
+        Some(beatmap) if drawn_positions.is_empty() => {
+            *leaderboard =
+                LocalLeaderboard::new(LocalScores::beatmap_key(beatmap), osu.local_scores());
+
+            let lines = leaderboard_lines(leaderboard.top_scores());
+            let lines: Vec<_> = lines
+                .iter()
+                .map(|(text, block)| (text.as_str(), *block))
+                .collect();
+
+            draw_lines(
+                &lines,
+                osu.playfield().left_margin_area(),
+                &mut surface,
+                &mut drawn_positions,
+            );
+        }
+        None if !drawn_positions.is_empty() => {
+            surface.fill(drawn_positions.drain(..), BlockState::AIR);
+        }
+        _ => {}
+    }
+}
+
+/// Lines of the leaderboard drawn on the playfield, with the block they are drawn with
+fn leaderboard_lines(top_scores: &[LocalScore]) -> Vec<(String, BlockState)> {
+    let mut lines = vec![("Local top".to_string(), BlockState::YELLOW_CONCRETE)];
+    if top_scores.is_empty() {
+        lines.push(("No scores yet".to_string(), BlockState::GRAY_CONCRETE));
+    }
+
+    for (idx, local_score) in top_scores.iter().enumerate() {
+        let block = if idx == 0 {
+            BlockState::GOLD_BLOCK
+        } else {
+            BlockState::WHITE_CONCRETE
+        };
+        lines.push((
+            format!("{}. {} {}", idx + 1, local_score.player, local_score.score),
+            block,
+        ));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aim_assist::NO_AIM_ASSIST;
+
+    fn local_score(player: &str, score: usize) -> LocalScore {
+        LocalScore {
+            player: player.to_string(),
+            score,
+            accuracy: 100.0,
+            max_combo: 0,
+            aim_assist: NO_AIM_ASSIST,
+            unranked: false,
+            pp: 0.0,
+        }
+    }
+
+    #[test]
+    fn beaten_local_scores() {
+        let key = "artist - title [difficulty]";
+        let mut local_scores = LocalScores::default();
+        for (player, score) in [("a", 500), ("b", 300), ("c", 200), ("d", 100)] {
+            local_scores.add(key.to_string(), local_score(player, score));
+        }
+
+        let leaderboard = LocalLeaderboard::new(key.to_string(), &local_scores);
+        assert_eq!(leaderboard.top_scores().len(), 4);
+        assert_eq!(leaderboard.beaten_ranks(key, 250), vec![3, 4]);
+        assert_eq!(leaderboard.beaten_ranks(key, 1000), vec![1, 2, 3, 4]);
+        assert!(leaderboard.beaten_ranks(key, 50).is_empty());
+        assert!(leaderboard.beaten_ranks("other beatmap", 1000).is_empty());
+
+        let lines = leaderboard_lines(leaderboard.top_scores());
+        assert_eq!(lines[1].0, "1. a 500");
+        assert_eq!(lines.len(), 5);
+        assert_eq!(leaderboard_lines(&[]).len(), 2);
+    }
+}
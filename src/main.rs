@@ -1,13 +1,12 @@
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use colored::Colorize;
-use osucraft::audio::AudioPlayer;
-
-use osucraft::configs::Configs;
-use osucraft::osu::{Osu, OsuInstance};
-use osucraft::plugin::OsuPlugin;
-use rodio::OutputStream;
-use tracing::Level;
+use osucraft::bundle_report::{install_crash_reporter, open_log_file};
+use osucraft::minecraft::MINECRAFT_VERSION;
+use osucraft::prelude::*;
+use tracing::{error, warn, Level};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
 use valence::client::despawn_disconnected_clients;
 use valence::client::event::default_event_handler;
 use valence::prelude::*;
@@ -22,26 +21,74 @@ pub fn main() {
         Level::WARN
     };
 
-    tracing_subscriber::fmt().with_max_level(log_level).init();
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let audio_player = AudioPlayer::new(&stream_handle).unwrap();
+    match open_log_file() {
+        Ok(log_file) => tracing_subscriber::fmt()
+            .with_max_level(log_level)
+            .with_writer(std::io::stdout.and(Mutex::new(log_file)))
+            .init(),
+        Err(error) => {
+            tracing_subscriber::fmt().with_max_level(log_level).init();
+            warn!("Error while opening log file: {}", error);
+        }
+    }
+    install_crash_reporter();
+
+    let configs = Configs::open();
+    // The stream must be kept alive while the audio plays
+    let (_stream, audio_player) = open_audio_player(configs.disable_audio());
+    audio_player.set_volume(configs.volume());
+    let storage = open_storage(configs.storage());
+    let block_skin = BlockSkin::open(&configs);
 
     App::new()
         .add_plugin(ServerPlugin::new(()).with_connection_mode(ConnectionMode::Offline))
-        .add_plugin(OsuPlugin)
+        .add_plugin(match configs.game_mode() {
+            GameModeKind::Standard => OsuPlugin::default(),
+            GameModeKind::Mania => {
+                OsuPlugin::with_game_mode(ManiaMode::new(configs.mania_lane_slots()))
+            }
+            GameModeKind::Catch => OsuPlugin::with_game_mode(CatchMode::default()),
+        })
         .add_system_to_stage(EventLoop, default_event_handler)
         .add_system_set(PlayerList::default_system_set())
         .add_startup_system(setup)
         .add_system(init_clients)
         .add_system(despawn_disconnected_clients)
         .add_system(reposition_clients)
-        .insert_resource(Osu::new(0.3, audio_player))
+        .insert_resource(
+            Osu::new(0.3, audio_player, storage)
+                .with_hitsounds(Hitsounds::new(configs.skin()))
+                .with_sound_effects(SoundEffects::load(configs.sounds_directory()))
+                .with_screen_background(block_skin.screen_background)
+                .with_preprocessor(Preprocessor::default().with_slider_ends(configs.slider_ends())),
+        )
+        .insert_resource(block_skin)
+        .insert_resource(Commentary::new(configs.webhook_url()))
+        .insert_resource(GlobalLeaderboard::new(
+            configs.leaderboard_url(),
+            configs.leaderboard_secret(),
+        ))
+        .insert_resource(Api::start(configs.api_bind_address(), configs.api_port()))
+        .insert_resource(configs)
+        .insert_resource(SessionStats::recover())
+        .insert_resource(Collections::open())
+        .insert_resource(DailyChallenge::open())
         .run();
 }
 
+fn open_storage(storage_kind: StorageKind) -> Box<dyn Storage> {
+    storage_kind.open().unwrap_or_else(|error| {
+        error!(
+            "Error while opening {:?} storage, falling back to JSON storage: {}",
+            storage_kind, error
+        );
+        StorageKind::Json.open().unwrap()
+    })
+}
+
 fn setup(world: &mut World) {
-    // Init configs
-    let configs = Configs::open();
+    // Print configs
+    let configs = world.resource::<Configs>();
     let configs_path = Configs::path();
     let header = format!(
         "================= CONFIGS ({}) =================",
@@ -54,17 +101,22 @@ fn setup(world: &mut World) {
         configs_path.display()
     );
     println!("{}", info.yellow());
-    println!(
-        "{}",
-        "INFO: The server is running on minecraft version 1.19.3\n".yellow()
-    );
+    if !configs.has_operators() {
+        let operators_info = "INFO: Nobody can run the operator commands, add your username to 'operators' in the configs to run them.";
+        println!("{}", operators_info.yellow());
+    }
+    let version_info =
+        format!("INFO: The server is running on minecraft version {MINECRAFT_VERSION}\n");
+    println!("{}", version_info.yellow());
+
+    let songs_directory = PathBuf::from(configs.songs_directory());
 
     let server = world.resource::<Server>();
     let mut instance = server.new_instance(DimensionId::default());
 
     // Init osu
     world.resource::<Osu>().init(&mut instance);
-    Osu::init_inventory_selections(world, PathBuf::from(configs.songs_directory()));
+    Osu::init_inventory_selections(world, songs_directory);
 
     world.spawn((instance, OsuInstance));
 
@@ -77,7 +129,7 @@ fn init_clients(
     osu: Res<Osu>,
 ) {
     let instance = instances.single();
-    let spawn_pos = osu.player_spawn_pos();
+    let spawn_pos = osu.playfield().player_spawn_pos();
 
     for mut client in &mut clients {
         client.set_position(spawn_pos);
@@ -89,7 +141,7 @@ fn init_clients(
 fn reposition_clients(osu: Res<Osu>, mut clients: Query<&mut Client>) {
     for mut client in &mut clients {
         if client.position().y < 0.0 {
-            client.set_position(osu.player_spawn_pos());
+            client.set_position(osu.playfield().player_spawn_pos());
         }
     }
 }
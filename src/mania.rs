@@ -0,0 +1,468 @@
+use anyhow::Result;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use bevy_ecs::prelude::Entity;
+use valence::prelude::{BlockPos, BlockState, Client};
+
+use crate::{
+    beatmap::{Beatmap, CircleSize},
+    block_batch::BlockBatch,
+    game_mode::{GameModeContext, GameModeHandler, GameModeHit, GameModeInput},
+    hit_object::{HitObject, HitObjectParams},
+    hit_score::HitScore,
+    osu::Hitwindow,
+};
+
+const MIN_KEYS: usize = 4;
+pub const MAX_KEYS: usize = 7;
+/// Hotbar slots of the lanes when they aren't set in the configs (the number keys from 1 to 7)
+pub const DEFAULT_LANE_SLOTS: [u8; MAX_KEYS] = [0, 1, 2, 3, 4, 5, 6];
+/// osu!pixels of the playfield width, split evenly between the lanes
+const PLAYFIELD_WIDTH: u32 = 512;
+/// Rows of a tap note, relative to the screen height
+const NOTE_HEIGHT_RATIO: f64 = 1.0 / 40.0;
+
+/// osu!mania: the hit objects fall in 4 to 7 vertical lanes (the CS of the beatmap, like in osu!) towards a judgement line
+/// at the bottom of the screen. A lane is pressed by selecting its hotbar slot (the number keys, which can be rebound in the
+/// Minecraft controls) and released by selecting a slot of another lane or of no lane. Clicking presses the selected lane
+/// again, since selecting the same slot twice doesn't reach the server.
+///
+/// Long notes (`OsuManiaHold`) are held from their start to their end: they are scored by the worst of the press and the release.
+pub struct ManiaMode {
+    /// Hotbar slot (0 to 8) pressing each lane, from left to right
+    lane_slots: Vec<u8>,
+    keys: usize,
+    notes: Vec<ManiaNote>,
+    /// Lane pressed by each player
+    pressed_lanes: HashMap<Entity, usize>,
+    /// Blocks drawn in the previous tick
+    drawn: Vec<BlockPos>,
+}
+
+struct ManiaNote {
+    entity: Entity,
+    lane: usize,
+    time: u32,
+    /// Same as `time` for tap notes
+    end_time: u32,
+    is_long: bool,
+    /// Player holding a long note, with the score and timing error of the press
+    held: Option<(Entity, HitScore, i32)>,
+    /// Expired notes keep falling until they pass the judgement line
+    expired: bool,
+}
+
+impl ManiaMode {
+    pub fn new(lane_slots: Vec<u8>) -> Self {
+        Self {
+            lane_slots,
+            keys: MIN_KEYS,
+            notes: Vec::new(),
+            pressed_lanes: HashMap::new(),
+            drawn: Vec::new(),
+        }
+    }
+
+    fn slot_lane(&self, slot: u8) -> Option<usize> {
+        self.lane_slots
+            .iter()
+            .take(self.keys)
+            .position(|&lane_slot| lane_slot == slot)
+    }
+
+    /// Judges the oldest note of `lane` which wasn't judged yet. Long notes are only judged once released.
+    fn press(
+        &mut self,
+        lane: usize,
+        player: Entity,
+        time_ms: i64,
+        hitwindow: &Hitwindow,
+    ) -> Option<GameModeHit> {
+        let note = self
+            .notes
+            .iter_mut()
+            .find(|note| note.lane == lane && note.held.is_none() && !note.expired)?;
+        let hit_error_ms = (time_ms - note.time as i64) as i32;
+        let hit = timing_score(hitwindow, hit_error_ms)?;
+
+        if note.is_long {
+            note.held = Some((player, hit, hit_error_ms));
+            None
+        } else {
+            Some(GameModeHit {
+                entity: note.entity,
+                player,
+                hit,
+                hit_error_ms: Some(hit_error_ms),
+            })
+        }
+    }
+
+    /// Judges the long note of `lane` held by `player`, if any
+    fn release(
+        &mut self,
+        lane: usize,
+        player: Entity,
+        time_ms: i64,
+        hitwindow: &Hitwindow,
+    ) -> Option<GameModeHit> {
+        let note = self.notes.iter_mut().find(|note| {
+            note.lane == lane && matches!(note.held, Some((holder, _, _)) if holder == player)
+        })?;
+        let (_, press, hit_error_ms) = note.held.take()?;
+        // Released too early
+        let release = timing_score(hitwindow, (time_ms - note.end_time as i64) as i32)
+            .unwrap_or(HitScore::Miss);
+
+        Some(GameModeHit {
+            entity: note.entity,
+            player,
+            hit: worst(press, release),
+            hit_error_ms: Some(hit_error_ms),
+        })
+    }
+
+    /// Draws the lanes, notes and judgement line
+    fn draw(&mut self, play_ms: i64, look_ahead_ms: i64, context: &mut GameModeContext) {
+        let layout = LaneLayout::new(
+            context.playfield.screen_size(),
+            context.playfield.screen_margin(),
+            self.keys,
+        );
+        let origin = context.playfield.origin();
+        // Blocks of the previous tick which aren't drawn again are cleared
+        let mut batch = BlockBatch::new();
+        batch.fill(self.drawn.drain(..), BlockState::AIR);
+        let mut frame = Vec::new();
+        let mut draw_rows = |lane: usize, rows: (i32, i32), block: BlockState| {
+            let (bottom, top) = (
+                rows.0.max(layout.line_y),
+                rows.1.min(layout.line_y + layout.height),
+            );
+            for y in bottom..=top {
+                for x in layout.lane_xs(lane) {
+                    frame.push((
+                        BlockPos {
+                            x: origin.x + x,
+                            y: origin.y + y,
+                            z: origin.z,
+                        },
+                        block,
+                    ));
+                }
+            }
+        };
+
+        for lane in 0..self.keys {
+            let pressed = self.pressed_lanes.values().any(|&pressed| pressed == lane);
+            let line_block = if pressed {
+                BlockState::LIME_CONCRETE
+            } else {
+                BlockState::RED_CONCRETE
+            };
+            draw_rows(lane, (layout.line_y, layout.line_y), line_block);
+        }
+
+        let note_rows = layout.note_rows();
+        for note in &self.notes {
+            let block = if note.expired {
+                BlockState::GRAY_CONCRETE
+            } else {
+                lane_block(note.lane, self.keys)
+            };
+            // The held part of long notes disappears in the judgement line
+            let start_ms = if note.held.is_some() {
+                play_ms.max(note.time as i64)
+            } else {
+                note.time as i64
+            };
+            let bottom = layout.note_y(start_ms, play_ms, look_ahead_ms) + 1;
+            let top = if note.is_long {
+                layout.note_y(note.end_time as i64, play_ms, look_ahead_ms)
+            } else {
+                bottom + note_rows - 1
+            };
+            draw_rows(note.lane, (bottom, top), block);
+        }
+
+        for (pos, block) in frame {
+            batch.set(pos, block);
+            self.drawn.push(pos);
+        }
+        batch.apply(context.instance.1);
+    }
+}
+
+impl Default for ManiaMode {
+    fn default() -> Self {
+        Self::new(DEFAULT_LANE_SLOTS.to_vec())
+    }
+}
+
+impl GameModeHandler for ManiaMode {
+    fn name(&self) -> &str {
+        "osu!mania"
+    }
+
+    fn start(&mut self, beatmap: &Beatmap) {
+        self.keys = keys(beatmap.data.cs).min(self.lane_slots.len().max(1));
+        self.notes.clear();
+        self.pressed_lanes.clear();
+        // The playfield was reset after the previous play
+        self.drawn.clear();
+    }
+
+    fn spawn_hit_object(
+        &mut self,
+        hit_object: &HitObject,
+        context: &mut GameModeContext,
+    ) -> Result<Entity> {
+        let entity = context.commands.spawn_empty().id();
+        self.notes.push(ManiaNote {
+            entity,
+            lane: lane(hit_object.x(), self.keys),
+            time: hit_object.time(),
+            end_time: hit_object.end_time().max(hit_object.time()),
+            is_long: matches!(hit_object.params(), HitObjectParams::OsuManiaHold),
+            held: None,
+            expired: false,
+        });
+
+        Ok(entity)
+    }
+
+    fn judge_input(
+        &mut self,
+        input: GameModeInput,
+        player: Entity,
+        _client: &Client,
+        input_offset_ms: i32,
+        _active_hit_objects: &VecDeque<Entity>,
+        context: &mut GameModeContext,
+    ) -> Vec<GameModeHit> {
+        let hitwindow: Hitwindow = context.beatmap.data.od.into();
+        let time_ms = context.beatmap.state.play_time.as_millis() as i64 - input_offset_ms as i64;
+        let mut hits = Vec::new();
+
+        let lane = match input {
+            GameModeInput::Click => self.pressed_lanes.get(&player).copied(),
+            GameModeInput::SelectSlot(slot) => {
+                let lane = self.slot_lane(slot);
+                let previous = match lane {
+                    Some(lane) => self.pressed_lanes.insert(player, lane),
+                    None => self.pressed_lanes.remove(&player),
+                };
+                if let Some(previous) = previous.filter(|&previous| Some(previous) != lane) {
+                    hits.extend(self.release(previous, player, time_ms, &hitwindow));
+                }
+                lane
+            }
+        };
+        if let Some(lane) = lane {
+            hits.extend(self.press(lane, player, time_ms, &hitwindow));
+        }
+
+        hits
+    }
+
+    fn update(&mut self, context: &mut GameModeContext) -> Vec<GameModeHit> {
+        let play_ms = context.beatmap.state.play_time.as_millis() as i64;
+        let look_ahead_ms = self.look_ahead(context.beatmap).as_millis().max(1) as i64;
+        let hitwindow: Hitwindow = context.beatmap.data.od.into();
+        let window_50 = hitwindow.window_50.as_millis() as i64;
+
+        // Long notes held until their end
+        let mut hits = Vec::new();
+        for note in &mut self.notes {
+            if note.end_time as i64 <= play_ms {
+                if let Some((player, hit, hit_error_ms)) = note.held.take() {
+                    hits.push(GameModeHit {
+                        entity: note.entity,
+                        player,
+                        hit,
+                        hit_error_ms: Some(hit_error_ms),
+                    });
+                }
+            }
+        }
+
+        // Notes which can't be pressed anymore are judged as misses by `is_expired`
+        for note in &mut self.notes {
+            note.expired |= note.held.is_none() && play_ms > note.time as i64 + window_50;
+        }
+        let mut passed = Vec::new();
+        self.notes.retain(|note| {
+            let visible = !note.expired || note.end_time as i64 + window_50 >= play_ms;
+            if !visible {
+                passed.push(note.entity);
+            }
+            visible
+        });
+        for entity in passed {
+            context.commands.entity(entity).despawn();
+        }
+
+        self.draw(play_ms, look_ahead_ms, context);
+
+        hits
+    }
+
+    fn is_expired(&self, entity: Entity, _play_time: Duration) -> bool {
+        self.notes
+            .iter()
+            .find(|note| note.entity == entity)
+            .map_or(true, |note| note.expired)
+    }
+
+    fn despawn_hit_object(
+        &mut self,
+        entity: Entity,
+        _hit: HitScore,
+        context: &mut GameModeContext,
+    ) {
+        self.notes.retain(|note| note.entity != entity);
+        context.commands.entity(entity).despawn();
+    }
+}
+
+/// Position of the lanes on the screen, relative to the playfield origin
+struct LaneLayout {
+    /// Left edge of the first lane (from the player's perspective, so it's the highest x)
+    left_x: i32,
+    lane_width: i32,
+    /// Row of the judgement line
+    line_y: i32,
+    /// Rows above the judgement line where notes fall
+    height: i32,
+}
+
+impl LaneLayout {
+    /// The lanes take half of the screen width, centered
+    fn new((screen_x, screen_y): (i32, i32), (_, margin_y): (i32, i32), keys: usize) -> Self {
+        let lane_width = (screen_x / (2 * keys as i32)).max(1);
+
+        Self {
+            left_x: screen_x / 2 + lane_width * keys as i32 / 2,
+            lane_width,
+            line_y: margin_y,
+            height: screen_y,
+        }
+    }
+
+    fn lane_xs(&self, lane: usize) -> impl Iterator<Item = i32> {
+        let right_x = self.left_x - (lane as i32 + 1) * self.lane_width;
+        right_x + 1..=right_x + self.lane_width
+    }
+
+    /// Row where a note of `time` is at `play_ms`: it reaches the judgement line on time, after falling from the top of the
+    /// screen for `look_ahead_ms`
+    fn note_y(&self, time: i64, play_ms: i64, look_ahead_ms: i64) -> i32 {
+        self.line_y + ((time - play_ms) * self.height as i64 / look_ahead_ms) as i32
+    }
+
+    fn note_rows(&self) -> i32 {
+        ((self.height as f64 * NOTE_HEIGHT_RATIO) as i32).max(1)
+    }
+}
+
+/// Lanes of the beatmap: its CS is the key count in osu!mania beatmaps (other beatmaps are converted with the same key count)
+fn keys(cs: CircleSize) -> usize {
+    (cs.0.round() as usize).clamp(MIN_KEYS, MAX_KEYS)
+}
+
+/// Lane of a hit object at `x` osu!pixels
+fn lane(x: u32, keys: usize) -> usize {
+    (x as usize * keys / PLAYFIELD_WIDTH as usize).min(keys - 1)
+}
+
+/// Score of a press `hit_error_ms` away from the note, or `None` if it's too early or too late to hit it
+fn timing_score(hitwindow: &Hitwindow, hit_error_ms: i32) -> Option<HitScore> {
+    let error = Duration::from_millis(hit_error_ms.unsigned_abs() as u64);
+    [
+        (hitwindow.window_300, HitScore::Hit300),
+        (hitwindow.window_100, HitScore::Hit100),
+        (hitwindow.window_50, HitScore::Hit50),
+    ]
+    .into_iter()
+    .find(|(window, _)| error <= *window)
+    .map(|(_, score)| score)
+}
+
+fn worst(a: HitScore, b: HitScore) -> HitScore {
+    if a.value() <= b.value() {
+        a
+    } else {
+        b
+    }
+}
+
+/// Colors of the lanes, symmetric like the default osu!mania skin
+fn lane_block(lane: usize, keys: usize) -> BlockState {
+    if keys % 2 == 1 && lane == keys / 2 {
+        BlockState::YELLOW_CONCRETE
+    } else if lane.min(keys - 1 - lane) % 2 == 0 {
+        BlockState::WHITE_CONCRETE
+    } else {
+        BlockState::LIGHT_BLUE_CONCRETE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::beatmap::OverallDifficulty;
+
+    #[test]
+    fn hit_object_lanes() {
+        let lanes: Vec<_> = [0, 127, 128, 300, 448, 512]
+            .iter()
+            .map(|&x| lane(x, 4))
+            .collect();
+        assert_eq!(lanes, vec![0, 0, 1, 2, 3, 3]);
+        assert_eq!(lane(256, 7), 3);
+        assert_eq!(keys(CircleSize(9.0)), MAX_KEYS);
+        assert_eq!(keys(CircleSize(2.0)), MIN_KEYS);
+    }
+
+    #[test]
+    fn notes_fall_to_judgement_line() {
+        let layout = LaneLayout::new((192, 144), (96, 72), 4);
+        assert_eq!(layout.note_y(1000, 1000, 500), layout.line_y);
+        assert_eq!(layout.note_y(1000, 500, 500), layout.line_y + layout.height);
+        assert_eq!(
+            layout.note_y(1000, 750, 500),
+            layout.line_y + layout.height / 2
+        );
+
+        // Lanes don't overlap and are ordered from the player's left
+        let first: Vec<_> = layout.lane_xs(0).collect();
+        let second: Vec<_> = layout.lane_xs(1).collect();
+        assert_eq!(first.len(), layout.lane_width as usize);
+        assert!(second.iter().all(|x| x < &first[0]));
+    }
+
+    #[test]
+    fn long_note_scores() {
+        let hitwindow: Hitwindow = OverallDifficulty(5.0).into();
+        assert!(matches!(
+            timing_score(&hitwindow, -20),
+            Some(HitScore::Hit300)
+        ));
+        assert!(matches!(
+            timing_score(&hitwindow, 120),
+            Some(HitScore::Hit50)
+        ));
+        assert!(timing_score(&hitwindow, -400).is_none());
+        assert!(matches!(
+            worst(HitScore::Hit300, HitScore::Hit50),
+            HitScore::Hit50
+        ));
+        assert!(matches!(
+            worst(HitScore::Miss, HitScore::Hit100),
+            HitScore::Miss
+        ));
+    }
+}
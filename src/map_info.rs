@@ -0,0 +1,97 @@
+use anyhow::anyhow;
+use bevy_ecs::{
+    prelude::EventReader,
+    system::{Query, Res},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    beatmap::Beatmap,
+    beatmap_selection::format_attribute,
+    command_registry::{CommandSpec, OsuCommand},
+    error::error_message,
+    osu::Osu,
+    star_rating::{star_rating, star_rating_color},
+};
+
+pub fn np_command() -> CommandSpec {
+    CommandSpec::new("np")
+}
+
+pub fn map_command() -> CommandSpec {
+    CommandSpec::new("map").subcommands(["info"]).required()
+}
+
+/// Handles `/np` and `/map info`, which show the beatmap being played (or about to start) to the player: its difficulty
+/// settings and star rating with the mods it's played with, its song folder and a link to its page in the osu! website.
+pub fn execute_map_info_commands(
+    osu: Res<Osu>,
+    mut clients: Query<&mut Client>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        let result = match (command_event.name, command_event.args.trim()) {
+            ("np", _) | ("map", "info") => osu
+                .current_beatmap()
+                .map(map_info)
+                .ok_or_else(|| anyhow!("No beatmap is being played")),
+            ("map", action) => Err(anyhow!("unknown action '{}' (expected info)", action)),
+            _ => continue,
+        };
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    client.send_message(line);
+                }
+            }
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+fn map_info(beatmap: &Beatmap) -> Vec<Text> {
+    let data = &beatmap.data;
+    let mods = data.mods;
+    let stars = star_rating(&data.hit_objects, mods.cs(data.cs));
+    let mods_text = if mods.is_empty() {
+        Text::default()
+    } else {
+        format!(" {}", mods).color(Color::LIGHT_PURPLE)
+    };
+
+    let song = "Now playing: ".color(Color::GOLD)
+        + format!("{} - {}", data.artist, data.title).color(Color::AQUA)
+        + format!(" [{}]", data.difficulty_name).color(Color::YELLOW)
+        + mods_text
+        + format!(" mapped by {}", data.creator).color(Color::GRAY);
+    let difficulty = format!(
+        "AR {}  OD {}  CS {}  HP {}  ",
+        format_attribute(mods.ar(data.ar).0),
+        format_attribute(mods.od(data.od).0),
+        format_attribute(mods.cs(data.cs).0),
+        format_attribute(mods.hp(data.hp).0)
+    )
+    .color(Color::GRAY)
+        + format!("{:.2}*", stars).color(star_rating_color(stars));
+    let folder = "Folder: ".color(Color::GRAY) + data.dir.display().to_string().color(Color::WHITE);
+    let link = match data.website_url() {
+        Some(url) => {
+            "osu! website: ".color(Color::GRAY)
+                + url
+                    .clone()
+                    .color(Color::BLUE)
+                    .underlined()
+                    .on_click_open_url(url)
+        }
+        None => "This beatmap was not submitted to the osu! website".color(Color::DARK_GRAY),
+    };
+
+    vec![song, difficulty, folder, link]
+}
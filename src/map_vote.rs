@@ -0,0 +1,387 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
+use tracing::error;
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Local, Query, Res, ResMut},
+};
+use valence::{
+    client::event::ClickContainer,
+    nbt::{compound, List},
+    prelude::{Client, Color, Inventory, InventoryKind, OpenInventory, Server},
+    protocol::{ItemKind, ItemStack, TextFormat},
+    Despawned,
+};
+
+use crate::{
+    beatmap_selection::{read_beatmap_dir, BeatmapSelectionInventory},
+    configs::Configs,
+    inventory::{open_new_inventory, InventoriesToOpen},
+    lobby::Lobby,
+    marathon::Marathon,
+    osu::{BeatmapSelectionData, Osu, OsuStateChange},
+    progress::{cancelled_error, LongOperation, LongOperations, Progress},
+    score_screen::ScoreScreenInventory,
+    song_selection::SongSelectionInventory,
+};
+
+const VOTE_DURATION_SECS: usize = 20;
+const CANDIDATE_SLOTS: [u16; 3] = [2, 4, 6];
+const CLOCK_SLOT: u16 = 8;
+/// Songs sampled to find the candidates, since some song directories may not have playable beatmaps
+const SAMPLED_SONGS: usize = 3 * CANDIDATE_SLOTS.len();
+
+/// Vote for the next map, started when a beatmap is finished. It's opened to each player once they close the score screen, and
+/// the most voted candidate starts automatically when the vote ends.
+#[derive(Component)]
+pub struct MapVoteInventory {
+    candidates: Vec<VoteCandidate>,
+    /// Candidate index voted by each client
+    votes: HashMap<Entity, usize>,
+    /// Clients the vote was opened to. The countdown starts once it's opened to someone.
+    opened_to: HashSet<Entity>,
+    ticks_left: usize,
+}
+
+struct VoteCandidate {
+    song_dir: PathBuf,
+    beatmap_path: PathBuf,
+    name: String,
+}
+
+impl MapVoteInventory {
+    fn new(candidates: Vec<VoteCandidate>, tps: usize) -> (Self, Inventory) {
+        let map_vote = Self {
+            candidates,
+            votes: HashMap::new(),
+            opened_to: HashSet::new(),
+            ticks_left: VOTE_DURATION_SECS * tps,
+        };
+        let mut inventory = Inventory::with_title(
+            InventoryKind::Generic9x1,
+            "Vote the next map".color(Color::DARK_BLUE),
+        );
+        map_vote.draw(&mut inventory, tps);
+
+        (map_vote, inventory)
+    }
+
+    fn draw(&self, inventory: &mut Inventory, tps: usize) {
+        for (idx, (candidate, &slot)) in self.candidates.iter().zip(&CANDIDATE_SLOTS).enumerate() {
+            let votes = self.votes.values().filter(|&&vote| vote == idx).count();
+            let item = ItemStack::new(
+                ItemKind::Map,
+                votes.clamp(1, 64) as u8,
+                Some(compound! {
+                    "display" => compound! {
+                        "Name" => format!(r#"{{"text": "{}", "color": "gold"}}"#, candidate.name),
+                        "Lore" => List::String(vec![
+                            format!(r#"{{"text": "Votes: {votes}", "color": "gray"}}"#),
+                        ])
+                    }
+                }),
+            );
+            inventory.replace_slot(slot, Some(item));
+        }
+
+        let secs_left = self.ticks_left.div_ceil(tps.max(1));
+        let clock = ItemStack::new(
+            ItemKind::Clock,
+            secs_left.clamp(1, 64) as u8,
+            Some(compound! {
+                "display" => compound! {
+                    "Name" => format!(r#"{{"text": "Vote ends in {secs_left}s", "color": "aqua"}}"#)
+                }
+            }),
+        );
+        inventory.replace_slot(CLOCK_SLOT, Some(clock));
+    }
+
+    /// Most voted candidate (ties are won by the left most candidate)
+    fn winner(&self) -> Option<&VoteCandidate> {
+        winner_idx(&self.votes, self.candidates.len()).and_then(|idx| self.candidates.get(idx))
+    }
+}
+
+fn winner_idx(votes: &HashMap<Entity, usize>, candidates: usize) -> Option<usize> {
+    let mut tally = vec![0; candidates];
+    for &vote in votes.values().filter(|&&vote| vote < candidates) {
+        tally[vote] += 1;
+    }
+
+    let max_votes = *tally.iter().max()?;
+    if max_votes == 0 {
+        return None;
+    }
+
+    tally.iter().position(|&count| count == max_votes)
+}
+
+/// Picks random playable beatmaps from `sampled_songs` (reading their folders, so it's run in the background)
+fn pick_candidates(
+    sampled_songs: Vec<PathBuf>,
+    max_map_length: Duration,
+    progress: &Progress,
+) -> Result<Vec<VoteCandidate>> {
+    let mut rng = rand::thread_rng();
+    let mut candidates = Vec::new();
+    progress.set_total(sampled_songs.len());
+
+    for song_dir in sampled_songs {
+        if candidates.len() >= CANDIDATE_SLOTS.len() {
+            break;
+        }
+        if progress.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        progress.advance();
+
+        let Ok(beatmaps) = read_beatmap_dir(&song_dir) else {
+            continue;
+        };
+        let playable: Vec<_> = beatmaps
+            .iter()
+            .filter(|beatmap| beatmap.length() <= max_map_length)
+            .collect();
+        if let Some(beatmap) = playable.choose(&mut rng) {
+            candidates.push(VoteCandidate {
+                beatmap_path: beatmap.path().clone(),
+                name: beatmap.display_name(),
+                song_dir,
+            });
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Starts the vote when a beatmap is finished and plays the winner once the vote time is over
+pub fn update_map_vote(
+    mut commands: Commands,
+    mut osu: ResMut<Osu>,
+    server: Res<Server>,
+    configs: Res<Configs>,
+    mut clients: Query<&mut Client>,
+    client_inventories: Query<(Entity, Option<&OpenInventory>), With<Client>>,
+    song_selections: Query<&SongSelectionInventory>,
+    score_screens: Query<Entity, With<ScoreScreenInventory>>,
+    mut beatmap_selections: Query<&mut BeatmapSelectionInventory>,
+    mut map_votes: Query<(Entity, &mut MapVoteInventory, &mut Inventory)>,
+    marathon: Res<Marathon>,
+    lobby: Res<Lobby>,
+    mut operations: ResMut<LongOperations>,
+    mut started: Local<bool>,
+    mut picking: Local<Option<LongOperation<Vec<VoteCandidate>>>>,
+) {
+    let tps = server.shared().tps() as usize;
+    let close_vote = |commands: &mut Commands, map_vote: Entity| {
+        for (client, open_inventory) in &client_inventories {
+            if open_inventory.is_some_and(|inventory| inventory.entity() == map_vote) {
+                commands.entity(client).remove::<OpenInventory>();
+            }
+        }
+        commands.entity(map_vote).insert(Despawned);
+    };
+
+    // Beatmap was left through the score screen (marathons and lobbies pick the next map by themselves, and only operators pick
+    // them while the song selection is locked)
+    if osu.finished_beatmap().is_none()
+        || marathon.is_running()
+        || lobby.is_active()
+        || osu.is_song_selection_locked()
+    {
+        for (map_vote, _, _) in &map_votes {
+            close_vote(&mut commands, map_vote);
+        }
+        if let Some(picking) = picking.take() {
+            picking.cancel();
+        }
+        *started = false;
+        return;
+    }
+
+    if !*started {
+        *started = true;
+
+        let Ok(song_selection) = song_selections.get_single() else {
+            return;
+        };
+        let sampled_songs: Vec<_> = song_selection
+            .songs()
+            .choose_multiple(&mut rand::thread_rng(), SAMPLED_SONGS)
+            .cloned()
+            .collect();
+        let max_map_length = configs.max_map_length();
+        *picking = Some(LongOperation::start(
+            "Picking the candidates of the map vote",
+            None,
+            &mut operations,
+            move |progress| pick_candidates(sampled_songs, max_map_length, progress),
+        ));
+        return;
+    }
+
+    if let Some(result) = picking.as_mut().and_then(|picking| picking.try_finish()) {
+        *picking = None;
+        match result {
+            Ok(candidates) if !candidates.is_empty() => {
+                commands.spawn(MapVoteInventory::new(candidates, tps));
+            }
+            Ok(_) => (),
+            Err(error) => error!("Error while picking the map vote candidates: '{}'", error),
+        }
+        return;
+    }
+
+    // The score screen is opened on the tick the beatmap is finished, the vote mustn't replace it before players see it
+    let Ok(score_screen) = score_screens.get_single() else {
+        return;
+    };
+
+    for (map_vote_entity, mut map_vote, mut inventory) in &mut map_votes {
+        for (client, open_inventory) in &client_inventories {
+            let open_inventory = open_inventory.map(|inventory| inventory.entity());
+            if map_vote.opened_to.contains(&client) || open_inventory == Some(score_screen) {
+                continue;
+            }
+            // Opened with the button of the score screen
+            if open_inventory != Some(map_vote_entity) {
+                commands
+                    .entity(client)
+                    .insert(OpenInventory::new(map_vote_entity));
+            }
+            map_vote.opened_to.insert(client);
+        }
+        if map_vote.opened_to.is_empty() {
+            continue;
+        }
+
+        map_vote.ticks_left = map_vote.ticks_left.saturating_sub(1);
+        if map_vote.ticks_left > 0 {
+            if map_vote.ticks_left % tps.max(1) == 0 {
+                map_vote.draw(&mut inventory, tps);
+            }
+            continue;
+        }
+
+        close_vote(&mut commands, map_vote_entity);
+
+        let Some(winner) = map_vote.winner() else {
+            for mut client in &mut clients {
+                client.send_message("Nobody voted for the next map".color(Color::GRAY));
+            }
+            continue;
+        };
+
+        for mut client in &mut clients {
+            client.send_message(
+                "Next map: ".color(Color::GOLD) + winner.name.clone().color(Color::AQUA),
+            );
+        }
+
+        if let Err(error) = play_candidate(winner, &mut osu, &mut clients, &mut beatmap_selections)
+        {
+            error!("Error while starting the voted map: '{}'", error);
+        }
+    }
+}
+
+fn play_candidate(
+    candidate: &VoteCandidate,
+    osu: &mut Osu,
+    clients: &mut Query<&mut Client>,
+    beatmap_selections: &mut Query<&mut BeatmapSelectionInventory>,
+) -> Result<()> {
+    // Keep the beatmap selection in sync, so players go back to the voted song after playing it
+    let mut beatmap_selection = beatmap_selections.get_single_mut()?;
+    let beatmaps = beatmap_selection.load_beatmap_dir(&candidate.song_dir)?;
+    // Nobody can pick maps by voting while the song selection is locked
+    osu.change_state_for(
+        OsuStateChange::BeatmapSelection(BeatmapSelectionData {
+            beatmap_dir: candidate.song_dir.clone(),
+            beatmaps: beatmaps
+                .iter()
+                .map(|beatmap| (beatmap.path().clone(), beatmap.osu_file().clone()))
+                .collect(),
+        }),
+        false,
+        clients,
+    )?;
+
+    osu.change_state_for(
+        OsuStateChange::PrePlaying {
+            beatmap_path: candidate.beatmap_path.clone(),
+        },
+        false,
+        clients,
+    )
+}
+
+pub fn handle_map_vote_clicks(
+    mut commands: Commands,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    server: Res<Server>,
+    open_inventories: Query<&OpenInventory, With<Client>>,
+    mut map_votes: Query<(&mut MapVoteInventory, &mut Inventory)>,
+    mut clicks: EventReader<ClickContainer>,
+) {
+    let tps = server.shared().tps() as usize;
+
+    for click in clicks.iter() {
+        let Ok(open_inventory) = open_inventories.get(click.client) else {
+            continue;
+        };
+        let inventory_entity = open_inventory.entity();
+        let Ok((mut map_vote, mut inventory)) = map_votes.get_mut(inventory_entity) else {
+            continue;
+        };
+        let Some(candidate_idx) = CANDIDATE_SLOTS
+            .iter()
+            .position(|&slot| slot == click.slot_id.unsigned_abs())
+            .filter(|&idx| idx < map_vote.candidates.len())
+        else {
+            continue;
+        };
+
+        map_vote.votes.insert(click.client, candidate_idx);
+        map_vote.draw(&mut inventory, tps);
+        open_new_inventory(
+            &mut commands,
+            click.client,
+            &mut inventories_to_open,
+            inventory_entity,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vote_winner() {
+        let mut votes = HashMap::new();
+        assert_eq!(winner_idx(&votes, 3), None);
+
+        votes.insert(Entity::from_raw(0), 2);
+        assert_eq!(winner_idx(&votes, 3), Some(2));
+
+        votes.insert(Entity::from_raw(1), 1);
+        assert_eq!(winner_idx(&votes, 3), Some(1));
+
+        votes.insert(Entity::from_raw(2), 2);
+        assert_eq!(winner_idx(&votes, 3), Some(2));
+
+        // Votes for missing candidates are ignored
+        votes.insert(Entity::from_raw(3), 5);
+        votes.insert(Entity::from_raw(4), 5);
+        assert_eq!(winner_idx(&votes, 3), Some(2));
+    }
+}
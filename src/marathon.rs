@@ -0,0 +1,331 @@
+use anyhow::{bail, Result};
+use std::{collections::VecDeque, path::PathBuf, time::Duration};
+use tracing::{error, warn};
+
+use bevy_ecs::system::{Query, Res, ResMut, Resource};
+use valence::{
+    prelude::{Client, Color, Server},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    beatmap::Beatmap,
+    beatmap_selection::{read_beatmap_dir, BeatmapFile},
+    configs::Configs,
+    osu::{Osu, OsuStateChange},
+    progress::{cancelled_error, LongOperation, LongOperations, Progress},
+};
+
+/// Time between the end of a map and the start of the next one
+const NEXT_MAP_COUNTDOWN: Duration = Duration::from_secs(5);
+
+/// Plays a queue of songs one after the other (the hardest playable difficulty of each one), adding up their scores.
+/// It ends when the queue is empty or a player goes back to the song selection.
+#[derive(Resource, Default)]
+pub struct Marathon {
+    /// Songs left to play (`None` if no marathon is running)
+    queue: Option<VecDeque<PathBuf>>,
+    results: Vec<MarathonResult>,
+    /// Ticks left before starting the next map, once the current one is finished
+    countdown_ticks: Option<usize>,
+    /// Whether the finished (or failed) map was recorded, so it's recorded once per play
+    recorded: bool,
+    /// Next beatmap being looked for in the background, since reading the song folders can take a while
+    next_beatmap: Option<LongOperation<NextBeatmap>>,
+    /// Whether the first map is still being looked for
+    starting: bool,
+}
+
+/// Beatmap to play next (`None` if no song left has a playable one) and the songs left after it
+struct NextBeatmap {
+    beatmap_path: Option<PathBuf>,
+    queue: VecDeque<PathBuf>,
+}
+
+struct MarathonResult {
+    name: String,
+    score: usize,
+    accuracy: f32,
+    passed: bool,
+}
+
+impl Marathon {
+    pub fn is_running(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    /// Starts a marathon with the songs. The first map is started by `update_marathon` once it's found.
+    pub fn start(
+        &mut self,
+        songs: Vec<PathBuf>,
+        max_length: Duration,
+        operations: &mut LongOperations,
+    ) -> Result<()> {
+        if self.is_running() {
+            bail!("a marathon is already running");
+        }
+        if songs.is_empty() {
+            bail!("there are no songs to play");
+        }
+
+        self.queue = Some(songs.into());
+        self.results.clear();
+        self.countdown_ticks = None;
+        self.recorded = false;
+        self.starting = true;
+        self.find_next_beatmap(max_length, operations);
+
+        Ok(())
+    }
+
+    /// Stops the marathon, returning its summary
+    pub fn stop(&mut self) -> Vec<Text> {
+        let summary = self.summary();
+        self.queue = None;
+        self.results.clear();
+        self.countdown_ticks = None;
+        self.recorded = false;
+        self.starting = false;
+        if let Some(next_beatmap) = self.next_beatmap.take() {
+            next_beatmap.cancel();
+        }
+
+        summary
+    }
+
+    pub fn total_score(&self) -> usize {
+        self.results.iter().map(|result| result.score).sum()
+    }
+
+    /// Songs left to play, not counting the current one
+    pub fn songs_left(&self) -> usize {
+        self.queue.as_ref().map_or(0, |queue| queue.len())
+    }
+
+    fn record(&mut self, beatmap: &Beatmap, passed: bool) {
+        self.results.push(MarathonResult {
+            name: format!(
+                "{} - {} [{}]",
+                beatmap.data.artist, beatmap.data.title, beatmap.data.difficulty_name
+            ),
+            score: beatmap.state.score,
+            accuracy: beatmap.state.accuracy(),
+            passed,
+        });
+    }
+
+    /// Looks for the next beatmap in the background, it's started by `update_marathon` once the countdown is over
+    fn find_next_beatmap(&mut self, max_length: Duration, operations: &mut LongOperations) {
+        let Some(queue) = self.queue.clone() else {
+            return;
+        };
+        // The previous map was retried
+        if let Some(next_beatmap) = self.next_beatmap.take() {
+            next_beatmap.cancel();
+        }
+
+        self.next_beatmap = Some(LongOperation::start(
+            "Looking for the next marathon map",
+            None,
+            operations,
+            move |progress| next_beatmap(queue, max_length, progress),
+        ));
+    }
+
+    fn summary(&self) -> Vec<Text> {
+        let mut summary = vec!["Marathon results".color(Color::GOLD)];
+
+        for (idx, result) in self.results.iter().enumerate() {
+            let status = if result.passed {
+                "".color(Color::WHITE)
+            } else {
+                " (failed)".color(Color::RED)
+            };
+
+            summary.push(
+                format!("{}. ", idx + 1).color(Color::GRAY)
+                    + result.name.clone().color(Color::AQUA)
+                    + format!("  {}", result.score).color(Color::WHITE)
+                    + format!("  {:.2}%", result.accuracy).color(Color::GREEN)
+                    + status,
+            );
+        }
+
+        summary.push(
+            "Total score: ".color(Color::GOLD) + self.total_score().to_string().color(Color::WHITE),
+        );
+
+        summary
+    }
+}
+
+/// Hardest playable beatmap of the next song of `queue`, skipping the songs which have none
+fn next_beatmap(
+    mut queue: VecDeque<PathBuf>,
+    max_length: Duration,
+    progress: &Progress,
+) -> Result<NextBeatmap> {
+    let star_rating =
+        |beatmap: &BeatmapFile| beatmap.stats().map_or(0.0, |stats| stats.star_rating);
+
+    while let Some(song) = queue.pop_front() {
+        if progress.is_cancelled() {
+            return Err(cancelled_error());
+        }
+
+        let hardest = read_beatmap_dir(&song).ok().and_then(|beatmaps| {
+            beatmaps
+                .into_iter()
+                .filter(|beatmap| beatmap.length() <= max_length)
+                .max_by(|a, b| star_rating(a).total_cmp(&star_rating(b)))
+        });
+
+        match hardest {
+            Some(beatmap) => {
+                return Ok(NextBeatmap {
+                    beatmap_path: Some(beatmap.path().clone()),
+                    queue,
+                })
+            }
+            None => warn!(
+                "Skipping song without playable beatmaps in the marathon: '{}'",
+                song.display()
+            ),
+        }
+    }
+
+    Ok(NextBeatmap {
+        beatmap_path: None,
+        queue,
+    })
+}
+
+/// Sends the summary of the stopped marathon to every player after `message`
+fn announce_stop(message: Text, summary: Vec<Text>, clients: &mut Query<&mut Client>) {
+    for mut client in clients {
+        client.send_message(message.clone());
+        for text in summary.iter() {
+            client.send_message(text.clone());
+        }
+    }
+}
+
+/// Records the finished maps of the marathon and starts the next one after a countdown
+pub fn update_marathon(
+    mut marathon: ResMut<Marathon>,
+    mut osu: ResMut<Osu>,
+    server: Res<Server>,
+    configs: Res<Configs>,
+    mut operations: ResMut<LongOperations>,
+    mut clients: Query<&mut Client>,
+) {
+    if !marathon.is_running() {
+        return;
+    }
+
+    let finished_beatmap = osu
+        .finished_beatmap()
+        .map(|beatmap| (beatmap, true))
+        .or_else(|| osu.failed_beatmap().map(|beatmap| (beatmap, false)));
+    let is_finished = finished_beatmap.is_some();
+
+    if let (Some((beatmap, passed)), false) = (finished_beatmap, marathon.recorded) {
+        marathon.record(beatmap, passed);
+        marathon.recorded = true;
+        marathon.countdown_ticks =
+            Some(NEXT_MAP_COUNTDOWN.as_secs() as usize * server.shared().tps() as usize);
+
+        let message = "Marathon total score: ".color(Color::GOLD)
+            + marathon.total_score().to_string().color(Color::WHITE)
+            + format!(
+                "  Next map in {} seconds ({} songs left)",
+                NEXT_MAP_COUNTDOWN.as_secs(),
+                marathon.songs_left()
+            )
+            .color(Color::GRAY);
+        for mut client in &mut clients {
+            client.send_message(message.clone());
+        }
+
+        // Looked for during the countdown
+        marathon.find_next_beatmap(configs.max_map_length(), &mut operations);
+        return;
+    }
+
+    // The first map, or the next one once the countdown is over, is started as soon as it's found
+    if marathon.starting || (is_finished && marathon.countdown_ticks == Some(0)) {
+        let Some(result) = marathon
+            .next_beatmap
+            .as_mut()
+            .and_then(|next_beatmap| next_beatmap.try_finish())
+        else {
+            return;
+        };
+        let starting = marathon.starting;
+        marathon.next_beatmap = None;
+        marathon.starting = false;
+        marathon.countdown_ticks = None;
+
+        match result {
+            Ok(NextBeatmap {
+                beatmap_path: Some(beatmap_path),
+                queue,
+            }) => {
+                marathon.queue = Some(queue);
+                if let Err(error) =
+                    osu.change_state(OsuStateChange::PrePlaying { beatmap_path }, &mut clients)
+                {
+                    // e.g. during a maintenance, the rest of the queue couldn't be played either
+                    error!("Error while starting the next marathon map: '{}'", error);
+                    announce_stop(
+                        "Marathon stopped, the next map could not be started".color(Color::RED),
+                        marathon.stop(),
+                        &mut clients,
+                    );
+                }
+            }
+            Ok(NextBeatmap {
+                beatmap_path: None, ..
+            }) if starting => {
+                marathon.stop();
+                for mut client in &mut clients {
+                    client.send_message(
+                        "Marathon cancelled, none of the songs has a playable beatmap"
+                            .color(Color::RED),
+                    );
+                }
+            }
+            Ok(NextBeatmap {
+                beatmap_path: None, ..
+            }) => announce_stop(
+                "Marathon finished!".color(Color::GREEN),
+                marathon.stop(),
+                &mut clients,
+            ),
+            Err(error) => {
+                error!("Error while looking for the next marathon map: '{}'", error);
+                announce_stop(
+                    "Marathon stopped, the next map could not be found".color(Color::RED),
+                    marathon.stop(),
+                    &mut clients,
+                );
+            }
+        }
+        return;
+    }
+
+    match (is_finished, marathon.countdown_ticks) {
+        (true, Some(ticks)) => marathon.countdown_ticks = Some(ticks.saturating_sub(1)),
+        // Left through the score screen or the retry menu
+        (false, _) if osu.is_selecting_beatmap() => announce_stop(
+            "Marathon stopped".color(Color::YELLOW),
+            marathon.stop(),
+            &mut clients,
+        ),
+        // The map is being played (or retried)
+        _ => {
+            marathon.countdown_ticks = None;
+            marathon.recorded = false;
+        }
+    }
+}
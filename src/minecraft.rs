@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use valence::prelude::DVec3;
 
+pub const MINECRAFT_VERSION: &str = "1.19.3";
 pub const PLAYER_EYE_OFFSET: DVec3 = DVec3::new(0.0, 1.62, 0.0);
 
 pub fn to_ticks(tps: usize, duration: Duration) -> usize {
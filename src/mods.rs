@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Display;
+
+use bevy_ecs::{
+    prelude::{DetectChanges, EventReader},
+    system::{Query, Res, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    beatmap::{ApproachRate, CircleSize, HpDrainRate, OverallDifficulty},
+    beatmap_selection::BeatmapSelectionInventory,
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    error::error_message,
+    lobby::Lobby,
+    osu::Osu,
+};
+
+/// Difficulty settings can't go above 10 with HR
+const MAX_DIFFICULTY: f64 = 10.0;
+
+/// Mods selected with `/mods` (e.g. `/mods hr hd`). The beatmap selection shows the difficulty settings and star rating they result in.
+/// There is no DT: the hitcircles are timed in ticks, so the songs can't be sped up.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Mods {
+    pub easy: bool,
+    pub hard_rock: bool,
+    /// No approach circles, and the hitcircles are cleared before they must be hit
+    pub hidden: bool,
+    /// The hitcircles are hit when the players aim at them, without clicking
+    pub relax: bool,
+    /// Any click hits the next hitcircle, wherever the players aim
+    pub autopilot: bool,
+}
+
+impl Mods {
+    /// Parses mod acronyms separated by spaces or `+` (e.g. `hr hd`, `+HRHD` or `nm` for no mods)
+    pub fn parse(mods: &str) -> Result<Self> {
+        let acronyms = mods.to_ascii_lowercase().replace(['+', ' ', ','], "");
+        if acronyms.len() % 2 != 0 {
+            bail!("mods must be 2 letter acronyms (ez, hr, hd, rx or ap)");
+        }
+
+        let mut parsed = Self::default();
+        for acronym in acronyms.as_bytes().chunks(2) {
+            match acronym {
+                b"nm" => (),
+                b"ez" => parsed.easy = true,
+                b"hr" => parsed.hard_rock = true,
+                b"dt" | b"nc" => bail!("DT and NC are not supported, the songs can't be sped up"),
+                b"hd" => parsed.hidden = true,
+                b"rx" => parsed.relax = true,
+                b"ap" => parsed.autopilot = true,
+                _ => bail!(
+                    "unknown mod '{}' (expected ez, hr, hd, rx or ap)",
+                    String::from_utf8_lossy(acronym)
+                ),
+            }
+        }
+
+        if parsed.easy && parsed.hard_rock {
+            bail!("EZ and HR can't be selected together");
+        }
+        if parsed.relax && parsed.autopilot {
+            bail!("RX and AP can't be selected together");
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Mods playing part of the map for the players, whose scores are flagged as unranked
+    pub fn is_unranked(&self) -> bool {
+        self.relax || self.autopilot
+    }
+
+    /// Multiplier of the combo bonus of the hits (https://osu.ppy.sh/wiki/en/Gameplay/Score/ScoreV1/osu%21#mod-multiplier)
+    pub fn score_multiplier(&self) -> f64 {
+        [
+            (self.easy, 0.5),
+            (self.hard_rock, 1.06),
+            (self.hidden, 1.06),
+        ]
+        .into_iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, multiplier)| multiplier)
+        .product()
+    }
+
+    /// Multiplier of the difficulty settings (except CS, see `Mods::cs`)
+    fn difficulty_multiplier(&self) -> f64 {
+        if self.easy {
+            0.5
+        } else if self.hard_rock {
+            1.4
+        } else {
+            1.0
+        }
+    }
+
+    fn scale(&self, value: f64) -> f64 {
+        (value * self.difficulty_multiplier()).min(MAX_DIFFICULTY.max(value))
+    }
+
+    /// AR the hit objects are spawned with (scaled by EZ and HR)
+    pub fn ar(&self, ar: ApproachRate) -> ApproachRate {
+        ApproachRate(self.scale(ar.0))
+    }
+
+    /// OD the hits are judged with (scaled by EZ and HR)
+    pub fn od(&self, od: OverallDifficulty) -> OverallDifficulty {
+        OverallDifficulty(self.scale(od.0))
+    }
+
+    /// CS is multiplied by 1.3 with HR (instead of 1.4)
+    pub fn cs(&self, cs: CircleSize) -> CircleSize {
+        let multiplier = if self.hard_rock {
+            1.3
+        } else {
+            self.difficulty_multiplier()
+        };
+
+        CircleSize((cs.0 * multiplier).min(MAX_DIFFICULTY.max(cs.0)))
+    }
+
+    pub fn hp(&self, hp: HpDrainRate) -> HpDrainRate {
+        HpDrainRate(self.scale(hp.0))
+    }
+}
+
+impl Display for Mods {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NM");
+        }
+
+        write!(f, "+")?;
+        for (selected, acronym) in [
+            (self.easy, "EZ"),
+            (self.hard_rock, "HR"),
+            (self.hidden, "HD"),
+            (self.relax, "RX"),
+            (self.autopilot, "AP"),
+        ] {
+            if selected {
+                write!(f, "{}", acronym)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes the next started beatmaps use the selected mods (see `Preprocessor::with_mods`)
+pub fn apply_mods_to_beatmaps(mods: Res<Mods>, mut osu: ResMut<Osu>) {
+    if !mods.is_changed() {
+        return;
+    }
+
+    osu.set_mods(*mods);
+}
+
+pub fn mods_command() -> CommandSpec {
+    CommandSpec::new("mods").arg(ArgSpec::phrase("mods"))
+}
+
+/// Handles `/mods [mods]`: shows the selected mods or selects new ones (only the host can change them in a lobby)
+pub fn execute_mods_commands(
+    mut mods: ResMut<Mods>,
+    osu: Res<Osu>,
+    lobby: Res<Lobby>,
+    mut clients: Query<&mut Client>,
+    mut beatmap_selections: Query<&mut BeatmapSelectionInventory>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "mods" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+
+        let result: Result<Text> = if args.trim().is_empty() {
+            Ok("Selected mods: ".color(Color::YELLOW) + mods.to_string().color(Color::GREEN))
+        } else if !osu.is_selecting_beatmap() {
+            Err(anyhow!(
+                "Mods can only be changed while selecting a beatmap"
+            ))
+        } else if lobby.is_active() && lobby.host() != Some(command_event.client) {
+            Err(anyhow!("Only the lobby host can change the mods"))
+        } else {
+            Mods::parse(args).map(|selected| {
+                *mods = selected;
+                // Shows the modded difficulty settings in the beatmap selection
+                for mut beatmap_selection in &mut beatmap_selections {
+                    beatmap_selection.set_changed();
+                }
+
+                "Mods set to ".color(Color::YELLOW) + selected.to_string().color(Color::GREEN)
+            })
+        };
+
+        match result {
+            Ok(message) if args.trim().is_empty() => {
+                if let Ok(mut client) = clients.get_mut(command_event.client) {
+                    client.send_message(message);
+                }
+            }
+            Ok(message) => {
+                for mut client in &mut clients {
+                    client.send_message(message.clone());
+                }
+            }
+            Err(error) => {
+                if let Ok(mut client) = clients.get_mut(command_event.client) {
+                    client.send_message(error_message(&error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_mods() {
+        let hdhr = Mods {
+            hard_rock: true,
+            hidden: true,
+            ..Default::default()
+        };
+
+        assert_eq!(Mods::parse("hr hd").unwrap(), hdhr);
+        assert_eq!(Mods::parse("+HDHR").unwrap(), hdhr);
+        assert_eq!(Mods::parse("nm").unwrap(), Mods::default());
+        assert!(Mods::parse("ez hr").is_err());
+        assert!(Mods::parse("rx ap").is_err());
+        assert!(Mods::parse("rx").unwrap().is_unranked());
+        assert!(!hdhr.is_unranked());
+        assert!(Mods::parse("fl").is_err());
+        // The songs can't be sped up
+        assert!(Mods::parse("hr dt").is_err());
+
+        assert_eq!(hdhr.to_string(), "+HRHD");
+        assert_eq!(Mods::default().to_string(), "NM");
+
+        assert!((hdhr.score_multiplier() - 1.06 * 1.06).abs() < 1e-9);
+        assert_eq!(Mods::default().score_multiplier(), 1.0);
+    }
+
+    #[test]
+    fn modded_difficulty() {
+        let hard_rock = Mods::parse("hr").unwrap();
+        assert!((hard_rock.ar(ApproachRate(9.0)).0 - 10.0).abs() < 1e-9);
+        assert!((hard_rock.cs(CircleSize(4.0)).0 - 5.2).abs() < 1e-9);
+        assert!((hard_rock.od(OverallDifficulty(5.0)).0 - 7.0).abs() < 1e-9);
+
+        let easy = Mods::parse("ez").unwrap();
+        assert!((easy.hp(HpDrainRate(6.0)).0 - 3.0).abs() < 1e-9);
+    }
+}
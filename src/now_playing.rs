@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::{fs, path::Path};
+use tracing::warn;
+
+use bevy_ecs::system::{Local, Res, ResMut};
+use valence::prelude::{BlockPos, BlockState, Server};
+
+use crate::{
+    beatmap::Beatmap,
+    configs::Configs,
+    digit::{char_mask, TextPosition, TextWriter, CHAR_SIZE},
+    mods::Mods,
+    osu::Osu,
+    playfield::PlayfieldSurface,
+};
+
+/// Blocks between the lines (in char scale units)
+const LINE_SPACING: usize = 1;
+
+/// Shows the title, artist and difficulty of the beatmap above the screen before it starts
+pub fn update_now_playing(
+    osu: Res<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut drawn_positions: Local<Vec<BlockPos>>,
+) {
+    match osu.pre_playing_beatmap() {
+        Some(beatmap) if drawn_positions.is_empty() => {
+            let lines = [
+                (beatmap.data.title.as_str(), BlockState::WHITE_CONCRETE),
+                (
+                    beatmap.data.artist.as_str(),
+                    BlockState::LIGHT_GRAY_CONCRETE,
+                ),
+                (
+                    beatmap.data.difficulty_name.as_str(),
+                    BlockState::YELLOW_CONCRETE,
+                ),
+            ];
+
+            draw_lines(
+                &lines,
+                osu.playfield().top_margin_area(),
+                &mut surface,
+                &mut drawn_positions,
+            );
+        }
+        None if !drawn_positions.is_empty() => {
+            surface.fill(drawn_positions.drain(..), BlockState::AIR);
+        }
+        _ => {}
+    }
+}
+
+/// Content of the now playing file, see `Configs::now_playing_file`
+#[derive(Serialize, Debug, PartialEq)]
+struct NowPlayingFile {
+    /// e.g. "Artist - Title [Diff] +HRHD"
+    song: String,
+    accuracy: f32,
+}
+
+impl NowPlayingFile {
+    fn from(beatmap: &Beatmap, mods: &Mods) -> Self {
+        let mut song = format!(
+            "{} - {} [{}]",
+            beatmap.data.artist, beatmap.data.title, beatmap.data.difficulty_name
+        );
+        if !mods.is_empty() {
+            song.push_str(&format!(" {}", mods));
+        }
+
+        let state = &beatmap.state;
+        // Like osu!, the accuracy starts at 100% before the first hit
+        let accuracy = if state.hits300 + state.hits100 + state.hits50 + state.misses == 0 {
+            100.0
+        } else {
+            state.accuracy()
+        };
+
+        Self { song, accuracy }
+    }
+
+    fn text(&self) -> String {
+        format!("{}\n{:.2}%", self.song, self.accuracy)
+    }
+}
+
+/// Writes the beatmap being played and its live accuracy to the now playing file once per second, for stream overlays.
+/// The file is emptied when nothing is played.
+pub fn write_now_playing_file(
+    osu: Res<Osu>,
+    mods: Res<Mods>,
+    configs: Res<Configs>,
+    server: Res<Server>,
+    mut ticks: Local<usize>,
+    mut last_content: Local<Option<String>>,
+) {
+    let Some(path) = configs.now_playing_file() else {
+        return;
+    };
+
+    *ticks += 1;
+    if *ticks < server.shared().tps() as usize {
+        return;
+    }
+    *ticks = 0;
+
+    let content = osu
+        .current_beatmap()
+        .map(|beatmap| now_playing_content(path, &NowPlayingFile::from(beatmap, &mods)))
+        .unwrap_or_default();
+
+    if last_content.as_ref() == Some(&content) {
+        return;
+    }
+    match fs::write(path, &content) {
+        Ok(()) => *last_content = Some(content),
+        Err(error) => warn!(
+            "Error while writing the now playing file '{}': {}",
+            path.display(),
+            error
+        ),
+    }
+}
+
+fn now_playing_content(path: &Path, now_playing: &NowPlayingFile) -> String {
+    let is_json = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("json"));
+
+    if is_json {
+        serde_json::to_string(now_playing).unwrap_or_default()
+    } else {
+        now_playing.text()
+    }
+}
+
+/// Draws the lines vertically centered in the area (center, width and height), each one as big as it fits.
+/// The drawn blocks are pushed to `drawn_positions`, so they can be cleared later.
+pub fn draw_lines(
+    lines: &[(&str, BlockState)],
+    (center, max_width, height): (BlockPos, usize, usize),
+    surface: &mut PlayfieldSurface,
+    drawn_positions: &mut Vec<BlockPos>,
+) {
+    let max_scale = max_line_scale(height, lines.len());
+    let line_height = ((CHAR_SIZE.1 + LINE_SPACING) * max_scale) as i32;
+    let last_line = lines.len().saturating_sub(1) as i32;
+
+    for (i, &(text, block)) in lines.iter().enumerate() {
+        let Some((text, scale)) = fit_line(text, max_scale, max_width) else {
+            continue;
+        };
+        let writer = TextWriter {
+            scale,
+            position: TextPosition::Center,
+        };
+        let origin = BlockPos {
+            y: center.y + (last_line - 2 * i as i32) * line_height / 2,
+            ..center
+        };
+
+        for pos in writer.iter_text_block_positions(&text, origin).flatten() {
+            surface.set(pos, block);
+            drawn_positions.push(pos);
+        }
+    }
+}
+
+/// Largest char scale which fits `lines` lines (with spacing) in `height` blocks
+fn max_line_scale(height: usize, lines: usize) -> usize {
+    let line_units = lines * CHAR_SIZE.1 + (lines + 1) * LINE_SPACING;
+
+    (height / line_units).max(1)
+}
+
+/// Supported characters of `text` and the largest scale (up to `max_scale`) they fit in `max_width`.
+/// Text which does not fit even with scale 1 is truncated.
+fn fit_line(text: &str, max_scale: usize, max_width: usize) -> Option<(String, usize)> {
+    let mut text: String = text.chars().filter(|&c| char_mask(c).is_some()).collect();
+    let text_width = |text: &str| {
+        TextWriter {
+            scale: 1,
+            position: TextPosition::Center,
+        }
+        .text_size(text)
+        .0
+    };
+
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    text = trimmed.to_string();
+
+    let width = text_width(&text);
+    if width <= max_width {
+        return Some((text, (max_width / width).clamp(1, max_scale)));
+    }
+
+    while !text.is_empty() && text_width(&format!("{text}...")) > max_width {
+        text.pop();
+    }
+    let text = format!("{}...", text.trim_end());
+
+    Some((text, 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fit_lines() {
+        assert_eq!(fit_line("", 3, 100), None);
+        // Unsupported characters are skipped
+        assert_eq!(fit_line("[Hard]", 3, 100), Some(("Hard".to_string(), 3)));
+        assert_eq!(fit_line("Hard", 3, 31), Some(("Hard".to_string(), 2)));
+        assert_eq!(fit_line("Hard", 3, 15), Some(("Hard".to_string(), 1)));
+        assert_eq!(fit_line("Insane", 3, 22), Some(("In...".to_string(), 1)));
+    }
+
+    #[test]
+    fn now_playing_file() {
+        let mut beatmap = crate::test_support::synthetic_beatmap(8.0, 5.0, &[1000, 2000]);
+        let mods = Mods {
+            hard_rock: true,
+            ..Default::default()
+        };
+        let now_playing = NowPlayingFile::from(&beatmap, &mods);
+        assert_eq!(
+            now_playing_content(Path::new("now_playing.txt"), &now_playing),
+            "Artist - Title [Synthetic] +HR\n100.00%"
+        );
+
+        beatmap.state.hits300 = 1;
+        beatmap.state.hits100 = 1;
+        let now_playing = NowPlayingFile::from(&beatmap, &Mods::default());
+        assert_eq!(
+            now_playing_content(Path::new("now_playing.json"), &now_playing),
+            r#"{"song":"Artist - Title [Synthetic]","accuracy":66.666664}"#
+        );
+    }
+
+    #[test]
+    fn line_scale() {
+        assert_eq!(max_line_scale(72, 3), 3);
+        assert_eq!(max_line_scale(10, 3), 1);
+    }
+}
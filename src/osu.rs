@@ -1,11 +1,10 @@
 use anyhow::{Context, Result};
 use osu_file_parser::OsuFile;
-use std::{cmp::max, fs::read_to_string, path::PathBuf, time::Duration};
+use std::{cmp::max, path::PathBuf, time::Duration};
 use tracing::{error, warn};
 
 use valence::{
-    client::event::{DropItem, StartSneaking, SwapItemInHand, SwingArm},
-    instance::ChunkEntry,
+    client::event::{DropItem, StartSneaking, SwapItemInHand, SwingArm, UpdateSelectedSlot},
     prelude::*,
     protocol::{
         packets::s2c::play::BossBar,
@@ -16,34 +15,61 @@ use valence::{
 };
 
 use crate::{
+    adaptive::AdaptiveDifficulty,
+    afk::Afk,
+    aim_assist::{effective_aim_assist, NO_AIM_ASSIST},
     audio::AudioPlayer,
+    audio_clock::AudioClock,
     beatmap::{audio_path_from, Beatmap, OverallDifficulty},
     beatmap_selection::BeatmapSelectionInventory,
+    combo::{combo_milestone_level, play_combo_milestone_sound, ComboMilestoneNumber},
+    configs::Configs,
+    credits_screen::CREDITS_SCREEN_DURATION,
+    error::OsuError,
+    game_mode::{CustomGameMode, GameModeContext, GameModeHit, GameModeInput},
     hit_score::HitScore,
-    hitcircle::Hitcircle,
+    hitcircle::{Hitcircle, HitcircleBlocks, HitcircleRadius},
+    hitsound::Hitsounds,
+    hud::HudSettings,
+    input::InputGuard,
+    lag::{ping_compensation_ms, LagCompensation},
+    mods::Mods,
+    player_name::PlayerName,
+    playfield::Playfield,
+    preprocess::Preprocessor,
     ring::Ring,
+    scores::{LocalScore, LocalScores},
+    skin::BlockSkin,
     song_selection::SongSelectionInventory,
+    sound_effects::{SoundEffect, SoundEffects},
+    storage::Storage,
+    volume::Volume,
 };
 
-const SCREEN_MARGIN_RATIO: f64 = 0.5;
-const DEFAULT_SCREEN_SIZE: (f64, f64) = (640.0, 480.0);
-const DEFAULT_SPAWN_POS: DVec3 = DVec3::new(
-    DEFAULT_SCREEN_SIZE.0 / 1.75,
-    DEFAULT_SCREEN_SIZE.1 * (1.0 + 2.0 * SCREEN_MARGIN_RATIO) / 2.25,
-    -500.0,
-);
-
 #[derive(Component)]
 pub struct OsuInstance;
 
 #[derive(Resource)]
 pub struct Osu {
-    scale: f64,
-    screen_z: f64,
-    audio_player: AudioPlayer,
+    playfield: Playfield,
+    audio_player: Box<dyn AudioPlayer>,
     life_bar_uuid: Uuid,
     state: Option<OsuState>,
     beatmap_selection_data: Option<BeatmapSelectionData>,
+    local_scores: LocalScores,
+    storage: Box<dyn Storage>,
+    /// Free play where no scores are recorded (e.g. to warm up before the real picks)
+    warmup: bool,
+    /// Beatmaps are started in the adaptive mode (see `AdaptiveDifficulty`)
+    adaptive: bool,
+    /// New beatmaps can't be started (e.g. before restarting the server), the current play can still finish
+    maintenance: bool,
+    /// Only operators can pick the maps (see `Osu::change_state_for`)
+    song_selection_locked: bool,
+    hitsounds: Hitsounds,
+    sound_effects: SoundEffects,
+    /// Run over the hit objects of the started beatmaps
+    preprocessor: Preprocessor,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -59,40 +85,156 @@ pub enum OsuState {
     BeatmapSelection,
     PrePlaying { ticks_left: usize, beatmap: Beatmap },
     Playing(Beatmap),
-    ScoreDisplay,
+    ScoreDisplay(Beatmap),
+    Failed(Beatmap),
 }
 
 #[derive(Clone)]
 pub struct BeatmapSelectionData {
     pub beatmap_dir: PathBuf,
-    pub beatmaps: Vec<OsuFile>,
+    /// .osu files of the beatmap set with their paths
+    pub beatmaps: Vec<(PathBuf, OsuFile)>,
 }
 
 pub enum OsuStateChange {
     SongSelection,
     BeatmapSelection(BeatmapSelectionData),
-    PrePlaying { beatmap_path: PathBuf },
+    PrePlaying {
+        beatmap_path: PathBuf,
+    },
     Playing(Beatmap),
     ScoreDisplay(Beatmap),
-    Failed,
+    Failed(Beatmap),
+    /// Plays the failed or finished beatmap again
+    Retry,
+    /// Plays the next difficulty of the finished beatmap set
+    NextBeatmap,
+    /// Leaves the failed beatmap and goes back to the beatmap selection
+    BackToSelection,
 }
 
 impl Osu {
-    pub fn new(scale: f64, audio_player: AudioPlayer) -> Self {
+    pub fn new(scale: f64, audio_player: Box<dyn AudioPlayer>, storage: Box<dyn Storage>) -> Self {
+        let local_scores = storage.load_scores().unwrap_or_else(|error| {
+            warn!("Error while loading local scores: {}", error);
+            LocalScores::default()
+        });
+
         Self {
-            scale,
-            screen_z: 0.0,
+            playfield: Playfield::new(BlockPos { x: 0, y: 0, z: 0 }, scale),
             state: None,
             life_bar_uuid: Uuid::new_v4(),
             audio_player,
             beatmap_selection_data: None,
+            local_scores,
+            storage,
+            warmup: false,
+            adaptive: false,
+            maintenance: false,
+            song_selection_locked: false,
+            hitsounds: Hitsounds::default(),
+            sound_effects: SoundEffects::default(),
+            preprocessor: Preprocessor::default(),
+        }
+    }
+
+    pub fn with_hitsounds(mut self, hitsounds: Hitsounds) -> Self {
+        self.hitsounds = hitsounds;
+        self
+    }
+
+    pub fn with_sound_effects(mut self, sound_effects: SoundEffects) -> Self {
+        self.sound_effects = sound_effects;
+        self
+    }
+
+    pub fn with_preprocessor(mut self, preprocessor: Preprocessor) -> Self {
+        self.set_preprocessor(preprocessor);
+        self
+    }
+
+    /// Takes effect from the next started beatmap
+    pub fn set_preprocessor(&mut self, preprocessor: Preprocessor) {
+        self.preprocessor = preprocessor;
+    }
+
+    pub fn preprocessor(&self) -> &Preprocessor {
+        &self.preprocessor
+    }
+
+    /// Mods of the next started beatmaps (see `Preprocessor::with_mods`)
+    pub fn set_mods(&mut self, mods: Mods) {
+        self.preprocessor = self.preprocessor.clone().with_mods(mods);
+    }
+
+    pub fn with_screen_background(mut self, background: BlockState) -> Self {
+        self.set_screen_background(background);
+        self
+    }
+
+    /// Changes the block of the screen drawn by `Playfield::init` and `Playfield::reset` (it's not repainted)
+    pub fn set_screen_background(&mut self, background: BlockState) {
+        self.playfield = self.playfield.with_background(background);
+    }
+
+    pub fn set_volume(&self, volume: Volume) {
+        self.audio_player.set_volume(volume);
+    }
+
+    /// Plays `effect` in the server audio (if its sample was found), mixed with the music
+    pub fn play_effect(&self, effect: SoundEffect) {
+        let Some(sample) = self.sound_effects.get(effect) else {
+            return;
+        };
+
+        if let Err(error) = self.audio_player.play_effect(sample) {
+            warn!(
+                "Error while playing the {:?} sound effect: {}",
+                effect, error
+            );
         }
     }
 
     pub fn init(&self, instance: &mut Instance) {
-        self.init_chunks(instance);
-        self.init_screen(instance);
-        self.init_player_spawn(instance);
+        self.playfield.init(instance);
+    }
+
+    /// Playfield of the game
+    pub fn playfield(&self) -> &Playfield {
+        &self.playfield
+    }
+
+    /// `Osu::change_state` requested by a player. While the song selection is locked (see `/admin`), only operators can pick the
+    /// next map, however it's picked (song selection, `/random`, `/marathon`, `/daily play`, `/lobby start`...).
+    pub fn change_state_for(
+        &mut self,
+        state_change: OsuStateChange,
+        is_operator: bool,
+        clients: &mut Query<&mut Client>,
+    ) -> Result<()> {
+        if matches!(
+            state_change,
+            OsuStateChange::BeatmapSelection(_)
+                | OsuStateChange::PrePlaying { .. }
+                | OsuStateChange::NextBeatmap
+        ) {
+            self.check_can_pick_maps(is_operator)?;
+        }
+
+        self.change_state(state_change, clients)
+    }
+
+    /// Fails while the song selection is locked, unless the player is an operator. Used to check maps picked later on
+    /// (e.g. the first map of a marathon is started once it's found).
+    pub fn check_can_pick_maps(&self, is_operator: bool) -> Result<()> {
+        if self.song_selection_locked && !is_operator {
+            return Err(OsuError::OperatorOnly {
+                action: "pick maps while the song selection is locked",
+            }
+            .into());
+        }
+
+        Ok(())
     }
 
     pub fn change_state(
@@ -100,6 +242,17 @@ impl Osu {
         state_change: OsuStateChange,
         clients: &mut Query<&mut Client>,
     ) -> Result<()> {
+        if self.maintenance
+            && matches!(
+                state_change,
+                OsuStateChange::PrePlaying { .. }
+                    | OsuStateChange::Retry
+                    | OsuStateChange::NextBeatmap
+            )
+        {
+            return Err(OsuError::Maintenance.into());
+        }
+
         self.audio_player.stop();
         let mut go_to_beatmap_selection = |messages: Vec<Text>| -> Result<()> {
             for mut client in clients.iter_mut() {
@@ -129,9 +282,9 @@ impl Osu {
                 self.state = Some(OsuState::SongSelection);
             }
             OsuStateChange::BeatmapSelection(data) => {
-                if let Some(osu_file) = data.beatmaps.first() {
+                if let Some((_, osu_file)) = data.beatmaps.first() {
                     if let Some(audio_path) = audio_path_from(osu_file, data.beatmap_dir.clone()) {
-                        self.audio_player.set_music(audio_path)?;
+                        self.audio_player.set_music(&audio_path)?;
                         self.audio_player.play();
                     }
                 }
@@ -140,25 +293,19 @@ impl Osu {
                 self.state = Some(OsuState::BeatmapSelection);
             }
             OsuStateChange::PrePlaying { beatmap_path } => {
-                let osu_file = read_to_string(&beatmap_path)?.parse::<OsuFile>()?;
-                let beatmap_dir = beatmap_path
-                    .parent()
-                    .with_context(|| "beatmap path does not contain parent directory")?;
-                let beatmap = Beatmap::try_from(osu_file, beatmap_dir.to_path_buf())?;
-                let time_per_tick = 1000 / 20;
-                let ticks_left = beatmap
-                    .data
-                    .hit_objects
-                    .first()
-                    .map(|hit_object| max((3000 - hit_object.time() as i32) / time_per_tick, 0))
-                    .unwrap_or(60) as usize;
-
-                self.state = Some(OsuState::PrePlaying {
-                    beatmap,
-                    ticks_left,
-                })
+                let beatmap = Beatmap::read(&beatmap_path, &self.preprocessor)?;
+
+                self.state = Some(Self::pre_playing_state(beatmap));
             }
-            OsuStateChange::Playing(beatmap) => {
+            OsuStateChange::Playing(mut beatmap) => {
+                if self.adaptive {
+                    beatmap.state.adaptive = Some(AdaptiveDifficulty::default());
+                }
+
+                if let Some(beatmap_dir) = beatmap.data.audio_path.parent() {
+                    self.hitsounds.load_beatmap_sample(beatmap_dir);
+                }
+
                 // Start playing music
                 self.audio_player.set_music(&beatmap.data.audio_path)?;
                 self.audio_player.play();
@@ -166,18 +313,102 @@ impl Osu {
                 self.state = Some(OsuState::Playing(beatmap));
             }
             OsuStateChange::ScoreDisplay(beatmap) => {
-                let score_texts = beatmap.score_text();
-                go_to_beatmap_selection(score_texts)?;
+                if beatmap.state.is_full_combo() {
+                    let full_combo = "FULL COMBO! ".color(Color::GOLD)
+                        + format!("{} [{}]", beatmap.data.title, beatmap.data.difficulty_name)
+                            .color(Color::AQUA)
+                        + format!(" x{}", beatmap.state.max_combo).color(Color::WHITE);
+
+                    for mut client in clients.iter_mut() {
+                        client.send_message(full_combo.clone());
+                    }
+                }
+                self.play_effect(SoundEffect::Applause);
+
+                self.state = Some(OsuState::ScoreDisplay(beatmap));
             }
-            OsuStateChange::Failed => {
-                let messages = vec!["Beatmap failed!".color(Color::RED)];
-                go_to_beatmap_selection(messages)?;
+            OsuStateChange::Failed(beatmap) => {
+                for mut client in clients.iter_mut() {
+                    client.send_message("Beatmap failed!".color(Color::RED));
+                    for text in beatmap.score_text() {
+                        client.send_message(text);
+                    }
+                    play_fail_sound(&mut client);
+                }
+                self.play_effect(SoundEffect::Fail);
+
+                self.state = Some(OsuState::Failed(beatmap));
+            }
+            OsuStateChange::Retry => match self.state.take() {
+                Some(OsuState::Failed(beatmap)) | Some(OsuState::ScoreDisplay(beatmap)) => {
+                    let beatmap = Beatmap {
+                        data: beatmap.data,
+                        state: Default::default(),
+                    };
+                    self.state = Some(Self::pre_playing_state(beatmap));
+                }
+                state => self.state = state,
+            },
+            OsuStateChange::NextBeatmap => {
+                if let Some(finished_beatmap) = self.finished_beatmap() {
+                    let beatmap = self.next_beatmap(finished_beatmap)?;
+                    self.state = Some(Self::pre_playing_state(beatmap));
+                }
+            }
+            OsuStateChange::BackToSelection => {
+                go_to_beatmap_selection(Vec::new())?;
             }
         };
 
         Ok(())
     }
 
+    /// Next difficulty (in the beatmap selection order) of the beatmap set of `beatmap`
+    fn next_beatmap(&self, beatmap: &Beatmap) -> Result<Beatmap> {
+        let data = self
+            .beatmap_selection_data
+            .as_ref()
+            .with_context(|| "no beatmap set was selected")?;
+        let current_idx = data.beatmaps.iter().position(|(_, osu_file)| {
+            let version: Option<String> = osu_file
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.version.clone())
+                .map(|version| version.into());
+
+            version.is_some_and(|version| version == beatmap.data.difficulty_name)
+        });
+        let next_idx = current_idx.map_or(0, |idx| (idx + 1) % data.beatmaps.len());
+        let (path, _) = data
+            .beatmaps
+            .get(next_idx)
+            .with_context(|| "beatmap set has no beatmaps")?;
+
+        Beatmap::read(path, &self.preprocessor)
+    }
+
+    fn pre_playing_state(beatmap: Beatmap) -> OsuState {
+        let time_per_tick = 1000 / 20;
+        let credits_ticks = CREDITS_SCREEN_DURATION.as_millis() as usize / time_per_tick;
+
+        OsuState::PrePlaying {
+            ticks_left: Self::lead_in_ticks(&beatmap) + credits_ticks,
+            beatmap,
+        }
+    }
+
+    /// Ticks before the music starts so the first hit object appears after 3 seconds
+    fn lead_in_ticks(beatmap: &Beatmap) -> usize {
+        let time_per_tick = 1000 / 20;
+
+        beatmap
+            .data
+            .hit_objects
+            .first()
+            .map(|hit_object| max((3000 - hit_object.time() as i32) / time_per_tick, 0))
+            .unwrap_or(60) as usize
+    }
+
     pub fn get_boss_bar_title(&self, tps: usize) -> Text {
         match &self.state {
             Some(OsuState::SongSelection) => {
@@ -190,100 +421,243 @@ impl Osu {
                     + " to open".color(Color::WHITE)
                     + " BEATMAP SELECTION".color(Color::AQUA)
             }
-            Some(OsuState::PrePlaying { ticks_left, .. }) => {
-                "Beatmap will start in".color(Color::WHITE)
+            Some(OsuState::PrePlaying {
+                ticks_left,
+                beatmap,
+            }) => {
+                let title = "Beatmap will start in".color(Color::WHITE)
                     + format!(" {}", ticks_left / tps + 1).color(Color::AQUA)
-                    + " seconds".color(Color::WHITE)
+                    + " seconds".color(Color::WHITE);
+
+                if *ticks_left > Self::lead_in_ticks(beatmap) {
+                    title
+                        + "   Sneak<LEFT SHIFT>".color(Color::GOLD)
+                        + " to skip".color(Color::WHITE)
+                } else {
+                    title
+                }
             }
             Some(OsuState::Playing(beatmap)) => {
-                "Score: ".color(Color::GOLD)
+                let warmup: Text = if self.warmup {
+                    "WARMUP   ".color(Color::YELLOW)
+                } else {
+                    "".into()
+                };
+                let maintenance: Text = if self.maintenance {
+                    "MAINTENANCE   ".color(Color::RED)
+                } else {
+                    "".into()
+                };
+                let adaptive: Text = match beatmap.state.adaptive {
+                    Some(_) => format!(
+                        "ADAPTIVE AR{:.1} CS{:.1}   ",
+                        beatmap.ar().0,
+                        beatmap.cs().0
+                    )
+                    .color(Color::LIGHT_PURPLE),
+                    None => "".into(),
+                };
+                let title = warmup
+                    + maintenance
+                    + adaptive
+                    + "Score: ".color(Color::GOLD)
                     + beatmap.state.score.to_string().color(Color::WHITE)
                     + "   Combo: ".color(Color::LIGHT_PURPLE)
                     + format!("x{}", beatmap.state.combo).color(Color::WHITE)
                     + "   Acc: ".color(Color::GREEN)
-                    + format!("{:.2}%", beatmap.state.accuracy()).color(Color::WHITE)
+                    + format!("{:.2}%", beatmap.state.accuracy()).color(Color::WHITE);
+
+                if beatmap.intro_skip_time().is_some() {
+                    title
+                        + "   Sneak<LEFT SHIFT>".color(Color::GOLD)
+                        + " to skip intro".color(Color::WHITE)
+                } else {
+                    title
+                }
+            }
+            Some(OsuState::ScoreDisplay(_)) => {
+                "Sneak<LEFT SHIFT>".color(Color::GOLD)
+                    + " to open".color(Color::WHITE)
+                    + " SCORE SCREEN".color(Color::AQUA)
+            }
+            Some(OsuState::Failed(_)) => {
+                "Beatmap failed!".color(Color::RED)
+                    + "   Sneak<LEFT SHIFT>".color(Color::GOLD)
+                    + " to open".color(Color::WHITE)
+                    + " RETRY MENU".color(Color::AQUA)
             }
             _ => "".into(),
         }
     }
 
-    fn init_chunks(&self, instance: &mut Instance) {
-        let (screen_x, _) = self.screen_size();
-        let (margin_x, _) = self.screen_margin();
-        let max_x = screen_x + margin_x;
-        let max_z = self.player_spawn_pos().z as i32;
-
-        for x in -1 - (margin_x / 16)..=(max_x / 16) + 1 {
-            for z in (max_z / 16) - 1..=1 {
-                if let ChunkEntry::Vacant(chunk) = instance.chunk_entry([x, z]) {
-                    chunk.insert(Default::default());
-                }
+    pub fn init_inventory_selections(world: &mut World, songs_dir: PathBuf) {
+        match SongSelectionInventory::new(songs_dir) {
+            Ok(song_selection) => {
+                world.spawn(song_selection);
             }
+            Err(error) => error!("Error while setting up song selection: {}", error),
+        };
+
+        world.spawn(BeatmapSelectionInventory::new());
+    }
+
+    /// Clock of the music, which drives the gameplay timing
+    pub fn clock(&self) -> &dyn AudioClock {
+        self.audio_player.clock()
+    }
+
+    pub fn has_finished_music(&self) -> bool {
+        self.audio_player.has_finished()
+    }
+
+    pub fn playing_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(OsuState::Playing(beatmap)) => Some(beatmap),
+            _ => None,
         }
     }
 
-    fn init_screen(&self, instance: &mut Instance) {
-        let (max_x, max_y) = self.screen_size();
-        let (margin_x, margin_y) = self.screen_margin();
+    pub fn state(&self) -> Option<&OsuState> {
+        self.state.as_ref()
+    }
 
-        for x in -margin_x..=max_x + margin_x {
-            for y in 0..=max_y + 2 * margin_y {
-                instance.set_block(
-                    BlockPos { x, y, z: 1 },
-                    Block::new(BlockState::BLACK_CONCRETE),
-                );
-            }
+    /// Beatmap which is about to start, being played or whose results are shown
+    pub fn current_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(
+                OsuState::PrePlaying { beatmap, .. }
+                | OsuState::Playing(beatmap)
+                | OsuState::ScoreDisplay(beatmap)
+                | OsuState::Failed(beatmap),
+            ) => Some(beatmap),
+            _ => None,
         }
     }
 
-    fn init_player_spawn(&self, instance: &mut Instance) {
-        let spawn_pos = self.player_spawn_pos();
+    /// Beatmap which is about to start
+    pub fn pre_playing_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(OsuState::PrePlaying { beatmap, .. }) => Some(beatmap),
+            _ => None,
+        }
+    }
 
-        let block_pos = BlockPos {
-            x: spawn_pos.x as i32,
-            y: spawn_pos.y as i32 - 1,
-            z: spawn_pos.z as i32 - 1,
-        };
+    /// Beatmap whose credits screen is being displayed (the first part of the pre playing state)
+    pub fn credits_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(OsuState::PrePlaying {
+                beatmap,
+                ticks_left,
+            }) if *ticks_left > Self::lead_in_ticks(beatmap) => Some(beatmap),
+            _ => None,
+        }
+    }
 
-        instance.set_block(block_pos, Block::new(BlockState::BEDROCK));
+    /// Beatmap whose score is being displayed
+    pub fn finished_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(OsuState::ScoreDisplay(beatmap)) => Some(beatmap),
+            _ => None,
+        }
     }
 
-    pub fn init_inventory_selections(world: &mut World, songs_dir: PathBuf) {
-        match SongSelectionInventory::new(songs_dir) {
-            Ok(song_selection) => {
-                world.spawn(song_selection);
-            }
-            Err(error) => error!("Error while setting up song selection: {}", error),
-        };
+    pub fn is_failed(&self) -> bool {
+        self.failed_beatmap().is_some()
+    }
 
-        world.spawn(BeatmapSelectionInventory::new());
+    pub fn failed_beatmap(&self) -> Option<&Beatmap> {
+        match &self.state {
+            Some(OsuState::Failed(beatmap)) => Some(beatmap),
+            _ => None,
+        }
     }
 
-    fn screen_size(&self) -> (i32, i32) {
-        let x = (DEFAULT_SCREEN_SIZE.0 * self.scale) as i32;
-        let y = (DEFAULT_SCREEN_SIZE.1 * self.scale) as i32;
+    /// Song directory of the last opened beatmap selection
+    pub fn selected_song_dir(&self) -> Option<&PathBuf> {
+        self.beatmap_selection_data
+            .as_ref()
+            .map(|data| &data.beatmap_dir)
+    }
 
-        (x, y)
+    /// Whether players are choosing the next beatmap to play (the lobby)
+    pub fn is_selecting_beatmap(&self) -> bool {
+        matches!(
+            self.state,
+            Some(OsuState::SongSelection) | Some(OsuState::BeatmapSelection)
+        )
     }
 
-    fn screen_margin(&self) -> (i32, i32) {
-        let screen_size = self.screen_size();
-        let x = screen_size.0 as f64 * SCREEN_MARGIN_RATIO;
-        let y = screen_size.1 as f64 * SCREEN_MARGIN_RATIO;
+    pub fn life_bar_uuid(&self) -> Uuid {
+        self.life_bar_uuid
+    }
 
-        (x as i32, y as i32)
+    pub fn local_scores(&self) -> &LocalScores {
+        &self.local_scores
     }
 
-    pub fn player_spawn_pos(&self) -> DVec3 {
-        DEFAULT_SPAWN_POS * self.scale
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
     }
 
-    pub fn scale(&self) -> f64 {
-        self.scale
+    pub fn is_warmup(&self) -> bool {
+        self.warmup
     }
 
-    pub fn has_finished_music(&self) -> bool {
-        self.audio_player.has_finished()
+    pub fn set_warmup(&mut self, warmup: bool) {
+        self.warmup = warmup;
+    }
+
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance
+    }
+
+    pub fn set_maintenance(&mut self, maintenance: bool) {
+        self.maintenance = maintenance;
+    }
+
+    pub fn is_song_selection_locked(&self) -> bool {
+        self.song_selection_locked
+    }
+
+    pub fn set_song_selection_locked(&mut self, locked: bool) {
+        self.song_selection_locked = locked;
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Takes effect from the next started beatmap
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    fn save_local_score(
+        &mut self,
+        beatmap: &Beatmap,
+        player_names: &Query<&PlayerName, Without<Afk>>,
+    ) {
+        let player = player_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let beatmap_key = LocalScores::beatmap_key(beatmap);
+        let score = LocalScore {
+            player,
+            score: beatmap.state.score,
+            accuracy: beatmap.state.accuracy(),
+            max_combo: beatmap.state.max_combo,
+            aim_assist: beatmap.state.aim_assist,
+            unranked: beatmap.data.mods.is_unranked(),
+            pp: beatmap.performance_points(),
+        };
+
+        if let Err(error) = self.storage.save_score(&beatmap_key, &score) {
+            warn!("Error while saving score: {}", error);
+        }
+        self.local_scores.add(beatmap_key, score);
     }
 }
 
@@ -305,6 +679,16 @@ pub fn update_osu(
     hitcircles: Query<&mut Hitcircle>,
     rings: Query<&Ring>,
     mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName, Without<Afk>>,
+    hud_settings: Query<(Entity, &HudSettings)>,
+    (lag, mut game_mode, mut selected_slot_events, configs, mut input_guard, block_skin): (
+        Res<LagCompensation>,
+        ResMut<CustomGameMode>,
+        EventReader<UpdateSelectedSlot>,
+        Res<Configs>,
+        ResMut<InputGuard>,
+        Res<BlockSkin>,
+    ),
     mut instances_set: ParamSet<(
         Query<(Entity, &mut Instance), With<OsuInstance>>,
         Query<(Entity, &mut Instance)>,
@@ -359,11 +743,18 @@ pub fn update_osu(
 
             Ok(None)
         }
-        Some(OsuState::ScoreDisplay) => Ok(None),
+        Some(OsuState::ScoreDisplay(_)) | Some(OsuState::Failed(_)) => Ok(None),
         Some(OsuState::PrePlaying {
             beatmap,
             ticks_left,
         }) => {
+            // Skip credits screen
+            let ticks_left = if sneaking_events.iter().count() > 0 {
+                ticks_left.min(Osu::lead_in_ticks(&beatmap))
+            } else {
+                ticks_left
+            };
+
             if ticks_left == 0 {
                 Ok(Some(OsuStateChange::Playing(beatmap)))
             } else {
@@ -381,153 +772,332 @@ pub fn update_osu(
                 && beatmap.state.next_hit_object_idx >= beatmap.data.hit_objects.len()
                 && osu.audio_player.has_finished()
             {
+                // Adaptive plays are unranked
+                if !osu.warmup && beatmap.state.adaptive.is_none() {
+                    osu.save_local_score(&beatmap, &player_names);
+                }
                 Ok(Some(OsuStateChange::ScoreDisplay(beatmap)))
             }
             // Failed beatmap
             else if beatmap.state.health <= 0.0 {
-                Ok(Some(OsuStateChange::Failed))
+                Ok(Some(OsuStateChange::Failed(beatmap)))
             }
             // Beatmap is playing
             else {
-                // Remove expired hitcircles
-                let expired_hitcircles_count = beatmap
-                    .state
-                    .active_hit_objects
-                    .iter()
-                    .take_while(|&&entity| matches!(hitcircles.get(entity), Err(_)))
-                    .count();
-                beatmap.state.misses += expired_hitcircles_count;
-                for _ in 0..expired_hitcircles_count {
-                    beatmap.state.active_hit_objects.pop_front();
-                    beatmap.state.combo = 0;
-                    // Update health
-                    beatmap.state.health =
-                        beatmap.data.hp.drain(beatmap.state.health, HitScore::Miss);
-
-                    for mut client in &mut clients {
-                        play_hit_sound(&mut client, HitScore::Miss);
+                // Skip intro
+                if sneaking_events.iter().count() > 0 {
+                    if let Some(skip_time) = beatmap.intro_skip_time() {
+                        osu.clock().seek(skip_time);
                     }
                 }
 
-                if let Some(next_hitobject) = beatmap
-                    .data
-                    .hit_objects
-                    .get(beatmap.state.next_hit_object_idx)
-                {
-                    // Check we need to spawn the next hitcircle
-                    let play_time = osu.audio_player.play_time();
-                    beatmap.state.play_time = play_time;
-                    let look_ahead = beatmap.data.ar.to_mc_duration();
-                    let threshold = play_time + look_ahead;
-
-                    if threshold.as_millis() as u32 >= next_hitobject.time() {
-                        // Spawn hitcircle
-                        let screen_size = osu.screen_size();
-                        let margin_size = osu.screen_margin();
-                        let z_offset = next_hitobject.z(
-                            &beatmap.data.hit_objects[beatmap.state.next_hit_object_idx + 1..],
-                            beatmap.data.cs,
-                        );
-
-                        let center = DVec3::new(
-                            screen_size.0 as f64 - next_hitobject.x() as f64 * osu.scale(),
-                            (screen_size.1 as f64 - next_hitobject.y() as f64 * osu.scale())
-                                + margin_size.1 as f64,
-                            osu.screen_z + z_offset as f64,
-                        );
-
-                        let color = next_hitobject.color();
-                        let scale = osu.scale;
-                        let combo_number = next_hitobject.combo_number();
+                let play_time = osu.clock().play_time();
+                beatmap.state.play_time = play_time;
+                let players = hud_settings
+                    .iter()
+                    .filter_map(|(entity, _)| {
+                        clients
+                            .get(entity)
+                            .ok()
+                            .map(|client| (entity, client.position()))
+                    })
+                    .collect::<Vec<_>>();
 
+                // Remove expired hitcircles
+                let expired = beatmap.expire_hit_objects(|entity| match game_mode.handler() {
+                    Some(handler) => handler.is_expired(entity, play_time),
+                    None => hitcircles.get(entity).is_err(),
+                });
+                for _ in 0..expired {
+                    for (client_entity, settings) in &hud_settings {
+                        if let Ok(mut client) = clients.get_mut(client_entity) {
+                            osu.hitsounds
+                                .play(&mut client, settings.hitsound, HitScore::Miss);
+                        }
+                    }
+                }
+
+                // Check we need to spawn the next hitcircle
+                let look_ahead = match game_mode.handler() {
+                    Some(handler) => handler.look_ahead(&beatmap),
+                    None => beatmap.ar().to_mc_duration(),
+                };
+                let spawned =
+                    beatmap.spawn_due_hit_object(look_ahead, |next_hitobject, beatmap| {
                         let mut osu_instances = instances_set.p0();
-                        let osu_instance = osu_instances.get_single_mut().unwrap();
-                        match Hitcircle::from_beatmap(
-                            center,
-                            &beatmap.data,
-                            color,
-                            scale,
-                            combo_number,
-                            tps,
-                            osu_instance,
-                            &mut commands,
-                        ) {
-                            Ok(hitcircle) => {
-                                let hitcircle_entity = commands.spawn(hitcircle).id();
-
-                                beatmap.state.active_hit_objects.push_back(hitcircle_entity);
-                                beatmap.state.next_hit_object_idx += 1;
-                            }
-                            Err(error) => {
-                                warn!("Error while creating hitcircle: {}", error.to_string());
+                        let mut osu_instance = osu_instances.get_single_mut().unwrap();
+
+                        match game_mode.handler_mut() {
+                            Some(handler) => handler.spawn_hit_object(
+                                next_hitobject,
+                                &mut GameModeContext {
+                                    beatmap,
+                                    playfield: &osu.playfield,
+                                    tps,
+                                    players: &players,
+                                    instance: (osu_instance.0, &mut *osu_instance.1),
+                                    commands: &mut commands,
+                                },
+                            ),
+                            None => {
+                                // Spawn hitcircle
+                                let z_offset = next_hitobject.z(
+                                    &beatmap.data.hit_objects
+                                        [beatmap.state.next_hit_object_idx + 1..],
+                                    beatmap.cs(),
+                                );
+
+                                let (x, y) =
+                                    next_hitobject.stacked_pos(beatmap.cs(), osu.playfield.scale());
+                                let center = osu.playfield.hit_object_pos(x, y, z_offset as f64);
+
+                                let color = next_hitobject
+                                    .skin_color(&block_skin, configs.skin().ignore_beatmap_colors);
+                                let blocks = HitcircleBlocks::new(color, &block_skin);
+                                let scale = osu.playfield.scale();
+                                let combo_number = next_hitobject.combo_number();
+
+                                let radius = HitcircleRadius::from(beatmap.cs(), scale).circle;
+                                let center = osu.playfield.secure_hit_object_pos(
+                                    center,
+                                    radius,
+                                    &mut osu_instance.1,
+                                );
+                                Hitcircle::from_beatmap(
+                                    center,
+                                    beatmap,
+                                    blocks,
+                                    scale,
+                                    combo_number,
+                                    configs.skin(),
+                                    tps,
+                                    osu_instance,
+                                    &mut commands,
+                                )
+                                .map(|hitcircle| commands.spawn(hitcircle).id())
                             }
                         }
-                    }
+                    });
+                if let Err(error) = spawned {
+                    warn!("Error while creating hit object: {}", error.to_string());
                 }
 
                 // Check hitcircle hit
-                if let Some(&hitcircle_entity) = beatmap.state.active_hit_objects.front() {
-                    for clicked_client_entity in swing_arm_events
-                        .iter()
-                        .map(|e| e.client)
-                        .chain(swap_item_hand_events.iter().map(|e| e.client))
-                        .chain(drop_item_events.iter().map(|e| e.client))
-                    {
-                        let Ok(mut clicked_client) = clients.get_mut(clicked_client_entity) else {
-                        continue;
-                    };
-
-                        if let Ok(hitcircle) = hitcircles.get(hitcircle_entity) {
-                            if let Some(hit) = hitcircle.hit_score(&clicked_client, &rings) {
-                                // Update score (https://osu.ppy.sh/wiki/en/Gameplay/Score/ScoreV1/osu%21#hit-circles)
-                                let combo = beatmap.state.combo;
-                                let combo_multiplier = if combo == 0 { 0 } else { combo - 1 };
-                                let difficulty_multiplier = beatmap.data.difficulty_multiplier();
-                                let mod_multiplier = 1.0; // Mods not implemented
-
-                                beatmap.state.score += (hit.value() as f64
-                                    * (1.0
-                                        + (combo_multiplier as f64
-                                            * difficulty_multiplier
-                                            * mod_multiplier)
-                                            / 25.0))
-                                    as usize;
-
-                                // Update hit scores
-                                match hit {
-                                    HitScore::Hit300 => beatmap.state.hits300 += 1,
-                                    HitScore::Hit100 => beatmap.state.hits100 += 1,
-                                    HitScore::Hit50 => beatmap.state.hits50 += 1,
-                                    HitScore::Miss => beatmap.state.misses += 1,
-                                }
-
-                                // Update combo
-                                match hit {
-                                    HitScore::Hit300 | HitScore::Hit100 | HitScore::Hit50 => {
-                                        beatmap.state.combo += 1;
-                                        beatmap.state.max_combo =
-                                            beatmap.state.max_combo.max(beatmap.state.combo);
-                                    }
-                                    HitScore::Miss => beatmap.state.combo = 0,
+                let inputs = swing_arm_events
+                    .iter()
+                    .map(|e| e.client)
+                    .chain(swap_item_hand_events.iter().map(|e| e.client))
+                    .chain(drop_item_events.iter().map(|e| e.client))
+                    .map(|client| (client, GameModeInput::Click))
+                    .chain(
+                        selected_slot_events
+                            .iter()
+                            .map(|e| (e.client, GameModeInput::SelectSlot(e.slot as u8))),
+                    )
+                    .collect::<Vec<_>>();
+                // Input offset of the player, plus the time their inputs take to arrive
+                let input_offset_ms = |client_entity: Entity| {
+                    let ping_ms = clients.get(client_entity).map_or(0, |client| client.ping());
+                    hud_settings
+                        .get(client_entity)
+                        .map_or(0, |(_, settings)| settings.input_offset_ms)
+                        + ping_compensation_ms(ping_ms)
+                };
+                let aim_assist = |client_entity: Entity| {
+                    hud_settings
+                        .get(client_entity)
+                        .map_or(NO_AIM_ASSIST, |(_, settings)| {
+                            effective_aim_assist(settings.aim_assist, configs.max_aim_assist())
+                        })
+                };
+                let play_ms = beatmap.state.play_time.as_millis() as u64;
+
+                let mut hits = Vec::new();
+                match game_mode.handler_mut() {
+                    Some(handler) => {
+                        let mut osu_instances = instances_set.p0();
+                        if let Ok(mut osu_instance) = osu_instances.get_single_mut() {
+                            let mut context = GameModeContext {
+                                beatmap: &beatmap,
+                                playfield: &osu.playfield,
+                                tps,
+                                players: &players,
+                                instance: (osu_instance.0, &mut *osu_instance.1),
+                                commands: &mut commands,
+                            };
+
+                            hits.extend(handler.update(&mut context));
+                            for (client_entity, input) in inputs {
+                                let Ok(client) = clients.get(client_entity) else {
+                                    continue;
+                                };
+                                if !input_guard.accept(client_entity, play_ms) {
+                                    continue;
                                 }
+                                hits.extend(handler.judge_input(
+                                    input,
+                                    client_entity,
+                                    client,
+                                    input_offset_ms(client_entity),
+                                    &beatmap.state.active_hit_objects,
+                                    &mut context,
+                                ));
+                            }
+                        }
+                    }
+                    None => {
+                        let mods = beatmap.data.mods;
+                        // With RX, the oldest hitcircle is hit once it's due by the first player aiming at it
+                        if let Some((hitcircle_entity, hitcircle)) = beatmap
+                            .state
+                            .active_hit_objects
+                            .front()
+                            .filter(|_| mods.relax)
+                            .and_then(|&entity| Some((entity, hitcircles.get(entity).ok()?)))
+                            .filter(|(_, hitcircle)| hitcircle.is_due())
+                        {
+                            let aiming_player = players.iter().find_map(|&(client_entity, _)| {
+                                let client = clients.get(client_entity).ok()?;
+                                hitcircle
+                                    .is_aimed_by(client, &rings, aim_assist(client_entity))
+                                    .then_some(client_entity)
+                            });
+
+                            if let Some(client_entity) = aiming_player {
+                                beatmap.state.aim_assist =
+                                    beatmap.state.aim_assist.max(aim_assist(client_entity));
+                                hits.push(GameModeHit {
+                                    entity: hitcircle_entity,
+                                    player: client_entity,
+                                    hit: hitcircle.judge(lag.grace_ticks(), 0, tps),
+                                    hit_error_ms: Some(hitcircle.hit_error(tps, 0)),
+                                });
+                            }
+                        }
 
-                                // Play hitsound
-                                play_hit_sound(&mut clicked_client, hit);
+                        // Clicks are ignored with RX
+                        for (client_entity, input) in inputs.into_iter().filter(|_| !mods.relax) {
+                            // Each hit judges the oldest hitcircle which wasn't judged yet
+                            let Some(&hitcircle_entity) =
+                                beatmap.state.active_hit_objects.get(hits.len())
+                            else {
+                                break;
+                            };
+                            let (GameModeInput::Click, Ok(client), Ok(hitcircle)) = (
+                                input,
+                                clients.get(client_entity),
+                                hitcircles.get(hitcircle_entity),
+                            ) else {
+                                continue;
+                            };
+                            if !input_guard.accept(client_entity, play_ms) {
+                                continue;
+                            }
 
-                                // Update health
-                                beatmap.state.health =
-                                    beatmap.data.hp.drain(beatmap.state.health, hit);
+                            let input_offset_ms = input_offset_ms(client_entity);
+                            let aim_assist = aim_assist(client_entity);
+                            let hit = if mods.autopilot {
+                                Some(hitcircle.judge(lag.grace_ticks(), input_offset_ms, tps))
+                            } else {
+                                hitcircle.hit_score(
+                                    client,
+                                    &rings,
+                                    lag.grace_ticks(),
+                                    input_offset_ms,
+                                    aim_assist,
+                                    tps,
+                                )
+                            };
+                            if let Some(hit) = hit {
+                                beatmap.state.aim_assist = beatmap.state.aim_assist.max(aim_assist);
+                                input_guard.record_click(client_entity, true);
+                                hits.push(GameModeHit {
+                                    entity: hitcircle_entity,
+                                    player: client_entity,
+                                    hit,
+                                    hit_error_ms: Some(hitcircle.hit_error(tps, input_offset_ms)),
+                                });
+                            } else if input_guard.record_click(client_entity, false) {
+                                // Mashing on empty space
+                                beatmap.state.combo = 0;
+                            }
+                        }
+                    }
+                }
 
-                                // Despawn hit hitcircle
+                for game_mode_hit in hits {
+                    beatmap.apply_hit(&game_mode_hit);
+                    let GameModeHit {
+                        entity: hitcircle_entity,
+                        player: clicked_client_entity,
+                        hit,
+                        ..
+                    } = game_mode_hit;
+
+                    // Despawn hit object
+                    match game_mode.handler_mut() {
+                        Some(handler) => {
+                            let mut osu_instances = instances_set.p0();
+                            if let Ok(mut osu_instance) = osu_instances.get_single_mut() {
+                                handler.despawn_hit_object(
+                                    hitcircle_entity,
+                                    hit,
+                                    &mut GameModeContext {
+                                        beatmap: &beatmap,
+                                        playfield: &osu.playfield,
+                                        tps,
+                                        players: &players,
+                                        instance: (osu_instance.0, &mut *osu_instance.1),
+                                        commands: &mut commands,
+                                    },
+                                );
+                            }
+                        }
+                        None => {
+                            if let Ok(hitcircle) = hitcircles.get(hitcircle_entity) {
                                 let mut instances = instances_set.p1();
                                 commands.entity(hitcircle_entity).insert(Despawned);
                                 hitcircle
                                     .despawn(&mut commands, &rings, &mut instances, hit)
                                     .unwrap();
-                                beatmap.state.active_hit_objects.pop_front();
                             }
                         }
                     }
+
+                    let Ok(mut clicked_client) = clients.get_mut(clicked_client_entity) else {
+                        continue;
+                    };
+
+                    // Announce combo milestones
+                    if let Some(level) = combo_milestone_level(beatmap.state.combo) {
+                        let combo_burst = hud_settings
+                            .get(clicked_client_entity)
+                            .map(|(_, settings)| settings.combo_burst)
+                            .unwrap_or(true);
+                        if combo_burst {
+                            play_combo_milestone_sound(&mut clicked_client, level);
+                        }
+
+                        let mut osu_instances = instances_set.p0();
+                        if let Ok(osu_instance) = osu_instances.get_single_mut() {
+                            commands.spawn(ComboMilestoneNumber::new(
+                                beatmap.state.combo,
+                                osu.playfield.combo_milestone_pos(),
+                                osu.playfield.hud_digit_scale(),
+                                tps,
+                                osu_instance,
+                            ));
+                        }
+                    }
+
+                    // Play hitsound
+                    let hitsound = hud_settings
+                        .get(clicked_client_entity)
+                        .ok()
+                        .and_then(|(_, settings)| settings.hitsound);
+                    osu.hitsounds.play(&mut clicked_client, hitsound, hit);
+                    osu.hitsounds
+                        .play_beatmap_sample(osu.audio_player.as_ref(), hit);
                 }
 
                 health = beatmap.state.health as f32;
@@ -537,7 +1107,14 @@ pub fn update_osu(
         }
     };
 
-    for mut client in &mut clients {
+    for (client_entity, settings) in &hud_settings {
+        if !settings.boss_bar {
+            continue;
+        }
+        let Ok(mut client) = clients.get_mut(client_entity) else {
+            continue;
+        };
+
         client.write_packet(&BossBar {
             id: osu.life_bar_uuid,
             action: BossBarAction::Add {
@@ -551,13 +1128,29 @@ pub fn update_osu(
     }
 
     if let Ok(Some(state_change)) = possible_state_change {
+        if matches!(state_change, OsuStateChange::Playing(_)) {
+            input_guard.clear();
+        }
+        if let (OsuStateChange::Playing(beatmap), Some(handler)) =
+            (&state_change, game_mode.handler_mut())
+        {
+            handler.start(beatmap);
+            if let Some(start_pos) = handler.player_start_pos(&osu.playfield) {
+                for mut client in &mut clients {
+                    client.set_position(start_pos);
+                }
+            }
+        }
         if let Err(error) = osu.change_state(state_change, &mut clients) {
             error!("Error while changing osu state: '{}'", error)
         }
     }
 }
 
-pub fn send_welcome_message(mut new_clients: Query<&mut Client, Added<Client>>) {
+pub fn send_welcome_message(
+    configs: Res<Configs>,
+    mut new_clients: Query<&mut Client, Added<Client>>,
+) {
     for mut client in &mut new_clients {
         let title = "Welcome to".color(Color::AQUA) + " osucraft!".color(Color::GOLD);
         let instructions = "To hit a circle press one of the following:".color(Color::BLUE);
@@ -574,8 +1167,127 @@ pub fn send_welcome_message(mut new_clients: Query<&mut Client, Added<Client>>)
         let commands = "Commands: ".color(Color::YELLOW);
         let filter_songs = " - ".color(Color::RED)
             + "/filter-songs".color(Color::YELLOW)
-            + " <keywords>".color(Color::GRAY);
+            + " <keywords, artist=name, title=name, bpm>180, length<120>".color(Color::GRAY);
+        let filter_tags = " - ".color(Color::RED)
+            + "/filter-tags".color(Color::YELLOW)
+            + " <tags>".color(Color::GRAY);
         let reset_filter = " - ".color(Color::RED) + "/reset-filter".color(Color::YELLOW);
+        let filter_diffs = " - ".color(Color::RED)
+            + "/filter-diffs".color(Color::YELLOW)
+            + " <keywords, ar>9, od<=8, stars>5, mapper=name>".color(Color::GRAY);
+        let random = " - ".color(Color::RED)
+            + "/random".color(Color::YELLOW)
+            + " (random song from the filtered ones)".color(Color::GRAY);
+        let random_diff = " - ".color(Color::RED)
+            + "/random-diff".color(Color::YELLOW)
+            + " (plays a random difficulty of the selected song)".color(Color::GRAY);
+        let collection = " - ".color(Color::RED)
+            + "/collection [create|delete|add|remove|play] [name]".color(Color::YELLOW)
+            + " (song collections, shift-click a song to favorite it)".color(Color::GRAY);
+        let marathon = " - ".color(Color::RED)
+            + "/marathon [collection <name>|filter|stop]".color(Color::YELLOW)
+            + " (plays the songs one after the other adding up the scores)".color(Color::GRAY);
+        let hype = " - ".color(Color::RED)
+            + "/hype".color(Color::YELLOW)
+            + ", ".color(Color::GRAY)
+            + "/gg".color(Color::YELLOW)
+            + " (fireworks and cheers for everyone)".color(Color::GRAY);
+        let lobby = " - ".color(Color::RED)
+            + "/lobby [create|join|leave|ready|start]".color(Color::YELLOW)
+            + " (take turns picking maps, the host rotates after each one)".color(Color::GRAY);
+        let np = " - ".color(Color::RED)
+            + "/np".color(Color::YELLOW)
+            + ", ".color(Color::GRAY)
+            + "/map info".color(Color::YELLOW)
+            + " (difficulty, folder and website link of the current beatmap)".color(Color::GRAY);
+        let leaderboard = " - ".color(Color::RED)
+            + "/leaderboard".color(Color::YELLOW)
+            + " (global top scores of the current beatmap, if the server has a leaderboard)"
+                .color(Color::GRAY);
+        let daily = " - ".color(Color::RED)
+            + "/daily".color(Color::YELLOW)
+            + " [play]".color(Color::GRAY)
+            + format!(
+                " (map of the day and its leaderboard, the winner is announced at midnight {})",
+                configs.timezone()
+            )
+            .color(Color::GRAY);
+        let profile = " - ".color(Color::RED)
+            + "/profile".color(Color::YELLOW)
+            + " [player]".color(Color::GRAY)
+            + " (level, lifetime stats, performance points and top plays)".color(Color::GRAY);
+        let cancel = " - ".color(Color::RED)
+            + "/cancel".color(Color::YELLOW)
+            + " (stops your running operations, e.g. song indexing)".color(Color::GRAY);
+        let mods = " - ".color(Color::RED)
+            + "/mods".color(Color::YELLOW)
+            + " [ez|hr|hd|rx|ap|nm]".color(Color::GRAY)
+            + " (plays the next beatmaps with these mods, RX and AP scores are unranked)"
+                .color(Color::GRAY);
+        let latency_test = " - ".color(Color::RED)
+            + "/latencytest [apply|reset]".color(Color::YELLOW)
+            + " (measures the delay of your taps to use it as input offset)".color(Color::GRAY);
+        let hitsound = " - ".color(Color::RED)
+            + "/hitsound".color(Color::YELLOW)
+            + " [name|default]".color(Color::GRAY)
+            + " (chooses the sound of your hits)".color(Color::GRAY);
+        let hud = " - ".color(Color::RED)
+            + "/hud".color(Color::YELLOW)
+            + " (HUD settings)".color(Color::GRAY);
+        let dim = " - ".color(Color::RED)
+            + "/dim".color(Color::YELLOW)
+            + " [0-100|off]".color(Color::GRAY)
+            + " (darkens or brightens the screen background you see)".color(Color::GRAY);
+        let browse = " - ".color(Color::RED)
+            + "/browse".color(Color::YELLOW)
+            + " <search> [status=ranked] [mode=osu]".color(Color::GRAY)
+            + " (downloads new maps from the beatmap mirror)".color(Color::GRAY);
+        let replays = " - ".color(Color::RED)
+            + "/replays".color(Color::YELLOW)
+            + " [all|passes|best|none]".color(Color::GRAY)
+            + " (which of your replays are saved)".color(Color::GRAY);
+        let set_songs_dir = " - ".color(Color::RED)
+            + "/set-songs-dir".color(Color::YELLOW)
+            + " <path>".color(Color::GRAY)
+            + " (operators only)".color(Color::DARK_GRAY);
+        let maintenance = " - ".color(Color::RED)
+            + "/maintenance".color(Color::YELLOW)
+            + " (operators only, blocks new maps before a restart)".color(Color::DARK_GRAY);
+        let warmup = " - ".color(Color::RED)
+            + "/warmup".color(Color::YELLOW)
+            + " (toggles free play without recording scores, operators only)"
+                .color(Color::DARK_GRAY);
+        let adaptive = " - ".color(Color::RED)
+            + "/adaptive".color(Color::YELLOW)
+            + " (toggles the unranked mode where AR and CS adapt to your hits, operators only)"
+                .color(Color::DARK_GRAY);
+        let reset_arena = " - ".color(Color::RED)
+            + "/reset-arena".color(Color::YELLOW)
+            + " (clears the playfield if it gets corrupted, operators only)"
+                .color(Color::DARK_GRAY);
+        let reset_playfield = " - ".color(Color::RED)
+            + "/reset-playfield".color(Color::YELLOW)
+            + " (clears leftover circles and numbers, keeps the lobby and menus, operators only)"
+                .color(Color::DARK_GRAY);
+        let volume = " - ".color(Color::RED)
+            + "/volume".color(Color::YELLOW)
+            + " [master|music|effects] <0-100>".color(Color::GRAY)
+            + " (server audio, operators only)".color(Color::DARK_GRAY);
+        let skin = " - ".color(Color::RED)
+            + "/skin".color(Color::YELLOW)
+            + " [name|default|beatmap-colors]".color(Color::GRAY)
+            + " (blocks of the playfield from the skins folder, operators only)"
+                .color(Color::DARK_GRAY);
+        let force_play = " - ".color(Color::RED)
+            + "/force-play".color(Color::YELLOW)
+            + " <song> [difficulty] [+mods]".color(Color::GRAY)
+            + " (starts the map for everyone, operators only)".color(Color::DARK_GRAY);
+        let bundle_report = " - ".color(Color::RED)
+            + "/bundle-report".color(Color::YELLOW)
+            + " (operators only, for bug reports)".color(Color::DARK_GRAY);
+        let admin = " - ".color(Color::RED)
+            + "/admin".color(Color::YELLOW)
+            + " (operators only, event controls)".color(Color::DARK_GRAY);
 
         let messages = [
             title,
@@ -587,7 +1299,38 @@ pub fn send_welcome_message(mut new_clients: Query<&mut Client, Added<Client>>)
             empty,
             commands,
             filter_songs,
+            filter_tags,
             reset_filter,
+            filter_diffs,
+            random,
+            random_diff,
+            collection,
+            marathon,
+            hype,
+            lobby,
+            np,
+            leaderboard,
+            daily,
+            profile,
+            cancel,
+            mods,
+            latency_test,
+            hitsound,
+            hud,
+            dim,
+            browse,
+            replays,
+            set_songs_dir,
+            warmup,
+            maintenance,
+            adaptive,
+            reset_arena,
+            reset_playfield,
+            volume,
+            skin,
+            force_play,
+            bundle_report,
+            admin,
         ];
 
         for message in messages.into_iter() {
@@ -596,12 +1339,13 @@ pub fn send_welcome_message(mut new_clients: Query<&mut Client, Added<Client>>)
     }
 }
 
-fn play_hit_sound(client: &mut Mut<Client>, hit: HitScore) {
-    let (sound, category) = if matches!(hit, HitScore::Miss) {
-        (Sound::EntityChickenHurt, SoundCategory::Block)
-    } else {
-        (Sound::EntityChickenEgg, SoundCategory::Block)
-    };
+fn play_fail_sound(client: &mut Mut<Client>) {
     let position = client.position();
-    client.play_sound(sound, category, position, 3.0, 1.0);
+    client.play_sound(
+        Sound::EntityPlayerDeath,
+        SoundCategory::Master,
+        position,
+        3.0,
+        0.5,
+    );
 }
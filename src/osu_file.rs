@@ -0,0 +1,132 @@
+use anyhow::{anyhow, bail, Result};
+use osu_file_parser::OsuFile;
+use tracing::warn;
+
+/// Sections of the .osu files read by osucraft, the other ones are dropped by the lenient parsing
+const KEPT_SECTIONS: [&str; 7] = [
+    "General",
+    "Metadata",
+    "Difficulty",
+    "Events",
+    "TimingPoints",
+    "Colours",
+    "HitObjects",
+];
+/// File format versions supported by `osu_file_parser`
+const OLDEST_VERSION: u32 = 3;
+const LATEST_VERSION: u32 = 14;
+
+/// Parses a .osu file. Files rejected by `osu_file_parser` (frequent in old beatmaps, e.g. with a line it doesn't expect) are
+/// parsed again leniently: only the sections osucraft needs are kept, without the lines which can't be parsed.
+pub fn parse_osu_file(text: &str) -> Result<OsuFile> {
+    let strict_error = match text.parse::<OsuFile>() {
+        Ok(osu_file) => return Ok(osu_file),
+        Err(error) => error.to_string(),
+    };
+
+    let (osu_file, skipped) = parse_lenient(text).map_err(|_| anyhow!("{}", strict_error))?;
+    warn!(
+        "Beatmap parsed leniently ({}), {} lines were skipped",
+        strict_error, skipped
+    );
+
+    Ok(osu_file)
+}
+
+/// Parses the `KEPT_SECTIONS` of `text`, skipping the lines `osu_file_parser` rejects. Returns how many lines were skipped.
+fn parse_lenient(text: &str) -> Result<(OsuFile, usize)> {
+    let mut lines = text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim_end)
+        .skip_while(|line| line.trim().is_empty());
+    let version = lines
+        .next()
+        .and_then(|header| header.trim().strip_prefix("osu file format v"))
+        .and_then(|version| version.parse::<u32>().ok())
+        .ok_or_else(|| anyhow!("not a .osu file"))?
+        .clamp(OLDEST_VERSION, LATEST_VERSION);
+    let header = format!("osu file format v{}", version);
+
+    let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with("//") {
+            continue;
+        }
+
+        match trimmed
+            .strip_prefix('[')
+            .and_then(|section| section.strip_suffix(']'))
+        {
+            Some(section) => sections.push((section, Vec::new())),
+            None => {
+                if let Some((_, section_lines)) = sections.last_mut() {
+                    section_lines.push(line);
+                }
+            }
+        }
+    }
+
+    let mut skipped = 0;
+    let mut lenient_text = header.clone();
+    for (section, section_lines) in sections
+        .iter()
+        .filter(|(section, _)| KEPT_SECTIONS.contains(section))
+    {
+        // Each line is parsed alone, the lines of these sections don't depend on each other
+        let (valid, invalid): (Vec<&str>, Vec<&str>) =
+            section_lines.iter().copied().partition(|line| {
+                format!("{}\n\n[{}]\n{}\n", header, section, line)
+                    .parse::<OsuFile>()
+                    .is_ok()
+            });
+        skipped += invalid.len();
+
+        lenient_text += &format!("\n\n[{}]\n{}", section, valid.join("\n"));
+    }
+
+    let osu_file = lenient_text
+        .parse::<OsuFile>()
+        .map_err(|error| anyhow!("{}", error))?;
+    if osu_file
+        .hitobjects
+        .as_ref()
+        .map_or(true, |hitobjects| hitobjects.0.is_empty())
+    {
+        bail!("no hit object could be parsed");
+    }
+
+    Ok((osu_file, skipped))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lenient_parsing() {
+        let text = "\u{feff}osu file format v14\n\
+            \n\
+            [Metadata]\n\
+            Title:Old classic\n\
+            \n\
+            [Difficulty]\n\
+            CircleSize:4\n\
+            \n\
+            [Unknown]\n\
+            whatever\n\
+            \n\
+            [HitObjects]\n\
+            256,192,1000,1,0,0:0:0:0:\n\
+            not a hit object\n\
+            128,96,1500,1,0,0:0:0:0:\n";
+
+        let (osu_file, skipped) = parse_lenient(text).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(osu_file.hitobjects.unwrap().0.len(), 2);
+        assert!(parse_osu_file(text).is_ok());
+
+        assert!(parse_osu_file("not a beatmap").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use bevy_ecs::system::{Local, Res, ResMut};
+use valence::{
+    prelude::{Color, PlayerList, Server},
+    protocol::TextFormat,
+};
+
+use crate::{aim_assist::NO_AIM_ASSIST, osu::Osu, scores::LocalScores};
+
+const LEADERBOARD_SIZE: usize = 5;
+
+/// Shows the current beatmap and its local leaderboard in the player list (tab) header/footer
+pub fn update_player_list_leaderboard(
+    osu: Res<Osu>,
+    server: Res<Server>,
+    mut player_list: ResMut<PlayerList>,
+    mut ticks: Local<usize>,
+    mut was_playing: Local<bool>,
+) {
+    let tps = server.shared().tps() as usize;
+    *ticks += 1;
+
+    let Some(beatmap) = osu.playing_beatmap() else {
+        if *was_playing {
+            player_list.set_header("");
+            player_list.set_footer("");
+            *was_playing = false;
+        }
+        return;
+    };
+
+    // Refresh every second
+    if *was_playing && *ticks < tps {
+        return;
+    }
+    *ticks = 0;
+    *was_playing = true;
+
+    let header = "Now playing: ".color(Color::GOLD)
+        + format!("{} - {}", beatmap.data.artist, beatmap.data.title).color(Color::WHITE)
+        + format!(" [{}]", beatmap.data.difficulty_name).color(Color::AQUA);
+
+    let beatmap_key = LocalScores::beatmap_key(beatmap);
+    let local_scores = osu.local_scores();
+    let mut footer = "======= Local leaderboard =======".color(Color::GOLD);
+
+    let top_scores = local_scores.top(&beatmap_key, LEADERBOARD_SIZE);
+    if top_scores.is_empty() {
+        footer = footer + "\n" + "No scores yet".color(Color::GRAY);
+    }
+    for (idx, local_score) in top_scores.iter().enumerate() {
+        footer = footer
+            + "\n"
+            + format!("#{} ", idx + 1).color(Color::YELLOW)
+            + local_score.player.clone().color(Color::WHITE)
+            + format!("  {}", local_score.score).color(Color::GOLD)
+            + format!("  {:.2}%", local_score.accuracy).color(Color::GREEN)
+            + format!("  x{}", local_score.max_combo).color(Color::LIGHT_PURPLE);
+        if local_score.aim_assist > NO_AIM_ASSIST {
+            footer =
+                footer + format!("  (aim assist x{})", local_score.aim_assist).color(Color::GRAY);
+        }
+        if local_score.unranked {
+            footer = footer + "  (unranked)".color(Color::GRAY);
+        }
+    }
+
+    let position = local_scores.position(&beatmap_key, beatmap.state.score);
+    footer = footer
+        + "\n\n"
+        + "Current run: ".color(Color::AQUA)
+        + format!("#{}", position).color(Color::YELLOW)
+        + format!("  {}", beatmap.state.score).color(Color::WHITE);
+
+    player_list.set_header(header);
+    player_list.set_footer(footer);
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use bevy_ecs::{
+    prelude::{Component, Entity},
+    query::{With, Without},
+    system::{Commands, Query},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::TextFormat,
+};
+
+/// Unique name of a player. In offline mode several players can join with the same username (e.g. everyone in a LAN party using "Player"),
+/// so the players who join later get a suffix derived from their IP address. Scores and settings are saved with this name.
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct PlayerName(String);
+
+impl PlayerName {
+    #[cfg(test)]
+    pub fn new(name: &str) -> Self {
+        Self(name.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Short code of an IP address. It's hashed with FNV-1a, which doesn't change across restarts, and it doesn't reveal the address.
+fn ip_code(ip: IpAddr) -> String {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let hash = octets.iter().fold(0x811c9dc5_u32, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+
+    format!("{:04x}", hash & 0xffff)
+}
+
+/// `username` if it's not taken, otherwise `username#code` with the code of `ip`, so the player gets the same name whenever
+/// they join from the same address. Players sharing the address and the username are numbered (`username#code-n`, n >= 2).
+fn unique_name(username: &str, ip: IpAddr, taken: &HashSet<String>) -> String {
+    if !taken.contains(username) {
+        return username.to_string();
+    }
+
+    let name = format!("{username}#{}", ip_code(ip));
+    if !taken.contains(&name) {
+        return name;
+    }
+
+    (2..)
+        .map(|n| format!("{name}-{n}"))
+        .find(|name| !taken.contains(name))
+        .unwrap()
+}
+
+pub fn assign_player_names(
+    mut commands: Commands,
+    mut new_clients: Query<(Entity, &mut Client), Without<PlayerName>>,
+    player_names: Query<&PlayerName, With<Client>>,
+) {
+    let mut taken: HashSet<_> = player_names
+        .iter()
+        .map(|name| name.as_str().to_string())
+        .collect();
+
+    for (entity, mut client) in &mut new_clients {
+        let name = unique_name(client.username(), client.ip(), &taken);
+
+        if name != client.username() {
+            client.send_message(
+                "Another player is already using the name ".color(Color::YELLOW)
+                    + client.username().to_string().color(Color::WHITE)
+                    + ", your scores and settings will be saved as ".color(Color::YELLOW)
+                    + name.clone().color(Color::GREEN),
+            );
+        }
+
+        taken.insert(name.clone());
+        commands.entity(entity).insert(PlayerName(name));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unique_names() {
+        let ip: IpAddr = "192.168.1.23".parse().unwrap();
+        let other_ip: IpAddr = "192.168.1.24".parse().unwrap();
+        let code = ip_code(ip);
+        assert_eq!(code.len(), 4);
+        assert_ne!(code, ip_code(other_ip));
+
+        let mut taken = HashSet::new();
+        assert_eq!(unique_name("Player", ip, &taken), "Player");
+
+        taken.insert("Player".to_string());
+        assert_eq!(unique_name("Player", ip, &taken), format!("Player#{code}"));
+        assert_eq!(unique_name("Other", ip, &taken), "Other");
+
+        taken.insert(format!("Player#{code}"));
+        assert_eq!(
+            unique_name("Player", ip, &taken),
+            format!("Player#{code}-2")
+        );
+        assert_eq!(
+            unique_name("Player", other_ip, &taken),
+            format!("Player#{}", ip_code(other_ip))
+        );
+    }
+}
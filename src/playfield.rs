@@ -0,0 +1,463 @@
+use std::{cmp::max, collections::HashMap};
+
+use bevy_ecs::{
+    query::With,
+    system::{Query, Res, ResMut, Resource},
+};
+use tracing::{info, warn};
+use valence::{
+    instance::ChunkEntry,
+    prelude::{Block, BlockPos, BlockState, DVec3, Instance},
+};
+
+use crate::{block_batch::BlockBatch, configs::Configs, osu::OsuInstance};
+
+const SCREEN_MARGIN_RATIO: f64 = 0.5;
+const DEFAULT_SCREEN_SIZE: (f64, f64) = (640.0, 480.0);
+const DEFAULT_SPAWN_POS: DVec3 = DVec3::new(
+    DEFAULT_SCREEN_SIZE.0 / 1.75,
+    DEFAULT_SCREEN_SIZE.1 * (1.0 + 2.0 * SCREEN_MARGIN_RATIO) / 2.25,
+    -500.0,
+);
+/// Distance from the screen cleared by `Playfield::reset` (hitcircles and numbers are drawn in front of the screen, stacked ones a few blocks further)
+const RESET_DEPTH: i32 = 8;
+
+/// Screen where the game is displayed, placed at `origin` in the instance. Every position is relative to the origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Playfield {
+    origin: BlockPos,
+    scale: f64,
+    /// Block of the screen behind the hitcircles (see `BlockSkin::screen_background`)
+    background: BlockState,
+}
+
+impl Playfield {
+    pub fn new(origin: BlockPos, scale: f64) -> Self {
+        Self {
+            origin,
+            scale,
+            background: BlockState::BLACK_CONCRETE,
+        }
+    }
+
+    pub fn with_background(mut self, background: BlockState) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn origin(&self) -> BlockPos {
+        self.origin
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn background(&self) -> BlockState {
+        self.background
+    }
+
+    /// Loads the chunks of the playfield and draws its screen
+    pub fn init(&self, instance: &mut Instance) {
+        self.init_chunks(instance);
+        self.init_screen(instance);
+        self.init_player_spawn(instance);
+    }
+
+    /// Clears every block drawn in front of the screen and draws the screen again, as it was after `init`
+    pub fn reset(&self, instance: &mut Instance) {
+        let mut batch = BlockBatch::new();
+        for z in -RESET_DEPTH..=0 {
+            batch.fill(self.screen_positions(z), BlockState::AIR);
+        }
+        batch.apply(instance);
+
+        self.init_screen(instance);
+        self.init_player_spawn(instance);
+    }
+
+    fn init_chunks(&self, instance: &mut Instance) {
+        let (screen_x, _) = self.screen_size();
+        let (margin_x, _) = self.screen_margin();
+        let min_x = self.origin.x - margin_x;
+        let max_x = self.origin.x + screen_x + margin_x;
+        let max_z = self.player_spawn_pos().z as i32;
+
+        for x in min_x.div_euclid(16) - 1..=max_x.div_euclid(16) + 1 {
+            for z in max_z.div_euclid(16) - 1..=self.origin.z.div_euclid(16) + 1 {
+                if let ChunkEntry::Vacant(chunk) = instance.chunk_entry([x, z]) {
+                    chunk.insert(Default::default());
+                }
+            }
+        }
+    }
+
+    fn init_screen(&self, instance: &mut Instance) {
+        let mut batch = BlockBatch::new();
+        batch.fill(self.screen_background_positions(), self.background);
+        batch.apply(instance);
+    }
+
+    fn init_player_spawn(&self, instance: &mut Instance) {
+        let spawn_pos = self.player_spawn_pos();
+
+        let block_pos = BlockPos {
+            x: spawn_pos.x as i32,
+            y: spawn_pos.y as i32 - 1,
+            z: spawn_pos.z as i32 - 1,
+        };
+
+        instance.set_block(block_pos, Block::new(BlockState::BEDROCK));
+    }
+
+    /// Fills the screen background (including margins) with `block`
+    pub fn paint_screen(&self, surface: &mut PlayfieldSurface, block: BlockState) {
+        surface.fill(self.screen_background_positions(), block);
+    }
+
+    /// Positions of the screen background, behind the hitcircles
+    pub fn background_positions(&self) -> impl Iterator<Item = BlockPos> + '_ {
+        self.screen_background_positions()
+    }
+
+    fn screen_background_positions(&self) -> impl Iterator<Item = BlockPos> + '_ {
+        self.screen_positions(1)
+    }
+
+    /// Positions of the screen (including margins) at the distance `z` from the playfield origin
+    fn screen_positions(&self, z: i32) -> impl Iterator<Item = BlockPos> + '_ {
+        let (max_x, max_y) = self.screen_size();
+        let (margin_x, margin_y) = self.screen_margin();
+
+        (-margin_x..=max_x + margin_x)
+            .flat_map(move |x| (0..=max_y + 2 * margin_y).map(move |y| self.block_pos(x, y, z)))
+    }
+
+    /// Position in the instance of the playfield relative position
+    fn block_pos(&self, x: i32, y: i32, z: i32) -> BlockPos {
+        BlockPos {
+            x: self.origin.x + x,
+            y: self.origin.y + y,
+            z: self.origin.z + z,
+        }
+    }
+
+    pub fn screen_size(&self) -> (i32, i32) {
+        let x = (DEFAULT_SCREEN_SIZE.0 * self.scale) as i32;
+        let y = (DEFAULT_SCREEN_SIZE.1 * self.scale) as i32;
+
+        (x, y)
+    }
+
+    pub fn screen_margin(&self) -> (i32, i32) {
+        let screen_size = self.screen_size();
+        let x = screen_size.0 as f64 * SCREEN_MARGIN_RATIO;
+        let y = screen_size.1 as f64 * SCREEN_MARGIN_RATIO;
+
+        (x as i32, y as i32)
+    }
+
+    /// Position in the instance of a hit object in the osu! playfield coordinates (`z_offset` is the distance from the screen)
+    pub fn hit_object_pos(&self, x: f64, y: f64, z_offset: f64) -> DVec3 {
+        let (screen_x, screen_y) = self.screen_size();
+        let (_, margin_y) = self.screen_margin();
+
+        DVec3::new(
+            self.origin.x as f64 + screen_x as f64 - x * self.scale,
+            self.origin.y as f64 + (screen_y as f64 - y * self.scale) + margin_y as f64,
+            self.origin.z as f64 + z_offset,
+        )
+    }
+
+    /// Moves a hit object of `radius` centered at `center` inside the screen (including margins) if it's outside, so none of its
+    /// blocks are dropped, and loads the chunks it covers which are not loaded. Returns the center where it must be drawn.
+    pub fn secure_hit_object_pos(
+        &self,
+        center: DVec3,
+        radius: f64,
+        instance: &mut Instance,
+    ) -> DVec3 {
+        let clamped = self.clamp_to_screen(center, radius);
+        if clamped != center {
+            warn!(
+                "Hit object at ({:.0}, {:.0}) is outside the screen, moved to ({:.0}, {:.0})",
+                center.x, center.y, clamped.x, clamped.y
+            );
+        }
+
+        // One more block for the outline around the circle
+        let min_x = (clamped.x - radius).floor() as i32 - 1;
+        let max_x = (clamped.x + radius).ceil() as i32 + 1;
+        let z = clamped.z.floor() as i32;
+        for chunk_x in min_x.div_euclid(16)..=max_x.div_euclid(16) {
+            if let ChunkEntry::Vacant(chunk) = instance.chunk_entry([chunk_x, z.div_euclid(16)]) {
+                info!(
+                    "Loading chunk ({}, {}) for a hit object",
+                    chunk_x,
+                    z.div_euclid(16)
+                );
+                chunk.insert(Default::default());
+            }
+        }
+
+        clamped
+    }
+
+    /// Osu!pixels x of the instance `x`, the inverse of `hit_object_pos`
+    pub fn osu_x(&self, x: f64) -> f64 {
+        let (screen_x, _) = self.screen_size();
+
+        (self.origin.x as f64 + screen_x as f64 - x) / self.scale
+    }
+
+    /// Whether `pos` is in front of the screen (including margins)
+    pub fn is_in_screen(&self, pos: DVec3) -> bool {
+        self.clamp_to_screen(pos, 0.0) == pos
+    }
+
+    /// `center` moved so that a hit object of `radius` fits in the screen (including margins), or the center of the screen if it can't fit
+    fn clamp_to_screen(&self, center: DVec3, radius: f64) -> DVec3 {
+        let (screen_x, screen_y) = self.screen_size();
+        let (margin_x, margin_y) = self.screen_margin();
+        let clamp = |value: f64, min: i32, max: i32| {
+            let (min, max) = (min as f64 + radius, max as f64 - radius);
+            if min > max {
+                (min + max) / 2.0
+            } else {
+                value.clamp(min, max)
+            }
+        };
+
+        DVec3::new(
+            clamp(
+                center.x,
+                self.origin.x - margin_x,
+                self.origin.x + screen_x + margin_x,
+            ),
+            clamp(
+                center.y,
+                self.origin.y,
+                self.origin.y + screen_y + 2 * margin_y,
+            ),
+            center.z,
+        )
+    }
+
+    pub fn player_spawn_pos(&self) -> DVec3 {
+        DVec3::new(
+            self.origin.x as f64,
+            self.origin.y as f64,
+            self.origin.z as f64,
+        ) + DEFAULT_SPAWN_POS * self.scale
+    }
+
+    /// Position in the bottom left corner of the screen (from the player's perspective) where combo milestones are displayed
+    pub fn combo_milestone_pos(&self) -> BlockPos {
+        let (screen_x, _) = self.screen_size();
+        let (margin_x, margin_y) = self.screen_margin();
+
+        self.block_pos(screen_x + margin_x / 2, margin_y / 2, 0)
+    }
+
+    /// Row of blocks in the bottom of the screen, ordered from left to right (from the player's perspective)
+    pub fn progress_bar_positions(&self) -> Vec<BlockPos> {
+        let (screen_x, _) = self.screen_size();
+        let (_, margin_y) = self.screen_margin();
+
+        (0..=screen_x)
+            .rev()
+            .map(|x| self.block_pos(x, margin_y / 4, 0))
+            .collect()
+    }
+
+    /// Row of blocks under the screen, above the progress bar, where the song waveform is drawn (ordered like `progress_bar_positions`)
+    pub fn waveform_positions(&self) -> Vec<BlockPos> {
+        let (screen_x, _) = self.screen_size();
+        let (_, margin_y) = self.screen_margin();
+
+        (0..=screen_x)
+            .rev()
+            .map(|x| self.block_pos(x, margin_y / 2, 0))
+            .collect()
+    }
+
+    /// Center of the top margin of the screen where the countdown before the first hit object is displayed
+    pub fn countdown_pos(&self) -> BlockPos {
+        let (screen_x, screen_y) = self.screen_size();
+        let (_, margin_y) = self.screen_margin();
+
+        self.block_pos(screen_x / 2, screen_y + margin_y + margin_y / 2, 0)
+    }
+
+    /// Center, width and height of the top margin of the screen
+    pub fn top_margin_area(&self) -> (BlockPos, usize, usize) {
+        let (screen_x, _) = self.screen_size();
+        let (margin_x, margin_y) = self.screen_margin();
+
+        (
+            self.countdown_pos(),
+            (screen_x + 2 * margin_x) as usize,
+            margin_y as usize,
+        )
+    }
+
+    /// Center, width and height of the left margin of the screen (from the player's perspective)
+    pub fn left_margin_area(&self) -> (BlockPos, usize, usize) {
+        let (screen_x, screen_y) = self.screen_size();
+        let (margin_x, margin_y) = self.screen_margin();
+
+        (
+            self.block_pos(screen_x + margin_x / 2, margin_y + screen_y / 2, 0),
+            margin_x as usize,
+            screen_y as usize,
+        )
+    }
+
+    /// Center of the playfield
+    pub fn screen_center(&self) -> BlockPos {
+        let (screen_x, screen_y) = self.screen_size();
+        let (_, margin_y) = self.screen_margin();
+
+        self.block_pos(screen_x / 2, margin_y + screen_y / 2, 0)
+    }
+
+    /// Scale of the grade drawn on the playfield after finishing a beatmap
+    pub fn grade_digit_scale(&self) -> usize {
+        max(self.screen_size().1 as usize / 8, 1)
+    }
+
+    /// Scale of the numbers displayed in the screen margins
+    pub fn hud_digit_scale(&self) -> usize {
+        max(self.screen_margin().1 as usize / 18, 1)
+    }
+}
+
+/// Double-buffered blocks of the playfield. Systems draw the target blocks into the back buffer during the tick
+/// and `flush_playfield` applies only the blocks which differ from the front buffer (what players are seeing) in a single batch,
+/// so redraws (e.g. clearing and drawing in the same positions) don't flicker.
+///
+/// The surface is meant for cosmetic redraws (screens, background art), which are spread over several ticks when they exceed
+/// `Configs::max_screen_block_updates`. Judgment-critical blocks (hitcircles, hit scores) are set directly in the instance, so they are never delayed.
+#[derive(Resource, Default)]
+pub struct PlayfieldSurface {
+    front: HashMap<BlockPos, BlockState>,
+    /// Blocks drawn since the last flush (any other block is the same as in the front buffer)
+    back: HashMap<BlockPos, BlockState>,
+    /// Flushed changes which didn't fit in the block updates budget of the previous ticks
+    pending: BlockBatch,
+}
+
+impl PlayfieldSurface {
+    pub fn set(&mut self, pos: BlockPos, block: BlockState) {
+        self.back.insert(pos, block);
+    }
+
+    /// Sets every position in `positions` to `block`
+    pub fn fill(&mut self, positions: impl IntoIterator<Item = BlockPos>, block: BlockState) {
+        self.back
+            .extend(positions.into_iter().map(|pos| (pos, block)));
+    }
+
+    /// Block which will be displayed in `pos` after the next flush (`None` if it was never drawn through the surface)
+    pub fn block(&self, pos: BlockPos) -> Option<BlockState> {
+        self.back
+            .get(&pos)
+            .or_else(|| self.front.get(&pos))
+            .copied()
+    }
+
+    /// Whether every block drawn so far was applied to the instance
+    pub fn is_flushed(&self) -> bool {
+        self.back.is_empty() && self.pending.is_empty()
+    }
+
+    /// Swaps the buffers, returning the blocks which changed
+    fn swap(&mut self) -> BlockBatch {
+        let mut changes = BlockBatch::new();
+
+        for (pos, block) in self.back.drain() {
+            if self.front.insert(pos, block) != Some(block) {
+                changes.set(pos, block);
+            }
+        }
+
+        changes
+    }
+}
+
+/// Applies the blocks drawn in the `PlayfieldSurface` during the tick
+pub fn flush_playfield(
+    mut surface: ResMut<PlayfieldSurface>,
+    configs: Res<Configs>,
+    mut instances: Query<&mut Instance, With<OsuInstance>>,
+) {
+    if surface.is_flushed() {
+        return;
+    }
+
+    let Ok(mut instance) = instances.get_single_mut() else {
+        return;
+    };
+
+    let changes = surface.swap();
+    surface.pending.merge(changes);
+
+    let max_changes = configs.max_screen_block_updates().unwrap_or(usize::MAX);
+    surface.pending.apply_limited(&mut instance, max_changes);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn positions_relative_to_origin() {
+        let first = Playfield::new(BlockPos { x: 0, y: 0, z: 0 }, 0.3);
+        let second = Playfield::new(BlockPos { x: 500, y: 0, z: 0 }, 0.3);
+
+        let offset = second.screen_center().x - first.screen_center().x;
+        assert_eq!(offset, second.origin().x);
+        assert_eq!(
+            second.player_spawn_pos().x - first.player_spawn_pos().x,
+            offset as f64
+        );
+    }
+
+    #[test]
+    fn hit_objects_stay_in_screen() {
+        let playfield = Playfield::new(BlockPos { x: 500, y: 0, z: 0 }, 0.3);
+        let inside = playfield.hit_object_pos(256.0, 192.0, -1.0);
+        assert_eq!(playfield.clamp_to_screen(inside, 10.0), inside);
+
+        let outside = playfield.hit_object_pos(-2000.0, 5000.0, -1.0);
+        let clamped = playfield.clamp_to_screen(outside, 10.0);
+        let (screen_x, _) = playfield.screen_size();
+        let (margin_x, _) = playfield.screen_margin();
+        assert_eq!(
+            clamped.x,
+            (playfield.origin().x + screen_x + margin_x) as f64 - 10.0
+        );
+        assert_eq!(clamped.y, playfield.origin().y as f64 + 10.0);
+        assert_eq!(clamped.z, outside.z);
+    }
+
+    #[test]
+    fn only_changed_blocks_are_flushed() {
+        let mut surface = PlayfieldSurface::default();
+        let a = BlockPos { x: 0, y: 0, z: 0 };
+        let b = BlockPos { x: 1, y: 0, z: 0 };
+
+        surface.fill([a, b], BlockState::BLACK_CONCRETE);
+        assert_eq!(surface.swap().len(), 2);
+
+        // Clearing and redrawing the same block doesn't change anything
+        surface.set(a, BlockState::AIR);
+        surface.set(a, BlockState::BLACK_CONCRETE);
+        surface.set(b, BlockState::GRAY_CONCRETE);
+        assert_eq!(surface.block(b), Some(BlockState::GRAY_CONCRETE));
+        assert_eq!(surface.swap().len(), 1);
+
+        assert!(surface.swap().is_empty());
+        assert_eq!(surface.block(a), Some(BlockState::BLACK_CONCRETE));
+    }
+}
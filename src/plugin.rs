@@ -1,37 +1,230 @@
 use bevy_ecs::schedule::{IntoSystemDescriptor, SystemSet};
+use std::sync::Mutex;
 use valence::bevy_app::Plugin;
 
 use crate::{
+    admin::{admin_command, execute_admin_commands, handle_admin_clicks, show_maintenance_notice},
+    afk::update_afk_players,
+    aim_assist::{assist_command, execute_assist_commands},
+    api::update_api,
+    arena::{
+        execute_reset_arena, execute_reset_playfield, reset_arena_command, reset_playfield_command,
+    },
+    background_dim::{dim_command, execute_dim_commands, update_background_dim},
+    beat_pulse::update_beat_pulse,
+    beatmap_browser::{
+        browse_command, execute_browse_commands, handle_beatmap_browser_clicks,
+        update_beatmap_browsers, BeatmapBrowserInventory,
+    },
     beatmap_selection::{handle_beatmap_selection_clicks, update_beatmap_selection_inventory},
-    commands::{execute_commands, register_mc_commands},
+    collections::handle_collection_browser_clicks,
+    combo::update_combo_milestone_numbers,
+    command_registry::{
+        answer_command_suggestions, dispatch_commands, OsuCommand, RegisterCommands,
+    },
+    commands::{execute_commands, osu_commands, register_mc_commands},
+    commentary::update_commentary,
+    countdown::update_countdown,
+    credits_screen::update_credits_screen,
+    crosshair::update_crosshairs,
+    cursor_trail::update_cursor_trails,
+    daily::{daily_command, execute_daily_commands, record_daily_scores, update_daily_challenge},
+    fail_screen::{handle_fail_screen_clicks, update_fail_screen},
+    force_play::{execute_force_play, force_play_command},
+    game_mode::{CustomGameMode, GameModeHandler},
     hit_score::update_score_hit_numbers,
-    hitcircle::update_hitcircle,
-    inventory::{open_queued_inventories, InventoriesToOpen},
+    hitcircle::{update_hitcircle, update_hitcircle_outlines},
+    hitsound::{execute_hitsound_commands, hitsound_command},
+    hud::{
+        handle_hud_settings_clicks, init_hud_settings, update_action_bar_hud, HudSettingsInventory,
+    },
+    hype::HypeCooldowns,
+    input::InputGuard,
+    inventory::{despawn_orphaned_inventories, open_queued_inventories, InventoriesToOpen},
+    key_overlay::update_key_overlay,
+    lag::{update_lag_compensation, LagCompensation},
+    latency_test::{
+        execute_latency_test_commands, latency_test_command, update_latency_tests, LatencyTests,
+    },
+    leaderboard::{
+        execute_leaderboard_commands, leaderboard_command, submit_scores,
+        update_leaderboard_requests,
+    },
+    lobby::{execute_lobby_commands, lobby_command, update_lobby, Lobby},
+    local_leaderboard::{update_local_leaderboard, LocalLeaderboard},
+    map_info::{execute_map_info_commands, map_command, np_command},
+    map_vote::{handle_map_vote_clicks, update_map_vote},
+    marathon::{update_marathon, Marathon},
+    mods::{apply_mods_to_beatmaps, execute_mods_commands, mods_command, Mods},
+    now_playing::{update_now_playing, write_now_playing_file},
     osu::{send_welcome_message, update_osu},
-    ring::update_rings,
-    song_selection::{handle_song_selection_clicks, update_song_selection_inventory},
+    player_list::update_player_list_leaderboard,
+    player_name::assign_player_names,
+    playfield::{flush_playfield, PlayfieldSurface},
+    profile::{execute_profile_commands, init_player_stats, profile_command, update_player_stats},
+    progress::{report_long_operations, LongOperations},
+    progress_bar::update_progress_bar,
+    replays::{execute_replays_commands, record_replays, replays_command, ReplayRecorder},
+    resets::update_reset_countdown,
+    resync::resync_joining_clients,
+    ring::{update_particle_rings, update_rings},
+    score_screen::{handle_score_screen_clicks, update_grade_display, update_score_screen},
+    scoreboard::update_sidebar_hud,
+    session::update_session_stats,
+    skin::{execute_skin_commands, skin_command, BlockSkin},
+    song_selection::{
+        handle_song_selection_clicks, update_metadata_indexing, update_song_selection_inventory,
+    },
+    sound_effects::play_menu_clicks,
+    volume::{execute_volume_commands, volume_command},
+    waveform::update_waveform,
 };
 
-pub struct OsuPlugin;
+#[derive(Default)]
+pub struct OsuPlugin {
+    /// Taken by `Plugin::build`, which only gets a shared reference
+    game_mode: Mutex<Option<Box<dyn GameModeHandler>>>,
+}
+
+impl OsuPlugin {
+    /// Plays the beatmaps with a custom game mode instead of osu!standard (see `GameModeHandler`)
+    pub fn with_game_mode(handler: impl GameModeHandler + 'static) -> Self {
+        Self {
+            game_mode: Mutex::new(Some(Box::new(handler))),
+        }
+    }
+}
 
 impl Plugin for OsuPlugin {
     fn build(&self, app: &mut valence::prelude::App) {
         app.add_system_set(
             SystemSet::new()
                 .label("osu")
+                .with_system(
+                    update_lag_compensation
+                        .before(update_osu)
+                        .before(update_hitcircle),
+                )
                 .with_system(update_osu)
+                .with_system(update_player_list_leaderboard)
+                .with_system(update_progress_bar)
+                .with_system(update_waveform)
+                .with_system(update_countdown)
+                .with_system(update_now_playing)
+                .with_system(update_credits_screen)
+                .with_system(update_local_leaderboard)
+                .with_system(update_reset_countdown)
+                .with_system(update_session_stats)
+                .with_system(report_long_operations)
+                .with_system(update_metadata_indexing)
+                .with_system(update_fail_screen)
+                .with_system(handle_fail_screen_clicks)
+                .with_system(update_score_screen)
+                .with_system(update_grade_display)
+                .with_system(update_background_dim.before(flush_playfield))
+                .with_system(
+                    flush_playfield
+                        .after(update_now_playing)
+                        .after(update_credits_screen)
+                        .after(update_local_leaderboard)
+                        .after(update_fail_screen)
+                        .after(update_grade_display)
+                        .after(update_waveform),
+                )
+                .with_system(update_map_vote.after(update_score_screen))
+                .with_system(update_marathon.after(update_osu))
+                .with_system(update_commentary.after(update_osu))
+                .with_system(submit_scores.after(update_osu))
+                .with_system(update_player_stats.after(update_osu))
+                .with_system(update_daily_challenge)
+                .with_system(record_daily_scores.after(update_osu))
+                .with_system(update_leaderboard_requests)
+                .with_system(update_api.after(update_osu))
+                .with_system(write_now_playing_file.after(update_osu))
+                .with_system(update_lobby.after(update_osu))
+                .with_system(handle_map_vote_clicks.after(open_queued_inventories))
+                .with_system(handle_score_screen_clicks)
+                .with_system(assign_player_names)
+                .with_system(update_afk_players)
+                .with_system(init_hud_settings)
+                .with_system(init_player_stats)
+                .with_system(resync_joining_clients.after(update_hitcircle_outlines))
+                .with_system(update_sidebar_hud)
+                .with_system(update_action_bar_hud)
+                .with_system(update_key_overlay)
+                .with_system(update_cursor_trails.after(update_osu))
+                .with_system(update_crosshairs.after(update_osu))
+                .with_system(handle_hud_settings_clicks.after(open_queued_inventories))
                 .with_system(update_rings)
+                .with_system(update_particle_rings)
+                .with_system(update_beat_pulse.after(update_rings))
                 .with_system(update_hitcircle)
+                .with_system(
+                    update_hitcircle_outlines
+                        .after(update_osu)
+                        .after(update_hitcircle),
+                )
                 .with_system(update_score_hit_numbers)
+                .with_system(update_combo_milestone_numbers)
                 .with_system(open_queued_inventories)
+                .with_system(despawn_orphaned_inventories::<HudSettingsInventory>)
+                .with_system(despawn_orphaned_inventories::<BeatmapBrowserInventory>)
                 .with_system(update_song_selection_inventory)
                 .with_system(handle_song_selection_clicks.after(open_queued_inventories))
                 .with_system(update_beatmap_selection_inventory)
                 .with_system(handle_beatmap_selection_clicks)
+                .with_system(apply_mods_to_beatmaps.before(handle_beatmap_selection_clicks))
+                .with_system(handle_collection_browser_clicks.after(open_queued_inventories))
                 .with_system(register_mc_commands)
-                .with_system(execute_commands)
+                .with_system(dispatch_commands)
+                .with_system(answer_command_suggestions)
+                .with_system(handle_admin_clicks.after(open_queued_inventories))
+                .with_system(show_maintenance_notice)
+                .with_system(record_replays.after(update_osu))
+                .with_system(update_beatmap_browsers)
+                .with_system(handle_beatmap_browser_clicks.after(open_queued_inventories))
+                .with_system(play_menu_clicks)
+                .with_system(update_latency_tests)
                 .with_system(send_welcome_message),
         )
-        .init_resource::<InventoriesToOpen>();
+        .add_event::<OsuCommand>()
+        .add_commands(osu_commands(), execute_commands)
+        .add_commands([lobby_command()], execute_lobby_commands)
+        .add_commands([reset_arena_command()], execute_reset_arena)
+        .add_commands([reset_playfield_command()], execute_reset_playfield)
+        .add_commands([latency_test_command()], execute_latency_test_commands)
+        .add_commands([mods_command()], execute_mods_commands)
+        .add_commands([hitsound_command()], execute_hitsound_commands)
+        .add_commands([force_play_command()], execute_force_play)
+        .add_commands([volume_command()], execute_volume_commands)
+        .add_commands([skin_command()], execute_skin_commands)
+        .add_commands([assist_command()], execute_assist_commands)
+        .add_commands([dim_command()], execute_dim_commands)
+        .add_commands([browse_command()], execute_browse_commands)
+        .add_commands([replays_command()], execute_replays_commands)
+        .add_commands([admin_command()], execute_admin_commands)
+        .add_commands([np_command(), map_command()], execute_map_info_commands)
+        .add_commands([leaderboard_command()], execute_leaderboard_commands)
+        .add_commands([profile_command()], execute_profile_commands)
+        .add_commands([daily_command()], execute_daily_commands)
+        .init_resource::<InventoriesToOpen>()
+        .init_resource::<LagCompensation>()
+        .init_resource::<InputGuard>()
+        .init_resource::<PlayfieldSurface>()
+        .init_resource::<BlockSkin>()
+        .init_resource::<LongOperations>()
+        .init_resource::<Marathon>()
+        .init_resource::<HypeCooldowns>()
+        .init_resource::<Lobby>()
+        .init_resource::<LatencyTests>()
+        .init_resource::<Mods>()
+        .insert_resource(ReplayRecorder::start())
+        .init_resource::<LocalLeaderboard>()
+        .insert_resource(CustomGameMode::new(
+            self.game_mode
+                .lock()
+                .ok()
+                .and_then(|mut game_mode| game_mode.take()),
+        ));
     }
 }
@@ -0,0 +1,39 @@
+//! Types needed to embed osucraft in a valence server (see `main.rs`), so they can be imported
+//! with `use osucraft::prelude::*` instead of from the modules where they are defined, which may change.
+
+pub use crate::{
+    afk::Afk,
+    api::Api,
+    audio::{open_audio_player, AudioPlayer, NullAudioBackend, RodioAudioBackend},
+    audio_clock::{AudioClock, MonotonicClock},
+    beatmap::{
+        ApproachRate, Beatmap, BeatmapData, BeatmapState, BeatmapStats, CircleSize, Grade,
+        HpDrainRate, OverallDifficulty,
+    },
+    catch::CatchMode,
+    collections::Collections,
+    commentary::Commentary,
+    configs::{Configs, Skin},
+    daily::DailyChallenge,
+    game_mode::{GameModeContext, GameModeHandler, GameModeHit, GameModeInput, GameModeKind},
+    hit_score::HitScore,
+    hitsound::{HitsoundKind, Hitsounds},
+    leaderboard::GlobalLeaderboard,
+    lobby::Lobby,
+    mania::ManiaMode,
+    marathon::Marathon,
+    mods::Mods,
+    osu::{BeatmapSelectionData, Hitwindow, Osu, OsuInstance, OsuState, OsuStateChange},
+    player_name::PlayerName,
+    playfield::{Playfield, PlayfieldSurface},
+    plugin::OsuPlugin,
+    preprocess::{PreprocessStep, Preprocessor},
+    profile::PlayerStats,
+    progress::{LongOperation, LongOperations, Progress},
+    scores::{LocalScore, LocalScores},
+    session::SessionStats,
+    skin::BlockSkin,
+    sound_effects::{SoundEffect, SoundEffects},
+    storage::{Storage, StorageKind},
+    volume::Volume,
+};
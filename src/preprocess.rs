@@ -0,0 +1,242 @@
+use crate::{
+    hit_object::{apply_stacking, HitObject},
+    mods::Mods,
+};
+
+/// Change to the hit objects of a beatmap, applied once when it's loaded (see `Preprocessor`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PreprocessStep {
+    /// Mirrors the hit objects upside down, added by HR
+    FlipVertically,
+    /// Turns each slider into a hitcircle at its head and another one at its tail
+    SliderEnds,
+    /// Moves the hit objects outside the playfield to its closest border, e.g. the ones of some old beatmaps
+    ClampToPlayfield,
+    /// Stacks the hit objects in the same position (see `apply_stacking`)
+    Stacking,
+}
+
+/// Pipeline over the hit objects of a beatmap, run by `Beatmap::try_from`. Sliders and spinners are played as hitcircles, the
+/// sliders can also be played as a hitcircle at each end with `with_slider_ends`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Preprocessor {
+    steps: Vec<PreprocessStep>,
+    /// Mods the beatmaps are loaded with, which also change their difficulty settings (see `Beatmap::ar`)
+    mods: Mods,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            steps: vec![PreprocessStep::ClampToPlayfield, PreprocessStep::Stacking],
+            mods: Mods::default(),
+        }
+    }
+}
+
+impl Preprocessor {
+    /// Adds `step` before the clamping and the stacking, which need the final positions of the hit objects (e.g. for mods
+    /// moving them)
+    pub fn with_step(mut self, step: PreprocessStep) -> Self {
+        if self.steps.contains(&step) {
+            return self;
+        }
+
+        let index = self
+            .steps
+            .iter()
+            .position(|&step| step == PreprocessStep::ClampToPlayfield)
+            .unwrap_or(self.steps.len());
+        self.steps.insert(index, step);
+        self
+    }
+
+    pub fn with_slider_ends(self, slider_ends: bool) -> Self {
+        if slider_ends {
+            self.with_step(PreprocessStep::SliderEnds)
+        } else {
+            self
+        }
+    }
+
+    /// Replaces the mods, with their steps (HR flips the hit objects)
+    pub fn with_mods(mut self, mods: Mods) -> Self {
+        self.mods = mods;
+        self.steps
+            .retain(|&step| step != PreprocessStep::FlipVertically);
+
+        if mods.hard_rock {
+            self.with_step(PreprocessStep::FlipVertically)
+        } else {
+            self
+        }
+    }
+
+    pub fn mods(&self) -> Mods {
+        self.mods
+    }
+
+    pub fn steps(&self) -> &[PreprocessStep] {
+        &self.steps
+    }
+
+    /// Runs the steps in order. `stack_threshold_ms` is the preempt time scaled by the stack leniency of the beatmap.
+    pub fn apply(&self, hit_objects: &mut Vec<HitObject>, stack_threshold_ms: u32) {
+        for step in &self.steps {
+            match step {
+                PreprocessStep::FlipVertically => {
+                    for hit_object in hit_objects.iter_mut() {
+                        hit_object.flip_vertically();
+                    }
+                }
+                PreprocessStep::SliderEnds => split_sliders(hit_objects),
+                PreprocessStep::ClampToPlayfield => {
+                    for hit_object in hit_objects.iter_mut() {
+                        hit_object.clamp_to_playfield();
+                    }
+                }
+                PreprocessStep::Stacking => apply_stacking(hit_objects, stack_threshold_ms),
+            }
+        }
+    }
+}
+
+/// Replaces the sliders by their ends (see `HitObject::slider_ends`), shifting the combo numbers of the next hit objects of
+/// their combos
+fn split_sliders(hit_objects: &mut Vec<HitObject>) {
+    let mut split = Vec::with_capacity(hit_objects.len());
+    let mut shift = 0;
+
+    for mut hit_object in hit_objects.drain(..) {
+        if hit_object.combo_number() == 1 {
+            shift = 0;
+        }
+        hit_object.shift_combo_number(shift);
+
+        match hit_object.slider_ends() {
+            Some((head, tail)) => {
+                split.push(head);
+                split.push(tail);
+                shift += 1;
+            }
+            None => split.push(hit_object),
+        }
+    }
+
+    // Tails ending after the next hit object started
+    split.sort_by_key(|hit_object| hit_object.time());
+    *hit_objects = split;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        beatmap::CircleSize,
+        hit_object::{HitObjectParams, SliderEnd},
+        hitcircle::HitcircleRadius,
+        test_support::synthetic_beatmap,
+    };
+
+    #[test]
+    fn preprocessing_steps() {
+        let preprocessor = Preprocessor::default().with_slider_ends(true);
+        assert_eq!(
+            preprocessor.steps(),
+            [
+                PreprocessStep::SliderEnds,
+                PreprocessStep::ClampToPlayfield,
+                PreprocessStep::Stacking
+            ]
+        );
+        assert_eq!(
+            Preprocessor::default().with_slider_ends(false),
+            Preprocessor::default()
+        );
+    }
+
+    #[test]
+    fn hard_rock() {
+        let hard_rock = Mods::parse("hr").unwrap();
+        let preprocessor = Preprocessor::default().with_mods(hard_rock);
+        assert_eq!(preprocessor.steps()[0], PreprocessStep::FlipVertically);
+        assert_eq!(
+            preprocessor.clone().with_mods(Mods::default()),
+            Preprocessor::default()
+        );
+
+        let mut hit_objects = vec![
+            HitObject::hitcircle_at(0).with_pos(100, 50),
+            HitObject::hitcircle_at(500)
+                .with_pos(200, 300)
+                .with_slider_end(SliderEnd {
+                    x: 300,
+                    y: 384,
+                    time: 800,
+                }),
+        ];
+        preprocessor.apply(&mut hit_objects, 0);
+        let positions: Vec<_> = hit_objects.iter().map(|h| (h.x(), h.y())).collect();
+        assert_eq!(positions, vec![(100, 334), (200, 84)]);
+        assert_eq!(hit_objects[1].slider_end().unwrap().y, 0);
+
+        // CS x1.3, AR and OD x1.4 capped at 10
+        let mut beatmap = synthetic_beatmap(5.0, 5.0, &[1000]);
+        let nomod_radius = HitcircleRadius::from(beatmap.cs(), 1.0).circle;
+        beatmap.data.mods = hard_rock;
+        let hard_rock_radius = HitcircleRadius::from(beatmap.cs(), 1.0).circle;
+        assert!(
+            (hard_rock_radius - HitcircleRadius::from(CircleSize(5.2), 1.0).circle).abs() < 1e-9
+        );
+        assert!(hard_rock_radius < nomod_radius);
+        assert!((beatmap.ar().0 - 10.0).abs() < 1e-9);
+        assert!((beatmap.od().0 - 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clamp_off_screen_hit_objects() {
+        let mut hit_objects = vec![
+            HitObject::hitcircle_at(0).with_pos(-20, 100),
+            HitObject::hitcircle_at(1000).with_pos(600, 500),
+        ];
+        Preprocessor::default().apply(&mut hit_objects, 0);
+
+        let positions: Vec<_> = hit_objects.iter().map(|h| (h.x(), h.y())).collect();
+        assert_eq!(positions, vec![(0, 100), (512, 384)]);
+    }
+
+    #[test]
+    fn sliders_into_hitcircles() {
+        let slider_end = SliderEnd {
+            x: 400,
+            y: 192,
+            time: 1500,
+        };
+        let mut hit_objects = vec![
+            HitObject::hitcircle_at(1000)
+                .with_pos(100, 192)
+                .with_slider_end(slider_end),
+            HitObject::hitcircle_at(2000),
+        ];
+        Preprocessor::default()
+            .with_slider_ends(true)
+            .apply(&mut hit_objects, 0);
+
+        let circles: Vec<_> = hit_objects
+            .iter()
+            .map(|h| (h.x(), h.time(), h.combo_number()))
+            .collect();
+        assert_eq!(
+            circles,
+            vec![(100, 1000, 1), (400, 1500, 2), (256, 2000, 1)]
+        );
+        assert!(hit_objects
+            .iter()
+            .all(|h| matches!(h.params(), HitObjectParams::Hitcircle)));
+
+        // Without the step, sliders are played as a hitcircle at their head
+        let mut hit_objects = vec![HitObject::hitcircle_at(1000).with_slider_end(slider_end)];
+        Preprocessor::default().apply(&mut hit_objects, 0);
+        assert_eq!(hit_objects.len(), 1);
+    }
+}
@@ -0,0 +1,338 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::{Added, Without},
+    system::{Commands, Local, Query, Res},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{types::SoundCategory, Sound, Text, TextFormat},
+};
+
+use crate::{
+    afk::Afk,
+    beatmap::BeatmapState,
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    error::error_message,
+    osu::Osu,
+    player_name::PlayerName,
+    scores::{LocalScore, LocalScores},
+};
+
+/// Plays counted in the total performance points, like in osu!
+const WEIGHTED_PLAYS: usize = 100;
+/// Weight decay of the performance points, from the best play to the worst one
+const PLAY_WEIGHT_DECAY: f64 = 0.95;
+/// Top plays shown by `/profile`
+const SHOWN_TOP_PLAYS: usize = 5;
+/// Total score of the level 2, the next levels need the square of the level (minus one) times this score
+const LEVEL_SCORE: u64 = 1_000_000;
+
+/// Lifetime stats of a player, recorded when a beatmap is finished or failed (except the warmup, adaptive and unranked plays)
+#[derive(Component, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct PlayerStats {
+    pub plays: usize,
+    pub total_score: u64,
+    /// Sum of the accuracies of every play (see `PlayerStats::accuracy`)
+    pub accuracy_sum: f64,
+    pub play_time_secs: u64,
+}
+
+impl PlayerStats {
+    pub fn record(&mut self, state: &BeatmapState) {
+        self.plays += 1;
+        self.total_score += state.score as u64;
+        self.accuracy_sum += state.accuracy() as f64;
+        self.play_time_secs += state.play_time.as_secs();
+    }
+
+    /// Average accuracy of the plays
+    pub fn accuracy(&self) -> f64 {
+        if self.plays == 0 {
+            return 0.0;
+        }
+
+        self.accuracy_sum / self.plays as f64
+    }
+
+    pub fn level(&self) -> u64 {
+        level(self.total_score)
+    }
+}
+
+/// Level reached with `total_score`, starting at 1
+pub fn level(total_score: u64) -> u64 {
+    ((total_score / LEVEL_SCORE) as f64).sqrt() as u64 + 1
+}
+
+/// Lifetime stats of a player and their performance over the local scores of the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerProfile {
+    pub player: String,
+    pub stats: PlayerStats,
+    /// Performance points of the best play of each beatmap, weighted from the best to the worst one
+    pub performance_points: f64,
+    /// Best plays with their beatmap keys (see `LocalScores::beatmap_key`)
+    pub top_plays: Vec<(String, LocalScore)>,
+}
+
+impl PlayerProfile {
+    pub fn new(player: &str, stats: PlayerStats, local_scores: &LocalScores) -> Self {
+        let top_plays = local_scores.top_plays(player, WEIGHTED_PLAYS);
+        let weights = std::iter::successors(Some(1.0), |weight| Some(weight * PLAY_WEIGHT_DECAY));
+        let performance_points = top_plays
+            .iter()
+            .zip(weights)
+            .map(|((_, score), weight)| score.pp * weight)
+            .sum();
+
+        Self {
+            player: player.to_string(),
+            stats,
+            performance_points,
+            top_plays: top_plays
+                .into_iter()
+                .take(SHOWN_TOP_PLAYS)
+                .map(|(beatmap_key, score)| (beatmap_key.to_string(), score.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn lines(&self) -> Vec<Text> {
+        let stats = &self.stats;
+        let play_time_mins = stats.play_time_secs / 60;
+        let mut lines = vec![
+            "Profile of ".color(Color::GOLD)
+                + self.player.clone().color(Color::AQUA)
+                + format!("  (level {})", stats.level()).color(Color::YELLOW),
+            "Plays: ".color(Color::YELLOW)
+                + stats.plays.to_string().color(Color::WHITE)
+                + "  Play time: ".color(Color::YELLOW)
+                + format!("{}h {:02}m", play_time_mins / 60, play_time_mins % 60)
+                    .color(Color::WHITE),
+            "Total score: ".color(Color::YELLOW)
+                + stats.total_score.to_string().color(Color::WHITE)
+                + "  Accuracy: ".color(Color::YELLOW)
+                + format!("{:.2}%", stats.accuracy()).color(Color::WHITE),
+            "Performance: ".color(Color::YELLOW)
+                + format!("{:.0}pp", self.performance_points).color(Color::WHITE),
+        ];
+        if self.top_plays.is_empty() {
+            lines.push("No ranked plays yet".color(Color::GRAY));
+        }
+
+        for (idx, (beatmap_key, score)) in self.top_plays.iter().enumerate() {
+            lines.push(
+                format!("#{} ", idx + 1).color(Color::YELLOW)
+                    + beatmap_key.clone().color(Color::WHITE)
+                    + format!("  {:.0}pp", score.pp).color(Color::AQUA)
+                    + format!("  {:.2}%", score.accuracy).color(Color::GREEN)
+                    + format!("  x{}", score.max_combo).color(Color::LIGHT_PURPLE),
+            );
+        }
+
+        lines
+    }
+}
+
+/// Loads the lifetime stats of the players who join
+pub fn init_player_stats(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    new_clients: Query<(Entity, &PlayerName), Added<PlayerName>>,
+) {
+    for (client_entity, player_name) in &new_clients {
+        let stats = osu
+            .storage()
+            .load_player_stats(player_name.as_str())
+            .unwrap_or_else(|error| {
+                warn!("Error while loading player stats: {}", error);
+                None
+            })
+            .unwrap_or_default();
+
+        commands.entity(client_entity).insert(stats);
+    }
+}
+
+/// Records finished and failed beatmaps in the lifetime stats of the players, congratulating the ones who level up
+pub fn update_player_stats(
+    osu: Res<Osu>,
+    mut players: Query<(&mut Client, &PlayerName, &mut PlayerStats), Without<Afk>>,
+    mut recorded: Local<bool>,
+) {
+    let Some(beatmap) = osu.finished_beatmap().or_else(|| osu.failed_beatmap()) else {
+        *recorded = false;
+        return;
+    };
+    if *recorded {
+        return;
+    }
+    *recorded = true;
+
+    // Warmup, adaptive and RX/AP plays don't count towards the level
+    if osu.is_warmup() || beatmap.state.adaptive.is_some() || beatmap.data.mods.is_unranked() {
+        return;
+    }
+
+    for (mut client, player_name, mut stats) in &mut players {
+        let level = stats.level();
+        stats.record(&beatmap.state);
+        if let Err(error) = osu
+            .storage()
+            .save_player_stats(player_name.as_str(), &stats)
+        {
+            warn!("Error while saving player stats: {}", error);
+        }
+
+        if stats.level() > level {
+            let message = "Level up! ".color(Color::GOLD)
+                + format!("You reached level {}", stats.level()).color(Color::YELLOW);
+            client.send_message(message.clone());
+            client.set_action_bar(message);
+
+            let position = client.position();
+            client.play_sound(
+                Sound::EntityPlayerLevelup,
+                SoundCategory::Player,
+                position,
+                1.0,
+                1.0,
+            );
+        }
+    }
+}
+
+pub fn profile_command() -> CommandSpec {
+    CommandSpec::new("profile").arg(ArgSpec::word("player"))
+}
+
+/// Handles `/profile [player]`, which shows the lifetime stats, the performance points and the top plays of a player (the
+/// one who sent it by default)
+pub fn execute_profile_commands(
+    osu: Res<Osu>,
+    mut clients: Query<&mut Client>,
+    players: Query<(&PlayerName, &PlayerStats)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "profile" {
+            continue;
+        }
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+
+        let player = command_event.args.trim();
+        let result = if player.is_empty() {
+            players
+                .get(command_event.client)
+                .map(|(name, stats)| (name.as_str().to_string(), stats.clone()))
+                .map_err(|_| anyhow!("Your stats are not loaded yet"))
+        } else {
+            find_player_stats(&osu, &players, player).map(|stats| (player.to_string(), stats))
+        };
+
+        match result {
+            Ok((player, stats)) => {
+                let profile = PlayerProfile::new(&player, stats, osu.local_scores());
+                for line in profile.lines() {
+                    client.send_message(line);
+                }
+            }
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+/// Stats of the online player named `player`, or the saved ones if they are offline
+fn find_player_stats(
+    osu: &Osu,
+    players: &Query<(&PlayerName, &PlayerStats)>,
+    player: &str,
+) -> Result<PlayerStats> {
+    if let Some((_, stats)) = players.iter().find(|(name, _)| name.as_str() == player) {
+        return Ok(stats.clone());
+    }
+
+    osu.storage()
+        .load_player_stats(player)?
+        .ok_or_else(|| anyhow!("No player named '{}' has played on this server", player))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aim_assist::NO_AIM_ASSIST;
+    use std::time::Duration;
+
+    fn local_score(pp: f64) -> LocalScore {
+        LocalScore {
+            player: "player".to_string(),
+            score: 1000,
+            accuracy: 100.0,
+            max_combo: 100,
+            aim_assist: NO_AIM_ASSIST,
+            unranked: false,
+            pp,
+        }
+    }
+
+    #[test]
+    fn weighted_performance_points() {
+        let mut local_scores = LocalScores::default();
+        for (idx, pp) in [100.0, 200.0, 50.0, 10.0, 10.0, 10.0]
+            .into_iter()
+            .enumerate()
+        {
+            local_scores.add(format!("beatmap {}", idx), local_score(pp));
+        }
+
+        let profile = PlayerProfile::new("player", PlayerStats::default(), &local_scores);
+        let expected = 200.0
+            + 100.0 * 0.95
+            + 50.0 * 0.95_f64.powi(2)
+            + 10.0 * (0.95_f64.powi(3) + 0.95_f64.powi(4) + 0.95_f64.powi(5));
+        assert!((profile.performance_points - expected).abs() < 1e-9);
+        assert_eq!(profile.top_plays.len(), SHOWN_TOP_PLAYS);
+        assert_eq!(profile.top_plays[0].0, "beatmap 1");
+        assert_eq!(profile.lines().len(), 4 + SHOWN_TOP_PLAYS);
+
+        let empty = PlayerProfile::new("nobody", PlayerStats::default(), &local_scores);
+        assert_eq!(empty.performance_points, 0.0);
+        assert_eq!(empty.lines().len(), 5);
+    }
+
+    #[test]
+    fn lifetime_stats() {
+        let mut stats = PlayerStats::default();
+        assert_eq!(stats.accuracy(), 0.0);
+        assert_eq!(stats.level(), 1);
+
+        let mut state = BeatmapState {
+            score: 900_000,
+            hits300: 1,
+            play_time: Duration::from_secs(90),
+            ..Default::default()
+        };
+        stats.record(&state);
+        state.hits300 = 0;
+        state.hits100 = 1;
+        state.score = 200_000;
+        stats.record(&state);
+
+        assert_eq!(stats.plays, 2);
+        assert_eq!(stats.total_score, 1_100_000);
+        assert!((stats.accuracy() - (100.0 + 100.0 / 3.0) / 2.0).abs() < 1e-4);
+        assert_eq!(stats.play_time_secs, 180);
+        assert_eq!(stats.level(), 2);
+
+        assert_eq!(level(3_999_999), 2);
+        assert_eq!(level(4_000_000), 3);
+        assert_eq!(level(100_000_000), 11);
+    }
+}
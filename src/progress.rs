@@ -0,0 +1,252 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use bevy_ecs::{
+    prelude::Entity,
+    system::{Query, ResMut, Resource},
+};
+use tracing::info;
+use valence::{
+    prelude::{Client, Color, Uuid},
+    protocol::{
+        packets::s2c::play::BossBar,
+        types::{BossBarAction, BossBarColor, BossBarDivision, BossBarFlags},
+        TextFormat,
+    },
+};
+
+/// Progress is logged to the console every time it advances this percentage
+const LOG_PERCENT_STEP: usize = 10;
+
+/// Progress of a long operation, shared between the thread running it and the server
+#[derive(Clone, Default)]
+pub struct Progress(Arc<ProgressState>);
+
+#[derive(Default)]
+struct ProgressState {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    pub fn set_total(&self, total: usize) {
+        self.0.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.0.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Long operations should check it between steps and stop early returning `cancelled_error()`
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Steps done and total steps
+    pub fn steps(&self) -> (usize, usize) {
+        (
+            self.0.done.load(Ordering::Relaxed),
+            self.0.total.load(Ordering::Relaxed),
+        )
+    }
+
+    fn percent(&self) -> usize {
+        match self.steps() {
+            (_, 0) => 0,
+            (done, total) => (done * 100 / total).min(100),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Relaxed)
+    }
+}
+
+pub fn cancelled_error() -> anyhow::Error {
+    anyhow!("operation cancelled")
+}
+
+/// Operation running in a background thread (e.g. indexing the whole song library), so the server doesn't stall while it runs.
+/// Its progress is shown in a boss bar of the player who started it and logged to the console (see `report_long_operations`).
+pub struct LongOperation<T> {
+    handle: Option<JoinHandle<Result<T>>>,
+    progress: Progress,
+}
+
+impl<T: Send + 'static> LongOperation<T> {
+    pub fn start(
+        name: impl Into<String>,
+        client: Option<Entity>,
+        operations: &mut LongOperations,
+        run: impl FnOnce(&Progress) -> Result<T> + Send + 'static,
+    ) -> Self {
+        let name = name.into();
+        let progress = Progress::default();
+        info!("Started {}", name);
+
+        operations.reports.push(OperationReport {
+            name,
+            client,
+            progress: progress.clone(),
+            boss_bar_uuid: Uuid::new_v4(),
+            logged_percent: 0,
+        });
+
+        let thread_progress = progress.clone();
+        let handle = thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run(&thread_progress)))
+                .unwrap_or_else(|_| Err(anyhow!("operation panicked")));
+            thread_progress.0.finished.store(true, Ordering::Relaxed);
+            result
+        });
+
+        Self {
+            handle: Some(handle),
+            progress,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.progress.is_finished()
+    }
+
+    /// Result of the operation if it has finished (it's returned only once)
+    pub fn try_finish(&mut self) -> Option<Result<T>> {
+        if !self.is_finished() {
+            return None;
+        }
+
+        let result = self
+            .handle
+            .take()?
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("operation panicked")));
+
+        Some(result)
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+}
+
+/// Long operations which are running
+#[derive(Resource, Default)]
+pub struct LongOperations {
+    reports: Vec<OperationReport>,
+}
+
+struct OperationReport {
+    name: String,
+    /// Player who started the operation
+    client: Option<Entity>,
+    progress: Progress,
+    boss_bar_uuid: Uuid,
+    logged_percent: usize,
+}
+
+impl LongOperations {
+    /// Cancels the operations started by `client`, returning how many were cancelled
+    pub fn cancel_started_by(&self, client: Entity) -> usize {
+        self.reports
+            .iter()
+            .filter(|report| report.client == Some(client) && !report.progress.is_cancelled())
+            .inspect(|report| report.progress.cancel())
+            .count()
+    }
+}
+
+/// Shows the progress of the long operations to the players who started them and logs it to the console
+pub fn report_long_operations(
+    mut operations: ResMut<LongOperations>,
+    mut clients: Query<&mut Client>,
+) {
+    operations.reports.retain_mut(|report| {
+        let mut client = report
+            .client
+            .and_then(|client| clients.get_mut(client).ok());
+
+        if report.progress.is_finished() {
+            if report.progress.is_cancelled() {
+                info!("Cancelled {}", report.name);
+            } else {
+                info!("Finished {}", report.name);
+            }
+
+            if let Some(client) = client.as_mut() {
+                client.write_packet(&BossBar {
+                    id: report.boss_bar_uuid,
+                    action: BossBarAction::Remove,
+                });
+            }
+
+            return false;
+        }
+
+        let percent = report.progress.percent();
+        let (done, total) = report.progress.steps();
+        if percent >= report.logged_percent + LOG_PERCENT_STEP {
+            report.logged_percent = percent - percent % LOG_PERCENT_STEP;
+            info!("{}: {}% ({}/{})", report.name, percent, done, total);
+        }
+
+        if let Some(client) = client.as_mut() {
+            client.write_packet(&BossBar {
+                id: report.boss_bar_uuid,
+                action: BossBarAction::Add {
+                    title: format!("{} ({}/{})", report.name, done, total).color(Color::WHITE)
+                        + "   /cancel".color(Color::GOLD)
+                        + " to stop".color(Color::WHITE),
+                    health: percent as f32 / 100.0,
+                    color: BossBarColor::Green,
+                    division: BossBarDivision::NoDivision,
+                    flags: BossBarFlags::new(),
+                },
+            });
+        }
+
+        true
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_operation_progress() {
+        let mut operations = LongOperations::default();
+        let mut operation = LongOperation::start("test", None, &mut operations, |progress| {
+            progress.set_total(4);
+            for _ in 0..4 {
+                progress.advance();
+            }
+            Ok(4)
+        });
+
+        while !operation.is_finished() {
+            thread::yield_now();
+        }
+
+        assert_eq!(operations.reports[0].progress.percent(), 100);
+        assert_eq!(operation.try_finish().unwrap().unwrap(), 4);
+        assert!(operation.try_finish().is_none());
+    }
+}
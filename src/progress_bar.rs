@@ -0,0 +1,46 @@
+use bevy_ecs::{
+    query::With,
+    system::{Local, Query, Res},
+};
+use valence::prelude::{Block, BlockState, Instance};
+
+use crate::osu::{Osu, OsuInstance};
+
+/// Number of blocks of the progress bar currently filled
+#[derive(Default)]
+pub struct ProgressBar {
+    filled: usize,
+}
+
+/// Fills a row of blocks in the bottom of the screen as the beatmap being played advances
+pub fn update_progress_bar(
+    osu: Res<Osu>,
+    mut instances: Query<&mut Instance, With<OsuInstance>>,
+    mut progress_bar: Local<ProgressBar>,
+) {
+    let Ok(mut instance) = instances.get_single_mut() else {
+        return;
+    };
+    let positions = osu.playfield().progress_bar_positions();
+
+    let filled = match osu.playing_beatmap() {
+        Some(beatmap) => {
+            let length = beatmap.data.length().as_millis().max(1) as f64;
+            let progress = (beatmap.state.play_time.as_millis() as f64 / length).min(1.0);
+            (progress * positions.len() as f64) as usize
+        }
+        None => 0,
+    };
+
+    if filled > progress_bar.filled {
+        for &pos in &positions[progress_bar.filled..filled] {
+            instance.set_block(pos, Block::new(BlockState::WHITE_CONCRETE));
+        }
+    } else if filled < progress_bar.filled {
+        for &pos in &positions[filled..progress_bar.filled] {
+            instance.set_block(pos, Block::new(BlockState::AIR));
+        }
+    }
+
+    progress_bar.filled = filled;
+}
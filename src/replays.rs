@@ -0,0 +1,441 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use bevy_ecs::{
+    prelude::{Entity, EventReader},
+    query::Without,
+    system::{Local, Query, Res, ResMut, Resource},
+};
+use valence::{
+    client::event::{DropItem, SwapItemInHand, SwingArm},
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    afk::Afk,
+    command_registry::{ArgSpec, CommandSpec, OsuCommand, Suggestions},
+    configs::Configs,
+    error::error_message,
+    hud::{save_hud_settings, HudSettings},
+    mods::Mods,
+    osu::Osu,
+    player_name::PlayerName,
+    scores::LocalScores,
+};
+
+/// Which replays of a player are kept once a map ends. Chosen with `/replays`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayRetention {
+    All,
+    Passes,
+    /// Only the replay of the best score of each map
+    #[default]
+    PersonalBests,
+    None,
+}
+
+impl ReplayRetention {
+    pub const ALL: [ReplayRetention; 4] = [
+        ReplayRetention::All,
+        ReplayRetention::Passes,
+        ReplayRetention::PersonalBests,
+        ReplayRetention::None,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplayRetention::All => "all",
+            ReplayRetention::Passes => "passes",
+            ReplayRetention::PersonalBests => "best",
+            ReplayRetention::None => "none",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|retention| retention.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn keeps(&self, passed: bool, personal_best: bool) -> bool {
+        match self {
+            ReplayRetention::All => true,
+            ReplayRetention::Passes => passed,
+            ReplayRetention::PersonalBests => passed && personal_best,
+            ReplayRetention::None => false,
+        }
+    }
+}
+
+/// Inputs of a player during a map, saved to `replays/<player>/<beatmap>/<unix time>_<score>.json`
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Replay {
+    pub player: String,
+    pub beatmap: String,
+    pub mods: String,
+    pub score: usize,
+    pub passed: bool,
+    pub frames: Vec<ReplayFrame>,
+}
+
+/// Look direction of the player, recorded when it changes or the player clicks
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ReplayFrame {
+    pub time_ms: u64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub click: bool,
+}
+
+/// Replays of the map being played, by player entity. Writing the replays and walking the whole replays folder would stall
+/// the server, so they're saved by a background thread.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    replays: HashMap<Entity, Replay>,
+    /// Replays of the finished maps, with the quota of the replays folder
+    writer: Sender<(Vec<(Replay, ReplayRetention)>, Option<u64>)>,
+}
+
+impl ReplayRecorder {
+    pub fn start() -> Self {
+        let (sender, receiver) = mpsc::channel::<(Vec<(Replay, ReplayRetention)>, Option<u64>)>();
+        thread::spawn(move || {
+            for (replays, quota_bytes) in receiver {
+                save_replays(&replays_dir(), replays, quota_bytes);
+            }
+        });
+
+        Self {
+            replays: HashMap::new(),
+            writer: sender,
+        }
+    }
+}
+
+fn replays_dir() -> PathBuf {
+    PathBuf::from("replays")
+}
+
+/// Removes the characters which can't be used in file names (e.g. `/` in beatmap titles)
+fn file_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+/// Score of a replay saved in `path` (see `Replay`)
+fn replay_score(path: &Path) -> Option<usize> {
+    path.file_stem()?.to_str()?.rsplit_once('_')?.1.parse().ok()
+}
+
+impl Replay {
+    /// Saves the replay if `retention` keeps it. With `ReplayRetention::PersonalBests`, the replays it beats are deleted once
+    /// it's written.
+    fn save(&self, dir: &Path, retention: ReplayRetention) -> Result<Option<PathBuf>> {
+        let beatmap_dir = dir
+            .join(file_name(&self.player))
+            .join(file_name(&self.beatmap));
+        let previous_replays: Vec<_> = match fs::read_dir(&beatmap_dir) {
+            Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+            Err(_) => Vec::new(),
+        };
+        let personal_best = previous_replays
+            .iter()
+            .filter_map(|path| replay_score(path))
+            .all(|score| score < self.score);
+
+        if !retention.keeps(self.passed, personal_best) {
+            return Ok(None);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        fs::create_dir_all(&beatmap_dir)?;
+        let path = beatmap_dir.join(format!("{}_{}.json", now, self.score));
+        fs::write(&path, serde_json::to_string(self)?)?;
+
+        if retention == ReplayRetention::PersonalBests {
+            for previous_path in previous_replays
+                .iter()
+                .filter(|&previous| previous != &path)
+            {
+                fs::remove_file(previous_path)?;
+            }
+        }
+
+        Ok(Some(path))
+    }
+}
+
+/// Deletes the oldest replays in `dir` until they take at most `quota_bytes`, returning how many were deleted
+fn prune_replays(dir: &Path, quota_bytes: u64) -> Result<usize> {
+    let mut replays = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        // Replays and folders may be deleted while walking the folder (e.g. a beaten personal best)
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => continue,
+            Err(error) => return Err(error.into()),
+        };
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                replays.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+    }
+
+    let mut total_bytes: u64 = replays.iter().map(|(_, len, _)| len).sum();
+    replays.sort();
+
+    let mut deleted = 0;
+    for (_, len, path) in replays {
+        if total_bytes <= quota_bytes {
+            break;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => deleted += 1,
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        total_bytes -= len;
+    }
+
+    Ok(deleted)
+}
+
+/// Records the inputs of every player while a map is played, and saves them when it's finished or failed
+/// following the `ReplayRetention` of each player. Replays of maps left before the end are discarded.
+pub fn record_replays(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    mods: Res<Mods>,
+    mut recorder: ResMut<ReplayRecorder>,
+    clients: Query<(Entity, &Client, &PlayerName, &HudSettings), Without<Afk>>,
+    mut swing_arm_events: EventReader<SwingArm>,
+    mut drop_item_events: EventReader<DropItem>,
+    mut swap_item_hand_events: EventReader<SwapItemInHand>,
+    mut saved: Local<bool>,
+) {
+    let clicks: Vec<_> = swing_arm_events
+        .iter()
+        .map(|event| event.client)
+        .chain(drop_item_events.iter().map(|event| event.client))
+        .chain(swap_item_hand_events.iter().map(|event| event.client))
+        .collect();
+
+    if let Some(beatmap) = osu.playing_beatmap() {
+        *saved = false;
+        let time_ms = beatmap.state.play_time.as_millis() as u64;
+
+        for (entity, client, player_name, _) in &clients {
+            let replay = recorder.replays.entry(entity).or_insert_with(|| Replay {
+                player: player_name.as_str().to_string(),
+                beatmap: LocalScores::beatmap_key(beatmap),
+                mods: mods.to_string(),
+                ..Default::default()
+            });
+
+            let click = clicks.contains(&entity);
+            let (yaw, pitch) = (client.yaw(), client.pitch());
+            let moved = replay
+                .frames
+                .last()
+                .map_or(true, |last| last.yaw != yaw || last.pitch != pitch);
+            if moved || click {
+                replay.frames.push(ReplayFrame {
+                    time_ms,
+                    yaw,
+                    pitch,
+                    click,
+                });
+            }
+        }
+        return;
+    }
+
+    let finished_beatmap = osu
+        .finished_beatmap()
+        .map(|beatmap| (beatmap, true))
+        .or_else(|| osu.failed_beatmap().map(|beatmap| (beatmap, false)));
+    let Some((beatmap, passed)) = finished_beatmap else {
+        recorder.replays.clear();
+        return;
+    };
+    if *saved {
+        return;
+    }
+    *saved = true;
+
+    let replays: Vec<_> = recorder
+        .replays
+        .drain()
+        .map(|(entity, mut replay)| {
+            let retention = clients
+                .get(entity)
+                .map(|(_, _, _, settings)| settings.replay_retention)
+                .unwrap_or_default();
+            replay.score = beatmap.state.score;
+            replay.passed = passed;
+            (replay, retention)
+        })
+        .collect();
+    if recorder
+        .writer
+        .send((replays, configs.replays_quota_bytes()))
+        .is_err()
+    {
+        warn!("Replay writer thread stopped, the replays were not saved");
+    }
+}
+
+/// Saves the `replays` and then deletes the oldest ones in `dir` if they take more than `quota_bytes`
+fn save_replays(dir: &Path, replays: Vec<(Replay, ReplayRetention)>, quota_bytes: Option<u64>) {
+    for (replay, retention) in replays {
+        match replay.save(dir, retention) {
+            Ok(Some(path)) => info!("Saved replay '{}'", path.display()),
+            Ok(None) => {}
+            Err(error) => warn!(
+                "Error while saving the replay of '{}': {}",
+                replay.player, error
+            ),
+        }
+    }
+
+    if let Some(quota_bytes) = quota_bytes {
+        match prune_replays(dir, quota_bytes) {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {} old replays to stay under the quota", deleted),
+            Err(error) => warn!("Error while pruning replays: {}", error),
+        }
+    }
+}
+
+pub fn replays_command() -> CommandSpec {
+    let names = ReplayRetention::ALL
+        .iter()
+        .map(|retention| retention.name());
+
+    CommandSpec::new("replays")
+        .arg(ArgSpec::word("replays").suggest(Suggestions::Values(names.collect())))
+}
+
+/// Handles `/replays [all|passes|best|none]`
+pub fn execute_replays_commands(
+    osu: Res<Osu>,
+    mut clients: Query<(&mut Client, &mut HudSettings, &PlayerName)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "replays" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, mut settings, player_name)) = clients.get_mut(command_event.client)
+        else {
+            continue;
+        };
+
+        let names: Vec<_> = ReplayRetention::ALL
+            .iter()
+            .map(|retention| retention.name())
+            .collect();
+        let result: Result<Text> = match args.trim() {
+            "" => Ok("Saved replays: ".color(Color::YELLOW)
+                + settings.replay_retention.name().color(Color::GREEN)
+                + format!(" (available: {})", names.join(", ")).color(Color::GRAY)),
+            name => match ReplayRetention::parse(name) {
+                Some(retention) => {
+                    settings.replay_retention = retention;
+                    save_hud_settings(&osu, player_name, &settings);
+                    Ok("Saved replays set to ".color(Color::YELLOW)
+                        + retention.name().color(Color::GREEN))
+                }
+                None => Err(anyhow!(
+                    "unknown option '{}' (expected {})",
+                    name,
+                    names.join(", ")
+                )),
+            },
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replay(score: usize, passed: bool) -> Replay {
+        Replay {
+            player: "player".to_string(),
+            beatmap: "Artist - Title [Insane]".to_string(),
+            score,
+            passed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keep_personal_bests() {
+        // Unique, so concurrent test runs don't delete each other's replays
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!(
+            "osucraft_replays_test_{}_{}",
+            std::process::id(),
+            nanos
+        ));
+
+        let best = ReplayRetention::PersonalBests;
+        assert!(replay(100, false).save(&dir, best).unwrap().is_none());
+        let first = replay(100, true).save(&dir, best).unwrap().unwrap();
+        assert_eq!(replay_score(&first), Some(100));
+        assert!(replay(50, true).save(&dir, best).unwrap().is_none());
+        let second = replay(200, true).save(&dir, best).unwrap().unwrap();
+        assert!(!first.exists() && second.exists());
+
+        assert_eq!(prune_replays(&dir, u64::MAX).unwrap(), 0);
+        assert_eq!(prune_replays(&dir, 0).unwrap(), 1);
+        assert!(!second.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_retention() {
+        for retention in ReplayRetention::ALL {
+            assert_eq!(ReplayRetention::parse(retention.name()), Some(retention));
+        }
+        assert_eq!(ReplayRetention::parse("forever"), None);
+    }
+}
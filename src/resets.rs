@@ -0,0 +1,148 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy_ecs::system::{Local, Query, Res};
+use valence::prelude::{Client, Color, Server};
+use valence::protocol::TextFormat;
+
+use crate::{configs::Configs, hud::HudSettings, osu::Osu};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+const DAYS_PER_WEEK: i64 = 7;
+/// 1970-01-01 was a thursday (monday = 0)
+const EPOCH_WEEKDAY: i64 = 3;
+
+/// Daily and weekly resets (at midnight and monday midnight) in the server timezone
+#[derive(Clone, Copy, Debug)]
+pub struct ResetClock {
+    utc_offset_secs: i64,
+}
+
+impl ResetClock {
+    pub fn new(utc_offset_minutes: i32) -> Self {
+        Self {
+            utc_offset_secs: utc_offset_minutes as i64 * 60,
+        }
+    }
+
+    pub fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+
+    /// Number of days since 1970-01-01 in the server timezone
+    pub fn day(&self, unix_secs: i64) -> i64 {
+        (unix_secs + self.utc_offset_secs).div_euclid(SECS_PER_DAY)
+    }
+
+    /// Number of weeks (starting on monday) since 1970-01-01 in the server timezone
+    pub fn week(&self, unix_secs: i64) -> i64 {
+        (self.day(unix_secs) + EPOCH_WEEKDAY).div_euclid(DAYS_PER_WEEK)
+    }
+
+    pub fn time_to_daily_reset(&self, unix_secs: i64) -> Duration {
+        let next_reset = (self.day(unix_secs) + 1) * SECS_PER_DAY - self.utc_offset_secs;
+        Duration::from_secs((next_reset - unix_secs) as u64)
+    }
+
+    pub fn time_to_weekly_reset(&self, unix_secs: i64) -> Duration {
+        let next_reset_day = (self.week(unix_secs) + 1) * DAYS_PER_WEEK - EPOCH_WEEKDAY;
+        let next_reset = next_reset_day * SECS_PER_DAY - self.utc_offset_secs;
+        Duration::from_secs((next_reset - unix_secs) as u64)
+    }
+}
+
+/// Formats the countdown as `1d 02h` or `3h 05m` or `4m 09s`
+pub fn format_countdown(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+
+    if days > 0 {
+        format!("{days}d {hours:02}h")
+    } else if hours > 0 {
+        format!("{hours}h {minutes:02}m")
+    } else {
+        format!("{minutes}m {:02}s", secs % 60)
+    }
+}
+
+/// Shows the time left until the daily and weekly resets in the action bar while selecting a beatmap
+pub fn update_reset_countdown(
+    osu: Res<Osu>,
+    configs: Res<Configs>,
+    server: Res<Server>,
+    mut clients: Query<(&mut Client, &HudSettings)>,
+    mut ticks: Local<usize>,
+) {
+    *ticks += 1;
+    if *ticks < server.shared().tps() as usize || !osu.is_selecting_beatmap() {
+        return;
+    }
+    *ticks = 0;
+
+    let clock = configs.reset_clock();
+    let now = ResetClock::now();
+    let text = "Daily reset in ".color(Color::GRAY)
+        + format_countdown(clock.time_to_daily_reset(now)).color(Color::AQUA)
+        + "   Weekly reset in ".color(Color::GRAY)
+        + format_countdown(clock.time_to_weekly_reset(now)).color(Color::AQUA)
+        + format!("   ({})", configs.timezone()).color(Color::DARK_GRAY);
+
+    for (mut client, settings) in &mut clients {
+        if settings.action_bar {
+            client.set_action_bar(text.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2023-05-03 (wednesday) 22:30:00 UTC
+    const WEDNESDAY_NIGHT: i64 = 1_683_153_000;
+
+    #[test]
+    fn daily_reset() {
+        let utc = ResetClock::new(0);
+        assert_eq!(
+            utc.time_to_daily_reset(WEDNESDAY_NIGHT),
+            Duration::from_secs(90 * 60)
+        );
+
+        // Already thursday 07:30 in UTC+9
+        let tokyo = ResetClock::new(9 * 60);
+        assert_eq!(tokyo.day(WEDNESDAY_NIGHT), utc.day(WEDNESDAY_NIGHT) + 1);
+        assert_eq!(
+            tokyo.time_to_daily_reset(WEDNESDAY_NIGHT),
+            Duration::from_secs((16 * 60 + 30) * 60)
+        );
+    }
+
+    #[test]
+    fn weekly_reset() {
+        let utc = ResetClock::new(0);
+        assert_eq!(
+            utc.time_to_weekly_reset(WEDNESDAY_NIGHT),
+            Duration::from_secs((4 * 24 + 1) * 60 * 60 + 30 * 60)
+        );
+
+        // Still wednesday 19:30 in UTC-3
+        let sao_paulo = ResetClock::new(-3 * 60);
+        assert_eq!(
+            sao_paulo.time_to_weekly_reset(WEDNESDAY_NIGHT),
+            Duration::from_secs((4 * 24 + 4) * 60 * 60 + 30 * 60)
+        );
+    }
+
+    #[test]
+    fn countdown_format() {
+        assert_eq!(format_countdown(Duration::from_secs(9)), "0m 09s");
+        assert_eq!(
+            format_countdown(Duration::from_secs(3 * 3600 + 5 * 60)),
+            "3h 05m"
+        );
+        assert_eq!(format_countdown(Duration::from_secs(26 * 3600)), "1d 02h");
+    }
+}
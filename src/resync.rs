@@ -0,0 +1,57 @@
+use bevy_ecs::{
+    query::{Added, Without},
+    system::{Query, Res},
+};
+use tracing::info;
+use valence::{
+    prelude::{Client, Color, Instance},
+    protocol::TextFormat,
+    Despawned,
+};
+
+use crate::{hitcircle::Hitcircle, hud::HudSettings, osu::Osu};
+
+/// Rebuilds the view of the players who join (or reconnect) in the middle of a play. The blocks and entities of the playfield
+/// are sent with the chunks when they join, but some parts of the view are only sent to each player when they change:
+/// - the hitcircle outlines, sent when each circle spawns
+/// - the time left of the beatmap, only shown when it starts
+///
+/// `HudSettings` are inserted once the player is named (see `init_hud_settings`), so they are up to date when the view is rebuilt.
+pub fn resync_joining_clients(
+    osu: Res<Osu>,
+    hitcircles: Query<&Hitcircle, Without<Despawned>>,
+    instances: Query<&Instance>,
+    mut joining_clients: Query<(&mut Client, &HudSettings), Added<HudSettings>>,
+) {
+    let Some(beatmap) = osu.playing_beatmap() else {
+        return;
+    };
+
+    for (mut client, settings) in &mut joining_clients {
+        info!(
+            "Resyncing '{}', who joined during a play",
+            client.username()
+        );
+
+        if settings.circle_outline {
+            for hitcircle in &hitcircles {
+                if let Ok(instance) = instances.get(hitcircle.instance()) {
+                    let positions = hitcircle.outline_positions(instance);
+                    hitcircle.send_outline(&mut client, &positions);
+                }
+            }
+        }
+
+        let time_left = beatmap
+            .data
+            .length()
+            .saturating_sub(beatmap.state.play_time)
+            .as_secs();
+        client.send_message(
+            "You joined during ".color(Color::GRAY)
+                + format!("{} [{}]", beatmap.data.title, beatmap.data.difficulty_name)
+                    .color(Color::AQUA)
+                + format!(", {}:{:02} left", time_left / 60, time_left % 60).color(Color::GRAY),
+        );
+    }
+}
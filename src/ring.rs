@@ -1,20 +1,27 @@
 use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
 use std::f64::consts::TAU;
 
 use bevy_ecs::{
     prelude::{Component, Entity},
-    query::With,
+    query::{With, Without},
     system::{Commands, Query},
 };
 use valence::{
     equipment::{Equipment, EquipmentSlot},
     math::from_yaw_and_pitch,
-    prelude::{Client, DVec3, EntityKind, McEntity, TrackedData},
-    protocol::{entity_meta::EulerAngle, ItemKind, ItemStack},
+    prelude::{Client, DVec3, EntityKind, McEntity, TrackedData, Vec3},
+    protocol::{entity_meta::EulerAngle, packets::s2c::particle::Particle, ItemKind, ItemStack},
     Despawned,
 };
 
-use crate::minecraft::PLAYER_EYE_OFFSET;
+use crate::{color::Color, minecraft::PLAYER_EYE_OFFSET};
+
+/// Particles per block of circumference of a `ParticleRing`
+const PARTICLES_PER_BLOCK: f64 = 1.5;
+/// Particles of the smallest `ParticleRing`s, so they stay round
+const MIN_RING_PARTICLES: usize = 12;
+const RING_PARTICLE_SCALE: f32 = 1.0;
 
 /// Ring in the XY plane
 #[derive(Component)]
@@ -24,6 +31,8 @@ pub struct Ring {
     ticks: usize,
     center: DVec3,
     radius: f64,
+    /// Visual offset of the ring parts from `radius` (it doesn't change the hit area)
+    pulse_offset: f64,
 }
 
 #[derive(Component)]
@@ -37,11 +46,21 @@ impl Ring {
         inner_radius: f64,
         item: ItemKind,
         ticks: usize,
+        tps: usize,
         instance: Entity,
         commands: &mut Commands,
     ) -> Result<Self> {
         let speed = (outer_radius - inner_radius).abs() / (ticks - 2).max(1) as f64;
-        Self::new(center, outer_radius, speed, item, ticks, instance, commands)
+        Self::new(
+            center,
+            outer_radius,
+            speed,
+            item,
+            ticks,
+            tps,
+            instance,
+            commands,
+        )
     }
 
     pub fn without_speed(
@@ -52,7 +71,7 @@ impl Ring {
         instance: Entity,
         commands: &mut Commands,
     ) -> Result<Self> {
-        Self::new(center, radius, 0.0, item, ticks, instance, commands)
+        Self::new(center, radius, 0.0, item, ticks, 0, instance, commands)
     }
 
     fn new(
@@ -61,6 +80,7 @@ impl Ring {
         speed: f64,
         item: ItemKind,
         ticks: usize,
+        tps: usize,
         instance: Entity,
         commands: &mut Commands,
     ) -> Result<Self> {
@@ -85,7 +105,12 @@ impl Ring {
                     yaw: 0.0,
                     roll,
                 };
-                create_rotated_item(item, rotation, pos, instance)
+                let mut bundle = create_rotated_item(item, rotation, pos, instance);
+                // The client moves the parts between position updates with the velocity (in blocks per second),
+                // so the ring shrinks smoothly instead of in per tick steps
+                bundle.0.set_velocity((-speed * tps as f64 * dir).as_vec3());
+
+                bundle
             })
             .map(|bundle| commands.spawn(bundle).id())
             .collect();
@@ -96,6 +121,7 @@ impl Ring {
             ticks,
             speed,
             radius,
+            pulse_offset: 0.0,
         };
 
         Ok(ring)
@@ -106,6 +132,25 @@ impl Ring {
             return;
         }
 
+        self.move_parts_radially(-self.speed, ring_entities);
+        self.radius -= self.speed;
+    }
+
+    /// Displaces the ring parts `offset` blocks away from `radius`, without changing the hit area
+    pub fn pulse(&mut self, offset: f64, ring_entities: &mut Query<&mut McEntity, With<RingPart>>) {
+        if offset == self.pulse_offset {
+            return;
+        }
+
+        self.move_parts_radially(offset - self.pulse_offset, ring_entities);
+        self.pulse_offset = offset;
+    }
+
+    fn move_parts_radially(
+        &self,
+        distance: f64,
+        ring_entities: &mut Query<&mut McEntity, With<RingPart>>,
+    ) {
         let len = self.armor_stands.len() as f64;
 
         self.armor_stands
@@ -115,14 +160,11 @@ impl Ring {
                 if let Ok(mut entity) = ring_entities.get_mut(*entity) {
                     let angle = TAU / len * n as f64;
                     let dir = DVec3::new(angle.cos(), angle.sin(), 0.0);
-                    let mov = -self.speed * dir;
-                    let new_pos = entity.position() + mov;
+                    let new_pos = entity.position() + distance * dir;
 
                     entity.set_position(new_pos);
                 }
             });
-
-        self.radius -= self.speed;
     }
 
     pub fn translate(
@@ -140,29 +182,23 @@ impl Ring {
         self.center += movement;
     }
 
-    pub fn raycast_client(&self, client: &Client) -> Option<DVec3> {
-        let origin = client.position() + PLAYER_EYE_OFFSET;
-        let direction = from_yaw_and_pitch(client.yaw(), client.pitch());
-        let direction = DVec3::new(direction.x as f64, direction.y as f64, direction.z as f64);
+    /// Where `client` is aiming in the ring, with its radius multiplied by `radius_multiplier` (see `HudSettings::aim_assist`)
+    pub fn raycast_client(&self, client: &Client, radius_multiplier: f64) -> Option<DVec3> {
+        let (origin, direction) = client_ray(client);
 
-        self.raycast(origin, direction)
+        self.raycast(origin, direction, radius_multiplier)
     }
 
-    pub fn raycast(&self, origin: DVec3, direction: DVec3) -> Option<DVec3> {
-        if direction.z == 0.0 {
-            return None;
-        }
-
-        let direction_scale = (self.center.z - origin.z) / direction.z;
-        if direction_scale < 0.0 {
-            // Direction not pointing to hitcircle plane
-            return None;
-        }
-
-        let intersection = origin + direction * direction_scale;
+    pub fn raycast(
+        &self,
+        origin: DVec3,
+        direction: DVec3,
+        radius_multiplier: f64,
+    ) -> Option<DVec3> {
+        let intersection = plane_intersection(origin, direction, self.center.z)?;
         let dist = self.center.distance(intersection);
 
-        (dist <= self.radius).then_some(intersection)
+        (dist <= self.radius * radius_multiplier).then_some(intersection)
     }
 
     pub fn despawn(&self, commands: &mut Commands) {
@@ -174,6 +210,39 @@ impl Ring {
     }
 }
 
+/// Where the ray from `origin` towards `direction` crosses the plane at `z` (e.g. the hitcircle plane), if it points to it
+pub fn plane_intersection(origin: DVec3, direction: DVec3, z: f64) -> Option<DVec3> {
+    if direction.z == 0.0 {
+        return None;
+    }
+
+    let direction_scale = (z - origin.z) / direction.z;
+    if direction_scale < 0.0 {
+        // Direction not pointing to hitcircle plane
+        return None;
+    }
+
+    Some(origin + direction * direction_scale)
+}
+
+/// Where `client` is aiming in the plane at `z`
+pub fn client_aim_point(client: &Client, z: f64) -> Option<DVec3> {
+    let (origin, direction) = client_ray(client);
+
+    plane_intersection(origin, direction, z)
+}
+
+/// Eye position and look direction of `client`
+fn client_ray(client: &Client) -> (DVec3, DVec3) {
+    let origin = client.position() + PLAYER_EYE_OFFSET;
+    let direction = from_yaw_and_pitch(client.yaw(), client.pitch());
+
+    (
+        origin,
+        DVec3::new(direction.x as f64, direction.y as f64, direction.z as f64),
+    )
+}
+
 /// Creates an invisible `ArmorStand` entity equiped with the `item` on the head
 fn create_rotated_item(
     item: ItemKind,
@@ -222,6 +291,57 @@ fn to_radians(degrees: f64) -> f64 {
     degrees * TAU / 360.0
 }
 
+/// How the approach circles are drawn, chosen in the skin of the configs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApproachCircleStyle {
+    /// Items worn by armor stands (`Ring`)
+    #[default]
+    Blocks,
+    /// Particles (`ParticleRing`), for crowded maps or slow connections
+    Particles,
+}
+
+/// Approach circle drawn with particles instead of the armor stands of `Ring` (see `ApproachCircleStyle`). It shrinks like
+/// `Ring::with_speed`, but it doesn't spawn any entity, so crowded maps send far fewer entity packets.
+#[derive(Component)]
+pub struct ParticleRing {
+    center: DVec3,
+    radius: f64,
+    speed: f64,
+    ticks: usize,
+    rgb: Vec3,
+}
+
+impl ParticleRing {
+    pub fn with_speed(
+        center: impl Into<DVec3>,
+        outer_radius: f64,
+        inner_radius: f64,
+        color: Color,
+        ticks: usize,
+    ) -> Self {
+        Self {
+            center: center.into(),
+            radius: outer_radius,
+            speed: (outer_radius - inner_radius).abs() / (ticks - 2).max(1) as f64,
+            ticks,
+            rgb: Vec3::new(color.r as f32, color.g as f32, color.b as f32) / 255.0,
+        }
+    }
+
+    /// Positions of the particles of the ring in the current tick
+    fn particle_positions(&self) -> impl Iterator<Item = DVec3> + '_ {
+        let count = ((PARTICLES_PER_BLOCK * TAU * self.radius) as usize).max(MIN_RING_PARTICLES);
+        let d_angle = TAU / count as f64;
+
+        (0..count).map(move |n| {
+            let angle = d_angle * n as f64;
+            self.center + self.radius * DVec3::new(angle.cos(), angle.sin(), 0.0)
+        })
+    }
+}
+
 pub fn update_rings(
     mut commands: Commands,
     mut rings: Query<(&mut Ring, Entity)>,
@@ -237,3 +357,29 @@ pub fn update_rings(
         }
     }
 }
+
+pub fn update_particle_rings(
+    mut commands: Commands,
+    mut rings: Query<(Entity, &mut ParticleRing), Without<Despawned>>,
+    mut clients: Query<&mut Client>,
+) {
+    for (entity, mut ring) in &mut rings {
+        if ring.ticks == 0 || ring.radius <= 0.0 {
+            commands.entity(entity).insert(Despawned);
+            continue;
+        }
+
+        let particle = Particle::Dust {
+            rgb: ring.rgb,
+            scale: RING_PARTICLE_SCALE,
+        };
+        for mut client in &mut clients {
+            for position in ring.particle_positions() {
+                client.play_particle(&particle, true, position, Vec3::ZERO, 0.0, 1);
+            }
+        }
+
+        ring.ticks -= 1;
+        ring.radius -= ring.speed;
+    }
+}
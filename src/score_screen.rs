@@ -0,0 +1,364 @@
+use bevy_ecs::{
+    prelude::{Component, Entity, EventReader},
+    query::With,
+    system::{Commands, Local, Query, Res, ResMut},
+};
+use tracing::error;
+use valence::{
+    client::event::{ClickContainer, StartSneaking},
+    nbt::{compound, List},
+    prelude::{BlockPos, BlockState, Client, Color, Inventory, InventoryKind, OpenInventory},
+    protocol::{ItemKind, ItemStack, TextFormat},
+    Despawned,
+};
+
+use crate::{
+    beatmap::{Beatmap, Grade},
+    configs::Configs,
+    digit::{char_mask, TextPosition, TextWriter},
+    error::{error_message, OsuError},
+    inventory::{open_new_inventory, InventoriesToOpen},
+    local_leaderboard::LocalLeaderboard,
+    map_vote::MapVoteInventory,
+    osu::{Osu, OsuStateChange},
+    player_name::PlayerName,
+    playfield::PlayfieldSurface,
+    scores::LocalScores,
+    song_selection::{self, SongSelectionInventory},
+};
+
+const COLUMNS: u16 = 9;
+const LOCAL_LEADERBOARD_SLOT: u16 = 45;
+const GRADE_COLUMNS: u16 = 7;
+const HIT_COUNTS_COLUMN: u16 = 8;
+const RETRY_SLOT: u16 = 47;
+const NEXT_BEATMAP_SLOT: u16 = 49;
+const SONG_SELECTION_SLOT: u16 = 51;
+const MAP_VOTE_SLOT: u16 = 53;
+
+const LETTER_SIZE: (u16, u16) = (3, 5);
+
+/// Results of the last finished beatmap: grade art, hit counts, local leaderboard and buttons to retry or pick another beatmap
+#[derive(Component)]
+pub struct ScoreScreenInventory;
+
+impl ScoreScreenInventory {
+    pub fn new(beatmap: &Beatmap, leaderboard: &LocalLeaderboard) -> (Self, Inventory) {
+        let mut inventory = Inventory::with_title(
+            InventoryKind::Generic9x6,
+            format!("{} [{}]", beatmap.data.title, beatmap.data.difficulty_name)
+                .color(Color::DARK_BLUE),
+        );
+
+        draw_grade(&mut inventory, beatmap.state.grade());
+        draw_hit_counts(&mut inventory, beatmap);
+        draw_local_leaderboard(&mut inventory, beatmap, leaderboard);
+
+        inventory.replace_slot(
+            RETRY_SLOT,
+            Some(named_item(ItemKind::LimeConcrete, 1, "Retry", "green")),
+        );
+        inventory.replace_slot(
+            NEXT_BEATMAP_SLOT,
+            Some(named_item(ItemKind::Map, 1, "Next map ->", "gold")),
+        );
+        inventory.replace_slot(
+            SONG_SELECTION_SLOT,
+            Some(named_item(
+                song_selection::SONG_ITEM_KIND,
+                1,
+                "<- (Return to song selection)",
+                "red",
+            )),
+        );
+        inventory.replace_slot(
+            MAP_VOTE_SLOT,
+            Some(named_item(ItemKind::Clock, 1, "Vote the next map", "aqua")),
+        );
+
+        (Self, inventory)
+    }
+}
+
+fn draw_grade(inventory: &mut Inventory, grade: Grade) {
+    let (glass, color) = match grade {
+        Grade::SS | Grade::S => (ItemKind::YellowStainedGlassPane, "yellow"),
+        Grade::A => (ItemKind::LimeStainedGlassPane, "green"),
+        Grade::B => (ItemKind::BlueStainedGlassPane, "blue"),
+        Grade::C => (ItemKind::PurpleStainedGlassPane, "dark_purple"),
+        Grade::D => (ItemKind::RedStainedGlassPane, "red"),
+    };
+    let name = grade.name();
+    let letters: Vec<_> = name.chars().filter_map(char_mask).collect();
+
+    // Letters are centered in the grade area and separated by one column
+    let width = letters.len() as u16 * (LETTER_SIZE.0 + 1) - 1;
+    let offset = (GRADE_COLUMNS - width) / 2;
+
+    for row in 0..LETTER_SIZE.1 {
+        for column in 0..GRADE_COLUMNS {
+            let letter_column = column.checked_sub(offset);
+            let has_glass = letter_column.is_some_and(|letter_column| {
+                let letter = letters.get((letter_column / (LETTER_SIZE.0 + 1)) as usize);
+                let x = letter_column % (LETTER_SIZE.0 + 1);
+                letter.is_some_and(|letter| x < LETTER_SIZE.0 && letter[row as usize][x as usize])
+            });
+
+            let item = if has_glass {
+                named_item(glass, 1, name, color)
+            } else {
+                named_item(ItemKind::BlackStainedGlassPane, 1, "", "white")
+            };
+            inventory.replace_slot(row * COLUMNS + column, Some(item));
+        }
+    }
+}
+
+fn draw_hit_counts(inventory: &mut Inventory, beatmap: &Beatmap) {
+    let state = &beatmap.state;
+    let hit_counts = [
+        (ItemKind::LightBlueDye, "300", "blue", state.hits300),
+        (ItemKind::LimeDye, "100", "green", state.hits100),
+        (ItemKind::YellowDye, "50", "gold", state.hits50),
+        (ItemKind::RedDye, "Miss", "red", state.misses),
+    ];
+
+    for (row, (item_kind, name, color, count)) in hit_counts.into_iter().enumerate() {
+        inventory.replace_slot(
+            row as u16 * COLUMNS + HIT_COUNTS_COLUMN,
+            Some(named_item(
+                item_kind,
+                count.clamp(1, 64) as u8,
+                &format!("{name}: {count}"),
+                color,
+            )),
+        );
+    }
+
+    let stats = ItemStack::new(
+        ItemKind::Paper,
+        1,
+        Some(compound! {
+            "display" => compound! {
+                "Name" => format!(r#"{{"text": "Score: {}", "color": "gold"}}"#, state.score),
+                "Lore" => List::String(vec![
+                    format!(r#"{{"text": "Combo: x{}", "color": "light_purple"}}"#, state.max_combo),
+                    format!(r#"{{"text": "Accuracy: {:.2}%", "color": "dark_green"}}"#, state.accuracy()),
+                    format!(r#"{{"text": "Performance: {:.0}pp", "color": "aqua"}}"#, beatmap.performance_points()),
+                ])
+            }
+        }),
+    );
+    inventory.replace_slot(
+        (LETTER_SIZE.1 - 1) * COLUMNS + HIT_COUNTS_COLUMN,
+        Some(stats),
+    );
+}
+
+/// Local leaderboard shown before the beatmap started, with the ranks beaten by the play highlighted
+fn draw_local_leaderboard(
+    inventory: &mut Inventory,
+    beatmap: &Beatmap,
+    leaderboard: &LocalLeaderboard,
+) {
+    let beaten_ranks =
+        leaderboard.beaten_ranks(&LocalScores::beatmap_key(beatmap), beatmap.state.score);
+    let name = match beaten_ranks.first() {
+        Some(rank) => format!("New local #{}!", rank),
+        None => "Local leaderboard".to_string(),
+    };
+
+    let mut lore: Vec<_> = leaderboard
+        .top_scores()
+        .iter()
+        .enumerate()
+        .map(|(idx, local_score)| {
+            let rank = idx + 1;
+            let (color, beaten) = if beaten_ranks.contains(&rank) {
+                ("green", " (beaten)")
+            } else {
+                ("gray", "")
+            };
+            format!(
+                r#"{{"text": "#{} {} {}{}", "color": "{}"}}"#,
+                rank, local_score.player, local_score.score, beaten, color
+            )
+        })
+        .collect();
+    if lore.is_empty() {
+        lore.push(r#"{"text": "First score of the beatmap", "color": "gray"}"#.to_string());
+    }
+
+    let item = ItemStack::new(
+        ItemKind::Book,
+        1,
+        Some(compound! {
+            "display" => compound! {
+                "Name" => format!(r#"{{"text": "{name}", "color": "gold"}}"#),
+                "Lore" => List::String(lore)
+            }
+        }),
+    );
+    inventory.replace_slot(LOCAL_LEADERBOARD_SLOT, Some(item));
+}
+
+fn named_item(kind: ItemKind, count: u8, name: &str, color: &str) -> ItemStack {
+    ItemStack::new(
+        kind,
+        count,
+        Some(compound! {
+            "display" => compound! {
+                "Name" => format!(r#"{{"text": "{name}", "color": "{color}"}}"#)
+            }
+        }),
+    )
+}
+
+/// Draws the grade of the finished beatmap in giant blocks on the playfield until players leave the score display
+pub fn update_grade_display(
+    osu: Res<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut drawn_positions: Local<Vec<BlockPos>>,
+) {
+    match osu.finished_beatmap() {
+        Some(beatmap) if drawn_positions.is_empty() => {
+            let grade = beatmap.state.grade();
+            let block = match grade {
+                Grade::SS | Grade::S => BlockState::YELLOW_CONCRETE,
+                Grade::A => BlockState::LIME_CONCRETE,
+                Grade::B => BlockState::BLUE_CONCRETE,
+                Grade::C => BlockState::PURPLE_CONCRETE,
+                Grade::D => BlockState::RED_CONCRETE,
+            };
+            let writer = TextWriter {
+                scale: osu.playfield().grade_digit_scale(),
+                position: TextPosition::Center,
+            };
+
+            *drawn_positions = writer
+                .iter_text_block_positions(grade.name(), osu.playfield().screen_center())
+                .flatten()
+                .collect();
+            surface.fill(drawn_positions.iter().copied(), block);
+        }
+        None if !drawn_positions.is_empty() => {
+            surface.fill(drawn_positions.drain(..), BlockState::AIR);
+        }
+        _ => {}
+    }
+}
+
+/// Opens the score screen for every player when a beatmap is finished and despawns it when leaving the score display
+pub fn update_score_screen(
+    mut commands: Commands,
+    osu: Res<Osu>,
+    leaderboard: Res<LocalLeaderboard>,
+    clients: Query<(Entity, Option<&OpenInventory>), With<Client>>,
+    score_screens: Query<Entity, With<ScoreScreenInventory>>,
+    mut sneaking_events: EventReader<StartSneaking>,
+    mut shown: Local<bool>,
+) {
+    match (osu.finished_beatmap(), *shown) {
+        (Some(beatmap), false) => {
+            let score_screen = commands
+                .spawn(ScoreScreenInventory::new(beatmap, &leaderboard))
+                .id();
+            for (client, _) in &clients {
+                commands
+                    .entity(client)
+                    .insert(OpenInventory::new(score_screen));
+            }
+
+            *shown = true;
+        }
+        (Some(_), true) => {
+            for sneaking_event in sneaking_events.iter() {
+                match score_screens.get_single() {
+                    Ok(score_screen) => {
+                        commands
+                            .entity(sneaking_event.client)
+                            .insert(OpenInventory::new(score_screen));
+                    }
+                    Err(_) => error!("Could not find a ScoreScreenInventory component"),
+                }
+            }
+        }
+        (None, true) => {
+            for score_screen in &score_screens {
+                for (client, open_inventory) in &clients {
+                    if open_inventory.is_some_and(|inventory| inventory.entity() == score_screen) {
+                        commands.entity(client).remove::<OpenInventory>();
+                    }
+                }
+                commands.entity(score_screen).insert(Despawned);
+            }
+
+            *shown = false;
+        }
+        (None, false) => {}
+    }
+}
+
+pub fn handle_score_screen_clicks(
+    mut commands: Commands,
+    mut osu: ResMut<Osu>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    open_inventories: Query<&OpenInventory, With<Client>>,
+    score_screens: Query<&ScoreScreenInventory>,
+    song_selections: Query<Entity, (With<SongSelectionInventory>, With<Inventory>)>,
+    map_votes: Query<Entity, With<MapVoteInventory>>,
+    mut inventories_to_open: ResMut<InventoriesToOpen>,
+    mut click_events: EventReader<ClickContainer>,
+    configs: Res<Configs>,
+) {
+    for click in click_events.iter() {
+        let Ok(open_inventory) = open_inventories.get(click.client) else {
+            continue;
+        };
+        if score_screens.get(open_inventory.entity()).is_err() || osu.finished_beatmap().is_none() {
+            continue;
+        }
+
+        let state_change = match click.slot_id.unsigned_abs() {
+            RETRY_SLOT => OsuStateChange::Retry,
+            NEXT_BEATMAP_SLOT => OsuStateChange::NextBeatmap,
+            SONG_SELECTION_SLOT => {
+                for song_selection in song_selections.iter().take(1) {
+                    open_new_inventory(
+                        &mut commands,
+                        click.client,
+                        &mut inventories_to_open,
+                        song_selection,
+                    );
+                }
+                OsuStateChange::SongSelection
+            }
+            MAP_VOTE_SLOT => {
+                for map_vote in map_votes.iter().take(1) {
+                    open_new_inventory(
+                        &mut commands,
+                        click.client,
+                        &mut inventories_to_open,
+                        map_vote,
+                    );
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        let is_operator = player_names
+            .get(click.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        if let Err(error) = osu.change_state_for(state_change, is_operator, &mut clients) {
+            match (
+                error.downcast_ref::<OsuError>(),
+                clients.get_mut(click.client),
+            ) {
+                (Some(_), Ok(mut client)) => client.send_message(error_message(&error)),
+                _ => error!("Error while leaving the score screen: '{}'", error),
+            }
+        }
+    }
+}
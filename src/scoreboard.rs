@@ -0,0 +1,148 @@
+use std::io::Write;
+
+use anyhow::Result;
+use bevy_ecs::{
+    prelude::Component,
+    system::{Query, Res},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Encode, Packet, Text, TextFormat, VarInt},
+};
+
+use crate::{hud::HudSettings, osu::Osu};
+
+const OBJECTIVE_NAME: &str = "osucraft";
+const SIDEBAR_POSITION: i8 = 1;
+
+/// Sidebar showing the live stats of the beatmap being played, if enabled in the client `HudSettings`
+#[derive(Component, Default)]
+pub struct SidebarHud {
+    shown: bool,
+    last_lines: Vec<(&'static str, i32)>,
+}
+
+/// https://wiki.vg/index.php?title=Protocol&oldid=18067#Update_Objectives
+#[derive(Clone, Debug, Packet)]
+#[packet_id = 0x54]
+struct UpdateObjectives<'a> {
+    objective_name: &'a str,
+    mode: ObjectiveMode,
+}
+
+#[derive(Clone, Debug)]
+enum ObjectiveMode {
+    Create { display_name: Text },
+    Remove,
+}
+
+/// https://wiki.vg/index.php?title=Protocol&oldid=18067#Display_Objective
+#[derive(Clone, Debug, Encode, Packet)]
+#[packet_id = 0x4d]
+struct DisplayObjective<'a> {
+    position: i8,
+    objective_name: &'a str,
+}
+
+/// https://wiki.vg/index.php?title=Protocol&oldid=18067#Update_Score
+#[derive(Clone, Debug, Packet)]
+#[packet_id = 0x57]
+struct UpdateScore<'a> {
+    entity_name: &'a str,
+    objective_name: &'a str,
+    value: i32,
+}
+
+impl Encode for UpdateObjectives<'_> {
+    fn encode(&self, mut w: impl Write) -> Result<()> {
+        self.objective_name.encode(&mut w)?;
+
+        match &self.mode {
+            ObjectiveMode::Create { display_name } => {
+                0_i8.encode(&mut w)?;
+                display_name.encode(&mut w)?;
+                // Integer objective type
+                VarInt(0).encode(&mut w)
+            }
+            ObjectiveMode::Remove => 1_i8.encode(&mut w),
+        }
+    }
+}
+
+impl Encode for UpdateScore<'_> {
+    fn encode(&self, mut w: impl Write) -> Result<()> {
+        self.entity_name.encode(&mut w)?;
+        // Create/update score action
+        VarInt(0).encode(&mut w)?;
+        self.objective_name.encode(&mut w)?;
+        VarInt(self.value).encode(&mut w)
+    }
+}
+
+impl SidebarHud {
+    pub fn show(&mut self, client: &mut Client) {
+        client.write_packet(&UpdateObjectives {
+            objective_name: OBJECTIVE_NAME,
+            mode: ObjectiveMode::Create {
+                display_name: "osu!".color(Color::LIGHT_PURPLE),
+            },
+        });
+        client.write_packet(&DisplayObjective {
+            position: SIDEBAR_POSITION,
+            objective_name: OBJECTIVE_NAME,
+        });
+
+        self.shown = true;
+        self.last_lines.clear();
+    }
+
+    pub fn hide(&mut self, client: &mut Client) {
+        if self.shown {
+            client.write_packet(&UpdateObjectives {
+                objective_name: OBJECTIVE_NAME,
+                mode: ObjectiveMode::Remove,
+            });
+        }
+
+        self.shown = false;
+    }
+
+    fn update_lines(&mut self, client: &mut Client, lines: Vec<(&'static str, i32)>) {
+        for line in lines.iter().filter(|line| !self.last_lines.contains(line)) {
+            client.write_packet(&UpdateScore {
+                entity_name: line.0,
+                objective_name: OBJECTIVE_NAME,
+                value: line.1,
+            });
+        }
+
+        self.last_lines = lines;
+    }
+}
+
+pub fn update_sidebar_hud(
+    osu: Res<Osu>,
+    mut clients: Query<(&mut Client, &HudSettings, &mut SidebarHud)>,
+) {
+    for (mut client, settings, mut sidebar) in &mut clients {
+        let (Some(beatmap), true) = (osu.playing_beatmap(), settings.sidebar) else {
+            sidebar.hide(&mut client);
+            continue;
+        };
+
+        if !sidebar.shown {
+            sidebar.show(&mut client);
+        }
+
+        let remaining_objects = beatmap.data.hit_objects.len() - beatmap.state.next_hit_object_idx
+            + beatmap.state.active_hit_objects.len();
+        let lines = vec![
+            ("Score", beatmap.state.score as i32),
+            ("Combo", beatmap.state.combo as i32),
+            ("Accuracy (%)", beatmap.state.accuracy().floor() as i32),
+            ("Remaining objects", remaining_objects as i32),
+        ];
+
+        sidebar.update_lines(&mut client, lines);
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{aim_assist::NO_AIM_ASSIST, beatmap::Beatmap};
+
+/// Scores of all the beatmaps played in this server
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct LocalScores {
+    /// Scores sorted from highest to lowest for each beatmap key (see `LocalScores::beatmap_key`)
+    beatmaps: HashMap<String, Vec<LocalScore>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LocalScore {
+    pub player: String,
+    pub score: usize,
+    pub accuracy: f32,
+    pub max_combo: usize,
+    /// Highest aim assist used by the players (see `HudSettings::aim_assist`)
+    #[serde(default = "default_aim_assist")]
+    pub aim_assist: f64,
+    /// Played with mods hitting the hitcircles for the players (see `Mods::is_unranked`)
+    #[serde(default)]
+    pub unranked: bool,
+    /// Performance points of the play (see `Beatmap::performance_points`), 0 for the scores saved before they were computed
+    #[serde(default)]
+    pub pp: f64,
+}
+
+fn default_aim_assist() -> f64 {
+    NO_AIM_ASSIST
+}
+
+impl LocalScore {
+    /// Whether `player` took part in the play (the scores of several players are saved with their names joined)
+    pub fn has_player(&self, player: &str) -> bool {
+        self.player.split(", ").any(|name| name == player)
+    }
+}
+
+impl LocalScores {
+    pub fn beatmap_key(beatmap: &Beatmap) -> String {
+        format!(
+            "{} - {} [{}]",
+            beatmap.data.artist, beatmap.data.title, beatmap.data.difficulty_name
+        )
+    }
+
+    pub fn add(&mut self, beatmap_key: String, score: LocalScore) {
+        let scores = self.beatmaps.entry(beatmap_key).or_default();
+        let position = scores
+            .iter()
+            .take_while(|other| other.score >= score.score)
+            .count();
+
+        scores.insert(position, score);
+    }
+
+    /// Returns the `count` highest scores of the beatmap
+    pub fn top(&self, beatmap_key: &str, count: usize) -> &[LocalScore] {
+        self.beatmaps
+            .get(beatmap_key)
+            .map(|scores| &scores[..scores.len().min(count)])
+            .unwrap_or_default()
+    }
+
+    /// Returns the ranked plays of `player` with the most performance points (the best one of each beatmap), with their
+    /// beatmap keys
+    pub fn top_plays(&self, player: &str, count: usize) -> Vec<(&str, &LocalScore)> {
+        let mut top_plays: Vec<_> = self
+            .beatmaps
+            .iter()
+            .filter_map(|(beatmap_key, scores)| {
+                scores
+                    .iter()
+                    .filter(|score| !score.unranked && score.has_player(player))
+                    .max_by(|a, b| a.pp.total_cmp(&b.pp))
+                    .map(|score| (beatmap_key.as_str(), score))
+            })
+            .collect();
+        top_plays.sort_by(|(_, a), (_, b)| b.pp.total_cmp(&a.pp));
+        top_plays.truncate(count);
+
+        top_plays
+    }
+
+    /// Returns the ranking position (starting at 1) that `score` would have in the beatmap leaderboard
+    pub fn position(&self, beatmap_key: &str, score: usize) -> usize {
+        1 + self
+            .beatmaps
+            .get(beatmap_key)
+            .map(|scores| scores.iter().filter(|other| other.score > score).count())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn local_score(score: usize) -> LocalScore {
+        LocalScore {
+            player: "player".to_string(),
+            score,
+            accuracy: 100.0,
+            max_combo: 0,
+            aim_assist: NO_AIM_ASSIST,
+            unranked: false,
+            pp: score as f64 / 10.0,
+        }
+    }
+
+    #[test]
+    fn local_scores_ranking() {
+        let key = "artist - title [difficulty]";
+        let mut scores = LocalScores::default();
+        assert_eq!(scores.position(key, 100), 1);
+        assert!(scores.top(key, 5).is_empty());
+
+        scores.add(key.to_string(), local_score(200));
+        scores.add(key.to_string(), local_score(500));
+        scores.add(key.to_string(), local_score(300));
+
+        assert_eq!(
+            scores.top(key, 2),
+            &[local_score(500), local_score(300)][..]
+        );
+        assert_eq!(scores.position(key, 1000), 1);
+        assert_eq!(scores.position(key, 300), 2);
+        assert_eq!(scores.position(key, 100), 4);
+    }
+
+    #[test]
+    fn player_top_plays() {
+        let mut scores = LocalScores::default();
+        scores.add("a".to_string(), local_score(500));
+        scores.add("a".to_string(), local_score(800));
+        scores.add("b".to_string(), local_score(900));
+        scores.add(
+            "c".to_string(),
+            LocalScore {
+                player: "other, player".to_string(),
+                ..local_score(100)
+            },
+        );
+        scores.add(
+            "d".to_string(),
+            LocalScore {
+                unranked: true,
+                ..local_score(2000)
+            },
+        );
+
+        let top_plays = scores.top_plays("player", 10);
+        let keys: Vec<_> = top_plays.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec!["b", "a", "c"]);
+        assert_eq!(top_plays[1].1.score, 800);
+        assert_eq!(scores.top_plays("player", 1).len(), 1);
+        assert!(scores.top_plays("nobody", 10).is_empty());
+    }
+}
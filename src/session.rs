@@ -0,0 +1,220 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    str,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use bevy_ecs::{
+    query::{With, Without},
+    system::{Local, Query, Res, ResMut, Resource},
+};
+use valence::prelude::{Client, Server};
+
+use crate::{
+    afk::Afk,
+    beatmap::{Beatmap, BeatmapState},
+    osu::Osu,
+    player_name::PlayerName,
+};
+
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// A saved session is continued on startup if it was saved less than this time ago (e.g. after a crash),
+/// otherwise a new session is started
+const SESSION_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Statistics of the players since the server started. They are saved periodically, so a crash doesn't lose the session records.
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct SessionStats {
+    /// Unix time in seconds
+    started_at: u64,
+    /// Unix time in seconds
+    saved_at: u64,
+    players: BTreeMap<String, PlayerSessionStats>,
+    #[serde(skip)]
+    has_changes: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct PlayerSessionStats {
+    pub plays: usize,
+    pub passes: usize,
+    pub fails: usize,
+    pub total_score: usize,
+    pub best_accuracy: f32,
+    pub max_combo: usize,
+    pub play_time_secs: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl SessionStats {
+    pub fn path() -> PathBuf {
+        PathBuf::from("session.json")
+    }
+
+    /// Continues the last session if it was saved recently, otherwise starts a new session
+    pub fn recover() -> Self {
+        let now = now_secs();
+
+        match Self::read() {
+            Ok(session) if now.saturating_sub(session.saved_at) < SESSION_TIMEOUT.as_secs() => {
+                info!(
+                    "Recovered session stats of {} players",
+                    session.players.len()
+                );
+                session
+            }
+            _ => Self {
+                started_at: now,
+                saved_at: now,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn read() -> Result<Self> {
+        let file_data = fs::read(Self::path())?;
+        let json = str::from_utf8(file_data.as_slice())?;
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Writes to a temporary file first, so a crash while saving doesn't corrupt the saved session
+    pub fn save(&mut self) -> Result<()> {
+        self.saved_at = now_secs();
+
+        let path = Self::path();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)?;
+
+        self.has_changes = false;
+
+        Ok(())
+    }
+
+    pub fn record_play<'a>(
+        &mut self,
+        players: impl IntoIterator<Item = &'a str>,
+        beatmap: &Beatmap,
+        passed: bool,
+    ) {
+        for player in players {
+            self.players
+                .entry(player.to_string())
+                .or_default()
+                .record_state(&beatmap.state, passed);
+        }
+
+        self.has_changes = true;
+    }
+
+    pub fn player(&self, name: &str) -> Option<&PlayerSessionStats> {
+        self.players.get(name)
+    }
+}
+
+impl PlayerSessionStats {
+    fn record_state(&mut self, state: &BeatmapState, passed: bool) {
+        self.plays += 1;
+        if passed {
+            self.passes += 1;
+            self.best_accuracy = self.best_accuracy.max(state.accuracy());
+        } else {
+            self.fails += 1;
+        }
+        self.total_score += state.score;
+        self.max_combo = self.max_combo.max(state.max_combo);
+        self.play_time_secs += state.play_time.as_secs();
+    }
+}
+
+/// Records finished and failed beatmaps and autosaves the session stats
+pub fn update_session_stats(
+    osu: Res<Osu>,
+    server: Res<Server>,
+    mut session: ResMut<SessionStats>,
+    player_names: Query<&PlayerName, (With<Client>, Without<Afk>)>,
+    mut recorded: Local<bool>,
+    mut ticks: Local<usize>,
+) {
+    let finished_beatmap = osu
+        .finished_beatmap()
+        .map(|beatmap| (beatmap, true))
+        .or_else(|| osu.failed_beatmap().map(|beatmap| (beatmap, false)));
+
+    match (finished_beatmap, *recorded) {
+        // Warmup and adaptive plays are unranked
+        (Some((beatmap, _)), false) if osu.is_warmup() || beatmap.state.adaptive.is_some() => {
+            *recorded = true
+        }
+        (Some((beatmap, passed)), false) => {
+            session.record_play(
+                player_names.iter().map(|name| name.as_str()),
+                beatmap,
+                passed,
+            );
+            *recorded = true;
+        }
+        (None, true) => *recorded = false,
+        _ => {}
+    }
+
+    let tps = server.shared().tps() as usize;
+    *ticks += 1;
+    if *ticks < AUTOSAVE_INTERVAL.as_secs() as usize * tps {
+        return;
+    }
+    *ticks = 0;
+
+    if session.has_changes {
+        if let Err(error) = session.save() {
+            warn!("Error while saving session stats: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn player_session_stats() {
+        let mut stats = PlayerSessionStats::default();
+        let mut state = BeatmapState {
+            score: 1000,
+            max_combo: 50,
+            hits300: 1,
+            play_time: Duration::from_secs(90),
+            ..Default::default()
+        };
+
+        stats.record_state(&state, true);
+        state.hits300 = 0;
+        state.misses = 1;
+        state.max_combo = 10;
+        stats.record_state(&state, false);
+
+        assert_eq!(
+            stats,
+            PlayerSessionStats {
+                plays: 2,
+                passes: 1,
+                fails: 1,
+                total_score: 2000,
+                best_accuracy: 100.0,
+                max_combo: 50,
+                play_time_secs: 180,
+            }
+        );
+    }
+}
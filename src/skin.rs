@@ -0,0 +1,377 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
+use tracing::{info, warn};
+
+use bevy_ecs::{
+    prelude::EventReader,
+    system::{Query, ResMut, Resource},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{BlockKind, BlockState, ItemKind, Text, TextFormat},
+};
+
+use crate::{
+    color::{self, BlockColor, DEFAULT_COMBO_COLORS},
+    command_registry::{ArgSpec, CommandSpec, OsuCommand},
+    configs::Configs,
+    error::{error_message, OsuError},
+    hit_score::HitScoreBlocks,
+    osu::Osu,
+    player_name::PlayerName,
+    playfield::PlayfieldSurface,
+};
+
+/// Name of `/skin` restoring the blocks used when no skin is selected
+const DEFAULT_SKIN_NAME: &str = "default";
+/// `/skin beatmap-colors` toggles `Skin::ignore_beatmap_colors`
+const BEATMAP_COLORS_SUBCOMMAND: &str = "beatmap-colors";
+
+/// Blocks the playfield is drawn with, loaded from a skin file of the `skins` folder (see `SkinFile`). The skin is shared by
+/// every player, since they all see the same blocks of the instance.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct BlockSkin {
+    /// `None` for the default skin
+    name: Option<String>,
+    /// Combo colors of the beatmaps without colors (or of every beatmap with `Skin::ignore_beatmap_colors`)
+    pub combo_colors: Vec<color::Color>,
+    /// Blocks of the hitcircle fillings, the closest one to each combo color is used
+    pub combo_palette: Vec<BlockColor>,
+    pub circle_ring: ItemKind,
+    /// Item of the approach circles made of blocks (`None` uses the item of the filling)
+    pub approach_circle: Option<ItemKind>,
+    pub combo_number: BlockState,
+    pub screen_background: BlockState,
+    pub hit_scores: HitScoreBlocks,
+}
+
+impl Default for BlockSkin {
+    fn default() -> Self {
+        Self {
+            name: None,
+            combo_colors: DEFAULT_COMBO_COLORS.to_vec(),
+            combo_palette: color::default_palette(),
+            circle_ring: ItemKind::WhiteConcrete,
+            approach_circle: None,
+            combo_number: BlockState::WHITE_CONCRETE,
+            screen_background: BlockState::BLACK_CONCRETE,
+            hit_scores: HitScoreBlocks::default(),
+        }
+    }
+}
+
+/// Skin file (`skins/<name>.json`). Blocks and items are named like in Minecraft (e.g. `white_wool`), and the missing fields
+/// keep the blocks of the default skin.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct SkinFile {
+    /// `[r, g, b]` of each combo
+    combo_colors: Vec<[u8; 3]>,
+    combo_palette: Vec<PaletteBlock>,
+    circle_ring: Option<String>,
+    approach_circle: Option<String>,
+    combo_number: Option<String>,
+    screen_background: Option<String>,
+    hit300: Option<String>,
+    hit100: Option<String>,
+    hit50: Option<String>,
+    miss: Option<String>,
+}
+
+/// Block of the combo palette with the color it looks like (`[r, g, b]`)
+#[derive(Deserialize, Debug)]
+struct PaletteBlock {
+    block: String,
+    color: [u8; 3],
+}
+
+impl BlockSkin {
+    pub fn dir() -> PathBuf {
+        PathBuf::from("skins")
+    }
+
+    /// Loads the skin selected in the configs, falling back to the default skin if it can't be loaded
+    pub fn open(configs: &Configs) -> Self {
+        let Some(name) = configs.block_skin() else {
+            return Self::default();
+        };
+
+        Self::load(name).unwrap_or_else(|error| {
+            warn!(
+                "Error while loading skin '{}', using the default skin: {}",
+                name, error
+            );
+            Self::default()
+        })
+    }
+
+    /// Loads the skin `name` of the `skins` folder (see `available_skins`)
+    pub fn load(name: &str) -> Result<Self> {
+        check_skin_name(name, &available_skins(&Self::dir()))?;
+        let path = Self::dir().join(format!("{}.json", name));
+
+        let file_data = fs::read(&path)?;
+        let skin = Self::parse(name, str::from_utf8(file_data.as_slice())?)?;
+        info!("Loaded skin '{}'", path.display());
+
+        Ok(skin)
+    }
+
+    fn parse(name: &str, json: &str) -> Result<Self> {
+        let file: SkinFile = serde_json::from_str(json)?;
+        let default = Self::default();
+        let block_or = |block: &Option<String>, default: BlockState| match block {
+            Some(block) => parse_block(block),
+            None => Ok(default),
+        };
+
+        let combo_palette = if file.combo_palette.is_empty() {
+            default.combo_palette
+        } else {
+            file.combo_palette
+                .iter()
+                .map(|palette_block| {
+                    let kind = parse_block_kind(&palette_block.block)?;
+                    let item = kind.to_item_kind().ok_or_else(|| {
+                        anyhow!("the block '{}' has no item", palette_block.block)
+                    })?;
+
+                    Ok(BlockColor::new(
+                        kind.to_state(),
+                        item,
+                        palette_block.color.into(),
+                    ))
+                })
+                .collect::<Result<_>>()?
+        };
+
+        Ok(Self {
+            name: Some(name.to_string()),
+            combo_colors: if file.combo_colors.is_empty() {
+                default.combo_colors
+            } else {
+                file.combo_colors.iter().map(|&rgb| rgb.into()).collect()
+            },
+            combo_palette,
+            circle_ring: match &file.circle_ring {
+                Some(item) => parse_item(item)?,
+                None => default.circle_ring,
+            },
+            approach_circle: file
+                .approach_circle
+                .as_deref()
+                .map(parse_item)
+                .transpose()?,
+            combo_number: block_or(&file.combo_number, default.combo_number)?,
+            screen_background: block_or(&file.screen_background, default.screen_background)?,
+            hit_scores: HitScoreBlocks {
+                hit300: block_or(&file.hit300, default.hit_scores.hit300)?,
+                hit100: block_or(&file.hit100, default.hit_scores.hit100)?,
+                hit50: block_or(&file.hit50, default.hit_scores.hit50)?,
+                miss: block_or(&file.miss, default.hit_scores.miss)?,
+            },
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_SKIN_NAME)
+    }
+}
+
+fn parse_block_kind(name: &str) -> Result<BlockKind> {
+    BlockKind::from_str(name.trim_start_matches("minecraft:"))
+        .ok_or_else(|| anyhow!("unknown block '{}'", name))
+}
+
+fn parse_block(name: &str) -> Result<BlockState> {
+    parse_block_kind(name).map(|kind| kind.to_state())
+}
+
+fn parse_item(name: &str) -> Result<ItemKind> {
+    ItemKind::from_str(name.trim_start_matches("minecraft:"))
+        .ok_or_else(|| anyhow!("unknown item '{}'", name))
+}
+
+/// Names of the skin files in the `skins` folder, sorted
+pub fn available_skins(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut skins: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    skins.sort();
+
+    skins
+}
+
+/// Only the skins in the `skins` folder can be loaded, so the names can't point to other files of the server
+fn check_skin_name(name: &str, available_skins: &[String]) -> Result<()> {
+    if name.contains(['/', '\\']) || name.contains("..") {
+        bail!("invalid skin name '{}'", name);
+    }
+    if !available_skins.iter().any(|skin| skin == name) {
+        bail!(
+            "skin '{}' not found in '{}'",
+            name,
+            BlockSkin::dir().display()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn skin_command() -> CommandSpec {
+    CommandSpec::new("skin")
+        .subcommands([BEATMAP_COLORS_SUBCOMMAND])
+        .arg(ArgSpec::word("skin"))
+}
+
+/// Handles `/skin [name|default|beatmap-colors]`, which changes the skin of the server or toggles whether the beatmap combo
+/// colors win over the skin ones (operators only). The hitcircles already on screen keep their blocks. Without arguments,
+/// it shows the current skin and the ones in the `skins` folder.
+pub fn execute_skin_commands(
+    mut skin: ResMut<BlockSkin>,
+    mut configs: ResMut<Configs>,
+    mut osu: ResMut<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "skin" {
+            continue;
+        }
+        let is_operator = player_names
+            .get(command_event.client)
+            .map_or(false, |player_name| configs.is_operator(player_name));
+        let name = command_event.args.trim();
+        let Ok(mut client) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+
+        let result: Result<Text> = if name.is_empty() {
+            let skins = available_skins(&BlockSkin::dir());
+            Ok("Skin: ".color(Color::YELLOW)
+                + skin.name().to_string().color(Color::GREEN)
+                + format!(
+                    " (available: {})",
+                    if skins.is_empty() {
+                        DEFAULT_SKIN_NAME.to_string()
+                    } else {
+                        format!("{}, {}", DEFAULT_SKIN_NAME, skins.join(", "))
+                    }
+                )
+                .color(Color::GRAY)
+                + beatmap_colors_text(configs.skin().ignore_beatmap_colors))
+        } else if !is_operator {
+            Err(OsuError::OperatorOnly {
+                action: "change the skin",
+            }
+            .into())
+        } else if name == BEATMAP_COLORS_SUBCOMMAND {
+            let ignore_beatmap_colors = !configs.skin().ignore_beatmap_colors;
+            configs
+                .set_ignore_beatmap_colors(ignore_beatmap_colors)
+                .map(|_| {
+                    "Skin updated:".color(Color::YELLOW)
+                        + beatmap_colors_text(ignore_beatmap_colors)
+                })
+        } else {
+            let new_skin = if name == DEFAULT_SKIN_NAME {
+                Ok(BlockSkin::default())
+            } else {
+                BlockSkin::load(name)
+            };
+
+            new_skin.and_then(|new_skin| {
+                configs.set_block_skin(new_skin.name.clone())?;
+                osu.set_screen_background(new_skin.screen_background);
+                osu.playfield()
+                    .paint_screen(&mut surface, new_skin.screen_background);
+                *skin = new_skin;
+
+                Ok("Skin set to ".color(Color::YELLOW)
+                    + skin.name().to_string().color(Color::GREEN))
+            })
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+fn beatmap_colors_text(ignore_beatmap_colors: bool) -> Text {
+    if ignore_beatmap_colors {
+        " the beatmap combo colors are ignored".color(Color::GRAY)
+    } else {
+        " the beatmap combo colors win over the skin ones".color(Color::GRAY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_skin_file() {
+        let skin = BlockSkin::parse(
+            "wool",
+            r#"{
+                "combo_palette": [
+                    { "block": "red_wool", "color": [160, 39, 34] },
+                    { "block": "minecraft:blue_wool", "color": [53, 57, 157] }
+                ],
+                "combo_number": "black_wool",
+                "miss": "red_wool"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(skin.name(), "wool");
+        let red = color::Color {
+            r: 200,
+            g: 20,
+            b: 20,
+        }
+        .to_block_color_in(&skin.combo_palette);
+        assert_eq!(red.item(), ItemKind::RedWool);
+        assert_eq!(skin.combo_number, BlockState::BLACK_WOOL);
+        assert_eq!(skin.hit_scores.miss, BlockState::RED_WOOL);
+        assert_eq!(skin.hit_scores.hit300, HitScoreBlocks::default().hit300);
+        assert_eq!(skin.screen_background, BlockState::BLACK_CONCRETE);
+
+        assert!(BlockSkin::parse("broken", r#"{ "combo_number": "rainbow_block" }"#).is_err());
+        assert_eq!(
+            BlockSkin::parse("empty", "{}").unwrap().combo_palette,
+            color::default_palette()
+        );
+    }
+
+    #[test]
+    fn skin_names() {
+        let available = vec!["wool".to_string(), "concrete".to_string()];
+
+        assert!(check_skin_name("wool", &available).is_ok());
+        assert!(check_skin_name("glass", &available).is_err());
+        assert!(check_skin_name("../configs", &available).is_err());
+        assert!(check_skin_name("skins/wool", &available).is_err());
+        assert!(check_skin_name("..\\wool", &available).is_err());
+    }
+}
@@ -2,27 +2,38 @@ use anyhow::{anyhow, Result};
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use std::{
     cmp::{min, Reverse},
-    fs::read_dir,
-    path::PathBuf,
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    mem,
+    path::{Path, PathBuf},
 };
 
 use bevy_ecs::{
     prelude::{Component, Entity, EventReader},
     query::{Changed, With},
-    system::{Commands, Query, ResMut},
+    system::{Commands, Query, Res, ResMut},
 };
-use tracing::error;
+use tracing::{error, warn};
 use valence::{
     client::event::ClickContainer,
     nbt::{compound, List},
     prelude::{Client, Color, Inventory, InventoryKind, OpenInventory},
-    protocol::{ItemKind, ItemStack, TextFormat},
+    protocol::{types::ClickContainerMode, ItemKind, ItemStack, TextFormat},
 };
 
 use crate::{
+    beatmap::beatmap_length,
     beatmap_selection::BeatmapSelectionInventory,
+    collections::{Collections, FAVORITES},
+    configs::Configs,
+    error::{error_message, OsuError},
+    filter_query::{Condition, FilterQuery},
     inventory::{open_new_inventory, InventoriesToOpen},
     osu::{BeatmapSelectionData, Osu, OsuStateChange},
+    osu_file::parse_osu_file,
+    player_name::PlayerName,
+    progress::{cancelled_error, LongOperation, LongOperations},
+    timing::{main_bpm, BeatTiming},
 };
 
 pub const SONG_ITEM_KIND: ItemKind = ItemKind::Jukebox;
@@ -30,13 +41,25 @@ const ARROW_ITEM_KIND: ItemKind = ItemKind::SpectralArrow;
 const PREVIOUS_PAGE_SLOT: u16 = 45;
 const NEXT_PAGE_SLOT: u16 = 53;
 const PAGE_SIZE: usize = 36;
+/// Keys of the conditions supported by the song filter (e.g. `bpm>180`)
+const SONG_FILTER_KEYS: [&str; 5] = ["artist", "title", "mapper", "bpm", "length"];
 
 #[derive(Component)]
 pub struct SongSelectionInventory {
     cur_page: usize,
     songs: Vec<PathBuf>,
     songs_dir: PathBuf,
-    keywords: Option<String>,
+    /// See `set_filter`
+    query: Option<String>,
+    tags: Option<Vec<String>>,
+    /// Name and songs of the collection the songs are restricted to (see `set_collection`)
+    collection: Option<(String, Vec<PathBuf>)>,
+    /// Metadata of each song directory, lazily read from their `.osu` files
+    metadata_cache: HashMap<PathBuf, SongMetadata>,
+    /// Reads the metadata of the songs which are not cached yet, the filters are applied once it finishes
+    metadata_indexing: Option<LongOperation<HashMap<PathBuf, SongMetadata>>>,
+    /// Titles of every song of the songs directory, suggested while typing `/filter-songs`
+    titles: Vec<String>,
 }
 
 struct Song {
@@ -44,6 +67,33 @@ struct Song {
     artist: String,
 }
 
+impl Song {
+    /// Parses the artist and title of a song directory named like `123 Artist - Title [no video]`
+    fn from_dir_name(song_path: &Path) -> Option<Self> {
+        let filename = song_path.file_name()?.to_str()?;
+        let filename = filename.split_once(' ')?.1.replace("[no video]", "");
+        let (artist, name) = filename.split_once(" - ")?;
+
+        Some(Self {
+            artist: artist.to_string(),
+            name: name.trim_end().to_string(),
+        })
+    }
+}
+
+/// Metadata of a song directory used by the filters
+#[derive(Default, Debug, Clone)]
+struct SongMetadata {
+    title: String,
+    artist: String,
+    mappers: Vec<String>,
+    tags: Vec<String>,
+    /// Main BPM of each difficulty
+    bpms: Vec<f64>,
+    /// Length of each difficulty in seconds
+    lengths: Vec<u64>,
+}
+
 impl SongSelectionInventory {
     pub fn new(songs_dir: PathBuf) -> Result<(Self, Inventory)> {
         let inventory = Inventory::new(InventoryKind::Generic9x6);
@@ -52,13 +102,25 @@ impl SongSelectionInventory {
             cur_page: 0,
             songs_dir,
             songs: Default::default(),
-            keywords: None,
+            query: None,
+            tags: None,
+            collection: None,
+            metadata_cache: Default::default(),
+            metadata_indexing: None,
+            titles: Vec::new(),
         };
-        result.songs = result.fetch_all_songs()?;
+        let songs = result.fetch_non_empty_songs()?;
+        result.index_titles(&songs);
+        result.songs = songs;
 
         Ok((result, inventory))
     }
 
+    /// Song directories matching the current filters
+    pub fn songs(&self) -> &[PathBuf] {
+        &self.songs
+    }
+
     pub fn go_to_next_page(&mut self) {
         self.cur_page += 1;
     }
@@ -67,29 +129,203 @@ impl SongSelectionInventory {
         self.cur_page -= 1;
     }
 
-    pub fn set_filter(&mut self, keywords: Option<&str>) -> Result<()> {
-        self.songs = Self::filter_songs(self.fetch_all_songs()?, keywords);
-        self.keywords = keywords.map(|s| s.to_string());
+    /// Changes the songs directory and rebuilds the song list, keeping the previous directory if the new one has no songs
+    pub fn set_songs_dir(&mut self, songs_dir: PathBuf) -> Result<()> {
+        let prev_songs_dir = mem::replace(&mut self.songs_dir, songs_dir);
+
+        match self.fetch_non_empty_songs() {
+            Ok(songs) => {
+                self.query = None;
+                self.tags = None;
+                self.collection = None;
+                self.metadata_cache.clear();
+                self.index_titles(&songs);
+                self.songs = songs;
+                if let Some(metadata_indexing) = self.metadata_indexing.take() {
+                    metadata_indexing.cancel();
+                }
+                self.cur_page = 0;
+
+                Ok(())
+            }
+            Err(error) => {
+                self.songs_dir = prev_songs_dir;
+                Err(error)
+            }
+        }
+    }
+
+    /// Only shows songs matching the query: fuzzy keywords mixed with osu!-style conditions like `artist=camellia`,
+    /// `title=...`, `mapper=...`, `bpm>180` or `length<120` (in seconds). See `set_tags_filter` for the return value.
+    pub fn set_filter(
+        &mut self,
+        query: Option<&str>,
+        client: Option<Entity>,
+        operations: &mut LongOperations,
+    ) -> Result<Option<usize>> {
+        self.query = query.map(|s| s.to_string());
+        self.apply_filters(client, operations)
+    }
+
+    /// Only shows songs containing all the space separated `tags` (e.g. genre or language tags like "anime" or "japanese").
+    ///
+    /// Reading the metadata of a big library takes a while, so the songs which are not cached yet are indexed in the background
+    /// and the filters are applied once it finishes. Returns the number of songs being indexed in this case.
+    pub fn set_tags_filter(
+        &mut self,
+        tags: Option<&str>,
+        client: Option<Entity>,
+        operations: &mut LongOperations,
+    ) -> Result<Option<usize>> {
+        self.tags = tags.map(|tags| {
+            tags.split_whitespace()
+                .map(|tag| tag.to_lowercase())
+                .collect()
+        });
+        self.apply_filters(client, operations)
+    }
+
+    /// Only shows the songs of a collection (in the collection order), or every song if `None`. The filters are applied on top of it.
+    pub fn set_collection(&mut self, collection: Option<(String, Vec<PathBuf>)>) -> Result<()> {
+        self.collection = collection;
+        self.refresh_songs()
+    }
+
+    pub fn collection_name(&self) -> Option<&str> {
+        self.collection.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Song titles best matching `typed`, fuzzy matched like the keywords of `set_filter`
+    pub fn title_suggestions(&self, typed: &str, max: usize) -> Vec<String> {
+        fuzzy_suggestions(&self.titles, typed, max)
+    }
+
+    pub fn reset_filters(&mut self) -> Result<()> {
+        self.query = None;
+        self.tags = None;
+        self.refresh_songs()
+    }
+
+    fn filter_query(&self) -> Option<FilterQuery> {
+        self.query
+            .as_deref()
+            .map(|query| FilterQuery::parse(query, &SONG_FILTER_KEYS))
+    }
+
+    fn apply_filters(
+        &mut self,
+        client: Option<Entity>,
+        operations: &mut LongOperations,
+    ) -> Result<Option<usize>> {
+        let needs_metadata = self.tags.is_some()
+            || self
+                .filter_query()
+                .is_some_and(|query| !query.conditions.is_empty());
+
+        if needs_metadata {
+            if let Some(metadata_indexing) = &self.metadata_indexing {
+                let (_, total) = metadata_indexing.progress().steps();
+                return Ok(Some(total));
+            }
+
+            let songs: Vec<_> = self
+                .base_songs()?
+                .into_iter()
+                .filter(|song_path| !self.metadata_cache.contains_key(song_path))
+                .collect();
+
+            if !songs.is_empty() {
+                let total = songs.len();
+                self.metadata_indexing = Some(LongOperation::start(
+                    "Indexing songs",
+                    client,
+                    operations,
+                    move |progress| {
+                        progress.set_total(songs.len());
+
+                        let mut metadata = HashMap::with_capacity(songs.len());
+                        for song_path in songs {
+                            if progress.is_cancelled() {
+                                return Err(cancelled_error());
+                            }
+
+                            let song_metadata = SongMetadata::read(&song_path);
+                            metadata.insert(song_path, song_metadata);
+                            progress.advance();
+                        }
+
+                        Ok(metadata)
+                    },
+                ));
+
+                return Ok(Some(total));
+            }
+        }
+
+        self.refresh_songs()?;
+        Ok(None)
+    }
+
+    /// Reads the songs directory again and applies the filters (e.g. after songs are downloaded)
+    pub fn refresh_songs(&mut self) -> Result<()> {
+        let songs = self.base_songs()?;
+        if self.collection.is_none() {
+            self.index_titles(&songs);
+        }
+        let query = self.filter_query().unwrap_or_default();
+        let tags = self.tags.clone().unwrap_or_default();
+
+        let songs = if query.conditions.is_empty() && tags.is_empty() {
+            songs
+        } else {
+            songs
+                .into_iter()
+                .filter(|song_path| {
+                    let metadata = self.song_metadata(song_path);
+                    tags.iter().all(|tag| metadata.tags.contains(tag))
+                        && query
+                            .conditions
+                            .iter()
+                            .all(|condition| metadata.matches(condition))
+                })
+                .collect()
+        };
+
+        let keywords = (!query.keywords.is_empty()).then_some(query.keywords.as_str());
+        self.songs = Self::filter_songs(songs, keywords);
         self.cur_page = 0;
 
         Ok(())
     }
 
+    fn song_metadata(&mut self, song_path: &Path) -> &SongMetadata {
+        self.metadata_cache
+            .entry(song_path.to_path_buf())
+            .or_insert_with(|| SongMetadata::read(song_path))
+    }
+
     fn page_songs(&self) -> Vec<Song> {
         self.page_song_paths()
             .iter()
-            .filter_map(|song_path| song_path.file_name().and_then(|f| f.to_str()))
-            .filter_map(|filename| Some(filename.split_once(' ')?.1.replace("[no video]", "")))
-            .filter_map(|filename| {
-                let (artist, name) = filename.split_once(" - ")?;
-                Some(Song {
-                    artist: artist.to_string(),
-                    name: name.to_string(),
-                })
-            })
+            .filter_map(|song_path| Song::from_dir_name(song_path))
             .collect()
     }
 
+    /// Uses the title of the metadata if the song was indexed, otherwise the one in the directory name
+    fn index_titles(&mut self, songs: &[PathBuf]) {
+        let mut titles: Vec<_> = songs
+            .iter()
+            .filter_map(|song_path| match self.metadata_cache.get(song_path) {
+                Some(metadata) if !metadata.title.is_empty() => Some(metadata.title.clone()),
+                _ => Song::from_dir_name(song_path).map(|song| song.name),
+            })
+            .collect();
+        titles.sort_unstable();
+        titles.dedup();
+
+        self.titles = titles;
+    }
+
     fn page_song_paths(&self) -> &[PathBuf] {
         let start_idx = self.cur_page * PAGE_SIZE;
         let end_idx = min(start_idx + PAGE_SIZE, self.songs.len());
@@ -105,7 +341,7 @@ impl SongSelectionInventory {
     }
 
     fn max_page(&self) -> usize {
-        (self.songs.len() - 1) / PAGE_SIZE
+        self.songs.len().saturating_sub(1) / PAGE_SIZE
     }
 
     fn fetch_all_songs(&self) -> Result<Vec<PathBuf>> {
@@ -123,6 +359,27 @@ impl SongSelectionInventory {
             .collect::<Vec<_>>())
     }
 
+    /// Songs which the filters are applied to: the ones of the collection or every song
+    fn base_songs(&self) -> Result<Vec<PathBuf>> {
+        match &self.collection {
+            Some((_, songs)) => Ok(songs.iter().filter(|song| song.is_dir()).cloned().collect()),
+            None => self.fetch_all_songs(),
+        }
+    }
+
+    fn fetch_non_empty_songs(&self) -> Result<Vec<PathBuf>> {
+        let songs = self.fetch_all_songs()?;
+
+        if songs.is_empty() {
+            Err(anyhow!(
+                "No songs found in directory: '{}'.",
+                self.songs_dir.display()
+            ))
+        } else {
+            Ok(songs)
+        }
+    }
+
     fn filter_songs(songs: Vec<PathBuf>, filter: Option<&str>) -> Vec<PathBuf> {
         match filter {
             Some(search_string) => {
@@ -150,6 +407,61 @@ impl SongSelectionInventory {
     }
 }
 
+/// The `max` entries of `candidates` best fuzzy matching `typed` (in order if nothing was typed yet)
+fn fuzzy_suggestions(candidates: &[String], typed: &str, max: usize) -> Vec<String> {
+    let typed = typed.trim();
+    if typed.is_empty() {
+        return candidates.iter().take(max).cloned().collect();
+    }
+
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut matches: Vec<_> = candidates
+        .iter()
+        .filter_map(|candidate| Some((matcher.fuzzy_match(candidate, typed)?, candidate)))
+        .collect();
+    matches.sort_by_key(|(fuzzy_score, _)| Reverse(*fuzzy_score));
+
+    matches
+        .into_iter()
+        .take(max)
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// Applies the filters once the songs are indexed
+pub fn update_metadata_indexing(mut song_selections: Query<&mut SongSelectionInventory>) {
+    for mut song_selection in &mut song_selections {
+        let is_finished = song_selection
+            .metadata_indexing
+            .as_ref()
+            .is_some_and(|metadata_indexing| metadata_indexing.is_finished());
+        if !is_finished {
+            continue;
+        }
+
+        let Some(result) = song_selection
+            .metadata_indexing
+            .take()
+            .and_then(|mut metadata_indexing| metadata_indexing.try_finish())
+        else {
+            continue;
+        };
+
+        match result {
+            Ok(metadata) => song_selection.metadata_cache.extend(metadata),
+            Err(error) => {
+                warn!("Song filters were not applied: {}", error);
+                song_selection.query = None;
+                song_selection.tags = None;
+            }
+        }
+
+        if let Err(error) = song_selection.refresh_songs() {
+            error!("Error while filtering songs: '{}'", error);
+        }
+    }
+}
+
 pub fn update_song_selection_inventory(
     mut inventories: Query<
         (&SongSelectionInventory, &mut Inventory),
@@ -168,7 +480,15 @@ pub fn update_song_selection_inventory(
         }
 
         let title = "Songs".color(Color::DARK_BLUE);
-        let title = if let Some(filter) = &song_selection.keywords {
+        let title = if let Some(collection) = song_selection.collection_name() {
+            title
+                + " (collection: '".color(Color::DARK_GRAY)
+                + collection.to_string().color(Color::DARK_PURPLE)
+                + "')".color(Color::DARK_GRAY)
+        } else {
+            title
+        };
+        let title = if let Some(filter) = &song_selection.query {
             title
                 + " (filter: '".color(Color::DARK_GRAY)
                 + filter.clone().color(Color::DARK_PURPLE)
@@ -176,6 +496,14 @@ pub fn update_song_selection_inventory(
         } else {
             title
         };
+        let title = if let Some(tags) = &song_selection.tags {
+            title
+                + " (tags: '".color(Color::DARK_GRAY)
+                + tags.join(" ").color(Color::DARK_PURPLE)
+                + "')".color(Color::DARK_GRAY)
+        } else {
+            title
+        };
 
         inventory.replace_title(title);
 
@@ -231,6 +559,9 @@ pub fn handle_song_selection_clicks(
     mut song_selections: Query<&mut SongSelectionInventory>,
     mut beatmap_selections: Query<(Entity, &mut BeatmapSelectionInventory)>,
     mut clients: Query<&mut Client>,
+    player_names: Query<&PlayerName>,
+    mut collections: ResMut<Collections>,
+    configs: Res<Configs>,
     mut clicks: EventReader<ClickContainer>,
 ) {
     for click in clicks.iter() {
@@ -266,49 +597,69 @@ pub fn handle_song_selection_clicks(
                     song_selection_entity,
                 );
             }
-            if let Some(selected_song) = song_selection
+            let selected_song = song_selection
                 .page_song_paths()
                 .get(click.slot_id.unsigned_abs() as usize)
-            {
+                .cloned();
+
+            // Shift-click toggles favorite songs
+            if let (Some(song), ClickContainerMode::ShiftClick) = (&selected_song, click.mode) {
+                let is_favorite = collections.toggle_favorite(song);
+                if let Err(error) = collections.save() {
+                    warn!("Error while saving collections: {}", error);
+                }
+
+                if song_selection.collection_name() == Some(FAVORITES) {
+                    let favorites = collections.get(FAVORITES).unwrap_or_default().to_vec();
+                    if let Err(error) =
+                        song_selection.set_collection(Some((FAVORITES.to_string(), favorites)))
+                    {
+                        warn!("Error while refreshing favorite songs: {}", error);
+                    }
+                }
+
+                if let Ok(mut client) = clients.get_mut(click.client) {
+                    let song_name = song
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    client.send_message(if is_favorite {
+                        "Added to favorites: ".color(Color::YELLOW) + song_name.color(Color::GREEN)
+                    } else {
+                        "Removed from favorites: ".color(Color::YELLOW)
+                            + song_name.color(Color::GRAY)
+                    });
+                }
+            } else if let Some(selected_song) = &selected_song {
+                let is_operator = player_names
+                    .get(click.client)
+                    .map_or(false, |player_name| configs.is_operator(player_name));
+
                 // Open beatmap selection
                 for (beatmap_selection_entity, mut beatmap_selection) in
                     beatmap_selections.iter_mut().take(1)
                 {
-                    match beatmap_selection.load_beatmap_dir(selected_song) {
-                        Ok(beatmaps) => {
-                            // Open beatmap selection window
-                            open_new_inventory(
-                                &mut commands,
-                                click.client,
-                                &mut inventories_to_open,
-                                beatmap_selection_entity,
-                            );
-
-                            // Update osu state
-                            if let Err(error) = osu.change_state(
-                                OsuStateChange::BeatmapSelection(BeatmapSelectionData {
-                                    beatmap_dir: selected_song.clone(),
-                                    beatmaps: beatmaps
-                                        .iter()
-                                        .map(|b| b.osu_file().clone())
-                                        .collect(),
-                                }),
-                                &mut clients,
-                            ) {
-                                error!(
-                                    "Error while changing to BeatmapSelection state: '{}'",
-                                    error
-                                )
-                            }
-                        }
-                        Err(error) => {
-                            clients.get_mut(click.client).unwrap().send_message(
-                                format!(
-                                    "Error occurred while reading beatmap directory: '{}'",
-                                    error
-                                )
-                                .color(Color::RED),
-                            );
+                    if let Err(error) = open_beatmap_selection(
+                        selected_song,
+                        click.client,
+                        is_operator,
+                        (beatmap_selection_entity, &mut beatmap_selection),
+                        &mut commands,
+                        &mut inventories_to_open,
+                        &mut osu,
+                        &mut clients,
+                    ) {
+                        let message = if error.downcast_ref::<OsuError>().is_some() {
+                            error_message(&error)
+                        } else {
+                            format!(
+                                "Error occurred while opening the beatmap selection: '{}'",
+                                error
+                            )
+                            .color(Color::RED)
+                        };
+                        if let Ok(mut client) = clients.get_mut(click.client) {
+                            client.send_message(message);
                         }
                     }
                 }
@@ -317,6 +668,132 @@ pub fn handle_song_selection_clicks(
     }
 }
 
+/// Loads the beatmaps of the song in the beatmap selection and opens it for `client` (see `Osu::change_state_for`)
+pub fn open_beatmap_selection(
+    song_dir: &PathBuf,
+    client: Entity,
+    is_operator: bool,
+    (beatmap_selection_entity, beatmap_selection): (Entity, &mut BeatmapSelectionInventory),
+    commands: &mut Commands,
+    inventories_to_open: &mut ResMut<InventoriesToOpen>,
+    osu: &mut Osu,
+    clients: &mut Query<&mut Client>,
+) -> Result<()> {
+    let beatmaps: Vec<_> = beatmap_selection
+        .load_beatmap_dir(song_dir)?
+        .iter()
+        .map(|b| (b.path().clone(), b.osu_file().clone()))
+        .collect();
+    if let Ok(mut client) = clients.get_mut(client) {
+        for warning in beatmap_selection.warnings() {
+            client.send_message(warning.clone().color(Color::YELLOW));
+        }
+    }
+
+    osu.change_state_for(
+        OsuStateChange::BeatmapSelection(BeatmapSelectionData {
+            beatmap_dir: song_dir.clone(),
+            beatmaps,
+        }),
+        is_operator,
+        clients,
+    )?;
+
+    open_new_inventory(
+        commands,
+        client,
+        inventories_to_open,
+        beatmap_selection_entity,
+    );
+
+    Ok(())
+}
+
+impl SongMetadata {
+    /// Reads the metadata of all the beatmaps inside of the song directory
+    fn read(song_path: &Path) -> Self {
+        let mut metadata = Self::default();
+        let Ok(entries) = read_dir(song_path) else {
+            return metadata;
+        };
+
+        let osu_files = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "osu"))
+            .filter_map(|path| read_to_string(path).ok());
+
+        for osu_file in osu_files {
+            metadata.tags.extend(parse_tags(&osu_file));
+
+            let Ok(osu_file) = parse_osu_file(&osu_file) else {
+                continue;
+            };
+
+            if let Some(beatmap_metadata) = osu_file.metadata.clone() {
+                if let Some(title) = beatmap_metadata.title {
+                    metadata.title = title.into();
+                }
+                if let Some(artist) = beatmap_metadata.artist {
+                    metadata.artist = artist.into();
+                }
+                if let Some(creator) = beatmap_metadata.creator {
+                    metadata.mappers.push(creator.into());
+                }
+            }
+
+            let Ok(length) = beatmap_length(&osu_file) else {
+                continue;
+            };
+            metadata.lengths.push(length.as_secs());
+
+            if let Some(bpm) = BeatTiming::from(&osu_file)
+                .ok()
+                .and_then(|beat_timings| main_bpm(&beat_timings, length.as_millis() as i32))
+            {
+                metadata.bpms.push(bpm);
+            }
+        }
+
+        metadata.tags.sort();
+        metadata.tags.dedup();
+        metadata.mappers.sort();
+        metadata.mappers.dedup();
+
+        metadata
+    }
+
+    /// Whether the song matches a filter condition (see `SONG_FILTER_KEYS`). Conditions on the difficulties (e.g. `bpm>180`) match if any difficulty matches.
+    fn matches(&self, condition: &Condition) -> bool {
+        match condition.key.as_str() {
+            "artist" => condition.matches_text(&self.artist),
+            "title" => condition.matches_text(&self.title),
+            "mapper" => self
+                .mappers
+                .iter()
+                .any(|mapper| condition.matches_text(mapper)),
+            "bpm" => self.bpms.iter().any(|&bpm| condition.matches_number(bpm)),
+            "length" => self
+                .lengths
+                .iter()
+                .any(|&length| condition.matches_number(length as f64)),
+            _ => false,
+        }
+    }
+}
+
+fn parse_tags(osu_file: &str) -> Vec<String> {
+    osu_file
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Tags:"))
+        .map(|tags| {
+            tags.split_whitespace()
+                .map(|tag| tag.to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -334,4 +811,59 @@ mod test {
         let filtered_beatmaps = SongSelectionInventory::filter_songs(beatmaps, Some("BaTaT"));
         assert_eq!(filtered_beatmaps, vec![second_beatmap]);
     }
+
+    #[test]
+    fn title_suggestions() {
+        let song =
+            Song::from_dir_name(Path::new("C:/test/123 xi - Blue Zenith [no video]")).unwrap();
+        assert_eq!(
+            (song.artist.as_str(), song.name.as_str()),
+            ("xi", "Blue Zenith")
+        );
+
+        let titles = vec![
+            "Blue Zenith".to_string(),
+            "Freedom Dive".to_string(),
+            "Harumachi Clover".to_string(),
+        ];
+        assert_eq!(fuzzy_suggestions(&titles, "fdive", 5), vec!["Freedom Dive"]);
+        assert_eq!(fuzzy_suggestions(&titles, "", 2), titles[..2].to_vec());
+        assert!(fuzzy_suggestions(&titles, "camellia", 5).is_empty());
+    }
+
+    #[test]
+    fn osu_file_tags() {
+        let osu_file = "[Metadata]\nTitle:test\nTags:Anime JAPANESE electronic\nBeatmapID:1";
+        assert_eq!(
+            parse_tags(osu_file),
+            vec!["anime", "japanese", "electronic"]
+        );
+        assert!(parse_tags("[Metadata]\nTitle:test").is_empty());
+    }
+
+    #[test]
+    fn song_metadata_conditions() {
+        let metadata = SongMetadata {
+            title: "Ghost Rule".to_string(),
+            artist: "DECO*27".to_string(),
+            mappers: vec!["Mapper".to_string(), "Other".to_string()],
+            bpms: vec![210.0, 105.0],
+            lengths: vec![200],
+            ..Default::default()
+        };
+        let query = FilterQuery::parse(
+            "artist=deco bpm>200 mapper=other length<240 ghost",
+            &SONG_FILTER_KEYS,
+        );
+
+        assert_eq!(query.keywords, "ghost");
+        assert!(query
+            .conditions
+            .iter()
+            .all(|condition| metadata.matches(condition)));
+
+        let query = FilterQuery::parse("bpm<100 title=rule", &SONG_FILTER_KEYS);
+        assert!(!metadata.matches(&query.conditions[0]));
+        assert!(metadata.matches(&query.conditions[1]));
+    }
 }
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, path::Path};
+use tracing::{info, warn};
+
+use bevy_ecs::{prelude::EventReader, system::Res};
+use valence::client::event::ClickContainer;
+
+use crate::{
+    audio::{find_sample, EffectSample},
+    osu::Osu,
+};
+
+/// Sounds of the game played in the server audio, with the file names used by osu! skins (https://osu.ppy.sh/wiki/en/Skinning/Sounds)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    Fail,
+    Applause,
+    MenuClick,
+}
+
+impl SoundEffect {
+    const ALL: [SoundEffect; 3] = [
+        SoundEffect::Fail,
+        SoundEffect::Applause,
+        SoundEffect::MenuClick,
+    ];
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            SoundEffect::Fail => "failsound",
+            SoundEffect::Applause => "applause",
+            SoundEffect::MenuClick => "menuclick",
+        }
+    }
+}
+
+/// Samples of the sound effects found in the `sounds_directory` of `configs.json` (e.g. the folder of an osu! skin).
+/// Missing samples are just not played.
+#[derive(Default)]
+pub struct SoundEffects {
+    samples: HashMap<SoundEffect, EffectSample>,
+}
+
+impl SoundEffects {
+    pub fn load(dir: Option<&Path>) -> Self {
+        let Some(dir) = dir else {
+            return Self::default();
+        };
+
+        let samples: HashMap<_, _> = SoundEffect::ALL
+            .into_iter()
+            .filter_map(|effect| {
+                let path = find_sample(dir, effect.file_name())?;
+                match EffectSample::open(&path) {
+                    Ok(sample) => Some((effect, sample)),
+                    Err(error) => {
+                        warn!(
+                            "Error while loading sound effect '{}': {}",
+                            path.display(),
+                            error
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+        info!(
+            "Loaded {} sound effects from '{}'",
+            samples.len(),
+            dir.display()
+        );
+
+        Self { samples }
+    }
+
+    pub fn get(&self, effect: SoundEffect) -> Option<&EffectSample> {
+        self.samples.get(&effect)
+    }
+}
+
+/// Plays the menu click sound when players click on any inventory (song selection, beatmap selection, votes...)
+pub fn play_menu_clicks(osu: Res<Osu>, mut click_events: EventReader<ClickContainer>) {
+    if click_events.iter().count() > 0 {
+        osu.play_effect(SoundEffect::MenuClick);
+    }
+}
@@ -0,0 +1,261 @@
+use valence::protocol::ItemKind;
+
+use crate::{
+    beatmap::CircleSize,
+    color::Color,
+    hit_object::{HitObject, HitObjectParams},
+    hitcircle::HitcircleRadius,
+};
+
+/// Length of the sections whose peak strains are summed
+const SECTION_LENGTH_MS: f64 = 400.0;
+/// Hit objects closer than this are considered as being this close (avoids infinite strains on stacked notes)
+const MIN_DELTA_TIME_MS: f64 = 50.0;
+/// Weight decay of the peak strains, from the hardest section to the easiest one
+const PEAK_WEIGHT_DECAY: f64 = 0.9;
+const RATING_MULTIPLIER: f64 = 0.0675;
+/// Circle radius which the distances are normalized to
+const NORMALIZED_RADIUS: f64 = 52.0;
+
+/// Strain of jumping between hit objects
+const AIM: Skill = Skill {
+    multiplier: 26.25,
+    decay_base: 0.15,
+};
+/// Strain of tapping fast
+const SPEED: Skill = Skill {
+    multiplier: 1400.0,
+    decay_base: 0.3,
+};
+
+struct Skill {
+    multiplier: f64,
+    /// Part of the strain left after one second
+    decay_base: f64,
+}
+
+/// Hit object position (normalized by the circle size) and time in milliseconds
+type Note = (f64, f64, f64);
+
+/// Approximation of the osu! star rating: strains of aim (distance between hit objects) and speed (time between hit objects)
+/// are accumulated with decay, and the peak strains of each section are summed with decreasing weights.
+pub fn star_rating(hit_objects: &[HitObject], cs: CircleSize) -> f64 {
+    let scale = NORMALIZED_RADIUS / HitcircleRadius::from(cs, 1.0).circle;
+    let notes: Vec<Note> = hit_objects
+        .iter()
+        .filter(|hit_object| !matches!(hit_object.params(), HitObjectParams::Spinner))
+        .map(|hit_object| {
+            (
+                hit_object.x() as f64 * scale,
+                hit_object.y() as f64 * scale,
+                hit_object.time() as f64,
+            )
+        })
+        .collect();
+
+    notes_star_rating(&notes)
+}
+
+fn notes_star_rating(notes: &[Note]) -> f64 {
+    let aim = skill_rating(notes, &AIM, |distance| distance.powf(0.99));
+    let speed = skill_rating(notes, &SPEED, speed_bonus);
+
+    aim + speed + (aim - speed).abs() / 2.0
+}
+
+/// Jumps make streams harder
+fn speed_bonus(distance: f64) -> f64 {
+    1.0 + 1.5 * (distance / 125.0).min(1.0).powi(2)
+}
+
+fn skill_rating(notes: &[Note], skill: &Skill, value: impl Fn(f64) -> f64) -> f64 {
+    let mut peaks = Vec::new();
+    let mut strain = 0.0;
+    let mut section_end = SECTION_LENGTH_MS;
+    let mut section_peak: f64 = 0.0;
+
+    for (prev, note) in notes.iter().zip(notes.iter().skip(1)) {
+        let delta_time = (note.2 - prev.2).max(MIN_DELTA_TIME_MS);
+        let distance = ((note.0 - prev.0).powi(2) + (note.1 - prev.1).powi(2)).sqrt();
+
+        while note.2 > section_end {
+            peaks.push(section_peak);
+            section_peak = strain * skill.decay_base.powf((section_end - prev.2) / 1000.0);
+            section_end += SECTION_LENGTH_MS;
+        }
+
+        strain = strain * skill.decay_base.powf(delta_time / 1000.0)
+            + value(distance) / delta_time * skill.multiplier;
+        section_peak = section_peak.max(strain);
+    }
+    peaks.push(section_peak);
+
+    peaks.sort_by(|a, b| b.total_cmp(a));
+    let difficulty: f64 = peaks
+        .iter()
+        .zip(std::iter::successors(Some(1.0), |weight| {
+            Some(weight * PEAK_WEIGHT_DECAY)
+        }))
+        .map(|(peak, weight)| peak * weight)
+        .sum();
+
+    difficulty.sqrt() * RATING_MULTIPLIER
+}
+
+/// Simplified osu! performance points of a play: the star rating gives the value of a perfect play, which is scaled down by
+/// the accuracy (from 0 to 100), the missed part of the combo and the misses
+pub fn performance_points(
+    star_rating: f64,
+    accuracy: f32,
+    max_combo: usize,
+    beatmap_max_combo: usize,
+    misses: usize,
+) -> f64 {
+    // Like the aim and speed values of osu!, with both skills at half the star rating
+    let skill = (star_rating / 2.0 / RATING_MULTIPLIER).max(1.0);
+    let base = 2.0 * (5.0 * skill - 4.0).powi(3) / 100_000.0;
+
+    let accuracy_factor = (accuracy as f64 / 100.0).clamp(0.0, 1.0).powi(5);
+    let combo_factor = if beatmap_max_combo == 0 {
+        1.0
+    } else {
+        (max_combo as f64 / beatmap_max_combo as f64)
+            .min(1.0)
+            .powf(0.8)
+    };
+    let miss_factor = 0.97_f64.powi(misses as i32);
+
+    base * accuracy_factor * combo_factor * miss_factor
+}
+
+/// Difficulty names used by osu! for each star rating range
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DifficultyTier {
+    Easy,
+    Normal,
+    Hard,
+    Insane,
+    Expert,
+    ExpertPlus,
+}
+
+impl DifficultyTier {
+    pub fn from(star_rating: f64) -> Self {
+        match star_rating {
+            stars if stars < 2.0 => Self::Easy,
+            stars if stars < 2.7 => Self::Normal,
+            stars if stars < 4.0 => Self::Hard,
+            stars if stars < 5.3 => Self::Insane,
+            stars if stars < 6.5 => Self::Expert,
+            _ => Self::ExpertPlus,
+        }
+    }
+
+    pub fn item_kind(self) -> ItemKind {
+        match self {
+            Self::Easy => ItemKind::LimeConcrete,
+            Self::Normal => ItemKind::LightBlueConcrete,
+            Self::Hard => ItemKind::YellowConcrete,
+            Self::Insane => ItemKind::PinkConcrete,
+            Self::Expert => ItemKind::PurpleConcrete,
+            Self::ExpertPlus => ItemKind::BlackConcrete,
+        }
+    }
+}
+
+/// Star ratings and colors of the difficulty spectrum used by the osu! website (blue, green, yellow, red, purple and black)
+const STAR_RATING_SPECTRUM: [(f64, [u8; 3]); 11] = [
+    (0.1, [0x42, 0x90, 0xfb]),
+    (1.25, [0x4f, 0xc0, 0xff]),
+    (2.0, [0x4f, 0xff, 0xd5]),
+    (2.5, [0x7c, 0xff, 0x4f]),
+    (3.3, [0xf6, 0xf0, 0x5c]),
+    (4.2, [0xff, 0x80, 0x68]),
+    (4.9, [0xff, 0x4e, 0x6f]),
+    (5.8, [0xc6, 0x45, 0xb8]),
+    (6.7, [0x65, 0x63, 0xde]),
+    (7.7, [0x18, 0x15, 0x8e]),
+    (9.0, [0x00, 0x00, 0x00]),
+];
+
+/// Color of the star rating in the osu! website difficulty spectrum
+pub fn star_rating_color(star_rating: f64) -> Color {
+    if star_rating < STAR_RATING_SPECTRUM[0].0 {
+        return Color {
+            r: 0xaa,
+            g: 0xaa,
+            b: 0xaa,
+        };
+    }
+
+    let (_, hardest) = STAR_RATING_SPECTRUM[STAR_RATING_SPECTRUM.len() - 1];
+
+    STAR_RATING_SPECTRUM
+        .windows(2)
+        .find(|range| star_rating < range[1].0)
+        .map(|range| {
+            let ((from_stars, from_color), (to_stars, to_color)) = (range[0], range[1]);
+            Color::from(from_color).lerp(
+                to_color.into(),
+                (star_rating - from_stars) / (to_stars - from_stars),
+            )
+        })
+        .unwrap_or(hardest.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stream(notes: usize, spacing: f64, interval_ms: f64) -> Vec<Note> {
+        (0..notes)
+            .map(|i| ((i % 2) as f64 * spacing, 0.0, i as f64 * interval_ms))
+            .collect()
+    }
+
+    #[test]
+    fn faster_and_wider_maps_are_harder() {
+        assert_eq!(notes_star_rating(&[]), 0.0);
+
+        let easy = notes_star_rating(&stream(100, 50.0, 500.0));
+        let fast = notes_star_rating(&stream(100, 50.0, 150.0));
+        let jumps = notes_star_rating(&stream(100, 200.0, 500.0));
+
+        assert!(easy > 0.0);
+        assert!(fast > easy);
+        assert!(jumps > easy);
+    }
+
+    #[test]
+    fn performance_points_of_plays() {
+        let perfect = performance_points(5.0, 100.0, 500, 500, 0);
+        assert!(perfect > 100.0 && perfect < 150.0);
+        assert!(performance_points(6.0, 100.0, 500, 500, 0) > perfect);
+
+        let choked = performance_points(5.0, 100.0, 250, 500, 1);
+        let inaccurate = performance_points(5.0, 95.0, 500, 500, 0);
+        assert!(choked < perfect);
+        assert!(inaccurate < perfect);
+        assert_eq!(performance_points(5.0, 0.0, 0, 500, 500), 0.0);
+    }
+
+    #[test]
+    fn difficulty_tiers() {
+        assert_eq!(DifficultyTier::from(1.5), DifficultyTier::Easy);
+        assert_eq!(DifficultyTier::from(2.0), DifficultyTier::Normal);
+        assert_eq!(DifficultyTier::from(4.5), DifficultyTier::Insane);
+        assert_eq!(DifficultyTier::from(8.0), DifficultyTier::ExpertPlus);
+    }
+
+    #[test]
+    fn star_rating_spectrum() {
+        assert_eq!(star_rating_color(0.0).to_hex(), "#aaaaaa");
+        assert_eq!(star_rating_color(2.0).to_hex(), "#4fffd5");
+        assert_eq!(star_rating_color(10.0).to_hex(), "#000000");
+
+        // Between yellow and red
+        let color = star_rating_color(3.75);
+        assert_eq!(color.r, 0xfb);
+        assert!(color.g < 0xf0 && color.g > 0x80);
+    }
+}
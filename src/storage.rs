@@ -0,0 +1,304 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf, str};
+
+use crate::{
+    hud::HudSettings,
+    profile::PlayerStats,
+    scores::{LocalScore, LocalScores},
+};
+
+/// Persistence layer for the data generated while the server is running (scores, player settings and lifetime stats)
+pub trait Storage: Send + Sync {
+    fn load_scores(&self) -> Result<LocalScores>;
+
+    fn save_score(&self, beatmap_key: &str, score: &LocalScore) -> Result<()>;
+
+    fn load_hud_settings(&self, username: &str) -> Result<Option<HudSettings>>;
+
+    fn save_hud_settings(&self, username: &str, settings: &HudSettings) -> Result<()>;
+
+    /// Backends which don't persist the lifetime stats start every player from scratch
+    fn load_player_stats(&self, _username: &str) -> Result<Option<PlayerStats>> {
+        Ok(None)
+    }
+
+    fn save_player_stats(&self, _username: &str, _stats: &PlayerStats) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Storage backends which can be selected in the configs file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// Flat JSON files, suited for small home servers
+    #[default]
+    Json,
+    /// SQLite database, suited for larger community servers (requires the `sqlite` feature)
+    Sqlite,
+}
+
+impl StorageKind {
+    pub fn open(self) -> Result<Box<dyn Storage>> {
+        match self {
+            StorageKind::Json => Ok(Box::new(JsonStorage::new(
+                PathBuf::from("scores.json"),
+                PathBuf::from("players.json"),
+                PathBuf::from("player_stats.json"),
+            ))),
+            #[cfg(feature = "sqlite")]
+            StorageKind::Sqlite => Ok(Box::new(sqlite::SqliteStorage::open(PathBuf::from(
+                "osucraft.db",
+            ))?)),
+            #[cfg(not(feature = "sqlite"))]
+            StorageKind::Sqlite => Err(anyhow::anyhow!(
+                "osucraft was built without the 'sqlite' feature"
+            )),
+        }
+    }
+}
+
+pub struct JsonStorage {
+    scores_path: PathBuf,
+    players_path: PathBuf,
+    stats_path: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn new(scores_path: PathBuf, players_path: PathBuf, stats_path: PathBuf) -> Self {
+        Self {
+            scores_path,
+            players_path,
+            stats_path,
+        }
+    }
+
+    fn read<T: Default + for<'de> Deserialize<'de>>(path: &PathBuf) -> Result<T> {
+        if !path.exists() {
+            return Ok(T::default());
+        }
+
+        let file_data = fs::read(path)?;
+        let json = str::from_utf8(file_data.as_slice())?;
+        Ok(serde_json::from_str(json)?)
+    }
+
+    fn write<T: Serialize>(path: &PathBuf, data: &T) -> Result<()> {
+        let json = serde_json::to_string_pretty(data)?;
+        fs::write(path, json)?;
+
+        Ok(())
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_scores(&self) -> Result<LocalScores> {
+        Self::read(&self.scores_path)
+    }
+
+    fn save_score(&self, beatmap_key: &str, score: &LocalScore) -> Result<()> {
+        let mut scores: LocalScores = Self::read(&self.scores_path)?;
+        scores.add(beatmap_key.to_string(), score.clone());
+        Self::write(&self.scores_path, &scores)
+    }
+
+    fn load_hud_settings(&self, username: &str) -> Result<Option<HudSettings>> {
+        let mut players: HashMap<String, HudSettings> = Self::read(&self.players_path)?;
+        Ok(players.remove(username))
+    }
+
+    fn save_hud_settings(&self, username: &str, settings: &HudSettings) -> Result<()> {
+        let mut players: HashMap<String, HudSettings> = Self::read(&self.players_path)?;
+        players.insert(username.to_string(), settings.clone());
+        Self::write(&self.players_path, &players)
+    }
+
+    fn load_player_stats(&self, username: &str) -> Result<Option<PlayerStats>> {
+        let mut players: HashMap<String, PlayerStats> = Self::read(&self.stats_path)?;
+        Ok(players.remove(username))
+    }
+
+    fn save_player_stats(&self, username: &str, stats: &PlayerStats) -> Result<()> {
+        let mut players: HashMap<String, PlayerStats> = Self::read(&self.stats_path)?;
+        players.insert(username.to_string(), stats.clone());
+        Self::write(&self.stats_path, &players)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use anyhow::Result;
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::{path::PathBuf, sync::Mutex};
+
+    use super::Storage;
+    use crate::{
+        hud::HudSettings,
+        profile::PlayerStats,
+        scores::{LocalScore, LocalScores},
+    };
+
+    pub struct SqliteStorage {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: PathBuf) -> Result<Self> {
+            let connection = Connection::open(path)?;
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS scores (
+                    beatmap_key TEXT NOT NULL,
+                    player TEXT NOT NULL,
+                    score INTEGER NOT NULL,
+                    accuracy REAL NOT NULL,
+                    max_combo INTEGER NOT NULL,
+                    aim_assist REAL NOT NULL DEFAULT 1.0,
+                    unranked INTEGER NOT NULL DEFAULT 0,
+                    pp REAL NOT NULL DEFAULT 0
+                );
+                CREATE INDEX IF NOT EXISTS scores_beatmap_key ON scores (beatmap_key);
+                CREATE TABLE IF NOT EXISTS hud_settings (
+                    username TEXT PRIMARY KEY,
+                    settings TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS player_stats (
+                    username TEXT PRIMARY KEY,
+                    stats TEXT NOT NULL
+                );",
+            )?;
+            // Databases created before the aim assist don't have its column
+            if connection
+                .prepare("SELECT aim_assist FROM scores LIMIT 0")
+                .is_err()
+            {
+                connection.execute(
+                    "ALTER TABLE scores ADD COLUMN aim_assist REAL NOT NULL DEFAULT 1.0",
+                    [],
+                )?;
+            }
+            // Nor the ones created before the RX and AP mods
+            if connection
+                .prepare("SELECT unranked FROM scores LIMIT 0")
+                .is_err()
+            {
+                connection.execute(
+                    "ALTER TABLE scores ADD COLUMN unranked INTEGER NOT NULL DEFAULT 0",
+                    [],
+                )?;
+            }
+            // Nor the ones created before the performance points
+            if connection.prepare("SELECT pp FROM scores LIMIT 0").is_err() {
+                connection.execute(
+                    "ALTER TABLE scores ADD COLUMN pp REAL NOT NULL DEFAULT 0",
+                    [],
+                )?;
+            }
+
+            Ok(Self {
+                connection: Mutex::new(connection),
+            })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn load_scores(&self) -> Result<LocalScores> {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(
+                "SELECT beatmap_key, player, score, accuracy, max_combo, aim_assist, unranked, pp FROM scores",
+            )?;
+            let rows = statement.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    LocalScore {
+                        player: row.get(1)?,
+                        score: row.get::<_, i64>(2)? as usize,
+                        accuracy: row.get::<_, f64>(3)? as f32,
+                        max_combo: row.get::<_, i64>(4)? as usize,
+                        aim_assist: row.get(5)?,
+                        unranked: row.get(6)?,
+                        pp: row.get(7)?,
+                    },
+                ))
+            })?;
+
+            let mut scores = LocalScores::default();
+            for row in rows {
+                let (beatmap_key, score) = row?;
+                scores.add(beatmap_key, score);
+            }
+
+            Ok(scores)
+        }
+
+        fn save_score(&self, beatmap_key: &str, score: &LocalScore) -> Result<()> {
+            self.connection.lock().unwrap().execute(
+                "INSERT INTO scores (beatmap_key, player, score, accuracy, max_combo, aim_assist, unranked, pp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    beatmap_key,
+                    score.player,
+                    score.score as i64,
+                    score.accuracy as f64,
+                    score.max_combo as i64,
+                    score.aim_assist,
+                    score.unranked,
+                    score.pp
+                ],
+            )?;
+
+            Ok(())
+        }
+
+        fn load_hud_settings(&self, username: &str) -> Result<Option<HudSettings>> {
+            let settings: Option<String> = self
+                .connection
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT settings FROM hud_settings WHERE username = ?1",
+                    params![username],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            Ok(settings
+                .map(|settings| serde_json::from_str(&settings))
+                .transpose()?)
+        }
+
+        fn save_hud_settings(&self, username: &str, settings: &HudSettings) -> Result<()> {
+            self.connection.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO hud_settings (username, settings) VALUES (?1, ?2)",
+                params![username, serde_json::to_string(settings)?],
+            )?;
+
+            Ok(())
+        }
+
+        fn load_player_stats(&self, username: &str) -> Result<Option<PlayerStats>> {
+            let stats: Option<String> = self
+                .connection
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT stats FROM player_stats WHERE username = ?1",
+                    params![username],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            Ok(stats
+                .map(|stats| serde_json::from_str(&stats))
+                .transpose()?)
+        }
+
+        fn save_player_stats(&self, username: &str, stats: &PlayerStats) -> Result<()> {
+            self.connection.lock().unwrap().execute(
+                "INSERT OR REPLACE INTO player_stats (username, stats) VALUES (?1, ?2)",
+                params![username, serde_json::to_string(stats)?],
+            )?;
+
+            Ok(())
+        }
+    }
+}
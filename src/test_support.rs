@@ -0,0 +1,169 @@
+//! Plays synthetic beatmaps without a server: a fake audio clock drives the ticks, and each tick goes through the
+//! steps `update_osu` plays in the default game mode (`Beatmap::expire_hit_objects`, `Beatmap::spawn_due_hit_object`
+//! and `Beatmap::apply_hit`), with the hitcircles expired by the `update_hitcircle` system and scripted clicks
+//! judged with `Hitcircle::judge`.
+
+use bevy_ecs::prelude::*;
+use std::{collections::VecDeque, path::PathBuf, time::Duration};
+use valence::Despawned;
+
+use crate::{
+    audio_clock::{AudioClock, MonotonicClock},
+    beatmap::{
+        ApproachRate, Beatmap, BeatmapData, BeatmapState, CircleSize, HpDrainRate,
+        OverallDifficulty,
+    },
+    game_mode::GameModeHit,
+    hit_object::HitObject,
+    hitcircle::{update_hitcircle, Hitcircle},
+    lag::LagCompensation,
+    mods::Mods,
+};
+
+pub const TPS: usize = 20;
+
+/// Beatmap with a hitcircle at each of `hit_times_ms`
+pub fn synthetic_beatmap(od: f64, hp: f64, hit_times_ms: &[u32]) -> Beatmap {
+    Beatmap {
+        data: BeatmapData {
+            od: OverallDifficulty(od),
+            ar: ApproachRate(9.0),
+            cs: CircleSize(4.0),
+            hp: HpDrainRate(hp),
+            hit_objects: hit_times_ms
+                .iter()
+                .map(|&time| HitObject::hitcircle_at(time))
+                .collect(),
+            breaks: Vec::new(),
+            beat_timings: Vec::new(),
+            audio_path: PathBuf::from("audio.mp3"),
+            artist: "Artist".to_string(),
+            title: "Title".to_string(),
+            difficulty_name: "Synthetic".to_string(),
+            creator: "Creator".to_string(),
+            source: String::new(),
+            dir: PathBuf::from("Songs/1 Artist - Title"),
+            beatmap_id: None,
+            beatmap_set_id: None,
+            file_hash: String::new(),
+            mods: Mods::default(),
+        },
+        state: Default::default(),
+    }
+}
+
+/// Plays `beatmap` clicking at each of `clicks_ms` (aiming at the next hitcircle), one tick at a time
+/// until every hit object is judged, and returns its final state.
+pub fn play(mut beatmap: Beatmap, clicks_ms: &[u32]) -> BeatmapState {
+    let clock = MonotonicClock::default();
+    let tick_duration = Duration::from_millis(1000 / TPS as u64);
+    let mut world = World::new();
+    world.insert_resource(LagCompensation::default());
+    let player = world.spawn_empty().id();
+    let mut hitcircle_systems = SystemStage::single_threaded().with_system(update_hitcircle);
+
+    let mut clicks: VecDeque<_> = clicks_ms
+        .iter()
+        .map(|&click_ms| Duration::from_millis(click_ms as u64))
+        .collect();
+
+    let hit_objects = beatmap.data.hit_objects.len();
+    while beatmap.state.hits300
+        + beatmap.state.hits100
+        + beatmap.state.hits50
+        + beatmap.state.misses
+        < hit_objects
+    {
+        clock.seek(clock.play_time() + tick_duration);
+        let play_time = clock.play_time();
+        beatmap.state.play_time = play_time;
+
+        // Remove the hitcircles despawned by `update_hitcircle`
+        beatmap.expire_hit_objects(|entity| world.get::<Hitcircle>(entity).is_none());
+
+        let look_ahead = beatmap.ar().to_mc_duration();
+        beatmap
+            .spawn_due_hit_object(look_ahead, |_, beatmap| {
+                Ok(world.spawn(Hitcircle::undrawn(beatmap, TPS)).id())
+            })
+            .unwrap();
+
+        // Each click judges the oldest hitcircle
+        while clicks.front().map_or(false, |&click| click <= play_time) {
+            clicks.pop_front();
+            let Some(&entity) = beatmap.state.active_hit_objects.front() else {
+                continue;
+            };
+            let grace_ticks = world.resource::<LagCompensation>().grace_ticks();
+            let hitcircle = world.get::<Hitcircle>(entity).unwrap();
+            let hit = GameModeHit {
+                entity,
+                player,
+                hit: hitcircle.judge(grace_ticks, 0, TPS),
+                hit_error_ms: Some(hitcircle.hit_error(TPS, 0)),
+            };
+            beatmap.apply_hit(&hit);
+            world.despawn(entity);
+        }
+
+        hitcircle_systems.run(&mut world);
+        let despawned = world
+            .query_filtered::<Entity, With<Despawned>>()
+            .iter(&world)
+            .collect::<Vec<_>>();
+        for entity in despawned {
+            world.despawn(entity);
+        }
+    }
+
+    beatmap.state
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HIT_TIMES_MS: [u32; 10] = [1000, 2000, 3000, 4000, 5000, 6000, 7000, 8000, 9000, 10000];
+
+    #[test]
+    fn perfect_play() {
+        let beatmap = synthetic_beatmap(8.0, 5.0, &HIT_TIMES_MS);
+        let state = play(beatmap, &HIT_TIMES_MS);
+
+        assert_eq!(state.hits300, 10);
+        assert_eq!(state.max_combo, 10);
+        assert_eq!(state.score, 4295);
+        assert_eq!(state.accuracy(), 100.0);
+        assert_eq!(state.grade().name(), "SS");
+        assert_eq!(state.health, 1.0);
+        assert!(state.hit_errors.iter().all(|&hit_error| hit_error == 0));
+    }
+
+    #[test]
+    fn play_with_misses() {
+        let beatmap = synthetic_beatmap(8.0, 5.0, &HIT_TIMES_MS);
+        // Late on the 3rd hitcircle, and the 5th hitcircle is never clicked
+        let clicks = [1000, 2000, 3100, 4000, 6000, 7000, 8000, 9000, 10000];
+        let state = play(beatmap, &clicks);
+
+        assert_eq!(
+            (state.hits300, state.hits100, state.hits50, state.misses),
+            (8, 1, 0, 1)
+        );
+        assert_eq!(state.max_combo, 5);
+        assert_eq!(state.combo, 5);
+        assert!((state.accuracy() - 83.33).abs() < 0.01);
+        assert_eq!(state.grade().name(), "C");
+        assert!(state.hit_errors.contains(&100));
+    }
+
+    #[test]
+    fn clicking_too_early_is_a_miss() {
+        let beatmap = synthetic_beatmap(8.0, 5.0, &[1000]);
+        let state = play(beatmap, &[500]);
+
+        assert_eq!(state.misses, 1);
+        assert_eq!(state.score, 0);
+        assert_eq!(state.grade().name(), "D");
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use osu_file_parser::{events::Event, OsuFile};
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// https://osu.ppy.sh/wiki/en/Client/File_formats/Osu_%28file_format%29#breaks
+pub struct BreakPeriod {
+    /// In milliseconds since the start of the beatmap
+    pub start_time: u32,
+    /// In milliseconds since the start of the beatmap
+    pub end_time: u32,
+}
+
+impl BreakPeriod {
+    pub fn from(osu_file: &OsuFile) -> Result<Vec<Self>> {
+        let events = osu_file.events.clone().unwrap_or_default().0;
+
+        let mut breaks = Vec::new();
+        for event in events {
+            if let Event::Break(break_period) = event {
+                breaks.push(Self {
+                    start_time: break_period.start_time.to_string().parse()?,
+                    end_time: break_period.end_time.to_string().parse()?,
+                });
+            }
+        }
+
+        breaks.sort_by_key(|break_period| break_period.start_time);
+
+        Ok(breaks)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.end_time.saturating_sub(self.start_time) as u64)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// Timing point which sets the beat length (`uninherited` timing point)
+/// https://osu.ppy.sh/wiki/en/Client/File_formats/Osu_%28file_format%29#timing-points
+pub struct BeatTiming {
+    /// In milliseconds since the start of the beatmap
+    pub time: i32,
+    /// Duration of a beat in milliseconds
+    pub beat_length: f64,
+}
+
+impl BeatTiming {
+    pub fn from(osu_file: &OsuFile) -> Result<Vec<Self>> {
+        let timing_points = osu_file.timing_points.clone().unwrap_or_default().0;
+
+        let mut beat_timings = Vec::new();
+        for timing_point in timing_points {
+            if !timing_point.uninherited() {
+                continue;
+            }
+
+            let Some(bpm) = timing_point.calc_bpm() else {
+                continue;
+            };
+            let bpm: f64 = bpm.to_string().parse()?;
+            if bpm <= 0.0 {
+                continue;
+            }
+
+            beat_timings.push(Self {
+                time: timing_point.time().to_string().parse::<f64>()? as i32,
+                beat_length: 60_000.0 / bpm,
+            });
+        }
+
+        beat_timings.sort_by_key(|beat_timing| beat_timing.time);
+
+        Ok(beat_timings)
+    }
+}
+
+/// Index of the timing point active in `time` (in milliseconds) and the number of beats since it started
+pub fn beat_at(beat_timings: &[BeatTiming], time: i32) -> Option<(usize, u32)> {
+    let idx = beat_timings
+        .iter()
+        .rposition(|beat_timing| beat_timing.time <= time)?;
+    let beat_timing = beat_timings[idx];
+    let beats = (time - beat_timing.time) as f64 / beat_timing.beat_length;
+
+    Some((idx, beats as u32))
+}
+
+/// BPM which lasts the longest until `end_time` (in milliseconds), like the BPM displayed by osu!
+pub fn main_bpm(beat_timings: &[BeatTiming], end_time: i32) -> Option<f64> {
+    let mut durations: Vec<(f64, i32)> = Vec::new();
+
+    for (idx, beat_timing) in beat_timings.iter().enumerate() {
+        let next_time = beat_timings
+            .get(idx + 1)
+            .map_or(end_time, |next| next.time.min(end_time));
+        let duration = (next_time - beat_timing.time).max(0);
+        let bpm = 60_000.0 / beat_timing.beat_length;
+
+        match durations
+            .iter_mut()
+            .find(|(other_bpm, _)| (other_bpm - bpm).abs() < 0.01)
+        {
+            Some((_, total_duration)) => *total_duration += duration,
+            None => durations.push((bpm, duration)),
+        }
+    }
+
+    durations
+        .into_iter()
+        .max_by_key(|(_, duration)| *duration)
+        .map(|(bpm, _)| bpm)
+}
+
+/// Total duration of the `breaks` which overlaps the interval between `start_time` and `end_time` (in milliseconds)
+pub fn total_break_duration(breaks: &[BreakPeriod], start_time: u32, end_time: u32) -> Duration {
+    breaks
+        .iter()
+        .map(|break_period| BreakPeriod {
+            start_time: break_period.start_time.clamp(start_time, end_time),
+            end_time: break_period.end_time.clamp(start_time, end_time),
+        })
+        .map(|break_period| break_period.duration())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn break_duration() {
+        let breaks = [
+            BreakPeriod {
+                start_time: 1_000,
+                end_time: 3_000,
+            },
+            BreakPeriod {
+                start_time: 10_000,
+                end_time: 15_000,
+            },
+        ];
+
+        assert_eq!(
+            total_break_duration(&breaks, 0, 20_000),
+            Duration::from_millis(7_000)
+        );
+        assert_eq!(
+            total_break_duration(&breaks, 2_000, 12_000),
+            Duration::from_millis(3_000)
+        );
+        assert_eq!(total_break_duration(&breaks, 4_000, 9_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn beats() {
+        let beat_timings = [
+            BeatTiming {
+                time: 100,
+                beat_length: 500.0,
+            },
+            BeatTiming {
+                time: 2_000,
+                beat_length: 250.0,
+            },
+        ];
+
+        assert_eq!(beat_at(&beat_timings, 0), None);
+        assert_eq!(beat_at(&beat_timings, 100), Some((0, 0)));
+        assert_eq!(beat_at(&beat_timings, 1_099), Some((0, 1)));
+        assert_eq!(beat_at(&beat_timings, 1_100), Some((0, 2)));
+        assert_eq!(beat_at(&beat_timings, 2_300), Some((1, 1)));
+    }
+
+    #[test]
+    fn longest_bpm() {
+        let beat_timing = |time, bpm: f64| BeatTiming {
+            time,
+            beat_length: 60_000.0 / bpm,
+        };
+        let beat_timings = [
+            beat_timing(0, 120.0),
+            beat_timing(10_000, 180.0),
+            beat_timing(15_000, 120.0),
+            beat_timing(20_000, 200.0),
+        ];
+
+        assert_eq!(main_bpm(&beat_timings, 40_000), Some(200.0));
+        assert_eq!(main_bpm(&beat_timings, 25_000), Some(120.0));
+        assert_eq!(main_bpm(&[], 1_000), None);
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use bevy_ecs::{
+    prelude::EventReader,
+    system::{Query, ResMut},
+};
+use valence::{
+    prelude::{Client, Color},
+    protocol::{Text, TextFormat},
+};
+
+use crate::{
+    command_registry::{ArgSpec, CommandSpec, OsuCommand, Suggestions},
+    configs::Configs,
+    error::{error_message, OsuError},
+    osu::Osu,
+    player_name::PlayerName,
+};
+
+/// Volumes (from 0 to 100) of the server audio. The music and the effects are scaled by the master volume.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Volume {
+    #[serde(default = "default_master")]
+    pub master: u8,
+    #[serde(default = "default_music")]
+    pub music: u8,
+    #[serde(default = "default_effects")]
+    pub effects: u8,
+}
+
+fn default_master() -> u8 {
+    100
+}
+
+fn default_music() -> u8 {
+    25
+}
+
+fn default_effects() -> u8 {
+    25
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            master: default_master(),
+            music: default_music(),
+            effects: default_effects(),
+        }
+    }
+}
+
+impl Volume {
+    pub fn music_gain(&self) -> f32 {
+        self.gain(self.music)
+    }
+
+    pub fn effects_gain(&self) -> f32 {
+        self.gain(self.effects)
+    }
+
+    fn gain(&self, volume: u8) -> f32 {
+        self.master.min(100) as f32 / 100.0 * volume.min(100) as f32 / 100.0
+    }
+
+    /// Applies `/volume [master|music|effects] <0-100>` (the master volume is changed if no channel is given)
+    pub fn apply_command(&mut self, args: &str) -> Result<()> {
+        let (channel, value) = match args.trim().split_once(' ') {
+            Some((channel, value)) => (channel, value.trim()),
+            None => ("master", args.trim()),
+        };
+        let value: u8 = value
+            .parse()
+            .ok()
+            .filter(|&value| value <= 100)
+            .ok_or_else(|| anyhow!("the volume must be between 0 and 100"))?;
+
+        match channel {
+            "master" => self.master = value,
+            "music" => self.music = value,
+            "effects" => self.effects = value,
+            _ => bail!(
+                "unknown channel '{}' (expected master, music or effects)",
+                channel
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+pub fn volume_command() -> CommandSpec {
+    CommandSpec::new("volume").arg(
+        ArgSpec::phrase("volume").suggest(Suggestions::Values(vec!["master", "music", "effects"])),
+    )
+}
+
+/// Handles `/volume [master|music|effects] <0-100>` (operators only, since it changes the audio of the server host).
+/// Without arguments, it shows the volumes.
+pub fn execute_volume_commands(
+    mut configs: ResMut<Configs>,
+    mut osu: ResMut<Osu>,
+    mut clients: Query<(&mut Client, Option<&PlayerName>)>,
+    mut command_events: EventReader<OsuCommand>,
+) {
+    for command_event in command_events.iter() {
+        if command_event.name != "volume" {
+            continue;
+        }
+        let args = command_event.args.as_str();
+        let Ok((mut client, player_name)) = clients.get_mut(command_event.client) else {
+            continue;
+        };
+
+        let result: Result<Text> = if args.trim().is_empty() {
+            let volume = configs.volume();
+            Ok("Volume: ".color(Color::YELLOW)
+                + format!(
+                    "master {}, music {}, effects {}",
+                    volume.master, volume.music, volume.effects
+                )
+                .color(Color::GREEN))
+        } else if !player_name.map_or(false, |player_name| configs.is_operator(player_name)) {
+            Err(OsuError::OperatorOnly {
+                action: "change the volume",
+            }
+            .into())
+        } else {
+            let mut volume = configs.volume();
+            volume.apply_command(args).and_then(|_| {
+                osu.set_volume(volume);
+                configs.set_volume(volume)?;
+                Ok("Volume ".color(Color::YELLOW) + "updated".color(Color::GREEN))
+            })
+        };
+
+        match result {
+            Ok(message) => client.send_message(message),
+            Err(error) => client.send_message(error_message(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn volume_command() {
+        let mut volume = Volume::default();
+        assert_eq!(volume.music_gain(), 0.25);
+
+        volume.apply_command("50").unwrap();
+        assert_eq!(volume.master, 50);
+        assert_eq!(volume.music_gain(), 0.125);
+
+        volume.apply_command("effects 100").unwrap();
+        assert_eq!(volume.effects_gain(), 0.5);
+
+        assert!(volume.apply_command("music 101").is_err());
+        assert!(volume.apply_command("voice 10").is_err());
+        assert!(volume.apply_command("loud").is_err());
+    }
+}
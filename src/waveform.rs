@@ -0,0 +1,214 @@
+use anyhow::Result;
+use rodio::{Decoder, Source};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::warn;
+
+use bevy_ecs::system::{Local, Res, ResMut};
+use valence::prelude::BlockState;
+
+use crate::{
+    osu::Osu,
+    playfield::PlayfieldSurface,
+    progress::{cancelled_error, LongOperation, LongOperations, Progress},
+};
+
+/// Duration of the audio windows whose loudness is measured
+const WINDOW: Duration = Duration::from_millis(10);
+/// Blocks of the strip from the quietest to the loudest parts of the song
+const LEVEL_BLOCKS: [BlockState; 5] = [
+    BlockState::GRAY_CONCRETE,
+    BlockState::LIGHT_GRAY_CONCRETE,
+    BlockState::WHITE_CONCRETE,
+    BlockState::YELLOW_CONCRETE,
+    BlockState::ORANGE_CONCRETE,
+];
+const PLAYHEAD_BLOCK: BlockState = BlockState::RED_CONCRETE;
+
+/// Loudness (RMS) of each window of the song, decoding the whole audio file
+fn decode_levels(audio_path: &Path, length: Duration, progress: &Progress) -> Result<Vec<f32>> {
+    let decoder = Decoder::new(BufReader::new(File::open(audio_path)?))?;
+    let window_samples =
+        (decoder.sample_rate() as u128 * decoder.channels() as u128 * WINDOW.as_millis() / 1000)
+            .max(1) as usize;
+    progress.set_total((length.as_millis() / WINDOW.as_millis()) as usize);
+
+    let mut levels = Vec::new();
+    let mut sum_squares = 0.0;
+    let mut samples = 0;
+
+    for sample in decoder {
+        let sample = sample as f64 / i16::MAX as f64;
+        sum_squares += sample * sample;
+        samples += 1;
+
+        if samples == window_samples {
+            if progress.is_cancelled() {
+                return Err(cancelled_error());
+            }
+
+            levels.push((sum_squares / samples as f64).sqrt() as f32);
+            sum_squares = 0.0;
+            samples = 0;
+            progress.advance();
+        }
+    }
+
+    if samples > 0 {
+        levels.push((sum_squares / samples as f64).sqrt() as f32);
+    }
+
+    Ok(levels)
+}
+
+/// Averages the `levels` into `columns` buckets, normalized so the loudest column is 1
+fn compress(levels: &[f32], columns: usize) -> Vec<f32> {
+    if levels.is_empty() || columns == 0 {
+        return vec![0.0; columns];
+    }
+
+    let compressed: Vec<f32> = (0..columns)
+        .map(|column| {
+            let start = column * levels.len() / columns;
+            let end = ((column + 1) * levels.len() / columns).max(start + 1);
+            let bucket = &levels[start..end.min(levels.len())];
+
+            bucket.iter().sum::<f32>() / bucket.len() as f32
+        })
+        .collect();
+
+    let max = compressed.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return compressed;
+    }
+
+    compressed.into_iter().map(|level| level / max).collect()
+}
+
+fn level_block(level: f32) -> BlockState {
+    let idx = (level * LEVEL_BLOCKS.len() as f32) as usize;
+    LEVEL_BLOCKS[idx.min(LEVEL_BLOCKS.len() - 1)]
+}
+
+/// Waveform of the song being played, drawn as a one-block-tall strip under the screen
+#[derive(Default)]
+pub struct Waveform {
+    /// Audio whose waveform is computed or being computed
+    audio_path: Option<PathBuf>,
+    computing: Option<LongOperation<Vec<f32>>>,
+    columns: Vec<f32>,
+    /// Column where the playhead is drawn
+    playhead: Option<usize>,
+}
+
+/// Computes the waveform once the beatmap is about to start (in the background) and moves its playhead as the song plays
+pub fn update_waveform(
+    osu: Res<Osu>,
+    mut surface: ResMut<PlayfieldSurface>,
+    mut operations: ResMut<LongOperations>,
+    mut waveform: Local<Waveform>,
+) {
+    let positions = osu.playfield().waveform_positions();
+    let beatmap = osu
+        .pre_playing_beatmap()
+        .or_else(|| osu.playing_beatmap())
+        .or_else(|| osu.finished_beatmap())
+        .or_else(|| osu.failed_beatmap());
+
+    let Some(beatmap) = beatmap else {
+        if waveform.audio_path.is_some() {
+            if let Some(computing) = &waveform.computing {
+                computing.cancel();
+            }
+            surface.fill(positions, BlockState::AIR);
+            *waveform = Waveform::default();
+        }
+        return;
+    };
+
+    // A new song started
+    if waveform.audio_path.as_ref() != Some(&beatmap.data.audio_path) {
+        if let Some(computing) = &waveform.computing {
+            computing.cancel();
+        }
+        surface.fill(positions.iter().copied(), BlockState::AIR);
+
+        let audio_path = beatmap.data.audio_path.clone();
+        let length = beatmap.data.length();
+        let columns = positions.len();
+        let thread_audio_path = audio_path.clone();
+        *waveform = Waveform {
+            audio_path: Some(audio_path),
+            computing: Some(LongOperation::start(
+                "Computing waveform",
+                None,
+                &mut operations,
+                move |progress| {
+                    let levels = decode_levels(&thread_audio_path, length, progress)?;
+                    Ok(compress(&levels, columns))
+                },
+            )),
+            ..Default::default()
+        };
+    }
+
+    if let Some(result) = waveform
+        .computing
+        .as_mut()
+        .and_then(|computing| computing.try_finish())
+    {
+        waveform.computing = None;
+        match result {
+            Ok(columns) => {
+                for (&pos, &level) in positions.iter().zip(columns.iter()) {
+                    surface.set(pos, level_block(level));
+                }
+                waveform.columns = columns;
+            }
+            Err(error) => warn!("Error while computing the waveform: {}", error),
+        }
+    }
+
+    if waveform.columns.is_empty() {
+        return;
+    }
+
+    let playhead = osu.playing_beatmap().map(|beatmap| {
+        let length = beatmap.data.length().as_millis().max(1) as f64;
+        let progress = (beatmap.state.play_time.as_millis() as f64 / length).min(1.0);
+        ((progress * positions.len() as f64) as usize).min(positions.len() - 1)
+    });
+
+    if playhead != waveform.playhead {
+        if let Some(previous) = waveform.playhead {
+            surface.set(positions[previous], level_block(waveform.columns[previous]));
+        }
+        if let Some(playhead) = playhead {
+            surface.set(positions[playhead], PLAYHEAD_BLOCK);
+        }
+        waveform.playhead = playhead;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compress_levels() {
+        let levels = [0.25, 0.25, 0.5, 0.5, 1.0, 0.0];
+
+        assert_eq!(compress(&levels, 3), vec![0.5, 1.0, 1.0]);
+        assert_eq!(compress(&levels, 0), Vec::<f32>::new());
+        assert_eq!(compress(&[], 2), vec![0.0, 0.0]);
+        // More columns than levels repeat the levels
+        assert_eq!(compress(&[0.5, 1.0], 4), vec![0.5, 0.5, 1.0, 1.0]);
+
+        assert_eq!(level_block(0.0), LEVEL_BLOCKS[0]);
+        assert_eq!(level_block(1.0), LEVEL_BLOCKS[4]);
+    }
+}